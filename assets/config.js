@@ -0,0 +1,53 @@
+// Form generated from the `/api/config` JSON, so it follows the settings
+// without having to be updated.
+let current = {};
+const kind = v => typeof v === 'boolean' ? 'checkbox' : typeof v === 'number' ? 'number' : 'text';
+fetch('/api/config').then(r => r.json()).then(c => {
+    current = c;
+    const form = document.getElementById('config');
+    for (const [k, v] of Object.entries(c)) {
+        form.insertAdjacentHTML('beforeend',
+            `<label>${k} <input name="${k}" type="${kind(v)}" step="any"></label><br>`);
+        const input = form.elements[k];
+        if (kind(v) === 'checkbox') input.checked = v;
+        else input.value = typeof v === 'object' ? JSON.stringify(v) : v;
+    }
+    offerNetworks(form.elements['wifi_ssid']);
+});
+// Pick-list of the networks in range, only available in safe mode
+function offerNetworks(input) {
+    fetch('/api/wifi/scan').then(r => r.ok ? r.json() : []).then(networks => {
+        const list = document.createElement('datalist');
+        list.id = 'networks';
+        for (const n of networks) {
+            const option = document.createElement('option');
+            option.value = n.ssid;
+            option.label = `${n.rssi} dBm${n.secured ? '' : ', open'}`;
+            list.appendChild(option);
+        }
+        document.body.appendChild(list);
+        input.setAttribute('list', 'networks');
+    });
+}
+// Provisioned devices ask for their API key
+function save(token) {
+    const form = document.getElementById('config');
+    const patch = {};
+    for (const [k, v] of Object.entries(current)) {
+        const input = form.elements[k];
+        patch[k] = kind(v) === 'checkbox' ? input.checked
+            : kind(v) === 'number' ? Number(input.value)
+            : typeof v === 'object' ? JSON.parse(input.value)
+            : input.value;
+    }
+    const headers = token ? {Authorization: `Bearer ${token}`} : {};
+    fetch('/api/config', {method: 'POST', headers, body: JSON.stringify(patch)})
+        .then(async r => {
+            if (r.status === 401 && !token) {
+                const key = prompt('API key');
+                if (key) return save(key);
+            }
+            const status = document.getElementById('status');
+            status.textContent = r.ok ? status.dataset.saved : await r.text();
+        });
+}
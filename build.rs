@@ -9,6 +9,15 @@ pub struct Config {
 }
 
 fn main() {
+    build_info();
+
+    // The host simulation doesn't connect anywhere, no credentials needed
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("espidf") {
+        return;
+    }
+
+    check_mcu();
+
     // Check if the `cfg.toml` file exists and has been filled out.
     if !std::path::Path::new("cfg.toml").exists() {
         panic!("You need to create a `cfg.toml` file with your Wi-Fi credentials! Use `cfg.toml.example` as a template.");
@@ -20,11 +29,124 @@ fn main() {
         panic!("You need to set the Wi-Fi credentials in `cfg.toml`!");
     }
 
-    if app_config.mqtt_broker_url == "" {
-        panic!("please set a mqtt broker url");
+    if app_config.mqtt_broker_url.is_empty() {
+        println!("cargo:warning=No mqtt broker url set, MQTT will be disabled");
     }
 
     println!("cargo:rustc-env=TOML_CFG=require_cfg_present");
 
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    compress_assets(&out_dir);
+    // Listing files disables the default of rerunning on any change
+    println!("cargo:rerun-if-changed=assets");
+    println!("cargo:rerun-if-changed=cfg.toml");
+    println!("cargo:rerun-if-changed=build.rs");
+    // A new commit, or staged changes, change the hash
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
     embuild::espidf::sysenv::output();
 }
+
+/// `MCU` selects the ESP-IDF target, it must match the Rust one, see the
+/// `build-<mcu>` aliases of `.cargo/config.toml`
+fn check_mcu() {
+    const TARGETS: &[(&str, &str)] = &[
+        ("esp32c6", "riscv32imac-esp-espidf"),
+        ("esp32c3", "riscv32imc-esp-espidf"),
+        ("esp32s3", "xtensa-esp32s3-espidf"),
+        ("esp32", "xtensa-esp32-espidf"),
+    ];
+    println!("cargo:rerun-if-env-changed=MCU");
+    let mcu = std::env::var("MCU").unwrap_or_default();
+    let target = std::env::var("TARGET").unwrap();
+    match TARGETS.iter().find(|(name, _)| *name == mcu) {
+        Some((_, expected)) if *expected == target => {}
+        Some((_, expected)) => {
+            panic!("MCU={mcu} needs the {expected} target, not {target}, use `cargo build-{mcu}`")
+        }
+        None => {
+            let names: Vec<&str> = TARGETS.iter().map(|(name, _)| *name).collect();
+            panic!(
+                "Unsupported MCU {mcu:?}, expected one of {}",
+                names.join(", ")
+            )
+        }
+    }
+    // The only one of them with an 802.15.4 radio
+    if std::env::var_os("CARGO_FEATURE_ZIGBEE").is_some() && mcu != "esp32c6" {
+        panic!("The zigbee feature needs the ESP32-C6, not the {mcu}");
+    }
+}
+
+/// Commit and time of the build, read by `src/build_info.rs`
+fn build_info() {
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let hash = match (
+        git(&["rev-parse", "--short=8", "HEAD"]),
+        git(&["status", "--porcelain", "--untracked-files=no"]),
+    ) {
+        (Some(hash), Some(status)) if !status.is_empty() => format!("{hash}-dirty"),
+        (Some(hash), _) => hash,
+        (None, _) => "unknown".to_string(),
+    };
+    // Set for reproducible builds
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+    println!("cargo:rustc-env=GIT_HASH={hash}");
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={timestamp}");
+}
+
+/// Gzip the files of `assets/` and list them in `assets.rs`, included by
+/// `src/assets.rs`
+fn compress_assets(out_dir: &std::path::Path) {
+    use std::io::Write;
+
+    let mut paths: Vec<_> = std::fs::read_dir("assets")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    // Same order, so the same output, on every build
+    paths.sort();
+    let mut list = String::from("&[\n");
+    for path in paths {
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        let content_type = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("css") => "text/css",
+            Some("js") => "text/javascript",
+            Some("html") => "text/html",
+            Some("svg") => "image/svg+xml",
+            _ => panic!("Unknown content type of asset {name}"),
+        };
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&std::fs::read(&path).unwrap()).unwrap();
+        let gzip = encoder.finish().unwrap();
+        // FNV-1a, only has to change with the content
+        let hash = gzip.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        let gzip_path = out_dir.join(format!("{name}.gz"));
+        std::fs::write(&gzip_path, gzip).unwrap();
+        list += &format!(
+            "    Asset {{ uri: \"/assets/{name}\", content_type: \"{content_type}\", \
+             etag: \"\\\"{hash:016x}\\\"\", gzip: include_bytes!({gzip_path:?}) }},\n"
+        );
+    }
+    list += "]\n";
+    std::fs::write(out_dir.join("assets.rs"), list).unwrap();
+}
@@ -0,0 +1,6 @@
+#include "sdkconfig.h"
+
+// Empty unless built with `sdkconfig.defaults.zigbee`, see the README
+#ifdef CONFIG_ZB_ENABLED
+#include "esp_zigbee_core.h"
+#endif
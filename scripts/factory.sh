@@ -0,0 +1,42 @@
+#!/bin/sh
+# Build the factory image, flashed at 0 on a blank device, and the ESP Web
+# Tools manifest pointing to it, in target/factory/. Build with an empty
+# `wifi_ssid` in cfg.toml: the network is then received over Improv.
+set -e
+
+cd "$(dirname "$0")/.."
+mcu=${MCU:-esp32c6}
+name=esp-particle-sensor-rs
+version=$(sed -n 's/^version = "\(.*\)"/\1/p' Cargo.toml | head -n 1)
+# esp32c6 -> ESP32-C6, as named by ESP Web Tools
+family=$(echo "$mcu" | tr '[:lower:]' '[:upper:]' | sed 's/^ESP32\(..*\)$/ESP32-\1/')
+image="$name-$version-$mcu.bin"
+
+# As the build-<mcu> aliases of .cargo/config.toml
+case "$mcu" in
+    esp32c6) target=riscv32imac-esp-espidf ;;
+    esp32c3) target=riscv32imc-esp-espidf ;;
+    esp32s3) target=xtensa-esp32s3-espidf ;;
+    esp32) target=xtensa-esp32-espidf ;;
+    *) echo "Unsupported MCU $mcu" >&2; exit 1 ;;
+esac
+
+cargo factory --target "$target" --config "env.MCU=\"$mcu\""
+mkdir -p target/factory
+espflash save-image --chip "$mcu" --merge --partition-table partitions.csv \
+    "target/$target/factory/$name" "target/factory/$image"
+
+cat > target/factory/manifest.json <<MANIFEST
+{
+  "name": "$name",
+  "version": "$version",
+  "new_install_prompt_erase": true,
+  "builds": [
+    {
+      "chipFamily": "$family",
+      "parts": [{ "path": "$image", "offset": 0 }]
+    }
+  ]
+}
+MANIFEST
+echo "target/factory/$image and target/factory/manifest.json"
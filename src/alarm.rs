@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::reading::{self, Kind, Reading};
+use crate::schedule::{self, Hours};
+
+/// When the buzzer sounds, from the settings
+#[derive(Debug, Clone, Copy)]
+pub struct Alarm {
+    /// µg/m³
+    pub pm25: f32,
+    pub pm10: f32,
+    /// Delay before sounding again while the level stays above a threshold
+    pub repeat: Duration,
+    pub quiet: Hours,
+}
+
+impl Alarm {
+    /// At least one of the values reached its threshold
+    pub fn is_severe(&self, readings: &[Reading]) -> bool {
+        let above =
+            |kind, threshold| reading::value(readings, kind).is_some_and(|v| v >= threshold);
+        above(Kind::Pm25, self.pm25) || above(Kind::Pm10, self.pm10)
+    }
+
+    /// Within the quiet hours, never when the clock is not synchronized
+    pub fn is_quiet(&self, now: Option<DateTime<Utc>>) -> bool {
+        schedule::local_hour(now).is_some_and(|hour| self.quiet.contains(hour))
+    }
+}
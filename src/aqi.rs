@@ -0,0 +1,146 @@
+/// Concentration and index at the bounds of a category
+struct Breakpoint {
+    low: f32,
+    high: f32,
+    index_low: f32,
+    index_high: f32,
+}
+
+const fn breakpoint(low: f32, high: f32, index_low: f32, index_high: f32) -> Breakpoint {
+    Breakpoint {
+        low,
+        high,
+        index_low,
+        index_high,
+    }
+}
+
+/// µg/m³, as revised by the EPA in 2024
+const PM25: [Breakpoint; 6] = [
+    breakpoint(0.0, 9.0, 0.0, 50.0),
+    breakpoint(9.1, 35.4, 51.0, 100.0),
+    breakpoint(35.5, 55.4, 101.0, 150.0),
+    breakpoint(55.5, 125.4, 151.0, 200.0),
+    breakpoint(125.5, 225.4, 201.0, 300.0),
+    breakpoint(225.5, 325.4, 301.0, 500.0),
+];
+
+/// µg/m³
+const PM10: [Breakpoint; 6] = [
+    breakpoint(0.0, 54.0, 0.0, 50.0),
+    breakpoint(55.0, 154.0, 51.0, 100.0),
+    breakpoint(155.0, 254.0, 101.0, 150.0),
+    breakpoint(255.0, 354.0, 151.0, 200.0),
+    breakpoint(355.0, 424.0, 201.0, 300.0),
+    breakpoint(425.0, 604.0, 301.0, 500.0),
+];
+
+/// Category of the US EPA AQI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Band {
+    Good,
+    Moderate,
+    UnhealthyForSensitiveGroups,
+    Unhealthy,
+    VeryUnhealthy,
+    Hazardous,
+}
+
+impl Band {
+    pub fn from_aqi(aqi: u16) -> Self {
+        match aqi {
+            0..=50 => Self::Good,
+            51..=100 => Self::Moderate,
+            101..=150 => Self::UnhealthyForSensitiveGroups,
+            151..=200 => Self::Unhealthy,
+            201..=300 => Self::VeryUnhealthy,
+            _ => Self::Hazardous,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Good => "Good",
+            Self::Moderate => "Moderate",
+            Self::UnhealthyForSensitiveGroups => "Unhealthy for sensitive groups",
+            Self::Unhealthy => "Unhealthy",
+            Self::VeryUnhealthy => "Very unhealthy",
+            Self::Hazardous => "Hazardous",
+        }
+    }
+}
+
+/// US EPA Air Quality Index, the highest of the PM2.5 and PM10 ones, from
+/// 0 to 500. The EPA computes it from daily averages, it is only an
+/// indication of the current level.
+pub fn us_epa(pm25: f32, pm10: f32) -> u16 {
+    // Truncated like the EPA does, to 0.1 µg/m³ and 1 µg/m³
+    let pm25 = sub_index(&PM25, (pm25 * 10.0).floor() / 10.0);
+    let pm10 = sub_index(&PM10, pm10.floor());
+    pm25.max(pm10)
+}
+
+fn sub_index(breakpoints: &[Breakpoint], concentration: f32) -> u16 {
+    let concentration = concentration.max(0.0);
+    let Some(category) = breakpoints
+        .iter()
+        // Up to the low bound of the next category
+        .find(|category| concentration < category.high + 0.1)
+    else {
+        // Beyond the index
+        return 500;
+    };
+    let ratio = (concentration - category.low).max(0.0) / (category.high - category.low);
+    (category.index_low + ratio * (category.index_high - category.index_low)).round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_air() {
+        assert_eq!(us_epa(0.0, 0.0), 0);
+        assert_eq!(Band::from_aqi(us_epa(0.0, 0.0)), Band::Good);
+    }
+
+    #[test]
+    fn pm25_breakpoints() {
+        assert_eq!(us_epa(9.0, 0.0), 50);
+        assert_eq!(us_epa(9.1, 0.0), 51);
+        assert_eq!(us_epa(35.4, 0.0), 100);
+        assert_eq!(us_epa(35.5, 0.0), 101);
+        assert_eq!(us_epa(325.4, 0.0), 500);
+    }
+
+    #[test]
+    fn truncated_like_the_epa() {
+        // 9.09 is 9.0, still good
+        assert_eq!(us_epa(9.09, 0.0), 50);
+        // 54.9 is 54
+        assert_eq!(us_epa(0.0, 54.9), 50);
+    }
+
+    #[test]
+    fn highest_of_both() {
+        assert_eq!(us_epa(0.0, 55.0), 51);
+        assert_eq!(us_epa(12.0, 55.0), us_epa(12.0, 0.0).max(51));
+    }
+
+    #[test]
+    fn beyond_the_index() {
+        assert_eq!(us_epa(1000.0, 0.0), 500);
+        assert_eq!(us_epa(0.0, 1000.0), 500);
+        assert_eq!(Band::from_aqi(500), Band::Hazardous);
+    }
+
+    #[test]
+    fn bands() {
+        assert_eq!(Band::from_aqi(50), Band::Good);
+        assert_eq!(Band::from_aqi(51), Band::Moderate);
+        assert_eq!(Band::from_aqi(150), Band::UnhealthyForSensitiveGroups);
+        assert_eq!(Band::from_aqi(200), Band::Unhealthy);
+        assert_eq!(Band::from_aqi(300), Band::VeryUnhealthy);
+        assert_eq!(Band::from_aqi(301), Band::Hazardous);
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::Result;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Write;
+
+use crate::http;
+
+/// File of `assets/`, gzipped by `build.rs`
+pub struct Asset {
+    pub uri: &'static str,
+    pub content_type: &'static str,
+    /// Quoted hash of the content
+    pub etag: &'static str,
+    pub gzip: &'static [u8],
+}
+
+pub static ASSETS: &[Asset] = include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+/// Serve the assets on `/assets/<name>`, as is since every browser accepts
+/// gzip. They are revalidated on each use, a matching `If-None-Match` gets
+/// an empty 304 instead of the content.
+pub fn register_handlers(handlers: &mut http::Handlers) -> Result<()> {
+    for asset in ASSETS {
+        http::fn_handler(
+            handlers,
+            asset.uri,
+            Method::Get,
+            move |request| -> Result<()> {
+                if request.header("If-None-Match") == Some(asset.etag) {
+                    request.into_response(304, None, &[("ETag", asset.etag)])?;
+                    return Ok(());
+                }
+                let mut response = request.into_response(
+                    200,
+                    None,
+                    &[
+                        ("Content-Type", asset.content_type),
+                        ("Content-Encoding", "gzip"),
+                        ("Cache-Control", "no-cache"),
+                        ("ETag", asset.etag),
+                    ],
+                )?;
+                response.write_all(asset.gzip)?;
+                Ok(())
+            },
+        )?;
+    }
+    Ok(())
+}
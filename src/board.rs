@@ -0,0 +1,385 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::sys;
+
+use crate::config::CONFIG;
+use crate::led::LedKind;
+use crate::segment::SegmentKind;
+
+/// GPIOs and peripherals wired on a board
+#[derive(Debug, Clone, Copy)]
+pub struct Board {
+    pub name: &'static str,
+    /// UART1 TX, goes to the first sensor's RX
+    pub sensor0_tx: i32,
+    /// UART1 RX, goes to the first sensor's TX
+    pub sensor0_rx: i32,
+    /// UART0 TX, for the optional second sensor
+    pub sensor1_tx: i32,
+    /// UART0 RX, for the optional second sensor
+    pub sensor1_rx: i32,
+    /// TX of the optional CO2 sensor, on UART2 or in place of the second
+    /// sensor on UART0 when there is no UART2
+    pub co2_tx: i32,
+    pub co2_rx: i32,
+    /// I2C bus of the optional VOC sensor
+    pub i2c_sda: i32,
+    pub i2c_scl: i32,
+    /// WS2812 data line
+    pub led: i32,
+    /// RMT channel driving the WS2812, 0 to 3
+    pub led_rmt_channel: u8,
+    /// Only set in `cfg.toml`, a WS2812 on every preset
+    pub led_kind: LedKind,
+    /// Red, green and blue pins of an RGB LED
+    pub led_rgb: Option<[i32; 3]>,
+    pub sd_sclk: i32,
+    pub sd_mosi: i32,
+    pub sd_miso: i32,
+    pub sd_cs: i32,
+    /// W5500 chip select, the module shares the SD card bus
+    pub eth_cs: i32,
+    /// W5500 interrupt, active low
+    pub eth_int: i32,
+    pub eth_rst: i32,
+    /// Optional on every board, only set in `cfg.toml`
+    pub buzzer: Option<i32>,
+    /// Optional on every board, only set in `cfg.toml`
+    pub relay: Option<i32>,
+    /// Optional on every board, only set in `cfg.toml`
+    pub fan: Option<i32>,
+    /// Optional on every board, only set in `cfg.toml`
+    pub dht22: Option<i32>,
+    /// Optional on every board, only set in `cfg.toml`
+    pub epaper: Option<EpaperPins>,
+    /// Optional on every board, only set in `cfg.toml`
+    pub segment: Option<SegmentPins>,
+}
+
+/// Control pins of an e-paper panel, on the SD card SPI bus
+#[derive(Debug, Clone, Copy)]
+pub struct EpaperPins {
+    pub cs: i32,
+    pub dc: i32,
+    pub rst: i32,
+    pub busy: i32,
+}
+
+/// 7-segment display and its pins, bit-banged
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentPins {
+    pub kind: SegmentKind,
+    pub clk: i32,
+    pub dio: i32,
+    /// MAX7219 only
+    pub cs: Option<i32>,
+}
+
+/// RMT channels which can transmit, for the WS2812: 4 of the 8 of the ESP32
+/// and ESP32-S3 are handled, the C3 and C6 receive on the other 2
+#[cfg(any(esp32, esp32s3))]
+const RMT_TX_CHANNELS: i32 = 4;
+#[cfg(not(any(esp32, esp32s3)))]
+const RMT_TX_CHANNELS: i32 = 2;
+
+const PRESETS: &[Board] = &[
+    Board {
+        name: "esp32c6-devkit",
+        sensor0_tx: 0,
+        sensor0_rx: 1,
+        sensor1_tx: 2,
+        sensor1_rx: 3,
+        co2_tx: 2,
+        co2_rx: 3,
+        i2c_sda: 22,
+        i2c_scl: 23,
+        led: 8,
+        led_rmt_channel: 0,
+        led_kind: LedKind::Ws2812,
+        led_rgb: None,
+        sd_sclk: 6,
+        sd_mosi: 7,
+        sd_miso: 5,
+        sd_cs: 4,
+        eth_cs: 18,
+        eth_int: 19,
+        eth_rst: 20,
+        buzzer: None,
+        relay: None,
+        fan: None,
+        dht22: None,
+        epaper: None,
+        segment: None,
+    },
+    Board {
+        name: "esp32c3-devkit",
+        sensor0_tx: 0,
+        sensor0_rx: 1,
+        sensor1_tx: 2,
+        sensor1_rx: 3,
+        co2_tx: 2,
+        co2_rx: 3,
+        i2c_sda: 9,
+        i2c_scl: 10,
+        led: 8,
+        led_rmt_channel: 0,
+        led_kind: LedKind::Ws2812,
+        led_rgb: None,
+        sd_sclk: 6,
+        sd_mosi: 7,
+        sd_miso: 5,
+        sd_cs: 4,
+        eth_cs: 10,
+        eth_int: 20,
+        eth_rst: 21,
+        buzzer: None,
+        relay: None,
+        fan: None,
+        dht22: None,
+        epaper: None,
+        segment: None,
+    },
+    // ESP32-S3-DevKitC-1 v1.0, the v1.1 moved the LED to GPIO38
+    Board {
+        name: "esp32s3-devkit",
+        sensor0_tx: 17,
+        sensor0_rx: 18,
+        sensor1_tx: 15,
+        sensor1_rx: 16,
+        co2_tx: 4,
+        co2_rx: 5,
+        i2c_sda: 8,
+        i2c_scl: 9,
+        led: 48,
+        led_rmt_channel: 0,
+        led_kind: LedKind::Ws2812,
+        led_rgb: None,
+        sd_sclk: 12,
+        sd_mosi: 11,
+        sd_miso: 13,
+        sd_cs: 10,
+        eth_cs: 14,
+        eth_int: 21,
+        eth_rst: 47,
+        buzzer: None,
+        relay: None,
+        fan: None,
+        dht22: None,
+        epaper: None,
+        segment: None,
+    },
+    // The ESP32-DevKitC has no addressable LED, an external one is expected
+    Board {
+        name: "esp32-devkit",
+        sensor0_tx: 17,
+        sensor0_rx: 16,
+        sensor1_tx: 26,
+        sensor1_rx: 27,
+        co2_tx: 25,
+        co2_rx: 14,
+        i2c_sda: 21,
+        i2c_scl: 22,
+        led: 2,
+        led_rmt_channel: 0,
+        led_kind: LedKind::Ws2812,
+        led_rgb: None,
+        sd_sclk: 18,
+        sd_mosi: 23,
+        sd_miso: 19,
+        sd_cs: 5,
+        eth_cs: 33,
+        eth_int: 34,
+        eth_rst: 32,
+        buzzer: None,
+        relay: None,
+        fan: None,
+        dht22: None,
+        epaper: None,
+        segment: None,
+    },
+];
+
+impl Board {
+    /// The board preset selected in `cfg.toml`, with the pins overridden
+    /// there. Overrides are ignored when negative.
+    pub fn from_config() -> Result<Self> {
+        // The devkit of the chip by default
+        let name = match CONFIG.board {
+            "" => format!("{}-devkit", chip()),
+            name => name.to_string(),
+        };
+        let Some(preset) = PRESETS.iter().find(|board| board.name == name) else {
+            let names: Vec<&str> = PRESETS.iter().map(|board| board.name).collect();
+            bail!("Unknown board {name}, expected one of {}", names.join(", "));
+        };
+        // The GPIOs and peripherals differ, e.g. no GPIO 22 on the C3
+        if !preset.name.starts_with(&format!("{}-", chip())) {
+            bail!("The {name} board doesn't fit the {} build", chip());
+        }
+        let pin = |configured: i32, preset: i32| {
+            if configured >= 0 {
+                configured
+            } else {
+                preset
+            }
+        };
+        let epaper = [
+            CONFIG.epaper_cs_pin,
+            CONFIG.epaper_dc_pin,
+            CONFIG.epaper_rst_pin,
+            CONFIG.epaper_busy_pin,
+        ];
+        let epaper = match epaper {
+            [cs, dc, rst, busy] if epaper.iter().all(|pin| *pin >= 0) => {
+                Some(EpaperPins { cs, dc, rst, busy })
+            }
+            _ if epaper.iter().all(|pin| *pin < 0) => None,
+            _ => bail!("The e-paper display needs its CS, DC, RST and BUSY pins"),
+        };
+        let segment = match CONFIG.segment_display {
+            "" => None,
+            kind => {
+                let kind = kind.parse()?;
+                let cs = (CONFIG.segment_cs_pin >= 0).then_some(CONFIG.segment_cs_pin);
+                if CONFIG.segment_clk_pin < 0 || CONFIG.segment_dio_pin < 0 {
+                    bail!("The 7-segment display needs its CLK and DIO pins");
+                }
+                if kind == SegmentKind::Max7219 && cs.is_none() {
+                    bail!("The MAX7219 display needs its CS pin");
+                }
+                Some(SegmentPins {
+                    kind,
+                    clk: CONFIG.segment_clk_pin,
+                    dio: CONFIG.segment_dio_pin,
+                    cs: cs.filter(|_| kind == SegmentKind::Max7219),
+                })
+            }
+        };
+        let led_kind = CONFIG.led_kind.parse()?;
+        let led_rgb = [
+            CONFIG.led_red_pin,
+            CONFIG.led_green_pin,
+            CONFIG.led_blue_pin,
+        ];
+        let led_rgb = match led_kind {
+            LedKind::Rgb if led_rgb.iter().all(|pin| *pin >= 0) => Some(led_rgb),
+            LedKind::Rgb => bail!("The RGB LED needs its red, green and blue pins"),
+            _ => None,
+        };
+        let led_rmt_channel = match CONFIG.led_rmt_channel {
+            channel if channel < 0 => preset.led_rmt_channel,
+            channel if channel < RMT_TX_CHANNELS => channel as u8,
+            channel => bail!(
+                "Invalid RMT channel {channel}, expected 0 to {} on the {}",
+                RMT_TX_CHANNELS - 1,
+                chip()
+            ),
+        };
+        Ok(Self {
+            name: preset.name,
+            sensor0_tx: pin(CONFIG.sensor0_tx_pin, preset.sensor0_tx),
+            sensor0_rx: pin(CONFIG.sensor0_rx_pin, preset.sensor0_rx),
+            sensor1_tx: pin(CONFIG.sensor1_tx_pin, preset.sensor1_tx),
+            sensor1_rx: pin(CONFIG.sensor1_rx_pin, preset.sensor1_rx),
+            co2_tx: pin(CONFIG.co2_tx_pin, preset.co2_tx),
+            co2_rx: pin(CONFIG.co2_rx_pin, preset.co2_rx),
+            i2c_sda: pin(CONFIG.i2c_sda_pin, preset.i2c_sda),
+            i2c_scl: pin(CONFIG.i2c_scl_pin, preset.i2c_scl),
+            led: pin(CONFIG.led_pin, preset.led),
+            led_rmt_channel,
+            led_kind,
+            led_rgb,
+            sd_sclk: pin(CONFIG.sd_sclk_pin, preset.sd_sclk),
+            sd_mosi: pin(CONFIG.sd_mosi_pin, preset.sd_mosi),
+            sd_miso: pin(CONFIG.sd_miso_pin, preset.sd_miso),
+            sd_cs: pin(CONFIG.sd_cs_pin, preset.sd_cs),
+            eth_cs: pin(CONFIG.eth_cs_pin, preset.eth_cs),
+            eth_int: pin(CONFIG.eth_int_pin, preset.eth_int),
+            eth_rst: pin(CONFIG.eth_rst_pin, preset.eth_rst),
+            buzzer: (CONFIG.buzzer_pin >= 0).then_some(CONFIG.buzzer_pin),
+            relay: (CONFIG.relay_pin >= 0).then_some(CONFIG.relay_pin),
+            fan: (CONFIG.fan_pin >= 0).then_some(CONFIG.fan_pin),
+            dht22: (CONFIG.dht22_pin >= 0).then_some(CONFIG.dht22_pin),
+            epaper,
+            segment,
+        })
+    }
+
+    /// Whether the I2C pins are free of the optional pins, and of the SPI
+    /// bus when it is set up
+    pub fn i2c_pins_free(&self, network: NetworkKind) -> bool {
+        let mut used: Vec<i32> = [self.buzzer, self.relay, self.fan, self.dht22]
+            .into_iter()
+            .flatten()
+            .collect();
+        if cfg!(feature = "sdcard") || network.on_spi() || self.epaper.is_some() {
+            used.extend([self.sd_sclk, self.sd_mosi, self.sd_miso, self.sd_cs]);
+        }
+        if let Some(epaper) = self.epaper {
+            used.extend([epaper.cs, epaper.dc, epaper.rst, epaper.busy]);
+        }
+        if let Some(rgb) = self.led_rgb {
+            used.extend(rgb);
+        }
+        if let Some(segment) = self.segment {
+            used.extend(
+                [Some(segment.clk), Some(segment.dio), segment.cs]
+                    .into_iter()
+                    .flatten(),
+            );
+        }
+        match network {
+            NetworkKind::Wifi => {}
+            NetworkKind::Ethernet => used.extend([self.eth_cs, self.eth_int, self.eth_rst]),
+            NetworkKind::Lorawan => used.extend([self.eth_cs, self.eth_rst]),
+            #[cfg(feature = "zigbee")]
+            NetworkKind::Zigbee => {}
+        }
+        !used.contains(&self.i2c_sda) && !used.contains(&self.i2c_scl)
+    }
+}
+
+/// Network interface selected by `network` in `cfg.toml`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkKind {
+    Wifi,
+    /// W5500 SPI module
+    Ethernet,
+    /// SX1276 SPI module, wired like the W5500 without its interrupt
+    Lorawan,
+    /// 802.15.4 radio of the ESP32-C6
+    #[cfg(feature = "zigbee")]
+    Zigbee,
+}
+
+impl NetworkKind {
+    pub fn from_config(name: &str) -> Result<Self> {
+        match name {
+            "wifi" => Ok(Self::Wifi),
+            "ethernet" => Ok(Self::Ethernet),
+            "lorawan" => Ok(Self::Lorawan),
+            #[cfg(feature = "zigbee")]
+            "zigbee" => Ok(Self::Zigbee),
+            #[cfg(not(feature = "zigbee"))]
+            "zigbee" => bail!("Zigbee needs the zigbee feature"),
+            other => bail!("Unknown network {other}, expected wifi, ethernet or lorawan"),
+        }
+    }
+
+    /// Whether it is a module on the SD card SPI bus
+    pub fn on_spi(self) -> bool {
+        matches!(self, Self::Ethernet | Self::Lorawan)
+    }
+
+    /// Whether it carries IP, for MQTT, the web server and the other
+    /// services, or only the measurements
+    pub fn is_ip(self) -> bool {
+        matches!(self, Self::Wifi | Self::Ethernet)
+    }
+}
+
+/// Target of the build, `esp32c6` for instance
+pub fn chip() -> &'static str {
+    core::str::from_utf8(sys::CONFIG_IDF_TARGET)
+        .unwrap_or_default()
+        .trim_end_matches('\0')
+}
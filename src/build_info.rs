@@ -0,0 +1,39 @@
+use core::fmt::{self, Display, Formatter};
+
+use chrono::DateTime;
+use serde::Serialize;
+
+/// Identifies the image, embedded by `build.rs`
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    /// Of `Cargo.toml`
+    pub version: &'static str,
+    /// Short hash of the commit, `-dirty` with uncommitted changes,
+    /// `unknown` outside of a git checkout
+    pub git_hash: &'static str,
+    /// RFC 3339, in UTC
+    pub built_at: String,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let timestamp = env!("BUILD_TIMESTAMP").parse().unwrap_or_default();
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("GIT_HASH"),
+            built_at: DateTime::from_timestamp(timestamp, 0)
+                .unwrap_or_default()
+                .to_rfc3339(),
+        }
+    }
+}
+
+impl Display for BuildInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, built {})",
+            self.version, self.git_hash, self.built_at
+        )
+    }
+}
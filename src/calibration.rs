@@ -0,0 +1,42 @@
+use crate::reading;
+use crate::sensor::Measurement;
+
+/// Settings of the calibration, the only ones which can be changed on MQTT
+pub const SETTINGS: [&str; 4] = ["pm25_offset", "pm25_slope", "pm10_offset", "pm10_slope"];
+
+/// Linear correction, `raw * slope + offset`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Correction {
+    /// µg/m³
+    pub offset: f32,
+    pub slope: f32,
+}
+
+impl Correction {
+    pub const IDENTITY: Self = Self {
+        offset: 0.0,
+        slope: 1.0,
+    };
+
+    /// Corrected value of `raw`, in tenths of µg/m³, never negative
+    fn apply(&self, raw: u16) -> u16 {
+        reading::to_tenths(reading::from_tenths(raw) * self.slope + self.offset)
+    }
+}
+
+/// Corrections of each metric, against a reference station
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub pm25: Correction,
+    pub pm10: Correction,
+}
+
+impl Calibration {
+    pub fn is_identity(&self) -> bool {
+        self.pm25 == Correction::IDENTITY && self.pm10 == Correction::IDENTITY
+    }
+
+    pub fn apply(&self, raw: &Measurement) -> Measurement {
+        Measurement::new(self.pm25.apply(raw.pm25()), self.pm10.apply(raw.pm10()))
+    }
+}
@@ -0,0 +1,66 @@
+const TYPE_ANALOG_INPUT: u8 = 2;
+/// Of the extended format of the Electronic Cats library
+const TYPE_CONCENTRATION: u8 = 125;
+const TYPE_TEMPERATURE: u8 = 103;
+const TYPE_HUMIDITY: u8 = 104;
+
+/// Cayenne Low Power Payload, values tagged with their channel and type
+#[derive(Debug, Default)]
+pub struct Payload(Vec<u8>);
+
+impl Payload {
+    /// Hundredths, clamped to ±327.67
+    pub fn analog_input(&mut self, channel: u8, value: f32) {
+        let value = (value * 100.0).round().clamp(-32767.0, 32767.0) as i16;
+        self.push(channel, TYPE_ANALOG_INPUT, &value.to_be_bytes());
+    }
+
+    /// ppm
+    pub fn concentration(&mut self, channel: u8, ppm: u16) {
+        self.push(channel, TYPE_CONCENTRATION, &ppm.to_be_bytes());
+    }
+
+    /// Tenths of °C
+    pub fn temperature(&mut self, channel: u8, celsius: f32) {
+        let value = (celsius * 10.0).round().clamp(-32767.0, 32767.0) as i16;
+        self.push(channel, TYPE_TEMPERATURE, &value.to_be_bytes());
+    }
+
+    /// Halves of %
+    pub fn humidity(&mut self, channel: u8, percent: f32) {
+        let value = (percent * 2.0).round().clamp(0.0, 200.0) as u8;
+        self.push(channel, TYPE_HUMIDITY, &[value]);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    fn push(&mut self, channel: u8, kind: u8, value: &[u8]) {
+        self.0.extend([channel, kind]);
+        self.0.extend(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let mut payload = Payload::default();
+        payload.analog_input(1, 12.3);
+        payload.concentration(2, 700);
+        payload.temperature(3, 27.2);
+        payload.temperature(4, -4.1);
+        payload.humidity(5, 64.0);
+        payload.analog_input(6, 1000.0);
+        assert_eq!(
+            payload.into_bytes(),
+            [
+                1, 2, 0x04, 0xCE, 2, 125, 0x02, 0xBC, 3, 103, 0x01, 0x10, 4, 103, 0xFF, 0xD7, 5,
+                104, 0x80, 6, 2, 0x7F, 0xFF
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+#[cfg(target_os = "espidf")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, FixedOffset, Offset, Utc};
+
+/// Anything before this (2024-01-01) means SNTP has not synchronized yet.
+const MIN_VALID_TIMESTAMP: u64 = 1_704_067_200;
+
+/// Offset of the local time without a time zone
+static UTC_OFFSET_MINUTES: AtomicI32 = AtomicI32::new(0);
+/// Whether newlib has a time zone
+#[cfg(target_os = "espidf")]
+static TIMEZONE: AtomicBool = AtomicBool::new(false);
+
+/// Current wall-clock time, `None` until SNTP has set the clock.
+pub fn now() -> Option<DateTime<Utc>> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    if since_epoch.as_secs() < MIN_VALID_TIMESTAMP {
+        return None;
+    }
+    DateTime::from_timestamp(since_epoch.as_secs() as i64, since_epoch.subsec_nanos())
+}
+
+/// When a sample was taken, both as the monotonic time since boot, ordering
+/// the samples even before SNTP has set the clock, and as the wall-clock time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub uptime: Duration,
+    /// `None` until SNTP has set the clock
+    pub wall: Option<DateTime<Utc>>,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Self {
+            uptime: uptime(),
+            wall: now(),
+        }
+    }
+}
+
+/// Set the local time: the POSIX TZ string `timezone`, such as
+/// `CET-1CEST,M3.5.0,M10.5.0/3`, applied by newlib with its daylight saving
+/// rules, or the fixed `utc_offset_minutes` when empty. The host only has
+/// the fixed offset.
+pub fn set_local_time(timezone: &str, utc_offset_minutes: i32) {
+    UTC_OFFSET_MINUTES.store(utc_offset_minutes, Ordering::Relaxed);
+    #[cfg(target_os = "espidf")]
+    if !timezone.is_empty() {
+        std::env::set_var("TZ", timezone);
+        // SAFETY: only reads TZ, before the tasks converting times start
+        unsafe { esp_idf_svc::sys::tzset() };
+        TIMEZONE.store(true, Ordering::Relaxed);
+    }
+    #[cfg(not(target_os = "espidf"))]
+    let _ = timezone;
+}
+
+/// Offset of the local time from UTC at `now`
+pub fn utc_offset(now: DateTime<Utc>) -> FixedOffset {
+    #[cfg(target_os = "espidf")]
+    if TIMEZONE.load(Ordering::Relaxed) {
+        if let Some(offset) = newlib_offset(now) {
+            return offset;
+        }
+    }
+    #[cfg(not(target_os = "espidf"))]
+    let _ = now;
+    FixedOffset::east_opt(UTC_OFFSET_MINUTES.load(Ordering::Relaxed) * 60).unwrap_or(Utc.fix())
+}
+
+/// Local time of `now`
+pub fn local(now: DateTime<Utc>) -> DateTime<FixedOffset> {
+    now.with_timezone(&utc_offset(now))
+}
+
+/// Offset given by the newlib time zone, from the broken-down local time
+#[cfg(target_os = "espidf")]
+fn newlib_offset(now: DateTime<Utc>) -> Option<FixedOffset> {
+    use esp_idf_svc::sys;
+
+    let time = now.timestamp() as sys::time_t;
+    // SAFETY: plain C struct, filled by localtime_r
+    let mut tm: sys::tm = unsafe { core::mem::zeroed() };
+    // SAFETY: both pointers are valid for the call
+    if unsafe { sys::localtime_r(&time, &mut tm) }.is_null() {
+        return None;
+    }
+    let local = chrono::NaiveDate::from_ymd_opt(
+        tm.tm_year + 1900,
+        (tm.tm_mon + 1) as u32,
+        tm.tm_mday as u32,
+    )?
+    .and_hms_opt(tm.tm_hour as u32, tm.tm_min as u32, tm.tm_sec as u32)?;
+    FixedOffset::east_opt((local - now.naive_utc()).num_seconds() as i32)
+}
+
+/// Time since boot
+#[cfg(target_os = "espidf")]
+pub fn uptime() -> Duration {
+    // SAFETY: esp_timer is started by ESP-IDF before app_main
+    let micros = unsafe { esp_idf_svc::sys::esp_timer_get_time() };
+    Duration::from_micros(micros as u64)
+}
+
+/// Time since the first call on the host
+#[cfg(not(target_os = "espidf"))]
+pub fn uptime() -> Duration {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed()
+}
+
+/// Serializes the tests setting the local time, which is global
+#[cfg(test)]
+pub static LOCAL_TIME: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use std::sync::PoisonError;
+
+    use chrono::Timelike;
+
+    use super::*;
+
+    #[test]
+    fn fixed_offset() {
+        let _local_time = LOCAL_TIME.lock().unwrap_or_else(PoisonError::into_inner);
+        // 2023-11-14T22:13:20Z
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        set_local_time("", 90);
+        assert_eq!(utc_offset(now).local_minus_utc(), 90 * 60);
+        let time = local(now);
+        assert_eq!((time.hour(), time.minute()), (23, 43));
+        assert_eq!(time, now);
+        set_local_time("", -300);
+        assert_eq!(local(now).hour(), 17);
+        // Out of range, UTC
+        set_local_time("", 24 * 60);
+        assert_eq!(utc_offset(now), Utc.fix());
+        set_local_time("", 0);
+        assert_eq!(local(now).hour(), 22);
+    }
+}
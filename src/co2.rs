@@ -0,0 +1,251 @@
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use embassy_futures::select::{select, Either};
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Read, ReadExactError, Write};
+use serde::{Deserialize, Serialize};
+
+const REPLY_TIMEOUT_MS: u32 = 1000;
+/// MH-Z19 command frames: start, sensor number, command, 5 data bytes and
+/// the checksum
+const MHZ19_CMD_READ: u8 = 0x86;
+const MHZ19_CMD_ZERO: u8 = 0x87;
+/// Data 0xA0 to enable the automatic baseline calibration, 0 to disable it
+const MHZ19_CMD_ABC: u8 = 0x79;
+/// Modbus address answered by every SenseAir S8
+const S8_ADDRESS: u8 = 0xFE;
+const S8_READ_INPUT: u8 = 0x04;
+const S8_WRITE_HOLDING: u8 = 0x06;
+/// Input register of the CO2 value, in ppm
+const S8_IR_CO2: u16 = 0x0003;
+/// Holding register acknowledging the calibrations
+const S8_HR_ACK: u16 = 0x0000;
+/// Holding register taking the calibration commands
+const S8_HR_COMMAND: u16 = 0x0001;
+/// Background calibration against 400 ppm
+const S8_BACKGROUND_CALIBRATION: u16 = 0x7C06;
+/// Holding register of the ABC period in hours, 0 disables it
+const S8_HR_ABC_PERIOD: u16 = 0x001F;
+/// Default ABC period of the S8, 8 days
+const S8_ABC_HOURS: u16 = 180;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Co2Kind {
+    /// Winsen MH-Z19B or MH-Z19C
+    Mhz19,
+    /// SenseAir S8
+    S8,
+}
+
+impl Display for Co2Kind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mhz19 => write!(f, "MH-Z19"),
+            Self::S8 => write!(f, "SenseAir S8"),
+        }
+    }
+}
+
+impl FromStr for Co2Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mhz19" => Ok(Self::Mhz19),
+            "s8" => Ok(Self::S8),
+            _ => bail!("Unknown CO2 sensor {s}, expected mhz19 or s8"),
+        }
+    }
+}
+
+/// Calibration command of the CO2 sensor, e.g.
+/// `{"command": "set_abc", "enabled": false}`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Co2Command {
+    /// Automatic baseline calibration, which takes the lowest value of the
+    /// last days as 400 ppm. To be disabled indoors when the room is never
+    /// aired.
+    SetAbc { enabled: bool },
+    /// Take the current value as 400 ppm, after 20 minutes outdoors
+    CalibrateZero,
+}
+
+/// NDIR CO2 sensor on a serial line at 9600 bauds, which measures on its
+/// own every few seconds
+pub struct Co2Sensor<RW> {
+    kind: Co2Kind,
+    serial: RW,
+}
+
+impl<RW> Co2Sensor<RW>
+where
+    RW: Read + Write,
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    pub fn new(kind: Co2Kind, serial: RW) -> Self {
+        Self { kind, serial }
+    }
+
+    pub fn kind(&self) -> Co2Kind {
+        self.kind
+    }
+
+    /// Last value of the sensor, in ppm
+    pub async fn measure(&mut self, delay: &mut impl DelayNs) -> Result<u16> {
+        match self.kind {
+            Co2Kind::Mhz19 => {
+                let reply = self.mhz19_request(delay, MHZ19_CMD_READ, 0).await?;
+                Ok(u16::from_be_bytes([reply[2], reply[3]]))
+            }
+            Co2Kind::S8 => {
+                let request = modbus_frame(S8_READ_INPUT, S8_IR_CO2, 1);
+                let reply = self.s8_exchange(delay, &request, 7).await?;
+                if reply[2] != 2 {
+                    bail!("Invalid reply from the S8");
+                }
+                Ok(u16::from_be_bytes([reply[3], reply[4]]))
+            }
+        }
+    }
+
+    pub async fn command(&mut self, command: Co2Command, delay: &mut impl DelayNs) -> Result<()> {
+        match (self.kind, command) {
+            (Co2Kind::Mhz19, Co2Command::SetAbc { enabled }) => {
+                let data = if enabled { 0xA0 } else { 0 };
+                self.mhz19_send(MHZ19_CMD_ABC, data).await
+            }
+            (Co2Kind::Mhz19, Co2Command::CalibrateZero) => self.mhz19_send(MHZ19_CMD_ZERO, 0).await,
+            (Co2Kind::S8, Co2Command::SetAbc { enabled }) => {
+                let hours = if enabled { S8_ABC_HOURS } else { 0 };
+                let request = modbus_frame(S8_WRITE_HOLDING, S8_HR_ABC_PERIOD, hours);
+                // Write requests are echoed back
+                self.s8_exchange(delay, &request, request.len()).await?;
+                Ok(())
+            }
+            (Co2Kind::S8, Co2Command::CalibrateZero) => {
+                for (register, value) in
+                    [(S8_HR_ACK, 0), (S8_HR_COMMAND, S8_BACKGROUND_CALIBRATION)]
+                {
+                    let request = modbus_frame(S8_WRITE_HOLDING, register, value);
+                    self.s8_exchange(delay, &request, request.len()).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Send a command which gets no reply
+    async fn mhz19_send(&mut self, cmd: u8, data: u8) -> Result<()> {
+        self.serial.write_all(&mhz19_command(cmd, data)).await?;
+        self.serial.flush().await?;
+        Ok(())
+    }
+
+    async fn mhz19_request(
+        &mut self,
+        delay: &mut impl DelayNs,
+        cmd: u8,
+        data: u8,
+    ) -> Result<[u8; 9]> {
+        self.mhz19_send(cmd, data).await?;
+        let serial = &mut self.serial;
+        let read_reply = async {
+            let mut reply = [0u8; 9];
+            loop {
+                serial.read_exact(&mut reply[..1]).await?;
+                if reply[0] != 0xFF {
+                    continue;
+                }
+                serial.read_exact(&mut reply[1..]).await?;
+                if reply[1] == cmd {
+                    return Ok::<_, ReadExactError<RW::Error>>(reply);
+                }
+            }
+        };
+        let reply = match select(read_reply, delay.delay_ms(REPLY_TIMEOUT_MS)).await {
+            Either::First(reply) => reply.map_err(|e| anyhow!("Unable to read reply: {e:?}"))?,
+            Either::Second(()) => bail!("No reply from the MH-Z19 to command {cmd:#x}"),
+        };
+        if reply[8] != mhz19_checksum(&reply) {
+            bail!("Invalid reply from the MH-Z19 to command {cmd:#x}");
+        }
+        Ok(reply)
+    }
+
+    /// Send a Modbus request and read its reply of `len` bytes
+    async fn s8_exchange(
+        &mut self,
+        delay: &mut impl DelayNs,
+        request: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        self.serial.write_all(request).await?;
+        self.serial.flush().await?;
+        let mut reply = vec![0u8; len];
+        match select(
+            self.serial.read_exact(&mut reply),
+            delay.delay_ms(REPLY_TIMEOUT_MS),
+        )
+        .await
+        {
+            Either::First(result) => result.map_err(|e| anyhow!("Unable to read reply: {e:?}"))?,
+            Either::Second(()) => bail!("No reply from the S8"),
+        }
+        let (frame, crc) = reply.split_at(len - 2);
+        if reply[0] != S8_ADDRESS
+            || reply[1] != request[1]
+            || modbus_crc(frame).to_le_bytes() != crc
+        {
+            bail!("Invalid reply from the S8");
+        }
+        Ok(reply)
+    }
+}
+
+impl<RW> Display for Co2Sensor<RW> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+/// MH-Z19 command frame to the sensor number 1
+pub fn mhz19_command(cmd: u8, data: u8) -> [u8; 9] {
+    let mut frame = [0xFF, 0x01, cmd, data, 0, 0, 0, 0, 0];
+    frame[8] = mhz19_checksum(&frame);
+    frame
+}
+
+/// Negated sum of the bytes between the start and the checksum
+pub fn mhz19_checksum(frame: &[u8; 9]) -> u8 {
+    let sum = frame[1..8].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    (!sum).wrapping_add(1)
+}
+
+/// Modbus RTU request to the S8: the register and a value or count
+pub fn modbus_frame(function: u8, register: u16, value: u16) -> [u8; 8] {
+    let mut frame = [0u8; 8];
+    frame[0] = S8_ADDRESS;
+    frame[1] = function;
+    frame[2..4].copy_from_slice(&register.to_be_bytes());
+    frame[4..6].copy_from_slice(&value.to_be_bytes());
+    let crc = modbus_crc(&frame[..6]);
+    frame[6..].copy_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+/// CRC-16/MODBUS, sent low byte first
+pub fn modbus_crc(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ u16::from(*byte), |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
@@ -0,0 +1,444 @@
+/// Default CoAP port
+pub const PORT: u16 = 5683;
+/// Large enough for the requests and replies, which fit in a datagram
+pub const MAX_MESSAGE_LEN: usize = 1152;
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xFF;
+const MAX_TOKEN_LEN: usize = 8;
+
+const CODE_EMPTY: u8 = 0x00;
+const CODE_GET: u8 = 0x01;
+/// 2.05
+const CODE_CONTENT: u8 = 0x45;
+/// 4.02
+const CODE_BAD_OPTION: u8 = 0x82;
+/// 4.04
+const CODE_NOT_FOUND: u8 = 0x84;
+/// 4.05
+const CODE_METHOD_NOT_ALLOWED: u8 = 0x85;
+/// 4.06
+const CODE_NOT_ACCEPTABLE: u8 = 0x86;
+
+const OPTION_URI_HOST: u16 = 3;
+const OPTION_OBSERVE: u16 = 6;
+const OPTION_URI_PORT: u16 = 7;
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_URI_QUERY: u16 = 15;
+const OPTION_ACCEPT: u16 = 17;
+
+const FORMAT_LINK: u32 = 40;
+const FORMAT_JSON: u32 = 50;
+
+/// Observe value of a request to register, any other deregisters
+const OBSERVE_REGISTER: u32 = 0;
+/// Oldest ones are dropped beyond
+const MAX_OBSERVERS: usize = 4;
+
+/// Path of the measurement resource
+const MEASUREMENT_PATH: &str = "measurement";
+/// Resource discovery, RFC 6690
+const DISCOVERY_PATH: &str = ".well-known/core";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Confirmable,
+    NonConfirmable,
+    Acknowledgement,
+    Reset,
+}
+
+/// CoAP message, RFC 7252
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: Type,
+    /// Class in the 3 upper bits, detail in the others
+    pub code: u8,
+    pub id: u16,
+    pub token: Vec<u8>,
+    /// Number and value, sorted by number
+    pub options: Vec<(u16, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    /// `None` when malformed
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let (&[first, code, id_high, id_low], rest) = buf.split_first_chunk::<4>()?;
+        if first >> 6 != VERSION {
+            return None;
+        }
+        let kind = match (first >> 4) & 0x03 {
+            0 => Type::Confirmable,
+            1 => Type::NonConfirmable,
+            2 => Type::Acknowledgement,
+            _ => Type::Reset,
+        };
+        let token_len = usize::from(first & 0x0F);
+        if token_len > MAX_TOKEN_LEN || rest.len() < token_len {
+            return None;
+        }
+        let (token, mut rest) = rest.split_at(token_len);
+        let mut options = Vec::new();
+        let mut number = 0u16;
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == PAYLOAD_MARKER {
+                // A marker is followed by a payload
+                if tail.is_empty() {
+                    return None;
+                }
+                rest = tail;
+                break;
+            }
+            let (delta, tail) = extended(byte >> 4, tail)?;
+            let (len, tail) = extended(byte & 0x0F, tail)?;
+            let len = usize::from(len);
+            if tail.len() < len {
+                return None;
+            }
+            number = number.checked_add(delta)?;
+            options.push((number, tail[..len].to_vec()));
+            rest = &tail[len..];
+        }
+        Some(Self {
+            kind,
+            code,
+            id: u16::from_be_bytes([id_high, id_low]),
+            token: token.to_vec(),
+            options,
+            payload: rest.to_vec(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let kind = match self.kind {
+            Type::Confirmable => 0,
+            Type::NonConfirmable => 1,
+            Type::Acknowledgement => 2,
+            Type::Reset => 3,
+        };
+        let mut buf = vec![VERSION << 6 | kind << 4 | self.token.len() as u8, self.code];
+        buf.extend(self.id.to_be_bytes());
+        buf.extend(&self.token);
+        let mut previous = 0;
+        for (number, value) in &self.options {
+            let (delta, delta_ext) = nibble(number - previous);
+            let (len, len_ext) = nibble(value.len() as u16);
+            buf.push(delta << 4 | len);
+            buf.extend(delta_ext);
+            buf.extend(len_ext);
+            buf.extend(value);
+            previous = *number;
+        }
+        if !self.payload.is_empty() {
+            buf.push(PAYLOAD_MARKER);
+            buf.extend(&self.payload);
+        }
+        buf
+    }
+
+    /// Uri-Path options joined by `/`
+    pub fn path(&self) -> String {
+        let segments: Vec<String> = self
+            .option_values(OPTION_URI_PATH)
+            .map(|segment| String::from_utf8_lossy(segment).into_owned())
+            .collect();
+        segments.join("/")
+    }
+
+    fn option_values(&self, number: u16) -> impl Iterator<Item = &[u8]> {
+        self.options
+            .iter()
+            .filter(move |(n, _)| *n == number)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option_values(number).next().map(|value| {
+            value
+                .iter()
+                .fold(0u32, |uint, &byte| uint << 8 | u32::from(byte))
+        })
+    }
+
+    /// Reply to `self` with the same token, piggybacked on the
+    /// acknowledgement of a confirmable request
+    fn reply(&self, code: u8, id: u16) -> Self {
+        let (kind, id) = match self.kind {
+            Type::Confirmable => (Type::Acknowledgement, self.id),
+            _ => (Type::NonConfirmable, id),
+        };
+        Self {
+            kind,
+            code,
+            id,
+            token: self.token.clone(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        }
+    }
+}
+
+/// Delta or length of an option, extended by the next bytes when 13 or 14
+fn extended(nibble: u8, buf: &[u8]) -> Option<(u16, &[u8])> {
+    match nibble {
+        13 => {
+            let (&byte, rest) = buf.split_first()?;
+            Some((u16::from(byte) + 13, rest))
+        }
+        14 => {
+            let (bytes, rest) = buf.split_first_chunk::<2>()?;
+            Some((u16::from_be_bytes(*bytes).checked_add(269)?, rest))
+        }
+        15 => None,
+        nibble => Some((nibble.into(), buf)),
+    }
+}
+
+/// Nibble and extended bytes of an option delta or length
+fn nibble(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, (value - 269).to_be_bytes().to_vec()),
+    }
+}
+
+/// Unsigned integer option value, without leading zeros
+fn uint(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    bytes[zeros..].to_vec()
+}
+
+/// Client observing the measurement
+struct Observer<A> {
+    address: A,
+    token: Vec<u8>,
+    /// Of the last notification, to tell which one a reset rejects
+    last_id: u16,
+}
+
+/// Serves the measurement resource to the clients at addresses `A`, which
+/// can observe it to be notified of each new measurement
+pub struct Server<A> {
+    observers: Vec<Observer<A>>,
+    next_id: u16,
+    /// Observe value of the notifications, increasing
+    sequence: u32,
+}
+
+impl<A: Copy + PartialEq> Server<A> {
+    /// `first_id` should be random, so that the IDs of a restarted server
+    /// are not taken for duplicates
+    pub fn new(first_id: u16) -> Self {
+        Self {
+            observers: Vec::new(),
+            next_id: first_id,
+            sequence: 0,
+        }
+    }
+
+    pub fn observer_count(&self) -> usize {
+        self.observers.len()
+    }
+
+    /// Reply to a message received from `from`, `measurement` being the
+    /// current measurement as JSON. `None` when there is nothing to reply.
+    pub fn handle(&mut self, from: A, request: &Message, measurement: &[u8]) -> Option<Message> {
+        match (request.kind, request.code) {
+            // The client rejected a notification
+            (Type::Reset, _) => {
+                self.observers.retain(|observer| {
+                    !(observer.address == from && observer.last_id == request.id)
+                });
+                return None;
+            }
+            // A ping
+            (Type::Confirmable, CODE_EMPTY) => {
+                return Some(Message {
+                    kind: Type::Reset,
+                    code: CODE_EMPTY,
+                    id: request.id,
+                    token: Vec::new(),
+                    options: Vec::new(),
+                    payload: Vec::new(),
+                })
+            }
+            (Type::Acknowledgement, _) | (_, CODE_EMPTY) => return None,
+            // Only requests, not responses
+            (_, code) if code >> 5 != 0 => return None,
+            _ => {}
+        }
+        let id = self.next_id();
+        // The critical options have an odd number, unknown ones are refused
+        let understood = [
+            OPTION_URI_HOST,
+            OPTION_URI_PORT,
+            OPTION_URI_PATH,
+            OPTION_URI_QUERY,
+            OPTION_ACCEPT,
+        ];
+        if request
+            .options
+            .iter()
+            .any(|(number, _)| number % 2 == 1 && !understood.contains(number))
+        {
+            return Some(request.reply(CODE_BAD_OPTION, id));
+        }
+        let (format, payload) = match request.path().as_str() {
+            MEASUREMENT_PATH => (FORMAT_JSON, measurement.to_vec()),
+            DISCOVERY_PATH => (
+                FORMAT_LINK,
+                format!("</{MEASUREMENT_PATH}>;rt=\"air-quality\";obs;ct={FORMAT_JSON}")
+                    .into_bytes(),
+            ),
+            _ => return Some(request.reply(CODE_NOT_FOUND, id)),
+        };
+        if request.code != CODE_GET {
+            return Some(request.reply(CODE_METHOD_NOT_ALLOWED, id));
+        }
+        if request
+            .uint_option(OPTION_ACCEPT)
+            .is_some_and(|accept| accept != format)
+        {
+            return Some(request.reply(CODE_NOT_ACCEPTABLE, id));
+        }
+        let mut response = request.reply(CODE_CONTENT, id);
+        if format == FORMAT_JSON {
+            // Registered again replaces the previous registration
+            self.observers
+                .retain(|observer| !(observer.address == from && observer.token == request.token));
+            if request.uint_option(OPTION_OBSERVE) == Some(OBSERVE_REGISTER) {
+                if self.observers.len() == MAX_OBSERVERS {
+                    self.observers.remove(0);
+                }
+                self.observers.push(Observer {
+                    address: from,
+                    token: request.token.clone(),
+                    last_id: response.id,
+                });
+                response.options.push((OPTION_OBSERVE, uint(self.sequence)));
+            }
+        }
+        response.options.push((OPTION_CONTENT_FORMAT, uint(format)));
+        response.payload = payload;
+        Some(response)
+    }
+
+    /// Notifications of a new `measurement` for each observer, as
+    /// non-confirmable messages
+    pub fn notify(&mut self, measurement: &[u8]) -> Vec<(A, Message)> {
+        // 24 bits
+        self.sequence = (self.sequence + 1) & 0xFF_FFFF;
+        let mut notifications = Vec::new();
+        for i in 0..self.observers.len() {
+            let id = self.next_id();
+            let observer = &mut self.observers[i];
+            observer.last_id = id;
+            let message = Message {
+                kind: Type::NonConfirmable,
+                code: CODE_CONTENT,
+                id,
+                token: observer.token.clone(),
+                options: vec![
+                    (OPTION_OBSERVE, uint(self.sequence)),
+                    (OPTION_CONTENT_FORMAT, uint(FORMAT_JSON)),
+                ],
+                payload: measurement.to_vec(),
+            };
+            notifications.push((observer.address, message));
+        }
+        notifications
+    }
+
+    fn next_id(&mut self) -> u16 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Confirmable GET of `path`, token 0x42
+    fn get(path: &str, observe: Option<u32>) -> Message {
+        let mut options: Vec<(u16, Vec<u8>)> = observe
+            .map(|observe| (OPTION_OBSERVE, uint(observe)))
+            .into_iter()
+            .collect();
+        options.extend(
+            path.split('/')
+                .map(|segment| (OPTION_URI_PATH, segment.as_bytes().to_vec())),
+        );
+        Message {
+            kind: Type::Confirmable,
+            code: CODE_GET,
+            id: 0x1234,
+            token: vec![0x42],
+            options,
+            payload: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        let message = get(".well-known/core", None);
+        let encoded = message.encode();
+        assert_eq!(&encoded[..5], [0x41, 0x01, 0x12, 0x34, 0x42]);
+        // Uri-Path, extended length of 13 + 0
+        assert_eq!(&encoded[5..7], [0xBB, b'.']);
+        assert_eq!(Message::parse(&encoded), Some(message.clone()));
+        assert_eq!(message.path(), ".well-known/core");
+        let mut long = message;
+        long.options = vec![(300, vec![0; 20])];
+        long.payload = b"{}".to_vec();
+        assert_eq!(Message::parse(&long.encode()), Some(long));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(Message::parse(&[0x41, 0x01, 0x12]).is_none());
+        // Version 2
+        assert!(Message::parse(&[0x80, 0x01, 0x12, 0x34]).is_none());
+        // Token longer than the message
+        assert!(Message::parse(&[0x42, 0x01, 0x12, 0x34, 0x00]).is_none());
+        // Payload marker without a payload
+        assert!(Message::parse(&[0x40, 0x01, 0x12, 0x34, 0xFF]).is_none());
+    }
+
+    #[test]
+    fn observe() {
+        let mut server = Server::new(100);
+        let response = server
+            .handle(1, &get("measurement", Some(0)), b"{}")
+            .unwrap();
+        assert_eq!(response.kind, Type::Acknowledgement);
+        assert_eq!(response.code, CODE_CONTENT);
+        assert_eq!(response.payload, b"{}");
+        assert_eq!(server.observer_count(), 1);
+        let notifications = server.notify(b"{\"pm25\":1}");
+        assert_eq!(notifications.len(), 1);
+        let (address, notification) = &notifications[0];
+        assert_eq!(
+            (*address, notification.token.as_slice()),
+            (1, [0x42].as_slice())
+        );
+        // The client forgot the observation
+        let reset = Message {
+            kind: Type::Reset,
+            code: CODE_EMPTY,
+            id: notification.id,
+            token: Vec::new(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        };
+        assert!(server.handle(1, &reset, b"{}").is_none());
+        assert_eq!(server.observer_count(), 0);
+        let missing = server.handle(1, &get("other", None), b"{}").unwrap();
+        assert_eq!(missing.code, CODE_NOT_FOUND);
+    }
+}
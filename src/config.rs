@@ -0,0 +1,2124 @@
+use std::net::Ipv4Addr;
+#[cfg(target_os = "espidf")]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use serde::{Deserialize, Serialize};
+
+use crate::alarm::Alarm;
+use crate::calibration::{Calibration, Correction};
+use crate::fan::{Curve, CurvePoint};
+use crate::i18n::Language;
+use crate::influx;
+use crate::modbus::Register;
+use crate::mqtt::{DataKind, Deltas, DomoticzDevice, Profile};
+use crate::ntfy;
+use crate::pipeline::{self, Stage};
+use crate::reading::Encoding;
+use crate::relay::Hysteresis;
+use crate::schedule::{self, Hours, Maintenance, Schedule};
+use crate::segment;
+use crate::sink::{SinkInterval, SinkKind};
+use crate::smtp::{self, Account};
+use crate::snmp;
+use crate::stats::Limits;
+use crate::subsystem::{Subsystem, Subsystems};
+use crate::voc::Compensation;
+
+/// This configuration is picked up at compile time by `build.rs` from the
+/// file `cfg.toml`. Apart from the board and pins, it only provides the
+/// defaults written to NVS on first boot, see [`ConfigStore`].
+#[toml_cfg::toml_config]
+pub struct Config {
+    #[default("Wokwi-GUEST")]
+    wifi_ssid: &'static str,
+    #[default("")]
+    wifi_psk: &'static str,
+    #[default("")]
+    mqtt_broker_url: &'static str,
+    #[default(300)]
+    measure_interval_secs: u32,
+    /// Pin preset, see `board.rs`, the devkit of the build target when empty
+    #[default("")]
+    board: &'static str,
+    /// Model of the first sensor, `sds011`, `pms5003` or `auto` to detect it
+    #[default("auto")]
+    sensor0: &'static str,
+    /// Model of the second sensor, none if empty
+    #[default("")]
+    sensor1: &'static str,
+    /// CO2 sensor, `mhz19` or `s8`, none if empty
+    #[default("")]
+    co2_sensor: &'static str,
+    /// VOC sensor on I2C, `sgp30`, `sgp40` or `auto` to detect it, none if
+    /// empty
+    #[default("auto")]
+    voc_sensor: &'static str,
+    // Overrides of the preset pins, ignored when negative
+    #[default(-1)]
+    sensor0_tx_pin: i32,
+    #[default(-1)]
+    sensor0_rx_pin: i32,
+    #[default(-1)]
+    sensor1_tx_pin: i32,
+    #[default(-1)]
+    sensor1_rx_pin: i32,
+    #[default(-1)]
+    co2_tx_pin: i32,
+    #[default(-1)]
+    co2_rx_pin: i32,
+    #[default(-1)]
+    i2c_sda_pin: i32,
+    #[default(-1)]
+    i2c_scl_pin: i32,
+    #[default(-1)]
+    led_pin: i32,
+    #[default(-1)]
+    led_rmt_channel: i32,
+    /// Status LED, `ws2812`, `gpio` for a single LED on `led_pin` or `rgb`
+    /// for an RGB LED on the `led_*_pin`
+    #[default("ws2812")]
+    led_kind: &'static str,
+    /// Of the `gpio` and `rgb` LEDs, lit by a low output as when wired to
+    /// 3.3 V or with a common anode
+    #[default(false)]
+    led_active_low: bool,
+    #[default(-1)]
+    led_red_pin: i32,
+    #[default(-1)]
+    led_green_pin: i32,
+    #[default(-1)]
+    led_blue_pin: i32,
+    #[default(-1)]
+    sd_sclk_pin: i32,
+    #[default(-1)]
+    sd_mosi_pin: i32,
+    #[default(-1)]
+    sd_miso_pin: i32,
+    #[default(-1)]
+    sd_cs_pin: i32,
+    /// `wifi`, `ethernet` for a W5500 module on the SD card SPI bus,
+    /// `lorawan` for an SX1276 module in place of the W5500, or `zigbee`
+    #[default("wifi")]
+    network: &'static str,
+    /// OTAA keys of the LoRaWAN network, in hexadecimal
+    #[default("")]
+    lora_dev_eui: &'static str,
+    #[default("")]
+    lora_join_eui: &'static str,
+    #[default("")]
+    lora_app_key: &'static str,
+    /// Of the uplinks, 7 to 12, higher reaches further but takes longer
+    #[default(9)]
+    lora_spreading_factor: u32,
+    #[default(-1)]
+    eth_cs_pin: i32,
+    #[default(-1)]
+    eth_int_pin: i32,
+    #[default(-1)]
+    eth_rst_pin: i32,
+    /// Piezo buzzer sounding on severe pollution, none when negative
+    #[default(-1)]
+    buzzer_pin: i32,
+    /// Relay of an air purifier or fan, active high, none when negative
+    #[default(-1)]
+    relay_pin: i32,
+    /// PWM input of a 4-pin PC fan, none when negative
+    #[default(-1)]
+    fan_pin: i32,
+    /// Data line of a DHT22 temperature and humidity sensor, none when
+    /// negative
+    #[default(-1)]
+    dht22_pin: i32,
+    /// SSD1680 e-paper panel on the SD card SPI bus, none unless its four
+    /// pins are set
+    #[default(-1)]
+    epaper_cs_pin: i32,
+    #[default(-1)]
+    epaper_dc_pin: i32,
+    #[default(-1)]
+    epaper_rst_pin: i32,
+    #[default(-1)]
+    epaper_busy_pin: i32,
+    /// 4-digit 7-segment display, `tm1637` or `max7219`, none if empty
+    #[default("")]
+    segment_display: &'static str,
+    #[default(-1)]
+    segment_clk_pin: i32,
+    /// DIO of the TM1637, DIN of the MAX7219
+    #[default(-1)]
+    segment_dio_pin: i32,
+    /// MAX7219 only
+    #[default(-1)]
+    segment_cs_pin: i32,
+    /// Replace the first sensor with a simulated SDS011, e.g. in Wokwi
+    #[default(false)]
+    simulate: bool,
+}
+
+const NAMESPACE: &str = "config";
+
+// NVS keys are limited to 15 characters
+const KEY_VERSION: &str = "version";
+const KEY_WIFI_SSID: &str = "wifi_ssid";
+const KEY_WIFI_PSK: &str = "wifi_psk";
+const KEY_MQTT_BROKER_URL: &str = "mqtt_url";
+const KEY_MQTT_SECONDARY_URL: &str = "mqtt_url2";
+const KEY_HOSTNAME: &str = "hostname";
+const KEY_NAME: &str = "name";
+const KEY_LANGUAGE: &str = "language";
+const KEY_MEASURE_INTERVAL: &str = "measure_itv";
+const KEY_SENSOR_WARMUP: &str = "sensor_warmup";
+const KEY_SIMULATE: &str = "simulate";
+const KEY_PM25_WARN: &str = "pm25_warn";
+const KEY_PM25_ALERT: &str = "pm25_alert";
+const KEY_PM25_OFFSET: &str = "pm25_offset";
+const KEY_PM25_SLOPE: &str = "pm25_slope";
+const KEY_PM10_OFFSET: &str = "pm10_offset";
+const KEY_PM10_SLOPE: &str = "pm10_slope";
+const KEY_PIPELINE: &str = "pipeline";
+const KEY_PM25_LIMIT: &str = "pm25_limit";
+const KEY_PM10_LIMIT: &str = "pm10_limit";
+const KEY_BUZZER_PM25: &str = "buzzer_pm25";
+const KEY_BUZZER_PM10: &str = "buzzer_pm10";
+const KEY_BUZZER_REPEAT: &str = "buzzer_repeat";
+const KEY_BUZZER_QUIET_START: &str = "buzz_quiet_on";
+const KEY_BUZZER_QUIET_END: &str = "buzz_quiet_off";
+const KEY_UTC_OFFSET: &str = "utc_offset";
+const KEY_TIMEZONE: &str = "timezone";
+const KEY_QUIET_START: &str = "quiet_start";
+const KEY_MAINTENANCE_TIME: &str = "maint_time";
+const KEY_MAINTENANCE_UPTIME: &str = "maint_uptime";
+const KEY_QUIET_END: &str = "quiet_end";
+const KEY_QUIET_MQTT_INTERVAL: &str = "quiet_mqtt_itv";
+const KEY_BOOST_START: &str = "boost_start";
+const KEY_BOOST_END: &str = "boost_end";
+const KEY_BOOST_INTERVAL: &str = "boost_itv";
+const KEY_RELAY_ON: &str = "relay_on";
+const KEY_RELAY_OFF: &str = "relay_off";
+const KEY_FAN_CURVE: &str = "fan_curve";
+const KEY_FAN_MIN_DUTY: &str = "fan_min_duty";
+const KEY_FAN_BOOST: &str = "fan_boost";
+const KEY_VOC_HUMIDITY: &str = "voc_humidity";
+const KEY_VOC_TEMPERATURE: &str = "voc_temp";
+const KEY_SENSOR1_ENABLED: &str = "sensor1_enabled";
+const KEY_CO2_ENABLED: &str = "co2_enabled";
+const KEY_VOC_ENABLED: &str = "voc_enabled";
+const KEY_DHT22_ENABLED: &str = "dht22_enabled";
+const KEY_BUZZER_ENABLED: &str = "buzzer_enabled";
+const KEY_RELAY_ENABLED: &str = "relay_enabled";
+const KEY_FAN_ENABLED: &str = "fan_enabled";
+const KEY_EPAPER_ENABLED: &str = "epaper_enabled";
+const KEY_SEGMENT_ENABLED: &str = "segment_enabled";
+const KEY_SEGMENT_BRIGHTNESS: &str = "segment_bright";
+const KEY_LED_ENABLED: &str = "led_enabled";
+const KEY_LED_BRIGHTNESS: &str = "led_bright";
+const KEY_MQTT_BATCH: &str = "mqtt_batch";
+const KEY_MQTT_TENTHS: &str = "mqtt_tenths";
+const KEY_MQTT_PROFILES: &str = "mqtt_profiles";
+const KEY_MQTT_MIN_INTERVAL: &str = "mqtt_min_itv";
+/// Of the particles, kept from when it applied to every kind
+const KEY_MQTT_DEADBAND_PM: &str = "mqtt_deadband";
+const KEY_MQTT_DEADBAND_CO2: &str = "mqtt_db_co2";
+const KEY_MQTT_DEADBAND_VOC: &str = "mqtt_db_voc";
+const KEY_MQTT_DEADBAND_TEMPERATURE: &str = "mqtt_db_temp";
+const KEY_MQTT_DEADBAND_HUMIDITY: &str = "mqtt_db_hum";
+const KEY_MQTT_MAX_SILENCE: &str = "mqtt_silence";
+const KEY_MQTT_QOS: &str = "mqtt_qos";
+const KEY_MQTT_RETAIN: &str = "mqtt_retain";
+const KEY_MQTT_MEASUREMENT_QOS: &str = "mqtt_meas_qos";
+const KEY_MQTT_MEASUREMENT_RETAIN: &str = "mqtt_meas_ret";
+const KEY_MQTT_SENSOR_QOS: &str = "mqtt_sens_qos";
+const KEY_MQTT_SENSOR_RETAIN: &str = "mqtt_sens_ret";
+const KEY_MQTT_TASMOTA: &str = "mqtt_tasmota";
+const KEY_MQTT_DOMOTICZ: &str = "mqtt_domoticz";
+const KEY_MQTT_HOMIE: &str = "mqtt_homie";
+const KEY_MQTT_FLEET_TOPIC: &str = "fleet_topic";
+const KEY_HTTPS_ENABLED: &str = "https_enabled";
+const KEY_HTTP_STACK_SIZE: &str = "http_stack";
+const KEY_HTTP_MAX_SESSIONS: &str = "http_sessions";
+const KEY_HTTP_TIMEOUT: &str = "http_timeout";
+const KEY_COAP_ENABLED: &str = "coap_enabled";
+const KEY_UDP_PORT: &str = "udp_port";
+const KEY_UDP_ADDRESS: &str = "udp_address";
+const KEY_INFLUX_ADDRESS: &str = "influx_address";
+const KEY_INFLUX_PORT: &str = "influx_port";
+const KEY_INFLUX_MEASUREMENT: &str = "influx_meas";
+const KEY_WEBHOOK_URL: &str = "webhook_url";
+const KEY_SINK_INTERVALS: &str = "sink_intervals";
+const KEY_MODBUS_ENABLED: &str = "modbus_enabled";
+const KEY_MODBUS_UNIT_ID: &str = "modbus_unit";
+const KEY_MODBUS_REGISTERS: &str = "modbus_regs";
+const KEY_SNMP_ENABLED: &str = "snmp_enabled";
+const KEY_SNMP_COMMUNITY: &str = "snmp_community";
+const KEY_SNMP_ENTERPRISE: &str = "snmp_ent";
+const KEY_NTFY_URL: &str = "ntfy_url";
+const KEY_NTFY_TOPIC: &str = "ntfy_topic";
+const KEY_NTFY_TOKEN: &str = "ntfy_token";
+const KEY_NTFY_PRIORITIES: &str = "ntfy_prio";
+const KEY_NTFY_INTERVAL: &str = "ntfy_itv";
+const KEY_SMTP_SERVER: &str = "smtp_server";
+const KEY_SMTP_PORT: &str = "smtp_port";
+const KEY_SMTP_USERNAME: &str = "smtp_user";
+const KEY_SMTP_PASSWORD: &str = "smtp_password";
+const KEY_SMTP_FROM: &str = "smtp_from";
+const KEY_SMTP_TO: &str = "smtp_to";
+const KEY_TELEGRAM_TOKEN: &str = "tg_token";
+const KEY_TELEGRAM_CHAT_ID: &str = "tg_chat";
+const KEY_CORS_ORIGINS: &str = "cors_origins";
+const KEY_API_TOKEN: &str = "api_token";
+const KEY_AGGREGATOR: &str = "aggregator";
+const KEY_WIFI_ROAM_INTERVAL: &str = "wifi_roam_itv";
+const KEY_UPLINK_CHECK_URL: &str = "uplink_url";
+const KEY_LIGHT_SLEEP: &str = "light_sleep";
+const KEY_WIFI_EAP_IDENTITY: &str = "eap_identity";
+const KEY_WIFI_EAP_USERNAME: &str = "eap_username";
+const KEY_WIFI_EAP_PASSWORD: &str = "eap_password";
+const KEY_WIFI_EAP_TTLS_PHASE2: &str = "eap_phase2";
+const KEY_BLE_PROVISIONING: &str = "ble_prov";
+/// PEM, not part of [`Settings`]
+const KEY_WIFI_EAP_CA_CERT: &str = "eap_ca_cert";
+
+/// As in the DHCP client settings of esp-idf-svc
+const MAX_HOSTNAME_LEN: usize = 30;
+const MAX_NAME_LEN: usize = 64;
+const MAX_TIMEZONE_LEN: usize = 64;
+/// Of the SSID and passphrases of Wi-Fi
+const MAX_SSID_LEN: usize = 32;
+const PSK_LEN: std::ops::RangeInclusive<usize> = 8..=64;
+/// Of `measure_interval_secs`, a day at most
+const MEASURE_INTERVAL_SECS: std::ops::RangeInclusive<u32> = 10..=24 * 3600;
+/// Of `http_stack_size`, when not 0
+const HTTP_STACK_SIZE: std::ops::RangeInclusive<u32> = 4 * 1024..=32 * 1024;
+/// Of `http_max_sessions`, the server keeping 3 of the 10 lwIP sockets
+const HTTP_MAX_SESSIONS: std::ops::RangeInclusive<u8> = 1..=7;
+const HTTP_TIMEOUT_SECS: std::ops::RangeInclusive<u8> = 1..=60;
+/// Of the `sink_intervals`, a day
+const MAX_SINK_INTERVAL_SECS: u32 = 24 * 3600;
+
+const EAP_TTLS_PHASE2_METHODS: [&str; 5] = ["mschapv2", "mschap", "pap", "chap", "eap"];
+
+const VERSION: u8 = 1;
+
+/// Served in place of the secrets which are set, posted back unchanged by
+/// the configuration page
+pub const REDACTED: &str = "********";
+
+/// Runtime settings, persisted in NVS.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub wifi_ssid: String,
+    pub wifi_psk: String,
+    pub mqtt_broker_url: String,
+    /// Broker used while `mqtt_broker_url` is unreachable, none when empty
+    pub mqtt_secondary_url: String,
+    /// Host name on DHCP and mDNS, also the MQTT client ID,
+    /// `esp-particle-<end of the MAC address>` when empty
+    pub hostname: String,
+    /// Where the station is, e.g. `Bedroom`, shown on the dashboard, in the
+    /// telemetry and to the aggregators, none when empty
+    pub name: String,
+    /// Of the web pages, `en`, `fr` or `de`, the one preferred by the browser
+    /// when empty
+    pub language: String,
+    /// Delay between two particle measurements
+    pub measure_interval_secs: u32,
+    /// First measurements of each particle sensor discarded after startup,
+    /// while their readings are unreliable
+    pub sensor_warmup: u8,
+    /// Synthetic values instead of the first sensor, for demos and
+    /// development without the hardware
+    pub simulate: bool,
+    /// PM2.5 level (µg/m³) above which the LED blinks orange
+    pub pm25_warn: f32,
+    /// PM2.5 level (µg/m³) above which the LED blinks red
+    pub pm25_alert: f32,
+    /// Calibration of the sensors, `raw * slope + offset` (µg/m³)
+    pub pm25_offset: f32,
+    pub pm25_slope: f32,
+    pub pm10_offset: f32,
+    pub pm10_slope: f32,
+    /// Stages the values of the sensors go through, in order
+    pub pipeline: Vec<Stage>,
+    /// Limits (µg/m³) of the 24 h means, the WHO 2021 guidelines by default
+    pub pm25_limit: f32,
+    pub pm10_limit: f32,
+    /// Levels (µg/m³) from which the buzzer sounds, if one is wired
+    pub buzzer_pm25: f32,
+    pub buzzer_pm10: f32,
+    /// Delay before the buzzer sounds again while the level stays high
+    pub buzzer_repeat_secs: u32,
+    /// Hours (local time, 0 to 23) during which the buzzer stays silent,
+    /// from `buzzer_quiet_start` to `buzzer_quiet_end`, none when equal
+    pub buzzer_quiet_start: u8,
+    pub buzzer_quiet_end: u8,
+    /// Offset of the local time from UTC, when there is no `timezone`
+    pub utc_offset_minutes: i32,
+    /// POSIX TZ string of the local time, with its daylight saving rules,
+    /// e.g. `CET-1CEST,M3.5.0,M10.5.0/3`
+    pub timezone: String,
+    /// Hours (local time) during which the LED and buzzer are off and a
+    /// topic is published at most every `quiet_mqtt_interval_secs`, from
+    /// `quiet_start` to `quiet_end`, none when equal
+    pub quiet_start: u8,
+    pub quiet_end: u8,
+    pub quiet_mqtt_interval_secs: u32,
+    /// Hours (local time) during which the sensors are measured every
+    /// `boost_interval_secs`, from `boost_start` to `boost_end`, none when
+    /// equal
+    pub boost_start: u8,
+    pub boost_end: u8,
+    pub boost_interval_secs: u32,
+    /// Local time, e.g. `03:30`, at which the device restarts once up for
+    /// `maintenance_uptime_days`, never when empty
+    pub maintenance_time: String,
+    pub maintenance_uptime_days: u16,
+    /// PM2.5 levels (µg/m³) switching the relay on and back off, if one is
+    /// wired
+    pub relay_on_pm25: f32,
+    pub relay_off_pm25: f32,
+    /// Duty cycles (%) of the PWM fan at PM2.5 levels (µg/m³), if one is
+    /// wired, interpolated in between
+    pub fan_curve: Vec<CurvePoint>,
+    /// Duty cycle (%) below which the fan is never driven
+    pub fan_min_duty: u8,
+    /// How long the fan runs at full speed when boosted
+    pub fan_boost_minutes: u32,
+    /// Relative humidity (%) and temperature (°C) compensating the VOC
+    /// sensor, if one is wired without a DHT22
+    pub voc_humidity: f32,
+    pub voc_temperature: f32,
+    /// Optional subsystems, skipped at startup when disabled even if wired
+    /// in `cfg.toml`
+    pub sensor1_enabled: bool,
+    pub co2_enabled: bool,
+    pub voc_enabled: bool,
+    pub dht22_enabled: bool,
+    pub buzzer_enabled: bool,
+    pub relay_enabled: bool,
+    pub fan_enabled: bool,
+    pub epaper_enabled: bool,
+    pub segment_enabled: bool,
+    pub led_enabled: bool,
+    pub led_brightness: u8,
+    /// Of the 7-segment display, 0 to 7, the lowest during the quiet hours
+    pub segment_brightness: u8,
+    /// Publish the values of a measurement as one JSON message on
+    /// `<root>/batch` instead of one message per topic
+    pub mqtt_batch: bool,
+    /// Publish the PM values, temperature and humidity as integers in
+    /// tenths of their unit, e.g. `123` for 12.3 µg/m³
+    pub mqtt_tenths: bool,
+    /// Copies of the values under `<root>/<topic>`, each in its own
+    /// encoding, `decimal` or `integer_tenths`, none when empty
+    pub mqtt_profiles: Vec<Profile>,
+    /// Minimum delay between two publications on the same topic
+    pub mqtt_min_interval_secs: u32,
+    /// Change of the particles (µg/m³) under which they are not published
+    /// again. 0 publishes every measurement.
+    pub mqtt_deadband_pm: f32,
+    /// Of the CO2, in ppm
+    pub mqtt_deadband_co2: f32,
+    /// Of the VOC index, or of the TVOC in ppb
+    pub mqtt_deadband_voc: f32,
+    /// Of the temperature, in °C
+    pub mqtt_deadband_temperature: f32,
+    /// Of the humidity, in %
+    pub mqtt_deadband_humidity: f32,
+    /// Delay after which a value is published again even if unchanged
+    pub mqtt_max_silence_secs: u32,
+    /// QoS level of the publications, 0 to 2
+    pub mqtt_qos: u8,
+    pub mqtt_retain: bool,
+    /// Overrides of `mqtt_qos` and `mqtt_retain` for the PM values and
+    /// batches
+    pub mqtt_measurement_qos: Option<u8>,
+    pub mqtt_measurement_retain: Option<bool>,
+    /// Overrides of `mqtt_qos` and `mqtt_retain` for the sensor details
+    pub mqtt_sensor_qos: Option<u8>,
+    pub mqtt_sensor_retain: Option<bool>,
+    /// Publish the PM values like Tasmota, on `tele/<device>/SENSOR`, with
+    /// its `tele/<device>/LWT` topic
+    pub mqtt_tasmota: bool,
+    /// Publish the values of these kinds to Domoticz on `domoticz/in`
+    /// instead of their topics, none when empty
+    pub mqtt_domoticz: Vec<DomoticzDevice>,
+    /// Publish the values as the properties of a Homie 4.0 device, on
+    /// `homie/<hostname>`, for OpenHAB and ioBroker to discover them
+    pub mqtt_homie: bool,
+    /// Shared by the stations for their heartbeats, none when empty
+    pub mqtt_fleet_topic: String,
+    /// Serve the web pages over HTTPS, plain HTTP redirects to it
+    pub https_enabled: bool,
+    /// Stack of the HTTP server task, in bytes, the default of esp-idf-svc
+    /// when 0
+    pub http_stack_size: u32,
+    /// Clients served at once, the least recently used one being closed for
+    /// a new one
+    pub http_max_sessions: u8,
+    /// Time a client has to send a request or receive a chunk of the
+    /// response, the server serving nobody else meanwhile
+    pub http_timeout_secs: u8,
+    /// Serve the measurement over CoAP, on `coap://<ip>/measurement`
+    pub coap_enabled: bool,
+    /// Broadcast each measurement as a JSON datagram to this UDP port,
+    /// disabled when 0
+    pub udp_port: u16,
+    /// Destination of the datagrams, the broadcast address or a multicast
+    /// group
+    pub udp_address: String,
+    /// Send each measurement as an InfluxDB line protocol datagram to this
+    /// IPv4 address, disabled when empty
+    pub influx_address: String,
+    pub influx_port: u16,
+    /// Name of the measurement of the lines
+    pub influx_measurement: String,
+    /// POST each measurement as JSON to this URL, disabled when empty
+    pub webhook_url: String,
+    /// Minimum delay between two measurements sent to an output, each one
+    /// by default
+    pub sink_intervals: Vec<SinkInterval>,
+    /// Serve the values as Modbus TCP input registers
+    pub modbus_enabled: bool,
+    /// Unit ID the Modbus requests are addressed to
+    pub modbus_unit_id: u8,
+    /// Content of the input registers, from address 0
+    pub modbus_registers: Vec<Register>,
+    /// Answer SNMPv2c requests, read only
+    pub snmp_enabled: bool,
+    /// Of the SNMP requests, others are ignored
+    pub snmp_community: String,
+    /// Private enterprise number of the OID subtree of the readings
+    pub snmp_enterprise: u32,
+    /// Of the ntfy server the AQI band changes are published to
+    pub ntfy_url: String,
+    /// Topic of the notifications, disabled when empty
+    pub ntfy_topic: String,
+    /// Access token of a protected topic, none when empty
+    pub ntfy_token: String,
+    /// ntfy priority of each AQI band, 1 (min) to 5 (max), from good to
+    /// hazardous
+    pub ntfy_priorities: [u8; 6],
+    /// Minimum delay between two notifications, a band change within it is
+    /// notified once it elapsed
+    pub ntfy_interval_secs: u32,
+    /// Host name of the SMTP server the alerts are emailed with, disabled
+    /// when empty
+    pub smtp_server: String,
+    /// 587 for STARTTLS, 465 for TLS from the start
+    pub smtp_port: u16,
+    /// No authentication when empty
+    pub smtp_username: String,
+    pub smtp_password: String,
+    /// Sender address, the username when empty
+    pub smtp_from: String,
+    /// Recipient addresses
+    pub smtp_to: Vec<String>,
+    /// Of the Telegram bot, from BotFather, disabled when empty
+    pub telegram_token: String,
+    /// Chat the alerts are sent to, the only one whose commands are
+    /// answered
+    pub telegram_chat_id: i64,
+    /// Origins allowed to call `/api/` from a browser, `*` for any, none
+    /// when empty
+    pub cors_origins: Vec<String>,
+    /// Bearer token of the device control endpoints, disabled when empty
+    pub api_token: String,
+    /// Poll the other stations found with mDNS and compare them on the
+    /// dashboard
+    pub aggregator: bool,
+    /// Delay between two scans for a stronger access point of the network,
+    /// no roaming when 0
+    pub wifi_roam_interval_secs: u32,
+    /// Fetched every minute to tell whether the internet is reachable
+    /// beyond the access point, no check when empty
+    pub uplink_check_url: String,
+    /// Automatic light sleep between the measurements and publications, the
+    /// Wi-Fi waking up on each DTIM beacon. Saves power but delays the
+    /// HTTP responses.
+    pub light_sleep: bool,
+    /// WPA2-Enterprise (PEAP or TTLS) username, `wifi_psk` being ignored,
+    /// WPA2-Personal when empty
+    pub wifi_eap_username: String,
+    pub wifi_eap_password: String,
+    /// Outer identity, the username when empty
+    pub wifi_eap_identity: String,
+    /// Inner method of TTLS, `mschapv2`, `mschap`, `pap`, `chap` or `eap`
+    pub wifi_eap_ttls_phase2: String,
+    /// Advertise the Espressif BLE provisioning service in safe mode
+    pub ble_provisioning: bool,
+}
+
+/// How the station measures and publishes, without any secret, in the MQTT
+/// birth message
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsSummary {
+    pub measure_interval_secs: u32,
+    pub sensor_warmup: u8,
+    pub timezone: String,
+    pub pm25_warn: f32,
+    pub pm25_alert: f32,
+    pub pm25_limit: f32,
+    pub pm10_limit: f32,
+    pub mqtt_batch: bool,
+    pub mqtt_tenths: bool,
+    pub mqtt_tasmota: bool,
+    pub mqtt_homie: bool,
+    pub https_enabled: bool,
+    pub light_sleep: bool,
+    /// Subsystems turned off by their settings
+    pub disabled: Vec<Subsystem>,
+}
+
+impl Settings {
+    /// The configured host name, or one made from the end of the MAC
+    /// address
+    pub fn hostname(&self, mac: [u8; 6]) -> String {
+        if self.hostname.is_empty() {
+            format!("esp-particle-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5])
+        } else {
+            self.hostname.clone()
+        }
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        Calibration {
+            pm25: Correction {
+                offset: self.pm25_offset,
+                slope: self.pm25_slope,
+            },
+            pm10: Correction {
+                offset: self.pm10_offset,
+                slope: self.pm10_slope,
+            },
+        }
+    }
+
+    /// Of the values published on MQTT
+    pub fn encoding(&self) -> Encoding {
+        if self.mqtt_tenths {
+            Encoding::Tenths
+        } else {
+            Encoding::Decimal
+        }
+    }
+
+    /// Deadband of the values published on MQTT
+    pub fn mqtt_deadband(&self) -> Deltas {
+        Deltas {
+            particles: self.mqtt_deadband_pm,
+            co2: self.mqtt_deadband_co2,
+            voc: self.mqtt_deadband_voc,
+            temperature: self.mqtt_deadband_temperature,
+            humidity: self.mqtt_deadband_humidity,
+        }
+    }
+
+    pub fn alarm(&self) -> Alarm {
+        Alarm {
+            pm25: self.buzzer_pm25,
+            pm10: self.buzzer_pm10,
+            repeat: Duration::from_secs(self.buzzer_repeat_secs.into()),
+            quiet: Hours {
+                start: self.buzzer_quiet_start,
+                end: self.buzzer_quiet_end,
+            },
+        }
+    }
+
+    pub fn schedule(&self) -> Schedule {
+        Schedule {
+            quiet: Hours {
+                start: self.quiet_start,
+                end: self.quiet_end,
+            },
+            boost: Hours {
+                start: self.boost_start,
+                end: self.boost_end,
+            },
+            boost_interval: Duration::from_secs(self.boost_interval_secs.into()),
+            quiet_mqtt_interval: Duration::from_secs(self.quiet_mqtt_interval_secs.into()),
+        }
+    }
+
+    /// Minimum delay between two measurements sent to `sink`, zero for each
+    /// one
+    pub fn sink_interval(&self, sink: SinkKind) -> Duration {
+        self.sink_intervals
+            .iter()
+            .find(|interval| interval.sink == sink)
+            .map_or(Duration::ZERO, |interval| {
+                Duration::from_secs(interval.interval_secs.into())
+            })
+    }
+
+    /// `None` without a `maintenance_time`
+    pub fn maintenance(&self) -> Option<Maintenance> {
+        Some(Maintenance {
+            start: schedule::parse_time(&self.maintenance_time)?,
+            min_uptime: Duration::from_secs(u64::from(self.maintenance_uptime_days) * 24 * 3600),
+        })
+    }
+
+    pub fn relay_hysteresis(&self) -> Hysteresis {
+        Hysteresis {
+            on: self.relay_on_pm25,
+            off: self.relay_off_pm25,
+        }
+    }
+
+    pub fn fan_curve(&self) -> Curve {
+        Curve {
+            points: self.fan_curve.clone(),
+            min_duty: self.fan_min_duty,
+        }
+    }
+
+    pub fn voc_compensation(&self) -> Compensation {
+        Compensation {
+            humidity: self.voc_humidity,
+            temperature: self.voc_temperature,
+        }
+    }
+
+    pub fn subsystems(&self) -> Subsystems {
+        Subsystems {
+            sensor1: self.sensor1_enabled,
+            co2: self.co2_enabled,
+            voc: self.voc_enabled,
+            dht22: self.dht22_enabled,
+            buzzer: self.buzzer_enabled,
+            relay: self.relay_enabled,
+            fan: self.fan_enabled,
+            epaper: self.epaper_enabled,
+            segment: self.segment_enabled,
+        }
+    }
+
+    /// `None` when the alerts are not emailed
+    pub fn smtp(&self) -> Option<Account> {
+        if self.smtp_server.is_empty() {
+            return None;
+        }
+        Some(Account {
+            server: self.smtp_server.clone(),
+            port: self.smtp_port,
+            username: self.smtp_username.clone(),
+            password: self.smtp_password.clone(),
+            from: if self.smtp_from.is_empty() {
+                self.smtp_username.clone()
+            } else {
+                self.smtp_from.clone()
+            },
+            to: self.smtp_to.clone(),
+        })
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            pm25: self.pm25_limit,
+            pm10: self.pm10_limit,
+        }
+    }
+
+    /// QoS level and retain flag of a kind of data
+    pub fn mqtt_flags(&self, kind: DataKind) -> (u8, bool) {
+        let (qos, retain) = match kind {
+            DataKind::Measurement => (self.mqtt_measurement_qos, self.mqtt_measurement_retain),
+            DataKind::Sensor => (self.mqtt_sensor_qos, self.mqtt_sensor_retain),
+            DataKind::Stats => (None, Some(true)),
+            DataKind::Event => (None, Some(false)),
+        };
+        (
+            qos.unwrap_or(self.mqtt_qos),
+            retain.unwrap_or(self.mqtt_retain),
+        )
+    }
+
+    pub fn summary(&self) -> SettingsSummary {
+        let enabled = self.subsystems();
+        SettingsSummary {
+            measure_interval_secs: self.measure_interval_secs,
+            sensor_warmup: self.sensor_warmup,
+            timezone: self.timezone.clone(),
+            pm25_warn: self.pm25_warn,
+            pm25_alert: self.pm25_alert,
+            pm25_limit: self.pm25_limit,
+            pm10_limit: self.pm10_limit,
+            mqtt_batch: self.mqtt_batch,
+            mqtt_tenths: self.mqtt_tenths,
+            mqtt_tasmota: self.mqtt_tasmota,
+            mqtt_homie: self.mqtt_homie,
+            https_enabled: self.https_enabled,
+            light_sleep: self.light_sleep,
+            disabled: Subsystem::ALL
+                .into_iter()
+                .filter(|subsystem| !enabled.get(*subsystem))
+                .collect(),
+        }
+    }
+
+    /// Copy served on `/api/config` and the serial console: the passwords
+    /// and tokens which are set are replaced by [`REDACTED`], as is the
+    /// password of the MQTT broker URL
+    pub fn redacted(&self) -> Self {
+        let mut redacted = self.clone();
+        for secret in [
+            &mut redacted.wifi_psk,
+            &mut redacted.wifi_eap_password,
+            &mut redacted.ntfy_token,
+            &mut redacted.smtp_password,
+            &mut redacted.telegram_token,
+            &mut redacted.api_token,
+        ] {
+            if !secret.is_empty() {
+                *secret = REDACTED.to_string();
+            }
+        }
+        redacted.mqtt_broker_url = redact_url_password(&self.mqtt_broker_url);
+        redacted.mqtt_secondary_url = redact_url_password(&self.mqtt_secondary_url);
+        redacted.webhook_url = redact_url_password(&self.webhook_url);
+        redacted
+    }
+
+    /// The first invalid value with what is expected, checked when saved
+    /// and at startup
+    pub fn validate(&self) -> Result<()> {
+        // Freshly flashed, the network is received in safe mode
+        if CONFIG.network == "wifi" && self.wifi_ssid.is_empty() {
+            bail!("No Wi-Fi network configured, expected a wifi_ssid");
+        }
+        if self.wifi_ssid.len() > MAX_SSID_LEN {
+            bail!("Invalid wifi_ssid, expected up to {MAX_SSID_LEN} bytes");
+        }
+        if !self.wifi_psk.is_empty() && !PSK_LEN.contains(&self.wifi_psk.len()) {
+            bail!("Invalid wifi_psk, expected 8 to 64 characters or empty for an open network");
+        }
+        if !self.mqtt_broker_url.is_empty()
+            && !valid_url(&self.mqtt_broker_url, &["mqtt", "mqtts", "ws", "wss"])
+        {
+            bail!(
+                "Invalid MQTT broker URL {}, expected mqtt://, mqtts://, ws:// or wss:// then \
+                 the host, or empty to disable MQTT",
+                self.mqtt_broker_url
+            );
+        }
+        if !self.mqtt_secondary_url.is_empty() {
+            if self.mqtt_broker_url.is_empty() {
+                bail!("mqtt_secondary_url needs an mqtt_broker_url to back up");
+            }
+            if !valid_url(&self.mqtt_secondary_url, &["mqtt", "mqtts", "ws", "wss"]) {
+                bail!(
+                    "Invalid MQTT secondary URL {}, expected mqtt://, mqtts://, ws:// or wss:// \
+                     then the host, or empty for none",
+                    self.mqtt_secondary_url
+                );
+            }
+        }
+        if !MEASURE_INTERVAL_SECS.contains(&self.measure_interval_secs) {
+            bail!(
+                "Invalid measure interval {} s, expected {} to {} s",
+                self.measure_interval_secs,
+                MEASURE_INTERVAL_SECS.start(),
+                MEASURE_INTERVAL_SECS.end()
+            );
+        }
+        let (warn, alert) = (self.pm25_warn, self.pm25_alert);
+        if !(warn > 0.0 && warn < alert && alert.is_finite()) {
+            bail!("Invalid PM2.5 levels {warn} and {alert}, expected 0 < pm25_warn < pm25_alert");
+        }
+        for qos in [
+            Some(self.mqtt_qos),
+            self.mqtt_measurement_qos,
+            self.mqtt_sensor_qos,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if qos > 2 {
+                bail!("Invalid MQTT QoS {qos}, expected 0 to 2");
+            }
+        }
+        let hostname = &self.hostname;
+        if hostname.len() > MAX_HOSTNAME_LEN
+            || hostname.starts_with('-')
+            || hostname.ends_with('-')
+            || !hostname
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            bail!(
+                "Invalid hostname {hostname}, expected up to {MAX_HOSTNAME_LEN} letters, digits \
+                 and inner hyphens"
+            );
+        }
+        if self.name.len() > MAX_NAME_LEN || self.name.chars().any(char::is_control) {
+            bail!("Invalid name, expected up to {MAX_NAME_LEN} bytes of text");
+        }
+        let codes: Vec<&str> = Language::ALL.iter().map(|l| l.code()).collect();
+        if !self.language.is_empty() && !codes.contains(&self.language.as_str()) {
+            bail!(
+                "Invalid language {}, expected one of {} or empty",
+                self.language,
+                codes.join(", ")
+            );
+        }
+        if self.mqtt_fleet_topic.contains(['#', '+']) {
+            bail!(
+                "Invalid fleet topic {}, wildcards are not allowed",
+                self.mqtt_fleet_topic
+            );
+        }
+        let modes = [
+            self.mqtt_tasmota,
+            !self.mqtt_domoticz.is_empty(),
+            self.mqtt_homie,
+        ];
+        if modes.into_iter().filter(|&mode| mode).count() > 1 {
+            bail!("The Tasmota, Domoticz and Homie modes are exclusive");
+        }
+        if self.mqtt_homie && self.mqtt_batch {
+            bail!("The Homie properties can't be batched");
+        }
+        if !self.mqtt_profiles.is_empty() && modes.contains(&true) {
+            bail!("The MQTT profiles can't be used with the Tasmota, Domoticz and Homie modes");
+        }
+        for (i, profile) in self.mqtt_profiles.iter().enumerate() {
+            if profile.topic.is_empty() || profile.topic.contains(['/', '#', '+', '$', ',', ':']) {
+                bail!(
+                    "Invalid MQTT profile topic {:?}, expected a single level without wildcards",
+                    profile.topic
+                );
+            }
+            if self.mqtt_profiles[..i]
+                .iter()
+                .any(|other| other.topic == profile.topic)
+            {
+                bail!("Several MQTT profiles on {}", profile.topic);
+            }
+        }
+        if self.mqtt_domoticz.iter().any(|device| device.idx == 0) {
+            bail!("Invalid Domoticz device, expected an idx starting at 1");
+        }
+        for (i, device) in self.mqtt_domoticz.iter().enumerate() {
+            if self.mqtt_domoticz[..i]
+                .iter()
+                .any(|other| other.kind == device.kind)
+            {
+                bail!("Several Domoticz devices for {}", device.kind);
+            }
+        }
+        if self.udp_address.parse::<Ipv4Addr>().is_err() {
+            bail!("Invalid UDP address {}", self.udp_address);
+        }
+        if !self.influx_address.is_empty() {
+            if self.influx_address.parse::<Ipv4Addr>().is_err() {
+                bail!("Invalid InfluxDB address {}", self.influx_address);
+            }
+            if self.influx_port == 0 {
+                bail!("Invalid InfluxDB port 0");
+            }
+        }
+        let measurement = &self.influx_measurement;
+        if measurement.is_empty()
+            || measurement.len() > 64
+            || measurement.starts_with('_')
+            || measurement.chars().any(char::is_control)
+        {
+            bail!("Invalid InfluxDB measurement {measurement}");
+        }
+        if !self.webhook_url.is_empty()
+            && (!valid_url(&self.webhook_url, &["http", "https"]) || self.webhook_url.len() > 255)
+        {
+            bail!(
+                "Invalid webhook URL {}, expected up to 255 bytes of http:// or https:// URL, \
+                 or empty to disable the webhook",
+                self.webhook_url
+            );
+        }
+        for (i, interval) in self.sink_intervals.iter().enumerate() {
+            if interval.interval_secs > MAX_SINK_INTERVAL_SECS {
+                bail!(
+                    "Invalid interval of the {} {}, expected up to {MAX_SINK_INTERVAL_SECS} s",
+                    interval.sink,
+                    interval.interval_secs
+                );
+            }
+            if self.sink_intervals[..i]
+                .iter()
+                .any(|other| other.sink == interval.sink)
+            {
+                bail!("Several intervals of the {}", interval.sink);
+            }
+        }
+        if self.snmp_community.is_empty() || self.snmp_community.len() > 32 {
+            bail!("Invalid SNMP community, expected 1 to 32 bytes");
+        }
+        if self.snmp_enterprise == 0 {
+            bail!("Invalid SNMP enterprise number 0");
+        }
+        if !self.uplink_check_url.is_empty()
+            && (!valid_url(&self.uplink_check_url, &["http", "https"])
+                || self.uplink_check_url.len() > 255)
+        {
+            bail!(
+                "Invalid uplink check URL {}, expected up to 255 bytes of http:// or https:// \
+                 URL, or empty to disable the check",
+                self.uplink_check_url
+            );
+        }
+        if !valid_url(&self.ntfy_url, &["http", "https"]) {
+            bail!("Invalid ntfy URL {}", self.ntfy_url);
+        }
+        let topic = &self.ntfy_topic;
+        if topic.len() > 64
+            || !topic
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Invalid ntfy topic {topic}, expected up to 64 letters, digits, - and _");
+        }
+        if let Some(priority) = self
+            .ntfy_priorities
+            .iter()
+            .find(|priority| !ntfy::PRIORITIES.contains(priority))
+        {
+            bail!("Invalid ntfy priority {priority}, expected 1 to 5");
+        }
+        if let Some(account) = self.smtp() {
+            if account.port == 0 {
+                bail!("Invalid SMTP port 0");
+            }
+            if account.to.is_empty() {
+                bail!("The SMTP alerts need at least one recipient");
+            }
+            for address in account.to.iter().chain([&account.from]) {
+                if !address.contains('@')
+                    || address
+                        .chars()
+                        .any(|c| c.is_whitespace() || c.is_control() || "<>,".contains(c))
+                {
+                    bail!("Invalid email address {address}");
+                }
+            }
+        }
+        if !(1..=247).contains(&self.modbus_unit_id) {
+            bail!(
+                "Invalid Modbus unit ID {}, expected 1 to 247",
+                self.modbus_unit_id
+            );
+        }
+        if self.http_stack_size != 0 && !HTTP_STACK_SIZE.contains(&self.http_stack_size) {
+            bail!(
+                "Invalid HTTP stack size {}, expected 4096 to 32768 bytes or 0 for the default",
+                self.http_stack_size
+            );
+        }
+        if !HTTP_MAX_SESSIONS.contains(&self.http_max_sessions) {
+            bail!(
+                "Invalid HTTP max sessions {}, expected 1 to 7",
+                self.http_max_sessions
+            );
+        }
+        if !HTTP_TIMEOUT_SECS.contains(&self.http_timeout_secs) {
+            bail!(
+                "Invalid HTTP timeout {}, expected 1 to 60 seconds",
+                self.http_timeout_secs
+            );
+        }
+        if let Some(origin) = self.cors_origins.iter().find(|o| o.contains(',')) {
+            bail!("Invalid CORS origin {origin}");
+        }
+        if !EAP_TTLS_PHASE2_METHODS.contains(&self.wifi_eap_ttls_phase2.as_str()) {
+            bail!(
+                "Invalid TTLS phase 2 method {}, expected one of {EAP_TTLS_PHASE2_METHODS:?}",
+                self.wifi_eap_ttls_phase2
+            );
+        }
+        for slope in [self.pm25_slope, self.pm10_slope] {
+            if !(slope > 0.0 && slope.is_finite()) {
+                bail!("Invalid calibration slope {slope}, expected a positive number");
+            }
+        }
+        for offset in [self.pm25_offset, self.pm10_offset] {
+            if !offset.is_finite() {
+                bail!("Invalid calibration offset {offset}");
+            }
+        }
+        for (i, stage) in self.pipeline.iter().enumerate() {
+            if self.pipeline[..i].contains(stage) {
+                bail!(
+                    "Several {} stages in the pipeline",
+                    name(stage).unwrap_or_default()
+                );
+            }
+        }
+        for limit in [self.pm25_limit, self.pm10_limit] {
+            if !(limit > 0.0 && limit.is_finite()) {
+                bail!("Invalid limit {limit}, expected a positive number");
+            }
+        }
+        let deltas = self.mqtt_deadband();
+        for delta in [
+            deltas.particles,
+            deltas.co2,
+            deltas.voc,
+            deltas.temperature,
+            deltas.humidity,
+        ] {
+            if !(delta >= 0.0 && delta.is_finite()) {
+                bail!("Invalid MQTT deadband {delta}, expected a positive number or 0");
+            }
+        }
+        for level in [self.buzzer_pm25, self.buzzer_pm10] {
+            if !(level > 0.0 && level.is_finite()) {
+                bail!("Invalid buzzer level {level}, expected a positive number");
+            }
+        }
+        for hour in [
+            self.buzzer_quiet_start,
+            self.buzzer_quiet_end,
+            self.quiet_start,
+            self.quiet_end,
+            self.boost_start,
+            self.boost_end,
+        ] {
+            if hour > 23 {
+                bail!("Invalid hour {hour}, expected 0 to 23");
+            }
+        }
+        if self.boost_interval_secs == 0 {
+            bail!("Invalid boost interval, expected at least a second");
+        }
+        if !self.maintenance_time.is_empty()
+            && schedule::parse_time(&self.maintenance_time).is_none()
+        {
+            bail!(
+                "Invalid maintenance time {}, expected HH:MM or empty for none",
+                self.maintenance_time
+            );
+        }
+        if self.maintenance_uptime_days == 0 {
+            bail!("Invalid maintenance uptime, expected at least a day");
+        }
+        if self.utc_offset_minutes.abs() > 14 * 60 {
+            bail!(
+                "Invalid UTC offset {} minutes, expected at most 14 hours",
+                self.utc_offset_minutes
+            );
+        }
+        if self.timezone.len() > MAX_TIMEZONE_LEN
+            || !self.timezone.chars().all(|c| c.is_ascii_graphic())
+        {
+            bail!(
+                "Invalid time zone {}, expected a POSIX TZ string such as \
+                 CET-1CEST,M3.5.0,M10.5.0/3",
+                self.timezone
+            );
+        }
+        let (on, off) = (self.relay_on_pm25, self.relay_off_pm25);
+        if !(on.is_finite() && off >= 0.0 && off < on) {
+            bail!("Invalid relay levels {on} and {off}, expected 0 <= off < on");
+        }
+        if self.segment_brightness > segment::MAX_BRIGHTNESS {
+            bail!(
+                "Invalid 7-segment brightness {}, expected 0 to {}",
+                self.segment_brightness,
+                segment::MAX_BRIGHTNESS
+            );
+        }
+        if self.fan_curve.is_empty() {
+            bail!("The fan curve needs at least one point");
+        }
+        if self
+            .fan_curve
+            .iter()
+            .any(|p| !p.pm25.is_finite() || p.duty > 100)
+        {
+            bail!("Invalid fan curve, expected PM2.5 levels and duty cycles of 0 to 100");
+        }
+        if self.fan_curve.windows(2).any(|w| w[0].pm25 >= w[1].pm25) {
+            bail!("Invalid fan curve, expected increasing PM2.5 levels");
+        }
+        if self.fan_min_duty > 100 {
+            bail!(
+                "Invalid fan minimum duty cycle {}, expected 0 to 100",
+                self.fan_min_duty
+            );
+        }
+        if !(0.0..=100.0).contains(&self.voc_humidity) {
+            bail!(
+                "Invalid VOC humidity {}, expected 0 to 100 %",
+                self.voc_humidity
+            );
+        }
+        if !(-45.0..=130.0).contains(&self.voc_temperature) {
+            bail!(
+                "Invalid VOC temperature {}, expected -45 to 130 °C",
+                self.voc_temperature
+            );
+        }
+        // Read back with the 256 bytes buffer of `get_str`
+        if self.ntfy_url.len() > 255 || self.ntfy_token.len() > 255 {
+            bail!("The ntfy URL and token are limited to 255 bytes");
+        }
+        let token = &self.telegram_token;
+        if !token.is_empty() {
+            if !token.contains(':') || token.contains(['/', '?', '#']) || token.len() > 255 {
+                bail!("Invalid Telegram bot token, expected <id>:<secret> from BotFather");
+            }
+            if self.telegram_chat_id == 0 {
+                bail!("The Telegram bot needs the ID of its chat");
+            }
+        }
+        if self.smtp_to.join(",").len() > 255 {
+            bail!("Too many SMTP recipients");
+        }
+        if self.cors_origins.join(",").len() > 255 {
+            bail!("Too many CORS origins");
+        }
+        if fan_curve_str(&self.fan_curve).len() > 255 {
+            bail!("Too many fan curve points");
+        }
+        if domoticz_str(&self.mqtt_domoticz).len() > 255 {
+            bail!("Too many Domoticz devices");
+        }
+        if profiles_str(&self.mqtt_profiles).len() > 255 {
+            bail!("Too many MQTT profiles");
+        }
+        if sink_intervals_str(&self.sink_intervals).len() > 255 {
+            bail!("Too many output intervals");
+        }
+        if names(&self.modbus_registers).len() > 255 {
+            bail!("Too many Modbus registers");
+        }
+        Ok(())
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            wifi_ssid: CONFIG.wifi_ssid.to_string(),
+            wifi_psk: CONFIG.wifi_psk.to_string(),
+            mqtt_broker_url: CONFIG.mqtt_broker_url.to_string(),
+            mqtt_secondary_url: String::new(),
+            hostname: String::new(),
+            name: String::new(),
+            language: String::new(),
+            measure_interval_secs: CONFIG.measure_interval_secs,
+            sensor_warmup: 1,
+            simulate: CONFIG.simulate,
+            pm25_warn: 15.0,
+            pm25_alert: 35.0,
+            pm25_offset: 0.0,
+            pm25_slope: 1.0,
+            pm10_offset: 0.0,
+            pm10_slope: 1.0,
+            pipeline: pipeline::DEFAULT_STAGES.to_vec(),
+            pm25_limit: 15.0,
+            pm10_limit: 45.0,
+            buzzer_pm25: 150.0,
+            buzzer_pm10: 250.0,
+            buzzer_repeat_secs: 600,
+            buzzer_quiet_start: 0,
+            buzzer_quiet_end: 0,
+            utc_offset_minutes: 0,
+            timezone: String::new(),
+            quiet_start: 0,
+            quiet_end: 0,
+            quiet_mqtt_interval_secs: 900,
+            boost_start: 0,
+            boost_end: 0,
+            boost_interval_secs: 60,
+            maintenance_time: String::new(),
+            maintenance_uptime_days: 7,
+            relay_on_pm25: 35.0,
+            relay_off_pm25: 15.0,
+            fan_curve: vec![
+                CurvePoint {
+                    pm25: 5.0,
+                    duty: 20,
+                },
+                CurvePoint {
+                    pm25: 55.0,
+                    duty: 100,
+                },
+            ],
+            fan_min_duty: 20,
+            fan_boost_minutes: 15,
+            voc_humidity: 50.0,
+            voc_temperature: 25.0,
+            sensor1_enabled: true,
+            co2_enabled: true,
+            voc_enabled: true,
+            dht22_enabled: true,
+            buzzer_enabled: true,
+            relay_enabled: true,
+            fan_enabled: true,
+            epaper_enabled: true,
+            segment_enabled: true,
+            led_enabled: true,
+            led_brightness: 255,
+            segment_brightness: segment::MAX_BRIGHTNESS,
+            mqtt_batch: false,
+            mqtt_tenths: false,
+            mqtt_profiles: Vec::new(),
+            mqtt_min_interval_secs: 0,
+            mqtt_deadband_pm: 0.0,
+            mqtt_deadband_co2: 0.0,
+            mqtt_deadband_voc: 0.0,
+            mqtt_deadband_temperature: 0.0,
+            mqtt_deadband_humidity: 0.0,
+            mqtt_max_silence_secs: 3600,
+            mqtt_qos: 1,
+            mqtt_retain: true,
+            mqtt_measurement_qos: None,
+            mqtt_measurement_retain: None,
+            mqtt_sensor_qos: None,
+            mqtt_sensor_retain: None,
+            mqtt_tasmota: false,
+            mqtt_domoticz: Vec::new(),
+            mqtt_homie: false,
+            mqtt_fleet_topic: "fleet/airsensors/heartbeat".to_string(),
+            https_enabled: false,
+            http_stack_size: 0,
+            http_max_sessions: 4,
+            http_timeout_secs: 5,
+            coap_enabled: false,
+            udp_port: 0,
+            udp_address: "255.255.255.255".to_string(),
+            influx_address: String::new(),
+            influx_port: influx::DEFAULT_PORT,
+            influx_measurement: influx::DEFAULT_MEASUREMENT.to_string(),
+            webhook_url: String::new(),
+            sink_intervals: Vec::new(),
+            modbus_enabled: false,
+            modbus_unit_id: 1,
+            modbus_registers: vec![
+                Register::Pm25,
+                Register::Pm10,
+                Register::Aqi,
+                Register::Temperature,
+                Register::Humidity,
+            ],
+            snmp_enabled: false,
+            snmp_community: "public".to_string(),
+            snmp_enterprise: snmp::EXAMPLE_ENTERPRISE,
+            ntfy_url: ntfy::DEFAULT_URL.to_string(),
+            ntfy_topic: String::new(),
+            ntfy_token: String::new(),
+            ntfy_priorities: ntfy::DEFAULT_PRIORITIES,
+            ntfy_interval_secs: 15 * 60,
+            smtp_server: String::new(),
+            smtp_port: smtp::DEFAULT_PORT,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            smtp_from: String::new(),
+            smtp_to: Vec::new(),
+            telegram_token: String::new(),
+            telegram_chat_id: 0,
+            cors_origins: Vec::new(),
+            api_token: String::new(),
+            aggregator: false,
+            wifi_roam_interval_secs: 0,
+            uplink_check_url: String::new(),
+            light_sleep: false,
+            wifi_eap_username: String::new(),
+            wifi_eap_password: String::new(),
+            wifi_eap_identity: String::new(),
+            wifi_eap_ttls_phase2: "mschapv2".to_string(),
+            ble_provisioning: true,
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+pub type SharedConfigStore = Arc<Mutex<ConfigStore>>;
+
+/// Typed access to the settings stored in the `config` NVS namespace.
+#[cfg(target_os = "espidf")]
+pub struct ConfigStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+#[cfg(target_os = "espidf")]
+impl ConfigStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        let mut store = Self { nvs };
+        if !store.nvs.contains(KEY_VERSION)? {
+            log::info!("No settings found in NVS, storing defaults from cfg.toml");
+            store.save(&Settings::default())?;
+        }
+        Ok(store)
+    }
+
+    pub fn load(&self) -> Result<Settings> {
+        let defaults = Settings::default();
+        Ok(Settings {
+            wifi_ssid: self.get_str(KEY_WIFI_SSID)?.unwrap_or(defaults.wifi_ssid),
+            wifi_psk: self.get_str(KEY_WIFI_PSK)?.unwrap_or(defaults.wifi_psk),
+            mqtt_broker_url: self
+                .get_str(KEY_MQTT_BROKER_URL)?
+                .unwrap_or(defaults.mqtt_broker_url),
+            mqtt_secondary_url: self
+                .get_str(KEY_MQTT_SECONDARY_URL)?
+                .unwrap_or(defaults.mqtt_secondary_url),
+            hostname: self.get_str(KEY_HOSTNAME)?.unwrap_or(defaults.hostname),
+            name: self.get_str(KEY_NAME)?.unwrap_or(defaults.name),
+            language: self.get_str(KEY_LANGUAGE)?.unwrap_or(defaults.language),
+            measure_interval_secs: self
+                .get_u32(KEY_MEASURE_INTERVAL)?
+                .unwrap_or(defaults.measure_interval_secs),
+            sensor_warmup: self
+                .get_u8(KEY_SENSOR_WARMUP)?
+                .unwrap_or(defaults.sensor_warmup),
+            simulate: self.get_bool(KEY_SIMULATE)?.unwrap_or(defaults.simulate),
+            pm25_warn: self.get_f32(KEY_PM25_WARN)?.unwrap_or(defaults.pm25_warn),
+            pm25_alert: self.get_f32(KEY_PM25_ALERT)?.unwrap_or(defaults.pm25_alert),
+            pm25_offset: self
+                .get_f32(KEY_PM25_OFFSET)?
+                .unwrap_or(defaults.pm25_offset),
+            pm25_slope: self.get_f32(KEY_PM25_SLOPE)?.unwrap_or(defaults.pm25_slope),
+            pm10_offset: self
+                .get_f32(KEY_PM10_OFFSET)?
+                .unwrap_or(defaults.pm10_offset),
+            pm10_slope: self.get_f32(KEY_PM10_SLOPE)?.unwrap_or(defaults.pm10_slope),
+            pipeline: self
+                .get_str(KEY_PIPELINE)?
+                .map(|stages| {
+                    split_list(&stages)
+                        .iter()
+                        .filter_map(|stage| from_name(stage))
+                        .collect()
+                })
+                .unwrap_or(defaults.pipeline),
+            pm25_limit: self.get_f32(KEY_PM25_LIMIT)?.unwrap_or(defaults.pm25_limit),
+            pm10_limit: self.get_f32(KEY_PM10_LIMIT)?.unwrap_or(defaults.pm10_limit),
+            buzzer_pm25: self
+                .get_f32(KEY_BUZZER_PM25)?
+                .unwrap_or(defaults.buzzer_pm25),
+            buzzer_pm10: self
+                .get_f32(KEY_BUZZER_PM10)?
+                .unwrap_or(defaults.buzzer_pm10),
+            buzzer_repeat_secs: self
+                .get_u32(KEY_BUZZER_REPEAT)?
+                .unwrap_or(defaults.buzzer_repeat_secs),
+            buzzer_quiet_start: self
+                .get_u8(KEY_BUZZER_QUIET_START)?
+                .unwrap_or(defaults.buzzer_quiet_start),
+            buzzer_quiet_end: self
+                .get_u8(KEY_BUZZER_QUIET_END)?
+                .unwrap_or(defaults.buzzer_quiet_end),
+            utc_offset_minutes: self
+                .get_i32(KEY_UTC_OFFSET)?
+                .unwrap_or(defaults.utc_offset_minutes),
+            timezone: self.get_str(KEY_TIMEZONE)?.unwrap_or(defaults.timezone),
+            quiet_start: self
+                .get_u8(KEY_QUIET_START)?
+                .unwrap_or(defaults.quiet_start),
+            quiet_end: self.get_u8(KEY_QUIET_END)?.unwrap_or(defaults.quiet_end),
+            quiet_mqtt_interval_secs: self
+                .get_u32(KEY_QUIET_MQTT_INTERVAL)?
+                .unwrap_or(defaults.quiet_mqtt_interval_secs),
+            boost_start: self
+                .get_u8(KEY_BOOST_START)?
+                .unwrap_or(defaults.boost_start),
+            boost_end: self.get_u8(KEY_BOOST_END)?.unwrap_or(defaults.boost_end),
+            boost_interval_secs: self
+                .get_u32(KEY_BOOST_INTERVAL)?
+                .unwrap_or(defaults.boost_interval_secs),
+            maintenance_time: self
+                .get_str(KEY_MAINTENANCE_TIME)?
+                .unwrap_or(defaults.maintenance_time),
+            maintenance_uptime_days: self
+                .get_u16(KEY_MAINTENANCE_UPTIME)?
+                .unwrap_or(defaults.maintenance_uptime_days),
+            relay_on_pm25: self
+                .get_f32(KEY_RELAY_ON)?
+                .unwrap_or(defaults.relay_on_pm25),
+            relay_off_pm25: self
+                .get_f32(KEY_RELAY_OFF)?
+                .unwrap_or(defaults.relay_off_pm25),
+            fan_curve: self
+                .get_str(KEY_FAN_CURVE)?
+                .map(|curve| parse_fan_curve(&curve))
+                .unwrap_or(defaults.fan_curve),
+            fan_min_duty: self
+                .get_u8(KEY_FAN_MIN_DUTY)?
+                .unwrap_or(defaults.fan_min_duty),
+            fan_boost_minutes: self
+                .get_u32(KEY_FAN_BOOST)?
+                .unwrap_or(defaults.fan_boost_minutes),
+            voc_humidity: self
+                .get_f32(KEY_VOC_HUMIDITY)?
+                .unwrap_or(defaults.voc_humidity),
+            voc_temperature: self
+                .get_f32(KEY_VOC_TEMPERATURE)?
+                .unwrap_or(defaults.voc_temperature),
+            sensor1_enabled: self
+                .get_bool(KEY_SENSOR1_ENABLED)?
+                .unwrap_or(defaults.sensor1_enabled),
+            co2_enabled: self
+                .get_bool(KEY_CO2_ENABLED)?
+                .unwrap_or(defaults.co2_enabled),
+            voc_enabled: self
+                .get_bool(KEY_VOC_ENABLED)?
+                .unwrap_or(defaults.voc_enabled),
+            dht22_enabled: self
+                .get_bool(KEY_DHT22_ENABLED)?
+                .unwrap_or(defaults.dht22_enabled),
+            buzzer_enabled: self
+                .get_bool(KEY_BUZZER_ENABLED)?
+                .unwrap_or(defaults.buzzer_enabled),
+            relay_enabled: self
+                .get_bool(KEY_RELAY_ENABLED)?
+                .unwrap_or(defaults.relay_enabled),
+            fan_enabled: self
+                .get_bool(KEY_FAN_ENABLED)?
+                .unwrap_or(defaults.fan_enabled),
+            epaper_enabled: self
+                .get_bool(KEY_EPAPER_ENABLED)?
+                .unwrap_or(defaults.epaper_enabled),
+            segment_enabled: self
+                .get_bool(KEY_SEGMENT_ENABLED)?
+                .unwrap_or(defaults.segment_enabled),
+            led_enabled: self
+                .get_bool(KEY_LED_ENABLED)?
+                .unwrap_or(defaults.led_enabled),
+            led_brightness: self
+                .get_u8(KEY_LED_BRIGHTNESS)?
+                .unwrap_or(defaults.led_brightness),
+            segment_brightness: self
+                .get_u8(KEY_SEGMENT_BRIGHTNESS)?
+                .unwrap_or(defaults.segment_brightness),
+            mqtt_batch: self
+                .get_bool(KEY_MQTT_BATCH)?
+                .unwrap_or(defaults.mqtt_batch),
+            mqtt_tenths: self
+                .get_bool(KEY_MQTT_TENTHS)?
+                .unwrap_or(defaults.mqtt_tenths),
+            mqtt_profiles: self
+                .get_str(KEY_MQTT_PROFILES)?
+                .map(|profiles| parse_profiles(&profiles))
+                .unwrap_or(defaults.mqtt_profiles),
+            mqtt_min_interval_secs: self
+                .get_u32(KEY_MQTT_MIN_INTERVAL)?
+                .unwrap_or(defaults.mqtt_min_interval_secs),
+            mqtt_deadband_pm: self
+                .get_f32(KEY_MQTT_DEADBAND_PM)?
+                .unwrap_or(defaults.mqtt_deadband_pm),
+            mqtt_deadband_co2: self
+                .get_f32(KEY_MQTT_DEADBAND_CO2)?
+                .unwrap_or(defaults.mqtt_deadband_co2),
+            mqtt_deadband_voc: self
+                .get_f32(KEY_MQTT_DEADBAND_VOC)?
+                .unwrap_or(defaults.mqtt_deadband_voc),
+            mqtt_deadband_temperature: self
+                .get_f32(KEY_MQTT_DEADBAND_TEMPERATURE)?
+                .unwrap_or(defaults.mqtt_deadband_temperature),
+            mqtt_deadband_humidity: self
+                .get_f32(KEY_MQTT_DEADBAND_HUMIDITY)?
+                .unwrap_or(defaults.mqtt_deadband_humidity),
+            mqtt_max_silence_secs: self
+                .get_u32(KEY_MQTT_MAX_SILENCE)?
+                .unwrap_or(defaults.mqtt_max_silence_secs),
+            mqtt_qos: self.get_u8(KEY_MQTT_QOS)?.unwrap_or(defaults.mqtt_qos),
+            mqtt_retain: self
+                .get_bool(KEY_MQTT_RETAIN)?
+                .unwrap_or(defaults.mqtt_retain),
+            // No override when missing
+            mqtt_measurement_qos: self.get_u8(KEY_MQTT_MEASUREMENT_QOS)?,
+            mqtt_measurement_retain: self.get_bool(KEY_MQTT_MEASUREMENT_RETAIN)?,
+            mqtt_sensor_qos: self.get_u8(KEY_MQTT_SENSOR_QOS)?,
+            mqtt_sensor_retain: self.get_bool(KEY_MQTT_SENSOR_RETAIN)?,
+            mqtt_tasmota: self
+                .get_bool(KEY_MQTT_TASMOTA)?
+                .unwrap_or(defaults.mqtt_tasmota),
+            mqtt_domoticz: self
+                .get_str(KEY_MQTT_DOMOTICZ)?
+                .map(|devices| parse_domoticz(&devices))
+                .unwrap_or(defaults.mqtt_domoticz),
+            mqtt_homie: self
+                .get_bool(KEY_MQTT_HOMIE)?
+                .unwrap_or(defaults.mqtt_homie),
+            mqtt_fleet_topic: self
+                .get_str(KEY_MQTT_FLEET_TOPIC)?
+                .unwrap_or(defaults.mqtt_fleet_topic),
+            https_enabled: self
+                .get_bool(KEY_HTTPS_ENABLED)?
+                .unwrap_or(defaults.https_enabled),
+            http_stack_size: self
+                .get_u32(KEY_HTTP_STACK_SIZE)?
+                .unwrap_or(defaults.http_stack_size),
+            http_max_sessions: self
+                .get_u8(KEY_HTTP_MAX_SESSIONS)?
+                .unwrap_or(defaults.http_max_sessions),
+            http_timeout_secs: self
+                .get_u8(KEY_HTTP_TIMEOUT)?
+                .unwrap_or(defaults.http_timeout_secs),
+            coap_enabled: self
+                .get_bool(KEY_COAP_ENABLED)?
+                .unwrap_or(defaults.coap_enabled),
+            udp_port: self.get_u16(KEY_UDP_PORT)?.unwrap_or(defaults.udp_port),
+            udp_address: self
+                .get_str(KEY_UDP_ADDRESS)?
+                .unwrap_or(defaults.udp_address),
+            influx_address: self
+                .get_str(KEY_INFLUX_ADDRESS)?
+                .unwrap_or(defaults.influx_address),
+            influx_port: self
+                .get_u16(KEY_INFLUX_PORT)?
+                .unwrap_or(defaults.influx_port),
+            influx_measurement: self
+                .get_str(KEY_INFLUX_MEASUREMENT)?
+                .unwrap_or(defaults.influx_measurement),
+            webhook_url: self
+                .get_str(KEY_WEBHOOK_URL)?
+                .unwrap_or(defaults.webhook_url),
+            sink_intervals: self
+                .get_str(KEY_SINK_INTERVALS)?
+                .map(|intervals| parse_sink_intervals(&intervals))
+                .unwrap_or(defaults.sink_intervals),
+            modbus_enabled: self
+                .get_bool(KEY_MODBUS_ENABLED)?
+                .unwrap_or(defaults.modbus_enabled),
+            modbus_unit_id: self
+                .get_u8(KEY_MODBUS_UNIT_ID)?
+                .unwrap_or(defaults.modbus_unit_id),
+            modbus_registers: self
+                .get_str(KEY_MODBUS_REGISTERS)?
+                .map(|registers| {
+                    split_list(&registers)
+                        .iter()
+                        .filter_map(|r| from_name(r))
+                        .collect()
+                })
+                .unwrap_or(defaults.modbus_registers),
+            snmp_enabled: self
+                .get_bool(KEY_SNMP_ENABLED)?
+                .unwrap_or(defaults.snmp_enabled),
+            snmp_community: self
+                .get_str(KEY_SNMP_COMMUNITY)?
+                .unwrap_or(defaults.snmp_community),
+            snmp_enterprise: self
+                .get_u32(KEY_SNMP_ENTERPRISE)?
+                .unwrap_or(defaults.snmp_enterprise),
+            ntfy_url: self.get_str(KEY_NTFY_URL)?.unwrap_or(defaults.ntfy_url),
+            ntfy_topic: self.get_str(KEY_NTFY_TOPIC)?.unwrap_or(defaults.ntfy_topic),
+            ntfy_token: self.get_str(KEY_NTFY_TOKEN)?.unwrap_or(defaults.ntfy_token),
+            ntfy_priorities: self
+                .get_str(KEY_NTFY_PRIORITIES)?
+                .and_then(|priorities| {
+                    let priorities: Vec<u8> = split_list(&priorities)
+                        .iter()
+                        .filter_map(|priority| priority.parse().ok())
+                        .collect();
+                    priorities.try_into().ok()
+                })
+                .unwrap_or(defaults.ntfy_priorities),
+            ntfy_interval_secs: self
+                .get_u32(KEY_NTFY_INTERVAL)?
+                .unwrap_or(defaults.ntfy_interval_secs),
+            smtp_server: self
+                .get_str(KEY_SMTP_SERVER)?
+                .unwrap_or(defaults.smtp_server),
+            smtp_port: self.get_u16(KEY_SMTP_PORT)?.unwrap_or(defaults.smtp_port),
+            smtp_username: self
+                .get_str(KEY_SMTP_USERNAME)?
+                .unwrap_or(defaults.smtp_username),
+            smtp_password: self
+                .get_str(KEY_SMTP_PASSWORD)?
+                .unwrap_or(defaults.smtp_password),
+            smtp_from: self.get_str(KEY_SMTP_FROM)?.unwrap_or(defaults.smtp_from),
+            smtp_to: self
+                .get_str(KEY_SMTP_TO)?
+                .map(|to| split_list(&to))
+                .unwrap_or(defaults.smtp_to),
+            telegram_token: self
+                .get_str(KEY_TELEGRAM_TOKEN)?
+                .unwrap_or(defaults.telegram_token),
+            telegram_chat_id: self
+                .get_i64(KEY_TELEGRAM_CHAT_ID)?
+                .unwrap_or(defaults.telegram_chat_id),
+            cors_origins: self
+                .get_str(KEY_CORS_ORIGINS)?
+                .map(|origins| split_list(&origins))
+                .unwrap_or(defaults.cors_origins),
+            api_token: self.get_str(KEY_API_TOKEN)?.unwrap_or(defaults.api_token),
+            aggregator: self
+                .get_bool(KEY_AGGREGATOR)?
+                .unwrap_or(defaults.aggregator),
+            wifi_roam_interval_secs: self
+                .get_u32(KEY_WIFI_ROAM_INTERVAL)?
+                .unwrap_or(defaults.wifi_roam_interval_secs),
+            uplink_check_url: self
+                .get_str(KEY_UPLINK_CHECK_URL)?
+                .unwrap_or(defaults.uplink_check_url),
+            light_sleep: self
+                .get_bool(KEY_LIGHT_SLEEP)?
+                .unwrap_or(defaults.light_sleep),
+            wifi_eap_username: self
+                .get_str(KEY_WIFI_EAP_USERNAME)?
+                .unwrap_or(defaults.wifi_eap_username),
+            wifi_eap_password: self
+                .get_str(KEY_WIFI_EAP_PASSWORD)?
+                .unwrap_or(defaults.wifi_eap_password),
+            wifi_eap_identity: self
+                .get_str(KEY_WIFI_EAP_IDENTITY)?
+                .unwrap_or(defaults.wifi_eap_identity),
+            wifi_eap_ttls_phase2: self
+                .get_str(KEY_WIFI_EAP_TTLS_PHASE2)?
+                .unwrap_or(defaults.wifi_eap_ttls_phase2),
+            ble_provisioning: self
+                .get_bool(KEY_BLE_PROVISIONING)?
+                .unwrap_or(defaults.ble_provisioning),
+        })
+    }
+
+    pub fn save(&mut self, settings: &Settings) -> Result<()> {
+        self.set_str(KEY_WIFI_SSID, &settings.wifi_ssid)?;
+        self.set_str(KEY_WIFI_PSK, &settings.wifi_psk)?;
+        self.set_str(KEY_MQTT_BROKER_URL, &settings.mqtt_broker_url)?;
+        self.set_str(KEY_MQTT_SECONDARY_URL, &settings.mqtt_secondary_url)?;
+        self.set_str(KEY_HOSTNAME, &settings.hostname)?;
+        self.set_str(KEY_NAME, &settings.name)?;
+        self.set_str(KEY_LANGUAGE, &settings.language)?;
+        self.set_u32(KEY_MEASURE_INTERVAL, settings.measure_interval_secs)?;
+        self.set_u8(KEY_SENSOR_WARMUP, settings.sensor_warmup)?;
+        self.set_bool(KEY_SIMULATE, settings.simulate)?;
+        self.set_f32(KEY_PM25_WARN, settings.pm25_warn)?;
+        self.set_f32(KEY_PM25_ALERT, settings.pm25_alert)?;
+        self.set_f32(KEY_PM25_OFFSET, settings.pm25_offset)?;
+        self.set_f32(KEY_PM25_SLOPE, settings.pm25_slope)?;
+        self.set_f32(KEY_PM10_OFFSET, settings.pm10_offset)?;
+        self.set_f32(KEY_PM10_SLOPE, settings.pm10_slope)?;
+        self.set_str(KEY_PIPELINE, &names(&settings.pipeline))?;
+        self.set_f32(KEY_PM25_LIMIT, settings.pm25_limit)?;
+        self.set_f32(KEY_PM10_LIMIT, settings.pm10_limit)?;
+        self.set_f32(KEY_BUZZER_PM25, settings.buzzer_pm25)?;
+        self.set_f32(KEY_BUZZER_PM10, settings.buzzer_pm10)?;
+        self.set_u32(KEY_BUZZER_REPEAT, settings.buzzer_repeat_secs)?;
+        self.set_u8(KEY_BUZZER_QUIET_START, settings.buzzer_quiet_start)?;
+        self.set_u8(KEY_BUZZER_QUIET_END, settings.buzzer_quiet_end)?;
+        self.set_i32(KEY_UTC_OFFSET, settings.utc_offset_minutes)?;
+        self.set_str(KEY_TIMEZONE, &settings.timezone)?;
+        self.set_u8(KEY_QUIET_START, settings.quiet_start)?;
+        self.set_u8(KEY_QUIET_END, settings.quiet_end)?;
+        self.set_u32(KEY_QUIET_MQTT_INTERVAL, settings.quiet_mqtt_interval_secs)?;
+        self.set_u8(KEY_BOOST_START, settings.boost_start)?;
+        self.set_u8(KEY_BOOST_END, settings.boost_end)?;
+        self.set_u32(KEY_BOOST_INTERVAL, settings.boost_interval_secs)?;
+        self.set_str(KEY_MAINTENANCE_TIME, &settings.maintenance_time)?;
+        self.set_u16(KEY_MAINTENANCE_UPTIME, settings.maintenance_uptime_days)?;
+        self.set_f32(KEY_RELAY_ON, settings.relay_on_pm25)?;
+        self.set_f32(KEY_RELAY_OFF, settings.relay_off_pm25)?;
+        self.set_str(KEY_FAN_CURVE, &fan_curve_str(&settings.fan_curve))?;
+        self.set_u8(KEY_FAN_MIN_DUTY, settings.fan_min_duty)?;
+        self.set_u32(KEY_FAN_BOOST, settings.fan_boost_minutes)?;
+        self.set_f32(KEY_VOC_HUMIDITY, settings.voc_humidity)?;
+        self.set_f32(KEY_VOC_TEMPERATURE, settings.voc_temperature)?;
+        self.set_bool(KEY_SENSOR1_ENABLED, settings.sensor1_enabled)?;
+        self.set_bool(KEY_CO2_ENABLED, settings.co2_enabled)?;
+        self.set_bool(KEY_VOC_ENABLED, settings.voc_enabled)?;
+        self.set_bool(KEY_DHT22_ENABLED, settings.dht22_enabled)?;
+        self.set_bool(KEY_BUZZER_ENABLED, settings.buzzer_enabled)?;
+        self.set_bool(KEY_RELAY_ENABLED, settings.relay_enabled)?;
+        self.set_bool(KEY_FAN_ENABLED, settings.fan_enabled)?;
+        self.set_bool(KEY_EPAPER_ENABLED, settings.epaper_enabled)?;
+        self.set_bool(KEY_SEGMENT_ENABLED, settings.segment_enabled)?;
+        self.set_bool(KEY_LED_ENABLED, settings.led_enabled)?;
+        self.set_u8(KEY_LED_BRIGHTNESS, settings.led_brightness)?;
+        self.set_u8(KEY_SEGMENT_BRIGHTNESS, settings.segment_brightness)?;
+        self.set_bool(KEY_MQTT_BATCH, settings.mqtt_batch)?;
+        self.set_bool(KEY_MQTT_TENTHS, settings.mqtt_tenths)?;
+        self.set_str(KEY_MQTT_PROFILES, &profiles_str(&settings.mqtt_profiles))?;
+        self.set_u32(KEY_MQTT_MIN_INTERVAL, settings.mqtt_min_interval_secs)?;
+        self.set_f32(KEY_MQTT_DEADBAND_PM, settings.mqtt_deadband_pm)?;
+        self.set_f32(KEY_MQTT_DEADBAND_CO2, settings.mqtt_deadband_co2)?;
+        self.set_f32(KEY_MQTT_DEADBAND_VOC, settings.mqtt_deadband_voc)?;
+        self.set_f32(
+            KEY_MQTT_DEADBAND_TEMPERATURE,
+            settings.mqtt_deadband_temperature,
+        )?;
+        self.set_f32(KEY_MQTT_DEADBAND_HUMIDITY, settings.mqtt_deadband_humidity)?;
+        self.set_u32(KEY_MQTT_MAX_SILENCE, settings.mqtt_max_silence_secs)?;
+        self.set_u8(KEY_MQTT_QOS, settings.mqtt_qos)?;
+        self.set_bool(KEY_MQTT_RETAIN, settings.mqtt_retain)?;
+        self.set_opt_u8(KEY_MQTT_MEASUREMENT_QOS, settings.mqtt_measurement_qos)?;
+        self.set_opt_bool(
+            KEY_MQTT_MEASUREMENT_RETAIN,
+            settings.mqtt_measurement_retain,
+        )?;
+        self.set_opt_u8(KEY_MQTT_SENSOR_QOS, settings.mqtt_sensor_qos)?;
+        self.set_opt_bool(KEY_MQTT_SENSOR_RETAIN, settings.mqtt_sensor_retain)?;
+        self.set_bool(KEY_MQTT_TASMOTA, settings.mqtt_tasmota)?;
+        self.set_str(KEY_MQTT_DOMOTICZ, &domoticz_str(&settings.mqtt_domoticz))?;
+        self.set_bool(KEY_MQTT_HOMIE, settings.mqtt_homie)?;
+        self.set_str(KEY_MQTT_FLEET_TOPIC, &settings.mqtt_fleet_topic)?;
+        self.set_bool(KEY_HTTPS_ENABLED, settings.https_enabled)?;
+        self.set_u32(KEY_HTTP_STACK_SIZE, settings.http_stack_size)?;
+        self.set_u8(KEY_HTTP_MAX_SESSIONS, settings.http_max_sessions)?;
+        self.set_u8(KEY_HTTP_TIMEOUT, settings.http_timeout_secs)?;
+        self.set_bool(KEY_COAP_ENABLED, settings.coap_enabled)?;
+        self.set_u16(KEY_UDP_PORT, settings.udp_port)?;
+        self.set_str(KEY_UDP_ADDRESS, &settings.udp_address)?;
+        self.set_str(KEY_INFLUX_ADDRESS, &settings.influx_address)?;
+        self.set_u16(KEY_INFLUX_PORT, settings.influx_port)?;
+        self.set_str(KEY_INFLUX_MEASUREMENT, &settings.influx_measurement)?;
+        self.set_str(KEY_WEBHOOK_URL, &settings.webhook_url)?;
+        self.set_str(
+            KEY_SINK_INTERVALS,
+            &sink_intervals_str(&settings.sink_intervals),
+        )?;
+        self.set_bool(KEY_MODBUS_ENABLED, settings.modbus_enabled)?;
+        self.set_u8(KEY_MODBUS_UNIT_ID, settings.modbus_unit_id)?;
+        self.set_str(KEY_MODBUS_REGISTERS, &names(&settings.modbus_registers))?;
+        self.set_bool(KEY_SNMP_ENABLED, settings.snmp_enabled)?;
+        self.set_str(KEY_SNMP_COMMUNITY, &settings.snmp_community)?;
+        self.set_u32(KEY_SNMP_ENTERPRISE, settings.snmp_enterprise)?;
+        self.set_str(KEY_NTFY_URL, &settings.ntfy_url)?;
+        self.set_str(KEY_NTFY_TOPIC, &settings.ntfy_topic)?;
+        self.set_str(KEY_NTFY_TOKEN, &settings.ntfy_token)?;
+        let priorities: Vec<String> = settings.ntfy_priorities.iter().map(u8::to_string).collect();
+        self.set_str(KEY_NTFY_PRIORITIES, &priorities.join(","))?;
+        self.set_u32(KEY_NTFY_INTERVAL, settings.ntfy_interval_secs)?;
+        self.set_str(KEY_SMTP_SERVER, &settings.smtp_server)?;
+        self.set_u16(KEY_SMTP_PORT, settings.smtp_port)?;
+        self.set_str(KEY_SMTP_USERNAME, &settings.smtp_username)?;
+        self.set_str(KEY_SMTP_PASSWORD, &settings.smtp_password)?;
+        self.set_str(KEY_SMTP_FROM, &settings.smtp_from)?;
+        self.set_str(KEY_SMTP_TO, &settings.smtp_to.join(","))?;
+        self.set_str(KEY_TELEGRAM_TOKEN, &settings.telegram_token)?;
+        self.set_i64(KEY_TELEGRAM_CHAT_ID, settings.telegram_chat_id)?;
+        self.set_str(KEY_CORS_ORIGINS, &settings.cors_origins.join(","))?;
+        self.set_str(KEY_API_TOKEN, &settings.api_token)?;
+        self.set_bool(KEY_AGGREGATOR, settings.aggregator)?;
+        self.set_u32(KEY_WIFI_ROAM_INTERVAL, settings.wifi_roam_interval_secs)?;
+        self.set_str(KEY_UPLINK_CHECK_URL, &settings.uplink_check_url)?;
+        self.set_bool(KEY_LIGHT_SLEEP, settings.light_sleep)?;
+        self.set_str(KEY_WIFI_EAP_USERNAME, &settings.wifi_eap_username)?;
+        self.set_str(KEY_WIFI_EAP_PASSWORD, &settings.wifi_eap_password)?;
+        self.set_str(KEY_WIFI_EAP_IDENTITY, &settings.wifi_eap_identity)?;
+        self.set_str(KEY_WIFI_EAP_TTLS_PHASE2, &settings.wifi_eap_ttls_phase2)?;
+        self.set_bool(KEY_BLE_PROVISIONING, settings.ble_provisioning)?;
+        self.set_u8(KEY_VERSION, VERSION)?;
+        Ok(())
+    }
+
+    /// Without the secrets, see [`Settings::redacted`]
+    pub fn export_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self.load()?.redacted())?)
+    }
+
+    /// Join `ssid` on the next restart, with WPA2-Personal or open when
+    /// `password` is empty, received by the provisioning
+    pub fn set_wifi_network(&mut self, ssid: &str, password: &str) -> Result<Settings> {
+        let patch = serde_json::json!({
+            "wifi_ssid": ssid,
+            "wifi_psk": password,
+            "wifi_eap_username": "",
+        });
+        self.import_json(patch.to_string().as_bytes())
+    }
+
+    /// Merge the given JSON object into the current settings and persist the
+    /// result. Keys not present in `json` are left untouched, as are the
+    /// secrets given as served, see [`Settings::redacted`].
+    pub fn import_json(&mut self, json: &[u8]) -> Result<Settings> {
+        let patch: serde_json::Value = serde_json::from_slice(json)?;
+        let serde_json::Value::Object(patch) = patch else {
+            bail!("Expected a JSON object");
+        };
+        let settings = self.load()?;
+        let redacted = serde_json::to_value(settings.redacted())?;
+        let mut current = serde_json::to_value(settings)?;
+        if let serde_json::Value::Object(current) = &mut current {
+            for (key, value) in patch {
+                if !current.contains_key(&key) {
+                    bail!("Unknown setting {key}");
+                }
+                if redacted.get(&key) == Some(&value) {
+                    continue;
+                }
+                current.insert(key, value);
+            }
+        }
+        let settings: Settings = serde_json::from_value(current)?;
+        settings.validate()?;
+        self.save(&settings)?;
+        Ok(settings)
+    }
+
+    pub fn get_str(&self, key: &str) -> Result<Option<String>> {
+        let mut buf = [0u8; 256];
+        Ok(self.nvs.get_str(key, &mut buf)?.map(str::to_string))
+    }
+
+    pub fn set_str(&mut self, key: &str, value: &str) -> Result<()> {
+        Ok(self.nvs.set_str(key, value)?)
+    }
+
+    pub fn get_u32(&self, key: &str) -> Result<Option<u32>> {
+        Ok(self.nvs.get_u32(key)?)
+    }
+
+    pub fn set_u32(&mut self, key: &str, value: u32) -> Result<()> {
+        Ok(self.nvs.set_u32(key, value)?)
+    }
+
+    pub fn get_i32(&self, key: &str) -> Result<Option<i32>> {
+        Ok(self.nvs.get_i32(key)?)
+    }
+
+    pub fn set_i32(&mut self, key: &str, value: i32) -> Result<()> {
+        Ok(self.nvs.set_i32(key, value)?)
+    }
+
+    pub fn get_i64(&self, key: &str) -> Result<Option<i64>> {
+        Ok(self.nvs.get_i64(key)?)
+    }
+
+    pub fn set_i64(&mut self, key: &str, value: i64) -> Result<()> {
+        Ok(self.nvs.set_i64(key, value)?)
+    }
+
+    pub fn get_u16(&self, key: &str) -> Result<Option<u16>> {
+        Ok(self.nvs.get_u16(key)?)
+    }
+
+    pub fn set_u16(&mut self, key: &str, value: u16) -> Result<()> {
+        Ok(self.nvs.set_u16(key, value)?)
+    }
+
+    pub fn get_u8(&self, key: &str) -> Result<Option<u8>> {
+        Ok(self.nvs.get_u8(key)?)
+    }
+
+    pub fn set_u8(&mut self, key: &str, value: u8) -> Result<()> {
+        Ok(self.nvs.set_u8(key, value)?)
+    }
+
+    /// Removes the key for `None`
+    pub fn set_opt_u8(&mut self, key: &str, value: Option<u8>) -> Result<()> {
+        match value {
+            Some(value) => self.set_u8(key, value),
+            None => {
+                self.nvs.remove(key)?;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        Ok(self.get_u8(key)?.map(|v| v != 0))
+    }
+
+    pub fn set_bool(&mut self, key: &str, value: bool) -> Result<()> {
+        self.set_u8(key, value as u8)
+    }
+
+    pub fn set_opt_bool(&mut self, key: &str, value: Option<bool>) -> Result<()> {
+        self.set_opt_u8(key, value.map(u8::from))
+    }
+
+    /// CA certificate of the WPA2-Enterprise server, as PEM with its NUL
+    /// terminator
+    pub fn eap_ca_cert(&self) -> Result<Option<Vec<u8>>> {
+        let Some(len) = self.nvs.blob_len(KEY_WIFI_EAP_CA_CERT)? else {
+            return Ok(None);
+        };
+        let mut buf = vec![0u8; len];
+        Ok(self
+            .nvs
+            .get_blob(KEY_WIFI_EAP_CA_CERT, &mut buf)?
+            .map(<[u8]>::to_vec))
+    }
+
+    /// Store the PEM CA certificate, or remove it for `None`
+    pub fn set_eap_ca_cert(&mut self, pem: Option<&str>) -> Result<()> {
+        let Some(pem) = pem else {
+            self.nvs.remove(KEY_WIFI_EAP_CA_CERT)?;
+            return Ok(());
+        };
+        if !pem.trim_start().starts_with("-----BEGIN ") || pem.contains('\0') {
+            bail!("Expected a PEM encoded certificate");
+        }
+        let mut blob = pem.as_bytes().to_vec();
+        blob.push(0);
+        Ok(self.nvs.set_blob(KEY_WIFI_EAP_CA_CERT, &blob)?)
+    }
+
+    /// NVS has no float type, the raw bits are stored in a `u32`.
+    pub fn get_f32(&self, key: &str) -> Result<Option<f32>> {
+        Ok(self.get_u32(key)?.map(f32::from_bits))
+    }
+
+    pub fn set_f32(&mut self, key: &str, value: f32) -> Result<()> {
+        self.set_u32(key, value.to_bits())
+    }
+}
+
+/// Items of a comma separated list stored in NVS
+#[cfg(target_os = "espidf")]
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `url` has one of the `schemes` and a host
+fn valid_url(url: &str, schemes: &[&str]) -> bool {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return false;
+    };
+    // Credentials and port included
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    schemes.contains(&scheme)
+        && !host.is_empty()
+        && !host.starts_with(':')
+        && !url.contains(char::is_whitespace)
+}
+
+/// `url` with [`REDACTED`] in place of its password, if any
+fn redact_url_password(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let Some((user, _password)) = authority
+        .rsplit_once('@')
+        .and_then(|(userinfo, _)| userinfo.split_once(':'))
+    else {
+        return url.to_string();
+    };
+    let host = &authority[authority.rfind('@').unwrap_or_default()..];
+    let scheme = &url[..url.len() - rest.len()];
+    let path = &rest[authority.len()..];
+    format!("{scheme}{user}:{REDACTED}{host}{path}")
+}
+
+fn fan_curve_str(curve: &[CurvePoint]) -> String {
+    let points: Vec<String> = curve
+        .iter()
+        .map(|point| format!("{}:{}", point.pm25, point.duty))
+        .collect();
+    points.join(",")
+}
+
+/// Name of an enum variant in the JSON settings
+fn name(value: impl Serialize) -> Option<String> {
+    match serde_json::to_value(value).ok()? {
+        serde_json::Value::String(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Enum variant of a name in the JSON settings
+#[cfg(target_os = "espidf")]
+fn from_name<T: serde::de::DeserializeOwned>(name: &str) -> Option<T> {
+    serde_json::from_value(name.into()).ok()
+}
+
+/// Comma separated list of enum variants stored in NVS
+fn names<T: Serialize>(values: &[T]) -> String {
+    let names: Vec<String> = values.iter().filter_map(name).collect();
+    names.join(",")
+}
+
+/// Domoticz devices stored in NVS, as `kind:idx` pairs
+fn domoticz_str(devices: &[DomoticzDevice]) -> String {
+    let devices: Vec<String> = devices
+        .iter()
+        .filter_map(|device| Some(format!("{}:{}", name(device.kind)?, device.idx)))
+        .collect();
+    devices.join(",")
+}
+
+/// Intervals of the outputs stored in NVS, as `sink:secs` pairs
+fn sink_intervals_str(intervals: &[SinkInterval]) -> String {
+    let intervals: Vec<String> = intervals
+        .iter()
+        .filter_map(|interval| {
+            Some(format!(
+                "{}:{}",
+                name(interval.sink)?,
+                interval.interval_secs
+            ))
+        })
+        .collect();
+    intervals.join(",")
+}
+
+/// MQTT profiles stored in NVS, as `topic:encoding` pairs
+fn profiles_str(profiles: &[Profile]) -> String {
+    let profiles: Vec<String> = profiles
+        .iter()
+        .filter_map(|profile| Some(format!("{}:{}", profile.topic, name(profile.encoding)?)))
+        .collect();
+    profiles.join(",")
+}
+
+/// MQTT profiles stored in NVS, invalid ones are skipped
+#[cfg(target_os = "espidf")]
+fn parse_profiles(profiles: &str) -> Vec<Profile> {
+    split_list(profiles)
+        .iter()
+        .filter_map(|profile| {
+            let (topic, encoding) = profile.split_once(':')?;
+            Some(Profile {
+                topic: topic.to_string(),
+                encoding: from_name(encoding)?,
+            })
+        })
+        .collect()
+}
+
+/// Intervals of the outputs stored in NVS, invalid ones are skipped
+#[cfg(target_os = "espidf")]
+fn parse_sink_intervals(intervals: &str) -> Vec<SinkInterval> {
+    split_list(intervals)
+        .iter()
+        .filter_map(|interval| {
+            let (sink, secs) = interval.split_once(':')?;
+            Some(SinkInterval {
+                sink: from_name(sink)?,
+                interval_secs: secs.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Domoticz devices stored in NVS, invalid ones are skipped
+#[cfg(target_os = "espidf")]
+fn parse_domoticz(devices: &str) -> Vec<DomoticzDevice> {
+    split_list(devices)
+        .iter()
+        .filter_map(|device| {
+            let (kind, idx) = device.split_once(':')?;
+            Some(DomoticzDevice {
+                kind: from_name(kind)?,
+                idx: idx.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Points of a fan curve stored in NVS, invalid ones are skipped
+#[cfg(target_os = "espidf")]
+fn parse_fan_curve(curve: &str) -> Vec<CurvePoint> {
+    split_list(curve)
+        .iter()
+        .filter_map(|point| {
+            let (pm25, duty) = point.split_once(':')?;
+            Some(CurvePoint {
+                pm25: pm25.parse().ok()?,
+                duty: duty.parse().ok()?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,65 @@
+#[cfg(target_os = "espidf")]
+use std::time::Duration;
+
+#[cfg(target_os = "espidf")]
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+#[cfg(target_os = "espidf")]
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How far the device reaches, telling a fault of the access point from one
+/// of the internet access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Connectivity {
+    /// Neither associated to the access point nor with an Ethernet link
+    Offline,
+    /// On the local network, but the MQTT broker or the internet is
+    /// unreachable
+    Degraded,
+    Online,
+}
+
+/// Results of the last checks, `None` for those disabled or not done yet
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Uplink {
+    /// Associated to the access point, or Ethernet link up
+    pub link: bool,
+    /// Connected to the MQTT broker
+    pub broker: Option<bool>,
+    /// Answered by the check URL
+    pub internet: Option<bool>,
+}
+
+impl Uplink {
+    pub fn connectivity(&self) -> Connectivity {
+        if !self.link {
+            Connectivity::Offline
+        } else if self.broker == Some(false) || self.internet == Some(false) {
+            Connectivity::Degraded
+        } else {
+            Connectivity::Online
+        }
+    }
+}
+
+/// Fetch `url`, the internet being reachable when it answers with a 2xx
+/// status
+#[cfg(target_os = "espidf")]
+pub fn check(url: &str) -> Result<()> {
+    use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+    use esp_idf_svc::http::Method;
+
+    let mut connection = EspHttpConnection::new(&Configuration {
+        timeout: Some(HTTP_TIMEOUT),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    connection.initiate_request(Method::Get, url, &[])?;
+    connection.initiate_response()?;
+    if !(200..300).contains(&connection.status()) {
+        bail!("HTTP status {}", connection.status());
+    }
+    Ok(())
+}
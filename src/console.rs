@@ -0,0 +1,113 @@
+use core::str::FromStr;
+
+use anyhow::{bail, Result};
+
+/// Listed by `help`
+pub const HELP: &str = "\
+Commands:
+  help                    this list
+  measure                 measure the particle sensors now
+  wifi status             signal, access point and disconnections
+  mqtt status             whether the broker is connected
+  health                  uptime, memory and subsystems
+  config get [key]        the settings, or one of them
+  config set <key> <value>
+                          change a setting, applied on the next restart
+  provision <device_id> <api_key>
+                          store the identity of the device, applied on the
+                          next restart
+  provision clear         forget it, back to api_token
+  restart                 restart the device";
+
+/// Command typed on the serial console
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Measure,
+    WifiStatus,
+    MqttStatus,
+    Health,
+    /// All the settings when `None`
+    ConfigGet(Option<String>),
+    ConfigSet {
+        key: String,
+        value: serde_json::Value,
+    },
+    /// Identity of the device in a fleet, its API key replacing `api_token`
+    Provision {
+        device_id: String,
+        api_key: String,
+    },
+    Unprovision,
+    Restart,
+}
+
+impl Command {
+    /// JSON object of the setting changed by [`Command::ConfigSet`], as
+    /// accepted by `POST /api/config`
+    pub fn config_patch(key: &str, value: &serde_json::Value) -> Vec<u8> {
+        let mut patch = serde_json::Map::new();
+        patch.insert(key.to_string(), value.clone());
+        serde_json::Value::Object(patch).to_string().into_bytes()
+    }
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("help"), None) => Self::Help,
+            (Some("measure"), None) => Self::Measure,
+            (Some("wifi"), Some("status")) => Self::WifiStatus,
+            (Some("mqtt"), Some("status")) => Self::MqttStatus,
+            (Some("health"), None) => Self::Health,
+            (Some("restart"), None) => Self::Restart,
+            (Some("config"), Some("get")) => Self::ConfigGet(words.next().map(str::to_string)),
+            (Some("provision"), Some("clear")) => Self::Unprovision,
+            (Some("provision"), Some(device_id)) => {
+                let Some(api_key) = words.next() else {
+                    bail!("Expected provision <device_id> <api_key>");
+                };
+                Self::Provision {
+                    device_id: device_id.to_string(),
+                    api_key: api_key.to_string(),
+                }
+            }
+            (Some("config"), Some("set")) => {
+                let Some(key) = words.next() else {
+                    bail!("Expected config set <key> <value>");
+                };
+                // The rest of the line, spaces included
+                let value = skip_words(line, 3).trim_end();
+                if value.is_empty() {
+                    bail!("Expected config set <key> <value>");
+                }
+                // Unquoted text is taken as a string
+                let value = serde_json::from_str(value)
+                    .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+                return Ok(Self::ConfigSet {
+                    key: key.to_string(),
+                    value,
+                });
+            }
+            _ => bail!("Unknown command {line:?}, try help"),
+        };
+        if words.next().is_some() {
+            bail!("Too many arguments, try help");
+        }
+        Ok(command)
+    }
+}
+
+/// `line` without its first `count` words
+fn skip_words(line: &str, count: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..count {
+        rest = rest
+            .trim_start_matches(|c: char| !c.is_whitespace())
+            .trim_start();
+    }
+    rest
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Prefix of the Prometheus metrics
+const METRIC_PREFIX: &str = "particle_sensor";
+
+/// Operational events of the firmware, counted since boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    WifiDisconnects,
+    WifiReconnects,
+    /// Switches to a stronger access point of the same network
+    WifiRoams,
+    MqttDisconnects,
+    /// Reported by the MQTT client, e.g. broker unreachable
+    MqttErrors,
+    /// Switches between the primary and secondary brokers
+    MqttFailovers,
+    /// Failed measurements of the particle sensors
+    SensorErrors,
+    Co2Errors,
+    VocErrors,
+    Dht22Errors,
+    /// Restarts of the HTTP server to recover memory
+    HttpRestarts,
+}
+
+impl Counter {
+    pub const ALL: [Self; 11] = [
+        Self::WifiDisconnects,
+        Self::WifiReconnects,
+        Self::WifiRoams,
+        Self::MqttDisconnects,
+        Self::MqttErrors,
+        Self::MqttFailovers,
+        Self::SensorErrors,
+        Self::Co2Errors,
+        Self::VocErrors,
+        Self::Dht22Errors,
+        Self::HttpRestarts,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::WifiDisconnects => "wifi_disconnects",
+            Self::WifiReconnects => "wifi_reconnects",
+            Self::WifiRoams => "wifi_roams",
+            Self::MqttDisconnects => "mqtt_disconnects",
+            Self::MqttErrors => "mqtt_errors",
+            Self::MqttFailovers => "mqtt_failovers",
+            Self::SensorErrors => "sensor_errors",
+            Self::Co2Errors => "co2_errors",
+            Self::VocErrors => "voc_errors",
+            Self::Dht22Errors => "dht22_errors",
+            Self::HttpRestarts => "http_restarts",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Self::WifiDisconnects => "Wi-Fi connections lost",
+            Self::WifiReconnects => "Wi-Fi connections established again",
+            Self::WifiRoams => "Switches to a stronger Wi-Fi access point",
+            Self::MqttDisconnects => "MQTT connections lost",
+            Self::MqttErrors => "Errors of the MQTT client",
+            Self::MqttFailovers => "Switches between the primary and secondary MQTT brokers",
+            Self::SensorErrors => "Failed measurements of the particle sensors",
+            Self::Co2Errors => "Failed measurements of the CO2 sensor",
+            Self::VocErrors => "Failed measurements of the VOC sensor",
+            Self::Dht22Errors => "Failed measurements of the DHT22",
+            Self::HttpRestarts => "Restarts of the HTTP server to recover memory",
+        }
+    }
+}
+
+/// One atomic counter per [`Counter`], shared by all the tasks
+#[derive(Default)]
+pub struct Counters([AtomicU32; Counter::ALL.len()]);
+
+impl Counters {
+    pub fn increment(&self, counter: Counter) {
+        self.0[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, counter: Counter) -> u32 {
+        self.0[counter as usize].load(Ordering::Relaxed)
+    }
+
+    /// By name, for the JSON of the health
+    pub fn snapshot(&self) -> BTreeMap<&'static str, u32> {
+        Counter::ALL
+            .into_iter()
+            .map(|counter| (counter.name(), self.get(counter)))
+            .collect()
+    }
+
+    /// In the Prometheus text format
+    pub fn write_metrics(&self, out: &mut String) {
+        for counter in Counter::ALL {
+            write_metric(out, counter.name(), counter.help(), self.get(counter));
+        }
+    }
+}
+
+/// A counter in the Prometheus text format, `_total` appended to its name
+pub fn write_metric(out: &mut String, name: &str, help: &str, value: u32) {
+    let name = format!("{METRIC_PREFIX}_{name}_total");
+    // Writing to a String can't fail
+    let _ = write!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+    );
+}
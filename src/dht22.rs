@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use crate::voc::Compensation;
+
+/// The sensor measures when read, at most every 2 s
+pub const MIN_INTERVAL: Duration = Duration::from_secs(2);
+/// Start signal, the sensor answers once the line is released
+const START_LOW_US: u32 = 2000;
+/// A bit is 50 µs low then high for 26 to 28 µs for a 0, 70 µs for a 1
+const BIT_SAMPLE_US: u32 = 40;
+/// The longest level of a frame is the 80 µs of the answer
+const LEVEL_TIMEOUT_US: u32 = 100;
+
+/// DHT22 (AM2302) on a single open drain line with a pull-up, bit-banged
+pub struct Dht22<P> {
+    pin: P,
+}
+
+impl<P> Dht22<P>
+where
+    P: InputPin + OutputPin,
+    P::Error: std::error::Error + Send + Sync + 'static,
+{
+    pub fn new(mut pin: P) -> Result<Self> {
+        // Released
+        pin.set_high()?;
+        Ok(Self { pin })
+    }
+
+    /// Humidity and temperature. The bits are told apart by their timing,
+    /// the interrupts should be disabled while reading.
+    pub fn read(&mut self, delay: &mut impl DelayNs) -> Result<Compensation> {
+        self.pin.set_low()?;
+        delay.delay_us(START_LOW_US);
+        self.pin.set_high()?;
+        // Answer: 80 µs low then 80 µs high
+        self.wait_for(false, delay)?;
+        self.wait_for(true, delay)?;
+        self.wait_for(false, delay)?;
+        let mut bytes = [0u8; 5];
+        for bit in 0..40 {
+            self.wait_for(true, delay)?;
+            delay.delay_us(BIT_SAMPLE_US);
+            if self.pin.is_high()? {
+                bytes[bit / 8] |= 0x80 >> (bit % 8);
+                self.wait_for(false, delay)?;
+            }
+        }
+        let sum = bytes[..4].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        if sum != bytes[4] {
+            bail!("Invalid checksum from the DHT22");
+        }
+        let humidity = u16::from_be_bytes([bytes[0], bytes[1]]);
+        // Sign and magnitude
+        let temperature = u16::from_be_bytes([bytes[2] & 0x7F, bytes[3]]);
+        let sign = if bytes[2] & 0x80 != 0 { -1.0 } else { 1.0 };
+        Ok(Compensation {
+            humidity: f32::from(humidity) / 10.0,
+            temperature: sign * f32::from(temperature) / 10.0,
+        })
+    }
+
+    fn wait_for(&mut self, high: bool, delay: &mut impl DelayNs) -> Result<()> {
+        for _ in 0..LEVEL_TIMEOUT_US {
+            if self.pin.is_high()? == high {
+                return Ok(());
+            }
+            delay.delay_us(1);
+        }
+        bail!("No answer from the DHT22")
+    }
+}
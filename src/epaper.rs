@@ -0,0 +1,417 @@
+#[cfg(target_os = "espidf")]
+use std::sync::Arc;
+#[cfg(target_os = "espidf")]
+use std::thread;
+#[cfg(target_os = "espidf")]
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "espidf")]
+use anyhow::{bail, Result};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::hal::{
+    gpio::{AnyIOPin, Input, Output, PinDriver},
+    spi::{config::Config, SpiDeviceDriver, SpiDriver},
+    units::Hertz,
+};
+
+use crate::aqi::{self, Band};
+use crate::reading;
+
+/// Of the 2.13" panels, in landscape
+pub const WIDTH: usize = 250;
+pub const HEIGHT: usize = 122;
+/// Of a line of the controller RAM, 122 pixels and padding
+const RAM_LINE_LEN: usize = 16;
+/// The others are partial, without flashing but leaving some ghosting
+#[cfg(target_os = "espidf")]
+const FULL_REFRESH_EVERY: u32 = 20;
+/// The SSD1680 supports up to 20 MHz
+#[cfg(target_os = "espidf")]
+const BAUDRATE: Hertz = Hertz(4_000_000);
+/// Of a full refresh, about 3 s
+#[cfg(target_os = "espidf")]
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Of the SPI transfers, within the DMA buffer
+#[cfg(target_os = "espidf")]
+const CHUNK_LEN: usize = 1024;
+
+#[cfg(target_os = "espidf")]
+const CMD_DRIVER_OUTPUT: u8 = 0x01;
+#[cfg(target_os = "espidf")]
+const CMD_DEEP_SLEEP: u8 = 0x10;
+#[cfg(target_os = "espidf")]
+const CMD_DATA_ENTRY_MODE: u8 = 0x11;
+#[cfg(target_os = "espidf")]
+const CMD_SW_RESET: u8 = 0x12;
+#[cfg(target_os = "espidf")]
+const CMD_TEMPERATURE_SENSOR: u8 = 0x18;
+#[cfg(target_os = "espidf")]
+const CMD_MASTER_ACTIVATION: u8 = 0x20;
+#[cfg(target_os = "espidf")]
+const CMD_UPDATE_CONTROL_1: u8 = 0x21;
+#[cfg(target_os = "espidf")]
+const CMD_UPDATE_CONTROL_2: u8 = 0x22;
+#[cfg(target_os = "espidf")]
+const CMD_WRITE_RAM_BW: u8 = 0x24;
+/// The previous image, for the partial refresh
+#[cfg(target_os = "espidf")]
+const CMD_WRITE_RAM_PREVIOUS: u8 = 0x26;
+#[cfg(target_os = "espidf")]
+const CMD_BORDER_WAVEFORM: u8 = 0x3C;
+#[cfg(target_os = "espidf")]
+const CMD_RAM_X_RANGE: u8 = 0x44;
+#[cfg(target_os = "espidf")]
+const CMD_RAM_Y_RANGE: u8 = 0x45;
+#[cfg(target_os = "espidf")]
+const CMD_RAM_X_COUNTER: u8 = 0x4E;
+#[cfg(target_os = "espidf")]
+const CMD_RAM_Y_COUNTER: u8 = 0x4F;
+/// Of `CMD_UPDATE_CONTROL_2`, with the clock and analog on then off
+#[cfg(target_os = "espidf")]
+const UPDATE_FULL: u8 = 0xF7;
+#[cfg(target_os = "espidf")]
+const UPDATE_PARTIAL: u8 = 0xFC;
+
+/// 5x7 glyphs of ` ` to `Z`, a byte per column, top pixel in the low bit
+const FONT: [[u8; 5]; 59] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5F, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14],
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x56, 0x20, 0x50],
+    [0x00, 0x08, 0x07, 0x03, 0x00],
+    [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00],
+    [0x2A, 0x1C, 0x7F, 0x1C, 0x2A],
+    [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3E, 0x51, 0x49, 0x45, 0x3E],
+    [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x72, 0x49, 0x49, 0x49, 0x46],
+    [0x21, 0x41, 0x49, 0x4D, 0x33],
+    [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3C, 0x4A, 0x49, 0x49, 0x31],
+    [0x41, 0x21, 0x11, 0x09, 0x07],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x46, 0x49, 0x49, 0x29, 0x1E],
+    [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x59, 0x09, 0x06],
+    [0x3E, 0x41, 0x5D, 0x59, 0x4E],
+    [0x7C, 0x12, 0x11, 0x12, 0x7C],
+    [0x7F, 0x49, 0x49, 0x49, 0x36],
+    [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x49, 0x49, 0x49, 0x41],
+    [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x41, 0x51, 0x73],
+    [0x7F, 0x08, 0x08, 0x08, 0x7F],
+    [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01],
+    [0x7F, 0x08, 0x14, 0x22, 0x41],
+    [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x1C, 0x02, 0x7F],
+    [0x7F, 0x04, 0x08, 0x10, 0x7F],
+    [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06],
+    [0x3E, 0x41, 0x51, 0x21, 0x5E],
+    [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x26, 0x49, 0x49, 0x49, 0x32],
+    [0x03, 0x01, 0x7F, 0x01, 0x03],
+    [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F],
+    [0x3F, 0x40, 0x38, 0x40, 0x3F],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03],
+    [0x61, 0x59, 0x49, 0x4D, 0x43],
+];
+
+/// What the panel shows
+#[derive(Debug, Clone)]
+pub struct Screen<'a> {
+    pub station: &'a str,
+    /// Local time of the measurement, `HH:MM`
+    pub time: Option<String>,
+    /// µg/m³, `None` before the first measurement
+    pub pm25: Option<f32>,
+    pub pm10: Option<f32>,
+    pub stale: bool,
+    /// PM2.5 of the last 24h in tenths of µg/m³, oldest first
+    pub history: &'a [u16],
+}
+
+/// Black and white image, in the layout of the SSD1680 RAM: the 2.13"
+/// panels are 122 pixels wide and 250 high, turned in landscape
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    ram: Vec<u8>,
+}
+
+impl Frame {
+    /// All white
+    pub fn new() -> Self {
+        Self {
+            ram: vec![0xFF; RAM_LINE_LEN * WIDTH],
+        }
+    }
+
+    /// Bytes to write to the RAM, a bit per pixel, 1 for white
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Blacken the pixel at `x`, `y` of the landscape image, clipped
+    pub fn set(&mut self, x: usize, y: usize) {
+        if x >= WIDTH || y >= HEIGHT {
+            return;
+        }
+        // Turned clockwise: the RAM lines are the columns of the image
+        let column = HEIGHT - 1 - y;
+        self.ram[x * RAM_LINE_LEN + column / 8] &= !(0x80 >> (column % 8));
+    }
+
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        for x in x..x + width {
+            for y in y..y + height {
+                self.set(x, y);
+            }
+        }
+    }
+
+    /// Write `text` in capitals from `x`, `y`, its glyphs `scale` times
+    /// larger, returning the width taken
+    pub fn text(&mut self, x: usize, y: usize, scale: usize, text: &str) -> usize {
+        for (i, c) in text.chars().enumerate() {
+            let glyph = FONT
+                .get((c.to_ascii_uppercase() as usize).wrapping_sub(0x20))
+                .unwrap_or(&FONT[usize::from(b'?' - 0x20)]);
+            let left = x + i * 6 * scale;
+            for (column, bits) in glyph.iter().enumerate() {
+                for row in (0..7).filter(|row| bits >> row & 1 == 1) {
+                    self.fill(left + column * scale, y + row * scale, scale, scale);
+                }
+            }
+        }
+        text_width(text, scale)
+    }
+
+    /// Line between two points, for the sparkline
+    pub fn line(&mut self, from: (usize, usize), to: (usize, usize)) {
+        let (x0, y0) = (from.0 as isize, from.1 as isize);
+        let (x1, y1) = (to.0 as isize, to.1 as isize);
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+        loop {
+            self.set(x as usize, y as usize);
+            if x == x1 && y == y1 {
+                return;
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x += sx;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn text_width(text: &str, scale: usize) -> usize {
+    (text.chars().count() * 6 * scale).saturating_sub(scale)
+}
+
+/// The station and time on top, PM2.5 in large with PM10 beside it, the
+/// AQI and its band, and the sparkline of the last 24h at the bottom
+pub fn render(screen: &Screen<'_>) -> Frame {
+    let mut frame = Frame::new();
+    let mut status = screen.time.clone().unwrap_or_default();
+    if screen.stale {
+        status = format!("STALE {status}");
+    }
+    let status_width = text_width(&status, 1);
+    frame.text(WIDTH - status_width, 2, 1, &status);
+    let station: String = screen
+        .station
+        .chars()
+        .take((WIDTH - status_width) / 6 - 1)
+        .collect();
+    frame.text(0, 2, 1, &station);
+    frame.fill(0, 12, WIDTH, 1);
+
+    let value = |value: Option<f32>| match value {
+        Some(value) if value >= 100.0 => format!("{value:.0}"),
+        Some(value) => format!("{value:.1}"),
+        None => "--".to_string(),
+    };
+    frame.text(0, 18, 5, &value(screen.pm25));
+    frame.text(160, 18, 1, "PM2.5 ug/m3");
+    frame.text(160, 34, 1, "PM10");
+    frame.text(160, 44, 2, &value(screen.pm10));
+    if let (Some(pm25), Some(pm10)) = (screen.pm25, screen.pm10) {
+        let aqi = aqi::us_epa(pm25, pm10);
+        frame.text(0, 60, 2, &format!("AQI {aqi}"));
+        frame.text(0, 78, 1, Band::from_aqi(aqi).name());
+    }
+
+    sparkline(&mut frame, 88, HEIGHT - 88, screen.history);
+    frame
+}
+
+/// Polyline of the `values`, scaled to their maximum, on the whole width
+/// from `top` and `height` high
+fn sparkline(frame: &mut Frame, top: usize, height: usize, values: &[u16]) {
+    frame.fill(0, top + height - 1, WIDTH, 1);
+    if values.len() < 2 {
+        return;
+    }
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    let point = |i: usize, value: u16| {
+        let x = i * (WIDTH - 1) / (values.len() - 1);
+        let y = top + (height - 2) - usize::from(value) * (height - 2) / usize::from(max);
+        (x, y)
+    };
+    for (i, pair) in values.windows(2).enumerate() {
+        frame.line(point(i, pair[0]), point(i + 1, pair[1]));
+    }
+    let label = format!("{:.0}", reading::from_tenths(max));
+    frame.text(WIDTH - text_width(&label, 1), top, 1, &label);
+}
+
+/// SSD1680 controller of the 2.13" panels, on the SPI bus. It sleeps between
+/// the updates, the panel keeping the image without power.
+#[cfg(target_os = "espidf")]
+pub struct Ssd1680 {
+    spi: SpiDeviceDriver<'static, Arc<SpiDriver<'static>>>,
+    /// Low for the commands, high for their data
+    dc: PinDriver<'static, AnyIOPin, Output>,
+    /// Wakes the controller up when pulsed low
+    reset: PinDriver<'static, AnyIOPin, Output>,
+    /// High while the controller is busy
+    busy: PinDriver<'static, AnyIOPin, Input>,
+    /// Image on the panel, the base of the partial refresh
+    shown: Option<Frame>,
+    partial_refreshes: u32,
+}
+
+#[cfg(target_os = "espidf")]
+impl Ssd1680 {
+    pub fn new(
+        spi: Arc<SpiDriver<'static>>,
+        cs: AnyIOPin,
+        dc: AnyIOPin,
+        rst: AnyIOPin,
+        busy: AnyIOPin,
+    ) -> Result<Self> {
+        let mut reset = PinDriver::output(rst)?;
+        reset.set_high()?;
+        Ok(Self {
+            spi: SpiDeviceDriver::new(spi, Some(cs), &Config::new().baudrate(BAUDRATE))?,
+            dc: PinDriver::output(dc)?,
+            reset,
+            busy: PinDriver::input(busy)?,
+            shown: None,
+            partial_refreshes: 0,
+        })
+    }
+
+    /// Show `frame`, unless it is already, then put the controller to sleep
+    pub fn show(&mut self, frame: &Frame) -> Result<()> {
+        if self.shown.as_ref() == Some(frame) {
+            return Ok(());
+        }
+        self.wake_up()?;
+        let previous = match &self.shown {
+            Some(shown) if self.partial_refreshes < FULL_REFRESH_EVERY => Some(shown.clone()),
+            _ => None,
+        };
+        self.write_ram(CMD_WRITE_RAM_BW, frame)?;
+        match &previous {
+            Some(previous) => {
+                self.write_ram(CMD_WRITE_RAM_PREVIOUS, previous)?;
+                self.refresh(UPDATE_PARTIAL)?;
+                self.partial_refreshes += 1;
+            }
+            None => {
+                self.write_ram(CMD_WRITE_RAM_PREVIOUS, frame)?;
+                self.refresh(UPDATE_FULL)?;
+                self.partial_refreshes = 0;
+            }
+        }
+        self.shown = Some(frame.clone());
+        // Keeps the RAM, but a reset is needed to wake up
+        self.command(CMD_DEEP_SLEEP, &[0x01])
+    }
+
+    /// Reset and set the controller up for the panel, in landscape
+    fn wake_up(&mut self) -> Result<()> {
+        self.reset.set_low()?;
+        thread::sleep(Duration::from_millis(10));
+        self.reset.set_high()?;
+        thread::sleep(Duration::from_millis(10));
+        self.command(CMD_SW_RESET, &[])?;
+        self.wait()?;
+        let last_line = (WIDTH - 1) as u16;
+        let [last_low, last_high] = last_line.to_le_bytes();
+        self.command(CMD_DRIVER_OUTPUT, &[last_low, last_high, 0x00])?;
+        // X then Y increasing
+        self.command(CMD_DATA_ENTRY_MODE, &[0x03])?;
+        self.command(CMD_RAM_X_RANGE, &[0x00, (RAM_LINE_LEN - 1) as u8])?;
+        self.command(CMD_RAM_Y_RANGE, &[0x00, 0x00, last_low, last_high])?;
+        self.command(CMD_BORDER_WAVEFORM, &[0x05])?;
+        self.command(CMD_UPDATE_CONTROL_1, &[0x00, 0x80])?;
+        // Internal sensor, for the waveforms of the temperature
+        self.command(CMD_TEMPERATURE_SENSOR, &[0x80])?;
+        self.wait()
+    }
+
+    fn write_ram(&mut self, command: u8, frame: &Frame) -> Result<()> {
+        self.command(CMD_RAM_X_COUNTER, &[0x00])?;
+        self.command(CMD_RAM_Y_COUNTER, &[0x00, 0x00])?;
+        self.command(command, frame.ram())
+    }
+
+    fn refresh(&mut self, update: u8) -> Result<()> {
+        self.command(CMD_UPDATE_CONTROL_2, &[update])?;
+        self.command(CMD_MASTER_ACTIVATION, &[])?;
+        self.wait()
+    }
+
+    fn command(&mut self, command: u8, data: &[u8]) -> Result<()> {
+        self.dc.set_low()?;
+        self.spi.write(&[command])?;
+        self.dc.set_high()?;
+        for chunk in data.chunks(CHUNK_LEN) {
+            self.spi.write(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn wait(&mut self) -> Result<()> {
+        let start = Instant::now();
+        while self.busy.is_high() {
+            if start.elapsed() > BUSY_TIMEOUT {
+                bail!("The e-paper controller stays busy");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+}
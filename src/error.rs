@@ -0,0 +1,71 @@
+use std::fmt;
+
+/// Errors of the firmware core, classified by the subsystem that failed so
+/// that the supervisor in `main` can pick a recovery strategy.
+#[derive(Debug)]
+pub enum Error {
+    /// The particle sensor is not responding
+    Sensor(anyhow::Error),
+    /// Could not join or stay on the Wi-Fi or Ethernet network
+    Network(anyhow::Error),
+    /// MQTT client creation or publishing failed
+    Mqtt(anyhow::Error),
+    /// The settings can't be loaded or are unusable
+    Config(anyhow::Error),
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+impl Error {
+    pub fn sensor(e: impl Into<anyhow::Error>) -> Self {
+        Self::Sensor(e.into())
+    }
+
+    pub fn network(e: impl Into<anyhow::Error>) -> Self {
+        Self::Network(e.into())
+    }
+
+    pub fn mqtt(e: impl Into<anyhow::Error>) -> Self {
+        Self::Mqtt(e.into())
+    }
+
+    pub fn config(e: impl Into<anyhow::Error>) -> Self {
+        Self::Config(e.into())
+    }
+
+    /// Red flashes of the LED telling the error until the restart
+    pub fn blink_code(&self) -> usize {
+        match self {
+            Error::Other(_) => 1,
+            Error::Network(_) => 2,
+            Error::Mqtt(_) => 3,
+            Error::Sensor(_) => 4,
+            Error::Config(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Sensor(e) => write!(f, "Sensor error: {e:#}"),
+            Error::Network(e) => write!(f, "Network error: {e:#}"),
+            Error::Mqtt(e) => write!(f, "MQTT error: {e:#}"),
+            Error::Config(e) => write!(f, "Configuration error: {e:#}"),
+            Error::Other(e) => write!(f, "{e:#}"),
+        }
+    }
+}
+
+/// Errors not explicitly classified, like driver errors of the LED or the
+/// HTTP server. Like `anyhow::Error`, `Error` does not implement
+/// `std::error::Error` so that this blanket conversion is allowed.
+impl<E> From<E> for Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn from(e: E) -> Self {
+        Self::Other(e.into())
+    }
+}
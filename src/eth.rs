@@ -0,0 +1,63 @@
+use std::ffi::CString;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use esp_idf_svc::{
+    eth::{AsyncEth, EspEth, EthDriver, SpiEth, SpiEthChipset},
+    eventloop::EspSystemEventLoop,
+    hal::{gpio::AnyIOPin, spi::SpiDriver, units::Hertz},
+    handle::RawHandle,
+    sys::{self, esp},
+    timer::EspTaskTimerService,
+};
+use log::info;
+
+/// SPI clock of the W5500, which supports up to 33 MHz on short wires
+const W5500_BAUDRATE: Hertz = Hertz(20_000_000);
+
+pub type Ethernet = AsyncEth<EspEth<'static, SpiEth<Arc<SpiDriver<'static>>>>>;
+
+/// Start a W5500 module connected to the `spi` bus and wait for an address
+/// from DHCP
+pub async fn ethernet(
+    spi: Arc<SpiDriver<'static>>,
+    int: AnyIOPin,
+    cs: AnyIOPin,
+    rst: AnyIOPin,
+    hostname: &str,
+    sysloop: EspSystemEventLoop,
+    timer_service: EspTaskTimerService,
+) -> Result<Ethernet> {
+    // The W5500 has no address of its own, it gets the one reserved for
+    // Ethernet in eFuse
+    let mut mac = [0u8; 6];
+    esp!(unsafe { sys::esp_read_mac(mac.as_mut_ptr(), sys::esp_mac_type_t_ESP_MAC_ETH) })?;
+    let driver = EthDriver::new_spi(
+        spi,
+        int,
+        Some(cs),
+        Some(rst),
+        SpiEthChipset::W5500,
+        W5500_BAUDRATE,
+        Some(&mac),
+        None,
+        sysloop.clone(),
+    )
+    .context("W5500 not found")?;
+    let eth = EspEth::wrap(driver)?;
+    // Sent with the DHCP requests, ESP-IDF keeps a copy
+    let hostname = CString::new(hostname)?;
+    esp!(unsafe { sys::esp_netif_set_hostname(eth.netif().handle(), hostname.as_ptr()) })?;
+    let mut eth = AsyncEth::wrap(eth, sysloop, timer_service)?;
+
+    eth.start().await?;
+    info!("Ethernet started");
+
+    eth.wait_connected().await.context("No Ethernet link")?;
+    info!("Ethernet link up");
+
+    eth.wait_netif_up().await?;
+    info!("Ethernet netif up");
+
+    Ok(eth)
+}
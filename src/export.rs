@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use chrono::DateTime;
+
+use crate::history::Sample;
+use crate::reading;
+
+/// Samples copied from the history at once, written as one chunk
+pub const BATCH_LEN: usize = 64;
+
+/// Format of `/api/export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// As the SD card logs: `timestamp,pm25,pm10`
+    Csv,
+    /// Array of the same rows
+    Json,
+}
+
+impl Format {
+    /// CSV when `None`
+    pub fn parse(format: Option<&str>) -> Result<Self> {
+        match format {
+            None | Some("csv") => Ok(Self::Csv),
+            Some("json") => Ok(Self::Json),
+            Some(format) => bail!("Invalid format {format}, expected csv or json"),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Json => "application/json",
+        }
+    }
+
+    pub fn header(self) -> &'static str {
+        match self {
+            Self::Csv => "timestamp,pm25,pm10\n",
+            Self::Json => "[",
+        }
+    }
+
+    /// Row of `sample`, the `first` JSON one without separator
+    pub fn row(self, sample: &Sample, first: bool) -> String {
+        let timestamp = DateTime::from_timestamp(sample.timestamp.into(), 0)
+            .unwrap_or_default()
+            .format("%Y-%m-%dT%H:%M:%SZ");
+        let pm25 = reading::from_tenths(sample.pm25);
+        let pm10 = reading::from_tenths(sample.pm10);
+        match self {
+            Self::Csv => format!("{timestamp},{pm25},{pm10}\n"),
+            Self::Json => format!(
+                r#"{}{{"timestamp":"{timestamp}","pm25":{pm25},"pm10":{pm10}}}"#,
+                if first { "" } else { "," }
+            ),
+        }
+    }
+
+    pub fn footer(self) -> &'static str {
+        match self {
+            Self::Csv => "",
+            Self::Json => "]",
+        }
+    }
+}
+
+/// Parameters of `/api/export`
+#[derive(Debug, Clone, Copy)]
+pub struct Query {
+    pub format: Format,
+    /// Unix timestamps, inclusive
+    pub from: u32,
+    pub to: u32,
+}
+
+impl Query {
+    /// The whole history as CSV by default
+    pub fn parse(format: Option<&str>, from: Option<&str>, to: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            format: Format::parse(format)?,
+            from: from.map_or(Ok(0), parse_time)?,
+            to: to.map_or(Ok(u32::MAX), parse_time)?,
+        })
+    }
+}
+
+/// Bound of the exported range, as a Unix timestamp or an RFC 3339 date,
+/// e.g. `2024-05-01T00:00:00Z`, with its colons URL-encoded or not
+fn parse_time(time: &str) -> Result<u32> {
+    if let Ok(timestamp) = time.parse() {
+        return Ok(timestamp);
+    }
+    let time = time.replace("%3A", ":").replace("%3a", ":");
+    match DateTime::parse_from_rfc3339(&time) {
+        Ok(time) => Ok(u32::try_from(time.timestamp()).unwrap_or_default()),
+        Err(_) => bail!("Invalid time {time}, expected a Unix timestamp or an RFC 3339 date"),
+    }
+}
+
+/// Next samples to export after the `after` timestamp and up to `to`, at
+/// most [`BATCH_LEN`]. Those taken before the clock was synchronized are
+/// never exported, their time is unknown.
+pub fn batch<'a>(samples: impl Iterator<Item = &'a Sample>, after: u32, to: u32) -> Vec<Sample> {
+    samples
+        .filter(|sample| sample.timestamp > after && sample.timestamp <= to)
+        .take(BATCH_LEN)
+        .copied()
+        .collect()
+}
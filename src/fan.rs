@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Point of the fan curve
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    /// µg/m³
+    pub pm25: f32,
+    /// Percent
+    pub duty: u8,
+}
+
+/// Duty cycle of the fan as a function of PM2.5, linear between the points
+/// and flat outside of them
+#[derive(Debug, Clone)]
+pub struct Curve {
+    /// Sorted by `pm25`, not empty
+    pub points: Vec<CurvePoint>,
+    /// Percent, the fan never runs slower
+    pub min_duty: u8,
+}
+
+impl Curve {
+    pub fn duty(&self, pm25: f32) -> u8 {
+        let duty = match self.points.iter().position(|point| point.pm25 > pm25) {
+            Some(0) => f32::from(self.points[0].duty),
+            Some(i) => {
+                let (low, high) = (self.points[i - 1], self.points[i]);
+                let ratio = (pm25 - low.pm25) / (high.pm25 - low.pm25);
+                f32::from(low.duty) + ratio * (f32::from(high.duty) - f32::from(low.duty))
+            }
+            None => self
+                .points
+                .last()
+                .map_or(0.0, |point| f32::from(point.duty)),
+        };
+        (duty.round() as u8).clamp(self.min_duty, 100)
+    }
+}
+
+/// Speed of a PWM fan following PM2.5, or at full speed for a while when
+/// boosted
+#[derive(Debug)]
+pub struct Fan {
+    curve: Curve,
+    boost: Duration,
+    pm25: Option<f32>,
+    boost_until: Option<Instant>,
+}
+
+impl Fan {
+    pub fn new(curve: Curve, boost: Duration) -> Self {
+        Self {
+            curve,
+            boost,
+            pm25: None,
+            boost_until: None,
+        }
+    }
+
+    pub fn set_pm25(&mut self, pm25: f32) {
+        self.pm25 = Some(pm25);
+    }
+
+    /// Start a boost, or end it early
+    pub fn set_boost(&mut self, on: bool, now: Instant) {
+        self.boost_until = on.then(|| now + self.boost);
+    }
+
+    pub fn boost_remaining(&self, now: Instant) -> Option<Duration> {
+        self.boost_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Percent, the minimum until PM2.5 is known
+    pub fn duty(&self, now: Instant) -> u8 {
+        if self.boost_remaining(now).is_some() {
+            return 100;
+        }
+        match self.pm25 {
+            Some(pm25) => self.curve.duty(pm25),
+            None => self.curve.min_duty,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve() -> Curve {
+        Curve {
+            points: vec![
+                CurvePoint {
+                    pm25: 10.0,
+                    duty: 20,
+                },
+                CurvePoint {
+                    pm25: 50.0,
+                    duty: 100,
+                },
+            ],
+            min_duty: 30,
+        }
+    }
+
+    #[test]
+    fn curve_duty() {
+        let curve = curve();
+        assert_eq!(curve.duty(0.0), 30);
+        assert_eq!(curve.duty(30.0), 60);
+        assert_eq!(curve.duty(45.0), 90);
+        assert_eq!(curve.duty(80.0), 100);
+    }
+
+    #[test]
+    fn boost() {
+        let now = Instant::now();
+        let mut fan = Fan::new(curve(), Duration::from_secs(600));
+        assert_eq!(fan.duty(now), 30);
+        fan.set_pm25(30.0);
+        assert_eq!(fan.duty(now), 60);
+        fan.set_boost(true, now);
+        assert_eq!(fan.duty(now), 100);
+        let later = now + Duration::from_secs(420);
+        assert_eq!(fan.boost_remaining(later), Some(Duration::from_secs(180)));
+        let ended = now + Duration::from_secs(600);
+        assert_eq!(fan.boost_remaining(ended), None);
+        assert_eq!(fan.duty(ended), 60);
+        fan.set_boost(true, now);
+        fan.set_boost(false, now);
+        assert_eq!(fan.duty(now), 60);
+    }
+}
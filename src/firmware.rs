@@ -0,0 +1,5642 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use log::LevelFilter;
+
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::cpu;
+use esp_idf_svc::hal::delay::Ets;
+use esp_idf_svc::hal::gpio::{AnyIOPin, InputOutput, Output, PinDriver};
+use esp_idf_svc::hal::i2c::{I2cConfig, I2cDriver};
+use esp_idf_svc::hal::interrupt;
+use esp_idf_svc::hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver};
+use esp_idf_svc::hal::prelude::Peripherals;
+use esp_idf_svc::hal::reset::restart;
+use esp_idf_svc::hal::spi::{Dma, SpiDriver, SpiDriverConfig};
+use esp_idf_svc::hal::task::block_on;
+use esp_idf_svc::hal::uart::{self, AsyncUartDriver, UartDriver};
+use esp_idf_svc::hal::units::Hertz;
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Write;
+use esp_idf_svc::mqtt::client::{
+    EspAsyncMqttClient, EventPayload, LwtConfiguration, MqttClientConfiguration, QoS,
+};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sntp::EspSntp;
+use esp_idf_svc::sys::{esp_random, EspError};
+use esp_idf_svc::timer::{EspAsyncTimer, EspTaskTimerService};
+use esp_idf_svc::wifi::{AsyncWifi, EspWifi};
+use macaddr::MacAddr;
+use serde::{Deserialize, Serialize};
+use ws2812_esp32_rmt_driver::Ws2812Esp32Rmt;
+
+use crate::alarm::Alarm;
+use crate::aqi::{self, Band};
+use crate::board::{self, Board, NetworkKind};
+use crate::build_info::BuildInfo;
+use crate::calibration::{self, Calibration};
+use crate::cayenne;
+use crate::clock;
+use crate::co2::{Co2Command, Co2Kind, Co2Sensor};
+use crate::coap;
+use crate::config::{ConfigStore, Settings, SettingsSummary, SharedConfigStore, CONFIG};
+use crate::connectivity::{self, Connectivity, Uplink};
+#[cfg(not(esp32))]
+use crate::console::{self, Command};
+use crate::counters::{self, Counter, Counters};
+use crate::dht22::{self, Dht22};
+use crate::epaper::{self, Screen, Ssd1680};
+use crate::error::{Error, Result};
+use crate::eth::{self, Ethernet};
+use crate::export::{self, Query};
+use crate::fan::Fan;
+use crate::history::{History, Sample, Tier};
+use crate::https::CertStore;
+use crate::i18n::{self, Language, Strings};
+use crate::i2c_bus::{self, Device, SharedBus};
+use crate::identity::{Identity, IdentityStore, SharedIdentityStore};
+use crate::image;
+use crate::improv;
+use crate::influx;
+use crate::led::{
+    self, level_color, GpioLed, LedKind, RgbLed, StatusIndicator, BLACK, BLUE, GREEN, ORANGE, RED,
+    WHITE,
+};
+use crate::loglevel::{self, Module};
+use crate::lorawan::{self, Keys};
+use crate::modbus::{self, Register};
+use crate::mqtt::{DataKind, DomoticzDevice};
+use crate::ntfy::{self, Notification, Notifier};
+use crate::openapi::{self, Endpoint};
+use crate::peers::{self, Peer, PeerMeasurement};
+use crate::pipeline::{self, Pipeline, Stage, StageOutput, Trace};
+use crate::power::{self, Awake, NoLightSleep};
+use crate::reading::{self, Encoding, Kind, Reading};
+use crate::recovery::{self, CrashCounter};
+use crate::relay::{self, Relay};
+use crate::resources::{self, Memory, TaskStack};
+use crate::retained::{self, Retained};
+use crate::schedule::{Maintenance, Period, Schedule};
+#[cfg(feature = "sdcard")]
+use crate::sdlog;
+use crate::segment::{self, Max7219, Page, SegmentKind, Segments, Tm1637};
+use crate::sensor::{Measurement, SensorCommand, SensorInfo, SensorKind, UartStats};
+use crate::shutdown::{self, Participant};
+use crate::sim::FakeSds011;
+use crate::sink::{Dispatcher, MeasurementSink, SinkKind};
+use crate::smtp::{self, Email};
+use crate::snmp::{self, Value};
+use crate::stats::{self, Exceedance, LimitAlerts, LimitExceeded, Limits, Rollover, Stats};
+use crate::subsystem::{self, Mismatch, Subsystem, Subsystems};
+use crate::sx1276::Sx1276;
+use crate::task::{self, Task};
+use crate::telegram::{self, Bot, Command as BotCommand};
+use crate::trend::{Direction, Trend};
+#[cfg(not(esp32))]
+use crate::usb_console::{Input, UsbConsole};
+use crate::voc::{self, BaselineStore, Compensation, VocKind, VocSensor};
+use crate::webhook;
+use crate::wifi::{self, wifi, Eap, WifiStats};
+#[cfg(feature = "zigbee")]
+use crate::zigbee;
+use crate::{http, https, mqtt, portal, storage, ws};
+
+/// How often the measurement history is written to flash
+const HISTORY_SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const BLINK_INTERVAL: Duration = Duration::from_secs(5);
+/// Slots of the web server for its handlers, the default being 32:
+/// `start_server` logs how many it registered, and fails naming the first
+/// handler left without a slot
+const MAX_URI_HANDLERS: usize = 48;
+/// Messages queued within this delay are published together
+const MQTT_BATCH_WINDOW: Duration = Duration::from_secs(1);
+/// Consecutive failed connections to an MQTT broker before switching to the
+/// other one, the client retrying every 10 seconds
+const MQTT_FAILOVER_ATTEMPTS: u32 = 3;
+/// Time on the secondary MQTT broker before trying the primary one again
+const MQTT_PRIMARY_RETRY: Duration = Duration::from_secs(10 * 60);
+/// Measurements of the history sent again to InfluxDB with each new one
+/// after an outage, not to overflow the UDP send buffers
+const INFLUX_REPLAY_MAX: usize = 32;
+/// Between two heartbeats on the fleet topic
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// Upper bound of the time taken by a measurement, fan warmup included
+const MEASURE_DURATION: Duration = Duration::from_secs(60);
+/// Management commands waiting for a sensor
+const COMMAND_QUEUE_LEN: usize = 2;
+/// Device ID of the simulated SDS011
+const SIMULATED_SENSOR_ID: u16 = 0x5151;
+/// Failed measurements in a row after which the sensor and its serial line
+/// are initialized again
+const SENSOR_REINIT_FAILURES: u32 = 3;
+/// Of the red flashes of an error code, and the pause between two codes
+const ERROR_FLASH: Duration = Duration::from_millis(200);
+const ERROR_GAP: Duration = Duration::from_millis(300);
+const ERROR_PAUSE: Duration = Duration::from_millis(1500);
+/// White flashes of `POST /api/identify`
+const IDENTIFY_BLINKS: usize = 10;
+/// Brightness steps of the LED when PM2.5 rises fast, out of 255
+const PULSE_LEVELS: [u8; 9] = [32, 96, 160, 224, 255, 224, 160, 96, 32];
+/// PWM frequency of the RGB status LED, flicker free
+const LED_FREQUENCY: Hertz = Hertz(1000);
+/// Flashes of the single color LED at the lowest level, one more per level
+const LED_CODE_FLASH: Duration = Duration::from_millis(50);
+const LED_CODE_GAP: Duration = Duration::from_millis(250);
+/// Flash of the single color LED when the measurement is stale
+const LED_STALE_FLASH: Duration = Duration::from_millis(500);
+/// PWM frequency of 4-pin PC fans
+const FAN_FREQUENCY: Hertz = Hertz(25_000);
+/// How often the end of a fan boost is checked
+const FAN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Resonant frequency of common piezo buzzers
+const BUZZER_FREQUENCY: Hertz = Hertz(2700);
+/// Beeps each time the buzzer sounds
+const BUZZER_BEEPS: usize = 5;
+const BUZZER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// The VOC index algorithm expects one sample per second
+const VOC_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the baseline of the VOC sensor is saved to NVS
+const VOC_BASELINE_SAVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often the free heap is checked
+const MONITOR_INTERVAL: Duration = Duration::from_secs(10);
+/// Free heap under which the HTTP server is restarted, releasing the
+/// buffers of its connections
+const LOW_HEAP_THRESHOLD: u32 = 24 * 1024;
+/// Of the reload of the dashboard, for the screens left on it
+const DASHBOARD_REFRESH: Duration = Duration::from_secs(60);
+/// Minimum delay between two restarts of the HTTP server
+const HTTP_RESTART_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+/// Delay between two rounds of polling of the other stations
+const PEERS_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Fetches the uplink check URL, blocking on HTTP
+const UPLINK_TASK: Task = Task {
+    name: c"uplink",
+    stack_size: 8 * 1024,
+};
+const UPLINK_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// mDNS queries and HTTP requests
+const PEERS_TASK: Task = Task {
+    name: c"peers",
+    stack_size: 8 * 1024,
+};
+/// Serves CoAP, blocking on its socket
+const COAP_TASK: Task = Task {
+    name: c"coap",
+    stack_size: 6 * 1024,
+};
+/// Timeout of the CoAP socket, after which a new measurement is notified
+const COAP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Serves Modbus TCP, blocking on its socket
+const MODBUS_TASK: Task = Task {
+    name: c"modbus",
+    stack_size: 6 * 1024,
+};
+/// A Modbus client silent for longer is disconnected, for the next one
+const MODBUS_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Answers SNMP, blocking on its socket
+const SNMP_TASK: Task = Task {
+    name: c"snmp",
+    stack_size: 8 * 1024,
+};
+/// Joins the LoRaWAN network and sends the uplinks, blocking on the radio
+const LORAWAN_TASK: Task = Task {
+    name: c"lorawan",
+    stack_size: 6 * 1024,
+};
+/// Posts the measurements to the webhook, blocking on HTTP
+const WEBHOOK_TASK: Task = Task {
+    name: c"webhook",
+    stack_size: 8 * 1024,
+};
+/// Measurements waiting for the webhook task, the next ones being refused
+/// while it is stuck
+const WEBHOOK_QUEUE_LEN: usize = 2;
+/// Publishes the notifications, blocking on HTTPS
+const NTFY_TASK: Task = Task {
+    name: c"ntfy",
+    stack_size: 8 * 1024,
+};
+/// Notifications waiting to be published, newer ones are dropped
+const NTFY_QUEUE_LEN: usize = 2;
+/// Sends the emails, blocking on the SMTP dialogue
+const SMTP_TASK: Task = Task {
+    name: c"smtp",
+    stack_size: 10 * 1024,
+};
+/// Emails waiting to be sent, newer ones are dropped
+const SMTP_QUEUE_LEN: usize = 4;
+/// Long polls the Telegram bot updates
+const TELEGRAM_TASK: Task = Task {
+    name: c"telegram",
+    stack_size: 10 * 1024,
+};
+/// Sends the Telegram alerts, while the other task waits for the updates
+const TELEGRAM_ALERTS_TASK: Task = Task {
+    name: c"telegram_alerts",
+    stack_size: 10 * 1024,
+};
+/// Alerts waiting to be sent, newer ones are dropped
+const TELEGRAM_QUEUE_LEN: usize = 4;
+/// After a failed polling
+const TELEGRAM_RETRY: Duration = Duration::from_secs(30);
+/// Between two join requests, or longer for the duty cycle
+const LORAWAN_JOIN_RETRY: Duration = Duration::from_secs(30);
+/// The receive windows open earlier, for the delays of the polling
+const LORAWAN_RX_MARGIN: Duration = Duration::from_millis(20);
+// CayenneLPP channels of the uplinks
+const LPP_PM25: u8 = 1;
+const LPP_PM10: u8 = 2;
+const LPP_TEMPERATURE: u8 = 3;
+const LPP_HUMIDITY: u8 = 4;
+const LPP_CO2: u8 = 5;
+const LPP_VOC_INDEX: u8 = 6;
+/// Redraws the e-paper display, blocking on its busy pin
+const EPAPER_TASK: Task = Task {
+    name: c"epaper",
+    stack_size: 6 * 1024,
+};
+/// Cycles the values on the 7-segment display, bit-banging its pins
+const SEGMENT_TASK: Task = Task {
+    name: c"segment",
+    stack_size: 4 * 1024,
+};
+/// Runs the Zigbee stack
+#[cfg(feature = "zigbee")]
+const ZIGBEE_TASK: Task = Task {
+    name: c"zigbee",
+    stack_size: 6 * 1024,
+};
+/// Of the main task on dual-core chips, above the HTTP server (5) which
+/// may run on its core, below the Wi-Fi (23) and lwIP (18) tasks on the
+/// other one
+const MAIN_TASK_PRIORITY: u32 = 10;
+/// Serial console, run in its own thread as it blocks on the USB port
+#[cfg(not(esp32))]
+const CONSOLE_TASK: Task = Task {
+    name: c"console",
+    stack_size: 8 * 1024,
+};
+/// Joining another network received over Improv Wi-Fi, the station may
+/// connect then wait for DHCP
+#[cfg(not(esp32))]
+const PROVISION_TIMEOUT: Duration = Duration::from_secs(45);
+
+type Sensor = crate::sensor::Sensor<SensorSerial>;
+type Co2 = Co2Sensor<AsyncUartDriver<'static, UartDriver<'static>>>;
+type I2cBus = SharedBus<I2cDriver<'static>>;
+type Voc = VocSensor<I2cBus>;
+type Dht = Dht22<PinDriver<'static, AnyIOPin, InputOutput>>;
+
+/// Serial line of a particle sensor, or the SDS011 simulated by the
+/// `simulate` setting
+enum SensorSerial {
+    Uart(AsyncUartDriver<'static, UartDriver<'static>>),
+    Simulated(FakeSds011),
+}
+
+impl SensorSerial {
+    /// Discard what the UART received, nothing to do for the simulation
+    fn clear_rx(&self) -> core::result::Result<(), EspError> {
+        match self {
+            Self::Uart(uart) => uart.driver().clear_rx(),
+            Self::Simulated(_) => Ok(()),
+        }
+    }
+}
+
+impl embedded_io_async::ErrorType for SensorSerial {
+    type Error = EspError;
+}
+
+impl embedded_io_async::Read for SensorSerial {
+    async fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, EspError> {
+        match self {
+            Self::Uart(uart) => embedded_io_async::Read::read(uart, buf).await,
+            Self::Simulated(sds011) => embedded_io_async::Read::read(sds011, buf)
+                .await
+                .map_err(|e| match e {}),
+        }
+    }
+}
+
+impl embedded_io_async::Write for SensorSerial {
+    async fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, EspError> {
+        match self {
+            Self::Uart(uart) => embedded_io_async::Write::write(uart, buf).await,
+            Self::Simulated(sds011) => embedded_io_async::Write::write(sds011, buf)
+                .await
+                .map_err(|e| match e {}),
+        }
+    }
+
+    async fn flush(&mut self) -> core::result::Result<(), EspError> {
+        match self {
+            Self::Uart(uart) => embedded_io_async::Write::flush(uart).await,
+            Self::Simulated(sds011) => embedded_io_async::Write::flush(sds011)
+                .await
+                .map_err(|e| match e {}),
+        }
+    }
+}
+
+/// Interface selected by `network` in `cfg.toml`
+enum Network {
+    Wifi(AsyncWifi<EspWifi<'static>>),
+    Ethernet(Ethernet),
+}
+
+impl Network {
+    fn ip(&self) -> anyhow::Result<Ipv4Addr> {
+        let ip_info = match self {
+            Self::Wifi(wifi) => wifi.wifi().sta_netif().get_ip_info()?,
+            Self::Ethernet(eth) => eth.eth().netif().get_ip_info()?,
+        };
+        Ok(ip_info.ip)
+    }
+}
+
+/// State shared between the tasks and the HTTP handlers
+struct Shared {
+    /// Host name on the network and MQTT client ID
+    hostname: String,
+    /// Friendly name of the station, none when empty
+    name: String,
+    /// Of the Wi-Fi station, whichever the network
+    mac: String,
+    /// Empty until provisioned
+    identity: Identity,
+    sensors: Mutex<Vec<SensorInfo>>,
+    /// Serial line errors of each sensor
+    uart: Mutex<Vec<UartStats>>,
+    /// Management commands of each sensor, run by its measurement task
+    commands: Vec<Channel<CriticalSectionRawMutex, SensorCommand, COMMAND_QUEUE_LEN>>,
+    /// Raised when a management command changed a sensor
+    sensors_changed: Signal<CriticalSectionRawMutex, ()>,
+    /// Raised to measure each sensor without waiting for the interval
+    measure_now: Vec<Signal<CriticalSectionRawMutex, ()>>,
+    /// Of each sensor, until its first measurements are discarded
+    warming_up: Vec<AtomicBool>,
+    /// Raised to blink the LED white, to find the device
+    identify: Signal<CriticalSectionRawMutex, ()>,
+    /// Of each module, in the order of `Module::ALL`
+    log_levels: Mutex<[LevelFilter; 4]>,
+    /// Of the dashboard, `None` until connected
+    url: Mutex<Option<String>>,
+    /// Wi-Fi network received over Improv, joined by the blink task
+    provision: Signal<CriticalSectionRawMutex, (String, String)>,
+    /// Raised by the blink task with the new address, or why the network
+    /// could not be joined
+    provisioned: Signal<CriticalSectionRawMutex, Result<Ipv4Addr, String>>,
+    /// Dashboards following the events on `/ws`
+    ws_clients: Arc<ws::Clients>,
+    /// Held while the sensors answer on their UART, `None` without light
+    /// sleep
+    no_light_sleep: Option<NoLightSleep>,
+    /// Other stations, polled when aggregating
+    peers: Mutex<Vec<Peer>>,
+    wifi: Mutex<WifiStats>,
+    /// Applied to the sensors, can be changed on MQTT
+    calibration: Mutex<Calibration>,
+    counters: Counters,
+    /// Associated to the access point, or Ethernet link up
+    link_up: AtomicBool,
+    /// Of the last uplink check, `None` until done or without a check URL
+    internet: Mutex<Option<bool>>,
+    mqtt_connected: AtomicBool,
+    /// Unix timestamp of the last measurement published to MQTT, those of
+    /// the history taken since are replayed on the next connection
+    mqtt_delivered: AtomicU32,
+    /// `None` while MQTT is disabled
+    mqtt_broker: Mutex<Option<mqtt::Broker>>,
+    /// Average of the sensors
+    measurement: Mutex<Option<Latest>>,
+    /// Age after which the measurement is stale
+    max_age: Duration,
+    readings: Mutex<Readings>,
+    /// Turns the values of the sensors into their average, locked after
+    /// `readings`
+    pipeline: Mutex<Pipeline>,
+    history: Mutex<History>,
+    /// Summaries of the periods which just ended, waiting to be published
+    stats: Mutex<Vec<Stats>>,
+    /// Time above the limits today
+    exceedance: Mutex<Exceedance>,
+    /// Limits exceeded, waiting to be published
+    limit_events: Mutex<Vec<LimitExceeded>>,
+    /// Of PM2.5, `None` until there are enough recent samples
+    trend: Mutex<Option<Trend>>,
+    /// `None` without a relay pin
+    relay: Option<Mutex<RelayOutput>>,
+    /// `None` without a fan pin
+    fan: Option<Mutex<Fan>>,
+    /// `None` without a CO2 sensor
+    co2_kind: Option<Co2Kind>,
+    /// Last CO2 value in ppm, `None` when the last read failed
+    co2: Mutex<Option<u16>>,
+    /// Calibration commands of the CO2 sensor, run by its task
+    co2_commands: Channel<CriticalSectionRawMutex, Co2Command, COMMAND_QUEUE_LEN>,
+    /// `None` without a VOC sensor
+    voc_kind: Option<VocKind>,
+    /// VOC index or TVOC, `None` while the sensor warms up or when the last
+    /// read failed
+    voc: Mutex<Option<u16>>,
+    /// `None` when the VOC sensor is disabled or its pins are in use
+    i2c: Option<I2cBus>,
+    /// Found by the last scan of the I2C bus
+    i2c_devices: Mutex<Vec<Device>>,
+    /// Quiet and boost hours
+    schedule: Schedule,
+    /// Subsystems enabled in the settings
+    enabled: Subsystems,
+    /// Subsystems wired in `cfg.toml`, or found on the I2C bus for the VOC
+    /// sensor
+    wired: Subsystems,
+    /// `None` without a DHT22, holds `None` when its last read failed
+    climate: Option<Mutex<Option<Compensation>>>,
+    /// Raised when PM2.5 or the boost changed, awaited by the fan task
+    fan_changed: Signal<CriticalSectionRawMutex, ()>,
+    /// Raised when the relay or fan changed, awaited by the MQTT task
+    outputs_changed: Signal<CriticalSectionRawMutex, ()>,
+    /// Raised once all the sensors have been measured, awaited by the
+    /// LoRaWAN task
+    new_measurement: Signal<CriticalSectionRawMutex, ()>,
+    /// Measurements handed to the MQTT sink, published by the MQTT task.
+    /// Only the latest one is kept while it is busy.
+    mqtt_measurements: Signal<CriticalSectionRawMutex, Latest>,
+    /// Raised with each measurement, awaited by the e-paper task
+    display_changed: Signal<CriticalSectionRawMutex, ()>,
+    /// Changes of AQI band, published by the ntfy task
+    notifications: Channel<CriticalSectionRawMutex, Notification, NTFY_QUEUE_LEN>,
+    /// JSON bodies of the measurements, posted by the webhook task
+    webhooks: Channel<CriticalSectionRawMutex, Vec<u8>, WEBHOOK_QUEUE_LEN>,
+    /// Alerts, sent by the SMTP task
+    emails: Channel<CriticalSectionRawMutex, Email, SMTP_QUEUE_LEN>,
+    /// Alerts, sent by the Telegram alerts task
+    telegram: Channel<CriticalSectionRawMutex, String, TELEGRAM_QUEUE_LEN>,
+}
+
+/// Relay and the GPIO driving it
+struct RelayOutput {
+    relay: Relay,
+    pin: PinDriver<'static, AnyIOPin, Output>,
+}
+
+/// Average of the sensors and when it was measured
+#[derive(Debug, Clone, Copy)]
+struct Latest {
+    /// Out of the pipeline
+    vals: Measurement,
+    /// Average of the sensors as measured, `None` when restored from before
+    /// the restart
+    raw: Option<Measurement>,
+    /// Uptime when the last frame was parsed, `None` when restored from
+    /// before the restart
+    measured: Option<Duration>,
+    /// `None` if the clock was not synchronized
+    measured_at: Option<DateTime<Utc>>,
+}
+
+impl Latest {
+    /// `sampled` when the last of the averaged frames was parsed, not when
+    /// published, so that measurements sent late stay in order
+    fn new(raw: Measurement, vals: Measurement, sampled: clock::Timestamp) -> Self {
+        Self {
+            vals,
+            raw: Some(raw),
+            measured: Some(sampled.uptime),
+            measured_at: sampled.wall,
+        }
+    }
+
+    /// `None` if unknown, for a measurement restored without a valid clock
+    fn age(&self) -> Option<Duration> {
+        match self.measured {
+            Some(measured) => Some(clock::uptime().saturating_sub(measured)),
+            None => (clock::now()? - self.measured_at?).to_std().ok(),
+        }
+    }
+
+    fn readings(&self) -> [Reading; 2] {
+        self.vals.readings(None, self.measured_at)
+    }
+
+    /// A restored measurement stays stale until the sensors are read again
+    fn is_stale(&self, max_age: Duration) -> bool {
+        self.measured.is_none() || self.age().map_or(true, |age| age > max_age)
+    }
+}
+
+impl From<Retained> for Latest {
+    fn from(retained: Retained) -> Self {
+        Self {
+            vals: retained.vals,
+            raw: None,
+            measured: None,
+            measured_at: retained.measured_at,
+        }
+    }
+}
+
+/// Last measurement of each sensor
+struct Readings {
+    /// `None` if the sensor failed
+    last: Vec<Option<Measurement>>,
+    /// When the frame of `last` was parsed
+    sampled: Vec<Option<clock::Timestamp>>,
+    /// Sensors measured since the last average
+    reported: Vec<bool>,
+}
+
+impl Shared {
+    /// Out of light sleep until dropped
+    fn stay_awake(&self) -> Option<Awake<'_>> {
+        self.no_light_sleep.as_ref().map(NoLightSleep::acquire)
+    }
+
+    /// Friendly name, or host name
+    fn station(&self) -> &str {
+        if self.name.is_empty() {
+            &self.hostname
+        } else {
+            &self.name
+        }
+    }
+
+    /// Last values of the CO2, VOC and climate sensors
+    fn other_readings(&self) -> Vec<Reading> {
+        let mut others = Vec::new();
+        if let Some(ppm) = *self.co2.lock().unwrap() {
+            others.push(Reading::new(Kind::Co2, f32::from(ppm), None, clock::now()));
+        }
+        if let (Some(kind), Some(value)) = (self.voc_kind, *self.voc.lock().unwrap()) {
+            others.push(Reading::new(
+                kind.reading_kind(),
+                f32::from(value),
+                None,
+                clock::now(),
+            ));
+        }
+        if let Some(climate) = self.climate() {
+            others.extend([
+                Reading::new(Kind::Temperature, climate.temperature, None, clock::now()),
+                Reading::new(Kind::Humidity, climate.humidity, None, clock::now()),
+            ]);
+        }
+        others
+    }
+
+    /// How far the network reaches, the broker counting while MQTT is
+    /// enabled
+    fn uplink(&self) -> Uplink {
+        Uplink {
+            link: self.link_up.load(Ordering::Relaxed),
+            broker: self
+                .mqtt_broker
+                .lock()
+                .unwrap()
+                .map(|_| self.mqtt_connected.load(Ordering::Relaxed)),
+            internet: *self.internet.lock().unwrap(),
+        }
+    }
+
+    /// Period of the schedule right now
+    fn period(&self) -> Period {
+        self.schedule.period(clock::now())
+    }
+
+    /// Nothing is reported until all the sensors warmed up
+    fn is_warming_up(&self) -> bool {
+        self.warming_up
+            .iter()
+            .any(|warming_up| warming_up.load(Ordering::Relaxed))
+    }
+
+    /// Delay before measuring again, shorter while boosted
+    fn measure_interval(&self, interval: Duration) -> Duration {
+        self.schedule.measure_interval(self.period(), interval)
+    }
+
+    /// Change the relay, when there is one, and drive its pin if `update`
+    /// returns true
+    fn update_relay(&self, update: impl FnOnce(&mut Relay) -> bool) -> anyhow::Result<()> {
+        let Some(output) = &self.relay else {
+            return Ok(());
+        };
+        let mut output = output.lock().unwrap();
+        if update(&mut output.relay) {
+            let on = output.relay.on;
+            output.pin.set_level(on.into())?;
+            log::info!("Relay {}", if on { "on" } else { "off" });
+        }
+        self.outputs_changed.signal(());
+        Ok(())
+    }
+
+    /// `None` without a relay
+    fn relay(&self) -> Option<Relay> {
+        self.relay
+            .as_ref()
+            .map(|output| output.lock().unwrap().relay)
+    }
+
+    /// Start or end a boost of the fan, when there is one
+    fn boost_fan(&self, on: bool) {
+        if let Some(fan) = &self.fan {
+            fan.lock().unwrap().set_boost(on, Instant::now());
+            self.fan_changed.signal(());
+        }
+    }
+
+    /// `None` without a fan
+    fn fan(&self) -> Option<FanJson> {
+        self.fan.as_ref().map(|fan| {
+            let fan = fan.lock().unwrap();
+            let now = Instant::now();
+            FanJson {
+                duty: fan.duty(now),
+                boost_remaining_seconds: fan.boost_remaining(now).map(|left| left.as_secs()),
+            }
+        })
+    }
+
+    /// Last value of the VOC sensor, `None` unless it is a `kind`
+    fn voc(&self, kind: VocKind) -> Option<u16> {
+        if self.voc_kind != Some(kind) {
+            return None;
+        }
+        *self.voc.lock().unwrap()
+    }
+
+    /// Whether a wired subsystem is there: the sensors read regularly
+    /// answered their last read, the others are trusted. Disabled ones are
+    /// not checked.
+    fn detected(&self, subsystem: Subsystem) -> bool {
+        match subsystem {
+            Subsystem::Co2 if self.co2_kind.is_some() => self.co2.lock().unwrap().is_some(),
+            Subsystem::Dht22 if self.climate.is_some() => self.climate().is_some(),
+            _ => true,
+        }
+    }
+
+    /// Last values of the DHT22, if any
+    fn climate(&self) -> Option<Compensation> {
+        self.climate
+            .as_ref()
+            .and_then(|climate| *climate.lock().unwrap())
+    }
+
+    /// Measure all the sensors without waiting for the interval
+    fn measure_now(&self) {
+        for measure_now in &self.measure_now {
+            measure_now.signal(());
+        }
+    }
+
+    /// Store the result of a sensor. Once all the sensors have reported,
+    /// returns their average through the pipeline, `None` if none of them
+    /// could measure.
+    fn report(
+        &self,
+        sensor: usize,
+        vals: Option<Measurement>,
+        sampled: clock::Timestamp,
+    ) -> Option<Latest> {
+        let mut readings = self.readings.lock().unwrap();
+        readings.last[sensor] = vals;
+        readings.sampled[sensor] = vals.map(|_| sampled);
+        readings.reported[sensor] = true;
+        if !readings.reported.iter().all(|reported| *reported) {
+            return None;
+        }
+        readings.reported.fill(false);
+        let raw = Measurement::average(readings.last.iter().flatten())?;
+        let sampled = readings
+            .sampled
+            .iter()
+            .flatten()
+            .max_by_key(|sampled| sampled.uptime)?;
+        let context = pipeline::Context {
+            calibration: *self.calibration.lock().unwrap(),
+            humidity: self.climate().map(|climate| climate.humidity),
+        };
+        let vals = self
+            .pipeline
+            .lock()
+            .unwrap()
+            .run(&readings.last, &context)?;
+        Some(Latest::new(raw, vals, *sampled))
+    }
+}
+
+pub fn main() {
+    // It is necessary to call this function once. Otherwise some patches to the runtime
+    // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
+    esp_idf_svc::sys::link_patches();
+
+    // Bind the log crate to the ESP Logging facilities
+    esp_idf_svc::log::EspLogger::initialize_default();
+    log::info!("starting app!");
+
+    // Pinned to core 1 by `sdkconfig.defaults.<mcu>`, where the sensor and
+    // LED tasks it polls preempt anything else
+    if cpu::CORES > 1 {
+        // SAFETY: the current task
+        unsafe { esp_idf_svc::sys::vTaskPrioritySet(core::ptr::null_mut(), MAIN_TASK_PRIORITY) };
+        log::info!(
+            "Main task on {:?}, priority {MAIN_TASK_PRIORITY}",
+            cpu::core()
+        );
+    }
+
+    let peripherals = Peripherals::take().unwrap();
+    let sysloop = EspSystemEventLoop::take().unwrap();
+    let nvs_partition = EspDefaultNvsPartition::take().unwrap();
+
+    let mut crash_counter = CrashCounter::new(nvs_partition.clone()).unwrap();
+    // Set by `do_main`, to tell its errors
+    let mut led = None;
+    if recovery::crashed_on_last_boot() {
+        let _ = crash_counter.record_failure();
+    }
+
+    let result = if crash_counter.failures() >= recovery::SAFE_MODE_THRESHOLD {
+        recovery::safe_mode(
+            peripherals.modem,
+            sysloop,
+            nvs_partition,
+            &mut crash_counter,
+        )
+        .map_err(Error::network)
+    } else {
+        // All the tasks run on the main thread
+        block_on(do_main(
+            peripherals,
+            sysloop,
+            nvs_partition,
+            &mut crash_counter,
+            &mut led,
+        ))
+    };
+    if let Err(e) = result {
+        log::error!("Error in do_main {e:?}");
+        let delay = recovery::restart_delay(&e, &mut crash_counter);
+        log::info!("Restarting in {delay:?}");
+        match led.as_mut() {
+            Some(led) => blink_error(led.as_mut(), e.blink_code(), delay),
+            None => std::thread::sleep(delay),
+        }
+    }
+    restart();
+}
+
+async fn do_main(
+    peripherals: Peripherals,
+    sysloop: EspSystemEventLoop,
+    nvs_partition: EspDefaultNvsPartition,
+    crash_counter: &mut CrashCounter,
+    led: &mut Option<Box<dyn StatusIndicator>>,
+) -> Result<()> {
+    let timer_service = EspTaskTimerService::new()?;
+    let board = Board::from_config().map_err(Error::Other)?;
+    log::info!("Firmware {}", BuildInfo::current());
+    log::info!("Board {board:?}");
+    let network_kind = NetworkKind::from_config(CONFIG.network).map_err(Error::Other)?;
+
+    // SAFETY: the pins come from the board configuration, each one is only
+    // taken once and `peripherals.pins` is left unused
+    let pin = |num| unsafe { AnyIOPin::new(num) };
+
+    let indicator: Box<dyn StatusIndicator> = match (board.led_kind, board.led_rgb) {
+        (LedKind::Gpio, _) => Box::new(
+            GpioLed::new(PinDriver::output(pin(board.led))?, CONFIG.led_active_low)
+                .map_err(Error::Other)?,
+        ),
+        (LedKind::Rgb, Some([red, green, blue])) => {
+            let timer = LedcTimerDriver::new(
+                peripherals.ledc.timer2,
+                &TimerConfig::new().frequency(LED_FREQUENCY),
+            )?;
+            Box::new(
+                RgbLed::new(
+                    LedcDriver::new(peripherals.ledc.channel2, &timer, pin(red))?,
+                    LedcDriver::new(peripherals.ledc.channel3, &timer, pin(green))?,
+                    LedcDriver::new(peripherals.ledc.channel4, &timer, pin(blue))?,
+                    CONFIG.led_active_low,
+                )
+                .map_err(Error::Other)?,
+            )
+        }
+        _ => Box::new(match board.led_rmt_channel {
+            0 => Ws2812Esp32Rmt::new(peripherals.rmt.channel0, pin(board.led)),
+            1 => Ws2812Esp32Rmt::new(peripherals.rmt.channel1, pin(board.led)),
+            2 => Ws2812Esp32Rmt::new(peripherals.rmt.channel2, pin(board.led)),
+            _ => Ws2812Esp32Rmt::new(peripherals.rmt.channel3, pin(board.led)),
+        }?),
+    };
+
+    let led = led.insert(indicator);
+    led.set(RED, u8::MAX).map_err(Error::Other)?;
+
+    let mut cert_store = CertStore::new(nvs_partition.clone()).map_err(Error::config)?;
+    let baseline_store = BaselineStore::new(nvs_partition.clone()).map_err(Error::config)?;
+    let identity_store = IdentityStore::new(nvs_partition.clone()).map_err(Error::config)?;
+    let identity = identity_store.load().map_err(Error::config)?;
+    if identity.is_provisioned() {
+        log::info!("Provisioned as {}", identity.device_id);
+    }
+    let identity_store = Arc::new(Mutex::new(identity_store));
+    let config_store = ConfigStore::new(nvs_partition).map_err(Error::config)?;
+    let settings = config_store.load().map_err(Error::config)?;
+    // Before any driver is set up with them, fixed in safe mode
+    settings.validate().map_err(Error::config)?;
+    clock::set_local_time(&settings.timezone, settings.utc_offset_minutes);
+
+    // Disabled subsystems are left alone, as if they were not wired
+    let enabled = settings.subsystems();
+    for subsystem in Subsystem::ALL {
+        if !enabled.get(subsystem) {
+            log::info!("The {subsystem} is disabled");
+        }
+    }
+
+    let mut buzzer = match board.buzzer.filter(|_| enabled.buzzer) {
+        Some(num) => {
+            let timer = LedcTimerDriver::new(
+                peripherals.ledc.timer0,
+                &TimerConfig::new().frequency(BUZZER_FREQUENCY),
+            )?;
+            Some(LedcDriver::new(peripherals.ledc.channel0, timer, pin(num))?)
+        }
+        None => None,
+    };
+
+    let mut fan = match board.fan.filter(|_| enabled.fan) {
+        Some(num) => {
+            let timer = LedcTimerDriver::new(
+                peripherals.ledc.timer1,
+                &TimerConfig::new().frequency(FAN_FREQUENCY),
+            )?;
+            Some(LedcDriver::new(peripherals.ledc.channel1, timer, pin(num))?)
+        }
+        None => None,
+    };
+
+    // Off until the first measurement
+    let relay = match board.relay.filter(|_| enabled.relay) {
+        Some(num) => {
+            let mut pin = PinDriver::output(pin(num))?;
+            pin.set_low()?;
+            Some(Mutex::new(RelayOutput {
+                relay: Relay::new(settings.relay_hysteresis()),
+                pin,
+            }))
+        }
+        None => None,
+    };
+
+    // Kept by ESP-IDF for the whole life of the app
+    let eap_ca_cert: Option<&'static [u8]> = config_store
+        .eap_ca_cert()
+        .map_err(Error::config)?
+        .map(|pem| &*Vec::leak(pem));
+    let config_store = Arc::new(Mutex::new(config_store));
+    let led_brightness = if settings.led_enabled {
+        settings.led_brightness
+    } else {
+        0
+    };
+
+    let sensor0_kind = SensorKind::from_config(CONFIG.sensor0).map_err(Error::Other)?;
+    let sensor1_kind = match CONFIG.sensor1 {
+        "" => None,
+        _ if !enabled.sensor1 => None,
+        model => Some(SensorKind::from_config(model).map_err(Error::Other)?),
+    };
+    let co2_kind = match CONFIG.co2_sensor {
+        "" => None,
+        _ if !enabled.co2 => None,
+        model => Some(model.parse::<Co2Kind>().map_err(Error::Other)?),
+    };
+    let voc_kind = match CONFIG.voc_sensor {
+        "" => None,
+        model => Some(VocKind::from_config(model).map_err(Error::Other)?),
+    };
+    let config = uart::config::Config::default()
+        .baudrate(Hertz(9600))
+        .stop_bits(uart::config::StopBits::STOP1)
+        .parity_none()
+        .data_bits(uart::config::DataBits::DataBits8);
+
+    let mut timer = timer_service.timer_async()?;
+    let (sensor0_kind, serial) = if settings.simulate {
+        log::warn!("Simulated sensor 0, for demos without the hardware");
+        let serial = SensorSerial::Simulated(FakeSds011::new(SIMULATED_SENSOR_ID));
+        (Some(SensorKind::Sds011), serial)
+    } else {
+        let uart = AsyncUartDriver::new(
+            peripherals.uart1,
+            pin(board.sensor0_tx),
+            pin(board.sensor0_rx),
+            Option::<AnyIOPin>::None,
+            Option::<AnyIOPin>::None,
+            &config,
+        )?;
+        (sensor0_kind, SensorSerial::Uart(uart))
+    };
+    let mut sensor0 = Sensor::init(sensor0_kind, serial, &mut timer)
+        .await
+        .map_err(Error::sensor)?;
+    log::info!("Sensor 0: {sensor0}");
+
+    // UART0 also carries the console on some boards, it is only taken when
+    // a second sensor is configured
+    let mut uart0 = Some(peripherals.uart0);
+    let mut sensor1_timer = timer_service.timer_async()?;
+    let mut sensor1 = match sensor1_kind {
+        Some(kind) => {
+            let uart = AsyncUartDriver::new(
+                uart0.take().unwrap(),
+                pin(board.sensor1_tx),
+                pin(board.sensor1_rx),
+                Option::<AnyIOPin>::None,
+                Option::<AnyIOPin>::None,
+                &config,
+            )?;
+            let sensor = Sensor::init(kind, SensorSerial::Uart(uart), &mut sensor1_timer)
+                .await
+                .map_err(Error::sensor)?;
+            log::info!("Sensor 1: {sensor}");
+            Some(sensor)
+        }
+        None => None,
+    };
+
+    // UART2 on the ESP32 and ESP32-S3, the C3 and C6 only have UART0 left
+    #[cfg(any(esp32, esp32s3))]
+    let co2_uart = Some(peripherals.uart2);
+    #[cfg(not(any(esp32, esp32s3)))]
+    let co2_uart = uart0.take();
+    let mut co2 = match (co2_kind, co2_uart) {
+        (Some(kind), Some(uart)) => {
+            let uart = AsyncUartDriver::new(
+                uart,
+                pin(board.co2_tx),
+                pin(board.co2_rx),
+                Option::<AnyIOPin>::None,
+                Option::<AnyIOPin>::None,
+                &config,
+            )?;
+            log::info!("CO2 sensor: {kind}");
+            Some(Co2::new(kind, uart))
+        }
+        (Some(_), None) => {
+            return Err(Error::config(anyhow::anyhow!(
+                "The CO2 sensor needs UART0, taken by the second particle sensor"
+            )))
+        }
+        (None, _) => None,
+    };
+    // The bus is scanned whenever nothing else is wired on its pins, and
+    // always set up for a configured VOC sensor
+    let i2c_pins_free = board.i2c_pins_free(network_kind);
+    let use_i2c = match voc_kind {
+        Some(Some(_)) => true,
+        Some(None) if !i2c_pins_free => {
+            log::warn!("I2C pins in use, no VOC sensor detection");
+            false
+        }
+        Some(None) | None => i2c_pins_free,
+    };
+    let i2c = if use_i2c {
+        Some(I2cBus::new(I2cDriver::new(
+            peripherals.i2c0,
+            pin(board.i2c_sda),
+            pin(board.i2c_scl),
+            &I2cConfig::new().baudrate(Hertz(100_000)),
+        )?))
+    } else {
+        None
+    };
+    let i2c_devices = match &i2c {
+        Some(bus) => {
+            let devices = i2c_bus::scan(&mut bus.clone());
+            for device in &devices {
+                log::info!("I2C device {device}");
+            }
+            devices
+        }
+        None => Vec::new(),
+    };
+    let voc_kind = match voc_kind {
+        Some(None) => {
+            let kind = i2c_bus::voc_kind(&i2c_devices);
+            if let Some(kind) = kind {
+                log::info!("Detected a {kind} VOC sensor");
+            }
+            kind
+        }
+        Some(kind) => kind,
+        None => None,
+    };
+    // Found but not used when disabled
+    let voc_detected = voc_kind.is_some();
+    let voc_kind = voc_kind.filter(|_| enabled.voc);
+    let mut voc = match (voc_kind, &i2c) {
+        (Some(kind), Some(bus)) => {
+            let sensor = Voc::init(kind, bus.clone(), &mut timer)
+                .await
+                .map_err(Error::sensor)?;
+            log::info!("VOC sensor: {sensor}");
+            Some(sensor)
+        }
+        _ => None,
+    };
+    let mut dht22 = match board.dht22.filter(|_| enabled.dht22) {
+        Some(num) => {
+            let sensor = Dht::new(PinDriver::input_output_od(pin(num))?).map_err(Error::sensor)?;
+            log::info!("DHT22 on GPIO{num}");
+            Some(sensor)
+        }
+        None => None,
+    };
+    let sensors: Vec<SensorInfo> = [Some(&sensor0), sensor1.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(|sensor| sensor.info())
+        .collect();
+    let sensor_count = sensors.len();
+
+    // Shared by the SD card, the e-paper display and the Ethernet or LoRa
+    // module, only set up when used
+    let epaper_pins = board.epaper.filter(|_| enabled.epaper);
+    let spi = (cfg!(feature = "sdcard") || network_kind.on_spi() || epaper_pins.is_some())
+        .then(|| {
+            SpiDriver::new(
+                peripherals.spi2,
+                pin(board.sd_sclk),
+                pin(board.sd_mosi),
+                Some(pin(board.sd_miso)),
+                &SpiDriverConfig::new().dma(Dma::Auto(4096)),
+            )
+        })
+        .transpose()?
+        .map(Arc::new);
+
+    #[cfg(feature = "sdcard")]
+    let sdcard = match sdlog::mount(spi.clone().unwrap(), pin(board.sd_cs)) {
+        Ok(sdcard) => Some(sdcard),
+        Err(e) => {
+            log::warn!("SD card not available, CSV logging disabled: {e:?}");
+            None
+        }
+    };
+    #[cfg(feature = "sdcard")]
+    let sdcard_mounted = sdcard.is_some();
+    #[cfg(not(feature = "sdcard"))]
+    let sdcard_mounted = false;
+
+    let measure_interval = Duration::from_secs(settings.measure_interval_secs.into());
+
+    let mut history = History::new(measure_interval);
+    let storage_mounted = match storage::mount() {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("Unable to mount storage, history won't survive reboots: {e:?}");
+            false
+        }
+    };
+    if storage_mounted {
+        match history.load(storage::HISTORY_PATH) {
+            Ok(()) => log::info!("Restored {} history samples", history.len()),
+            Err(e) => log::info!("No history restored: {e:?}"),
+        }
+    }
+    let restored = retained::restore();
+    if let Some(restored) = &restored {
+        log::info!("Restored last measurement: {}", restored.vals);
+    }
+    // The topics and hostname stay the same whichever the network
+    let mut mac = [0u8; 6];
+    esp_idf_svc::sys::esp!(unsafe {
+        esp_idf_svc::sys::esp_read_mac(
+            mac.as_mut_ptr(),
+            esp_idf_svc::sys::esp_mac_type_t_ESP_MAC_WIFI_STA,
+        )
+    })?;
+    let hostname = settings.hostname(mac);
+    log::info!("Hostname {hostname}");
+    http::set_title(if settings.name.is_empty() {
+        hostname.clone()
+    } else {
+        settings.name.clone()
+    });
+
+    // The W5500 would need its interrupt to wake the chip up
+    let light_sleep = settings.light_sleep && network_kind == NetworkKind::Wifi;
+    if settings.light_sleep && !light_sleep {
+        log::warn!("Light sleep is only supported on Wi-Fi");
+    }
+    let shared = Arc::new(Shared {
+        hostname: hostname.clone(),
+        name: settings.name.clone(),
+        mac: MacAddr::from(mac).to_string(),
+        identity,
+        sensors: Mutex::new(sensors),
+        uart: Mutex::new(vec![UartStats::default(); sensor_count]),
+        commands: (0..sensor_count).map(|_| Channel::new()).collect(),
+        sensors_changed: Signal::new(),
+        measure_now: (0..sensor_count).map(|_| Signal::new()).collect(),
+        warming_up: (0..sensor_count)
+            .map(|_| AtomicBool::new(settings.sensor_warmup > 0))
+            .collect(),
+        identify: Signal::new(),
+        // `CONFIG_LOG_DEFAULT_LEVEL`
+        log_levels: Mutex::new([LevelFilter::Info; 4]),
+        url: Mutex::new(None),
+        provision: Signal::new(),
+        provisioned: Signal::new(),
+        ws_clients: Arc::default(),
+        no_light_sleep: light_sleep
+            .then(|| NoLightSleep::new(c"sensors"))
+            .transpose()?,
+        peers: Mutex::new(Vec::new()),
+        wifi: Mutex::default(),
+        calibration: Mutex::new(settings.calibration()),
+        counters: Counters::default(),
+        link_up: AtomicBool::new(true),
+        internet: Mutex::new(None),
+        mqtt_connected: AtomicBool::new(false),
+        // Not to replay the history restored from flash at each start
+        mqtt_delivered: AtomicU32::new(history.last_timestamp()),
+        mqtt_broker: Mutex::new(None),
+        measurement: Mutex::new(restored.map(Latest::from)),
+        // Missed a whole measurement cycle
+        max_age: 2 * (measure_interval + MEASURE_DURATION),
+        readings: Mutex::new(Readings {
+            last: vec![None; sensor_count],
+            sampled: vec![None; sensor_count],
+            reported: vec![false; sensor_count],
+        }),
+        pipeline: Mutex::new(Pipeline::new(settings.pipeline.clone(), sensor_count)),
+        history: Mutex::new(history),
+        stats: Mutex::new(Vec::new()),
+        exceedance: Mutex::default(),
+        limit_events: Mutex::new(Vec::new()),
+        trend: Mutex::new(None),
+        relay,
+        fan: fan.as_ref().map(|_| {
+            Mutex::new(Fan::new(
+                settings.fan_curve(),
+                Duration::from_secs(u64::from(settings.fan_boost_minutes) * 60),
+            ))
+        }),
+        co2_kind,
+        co2: Mutex::new(None),
+        co2_commands: Channel::new(),
+        notifications: Channel::new(),
+        webhooks: Channel::new(),
+        emails: Channel::new(),
+        telegram: Channel::new(),
+        voc_kind,
+        voc: Mutex::new(None),
+        i2c,
+        i2c_devices: Mutex::new(i2c_devices),
+        climate: dht22.as_ref().map(|_| Mutex::new(None)),
+        schedule: settings.schedule(),
+        enabled,
+        wired: Subsystems {
+            sensor1: !CONFIG.sensor1.is_empty(),
+            co2: !CONFIG.co2_sensor.is_empty(),
+            voc: voc_detected,
+            dht22: board.dht22.is_some(),
+            buzzer: board.buzzer.is_some(),
+            relay: board.relay.is_some(),
+            fan: board.fan.is_some(),
+            epaper: board.epaper.is_some(),
+            segment: board.segment.is_some(),
+        },
+        fan_changed: Signal::new(),
+        outputs_changed: Signal::new(),
+        new_measurement: Signal::new(),
+        mqtt_measurements: Signal::new(),
+        display_changed: Signal::new(),
+    });
+
+    let display = epaper_pins.is_some();
+    if let Some(pins) = epaper_pins {
+        let display = Ssd1680::new(
+            spi.clone().unwrap(),
+            pin(pins.cs),
+            pin(pins.dc),
+            pin(pins.rst),
+            pin(pins.busy),
+        )?;
+        let timer = timer_service.timer_async()?;
+        let shared = shared.clone();
+        EPAPER_TASK.spawn(move || epaper_updates(display, timer, &shared))?;
+    }
+
+    if let Some(pins) = board.segment.filter(|_| enabled.segment) {
+        let display: Box<dyn Segments + Send> = match (pins.kind, pins.cs) {
+            (SegmentKind::Max7219, Some(cs)) => Box::new(Max7219::new(
+                PinDriver::output(pin(pins.clk))?,
+                PinDriver::output(pin(pins.dio))?,
+                PinDriver::output(pin(cs))?,
+            )?),
+            _ => Box::new(Tm1637::new(
+                PinDriver::output(pin(pins.clk))?,
+                PinDriver::input_output_od(pin(pins.dio))?,
+                Ets,
+            )?),
+        };
+        let brightness = settings.segment_brightness;
+        let shared = shared.clone();
+        SEGMENT_TASK.spawn(move || segment_updates(display, brightness, &shared))?;
+    }
+
+    led.set(ORANGE, u8::MAX).map_err(Error::Other)?;
+
+    // Off the grid, or on a Zigbee network, the measurements are only sent
+    // over the radio, without starting the Wi-Fi, MQTT and HTTP stacks which
+    // would drain the battery
+    if !network_kind.is_ip() {
+        #[cfg(feature = "zigbee")]
+        if network_kind == NetworkKind::Zigbee {
+            let climate = shared.climate.is_some();
+            ZIGBEE_TASK.spawn(move || {
+                if let Err(e) = zigbee::run(climate) {
+                    log::error!("Zigbee stopped: {e:?}");
+                }
+                // Nothing is reported anymore
+                restart();
+            })?;
+        }
+        if network_kind == NetworkKind::Lorawan {
+            let keys = Keys::parse(
+                CONFIG.lora_dev_eui,
+                CONFIG.lora_join_eui,
+                CONFIG.lora_app_key,
+            )
+            .map_err(Error::config)?;
+            let spreading_factor = match CONFIG.lora_spreading_factor {
+                sf @ 7..=12 => sf as u8,
+                sf => {
+                    return Err(Error::config(anyhow::anyhow!(
+                        "Invalid LoRa spreading factor {sf}, expected 7 to 12"
+                    )))
+                }
+            };
+            let radio = Sx1276::new(spi.unwrap(), pin(board.eth_cs), pin(board.eth_rst))
+                .map_err(Error::Network)?;
+            let shared = shared.clone();
+            LORAWAN_TASK.spawn(move || {
+                if let Err(e) = lorawan_uplinks(radio, &keys, spreading_factor, &shared) {
+                    log::error!("LoRaWAN stopped: {e:?}");
+                }
+                // Nothing is sent anymore
+                restart();
+            })?;
+        }
+        led.set(BLACK, 0).map_err(Error::Other)?;
+        let sinks = RefCell::new(measurement_sinks(
+            &settings,
+            &shared,
+            network_kind,
+            display,
+            sdcard_mounted,
+        ));
+        // The Zigbee attributes are set
+        #[cfg_attr(not(feature = "zigbee"), allow(unused_variables))]
+        let on_measurement = |latest: &Latest| {
+            #[cfg(feature = "zigbee")]
+            if network_kind == NetworkKind::Zigbee {
+                zigbee::update(reading::from_tenths(latest.vals.pm25()), shared.climate());
+            }
+        };
+        let sensor1 = async {
+            match sensor1.as_mut() {
+                Some(sensor) => {
+                    measure_task(
+                        1,
+                        sensor,
+                        &mut sensor1_timer,
+                        measure_interval,
+                        settings.sensor_warmup,
+                        &shared,
+                        &on_measurement,
+                        &sinks,
+                    )
+                    .await
+                }
+                None => core::future::pending().await,
+            }
+        };
+        let co2 = co2_task(
+            co2.as_mut(),
+            timer_service.timer_async()?,
+            measure_interval,
+            &shared,
+        );
+        let voc = voc_task(
+            voc.as_mut(),
+            timer_service.timer_async()?,
+            settings.voc_compensation(),
+            baseline_store,
+            &shared,
+        );
+        let dht22 = dht22_task(
+            dht22.as_mut(),
+            timer_service.timer_async()?,
+            measure_interval,
+            &shared,
+        );
+        let measure0 = measure_task(
+            0,
+            &mut sensor0,
+            &mut timer,
+            measure_interval,
+            settings.sensor_warmup,
+            &shared,
+            &on_measurement,
+            &sinks,
+        );
+        return match select4(measure0, sensor1, co2, select(voc, dht22)).await {
+            Either4::First(result)
+            | Either4::Second(result)
+            | Either4::Third(result)
+            | Either4::Fourth(Either::First(result) | Either::Second(result)) => result,
+        };
+    }
+
+    // Connect to the Wi-Fi or Ethernet network
+    let eap = (!settings.wifi_eap_username.is_empty()).then(|| Eap {
+        identity: if settings.wifi_eap_identity.is_empty() {
+            &settings.wifi_eap_username
+        } else {
+            &settings.wifi_eap_identity
+        },
+        username: &settings.wifi_eap_username,
+        password: &settings.wifi_eap_password,
+        ttls_phase2: &settings.wifi_eap_ttls_phase2,
+        ca_cert: eap_ca_cert,
+    });
+    let network = match network_kind {
+        NetworkKind::Wifi => wifi(
+            &settings.wifi_ssid,
+            &settings.wifi_psk,
+            eap.as_ref(),
+            &hostname,
+            peripherals.modem,
+            sysloop,
+            timer_service.clone(),
+        )
+        .await
+        .map(Network::Wifi)
+        .context("Could not connect to Wi-Fi network"),
+        NetworkKind::Ethernet => eth::ethernet(
+            spi.unwrap(),
+            pin(board.eth_int),
+            pin(board.eth_cs),
+            pin(board.eth_rst),
+            &hostname,
+            sysloop,
+            timer_service.clone(),
+        )
+        .await
+        .map(Network::Ethernet)
+        .context("Could not connect to Ethernet network"),
+        // Without IP, measuring only
+        _ => unreachable!(),
+    };
+    let mut network = match network {
+        Ok(inner) => inner,
+        Err(err) => {
+            // Red!
+            led.set(RED, u8::MAX).map_err(Error::Other)?;
+            return Err(Error::Network(err));
+        }
+    };
+    if light_sleep {
+        match power::enable_light_sleep() {
+            Ok(()) => log::info!("Light sleep enabled"),
+            Err(e) => log::warn!("Unable to enable the light sleep: {e}"),
+        }
+    }
+    match network.ip() {
+        Ok(ip) => *shared.url.lock().unwrap() = Some(improv::url(settings.https_enabled, ip)),
+        Err(e) => log::warn!("Unable to get the address: {e:?}"),
+    }
+    let root_topic = format!("esp32/{}", shared.mac);
+    // Named like Tasmota's default, from the end of the MAC address
+    let tasmota_device = format!("esp32_{:02X}{:02X}{:02X}", mac[3], mac[4], mac[5]);
+
+    // Keep the clock synchronized for timestamps
+    let _sntp = EspSntp::new_default()?;
+
+    // Found by the aggregators, which also use it to find the others
+    let port = if settings.https_enabled { 443 } else { 80 };
+    // Advertised as long as it lives
+    let _mdns = match peers::advertise(&hostname, &settings.name, port, settings.https_enabled) {
+        Ok(mdns) => Some(Arc::new(mdns)),
+        Err(e) => {
+            log::warn!("Unable to advertise on mDNS: {e:?}");
+            None
+        }
+    };
+    if let (true, Some(mdns)) = (settings.aggregator, _mdns.clone()) {
+        let shared = shared.clone();
+        PEERS_TASK.spawn(move || loop {
+            match peers::poll(&mdns, &hostname) {
+                Ok(peers) => *shared.peers.lock().unwrap() = peers,
+                Err(e) => log::warn!("Unable to find the other stations: {e:?}"),
+            }
+            std::thread::sleep(PEERS_POLL_INTERVAL);
+        })?;
+    }
+
+    if !settings.uplink_check_url.is_empty() {
+        let shared = shared.clone();
+        let url = settings.uplink_check_url.clone();
+        UPLINK_TASK.spawn(move || loop {
+            std::thread::sleep(UPLINK_CHECK_INTERVAL);
+            // Not worth checking, the device being offline anyway
+            if !shared.link_up.load(Ordering::Relaxed) {
+                continue;
+            }
+            let reachable = match connectivity::check(&url) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::debug!("Uplink check failed: {e:?}");
+                    false
+                }
+            };
+            let previous = shared.internet.lock().unwrap().replace(reachable);
+            if previous != Some(reachable) {
+                if reachable {
+                    log::info!("Internet reachable");
+                } else {
+                    log::warn!("Internet unreachable beyond the local network");
+                }
+            }
+        })?;
+    }
+
+    let mqtt_enabled = !settings.mqtt_broker_url.is_empty();
+
+    #[cfg(not(esp32))]
+    if let Err(e) = start_console(
+        shared.clone(),
+        config_store.clone(),
+        identity_store.clone(),
+        mqtt_enabled,
+    ) {
+        log::warn!("Unable to start the serial console: {e:?}");
+    }
+
+    // Set the HTTP server, its handlers run in the server's own task
+    let server_config = if settings.https_enabled {
+        cert_store.server_configuration().map_err(Error::config)?
+    } else {
+        Configuration::default()
+    };
+    http::set_cors_origins(settings.cors_origins.clone());
+    http::set_timeout(Duration::from_secs(settings.http_timeout_secs.into()));
+    let _redirect_server = if settings.https_enabled {
+        Some(https::redirect_server().map_err(Error::Other)?)
+    } else {
+        None
+    };
+    let server_context = ServerContext {
+        configuration: Configuration {
+            // For the CORS preflight handler of `/api/*`
+            uri_match_wildcard: true,
+            max_uri_handlers: MAX_URI_HANDLERS,
+            stack_size: match settings.http_stack_size {
+                0 => server_config.stack_size,
+                size => size as usize,
+            },
+            max_open_sockets: settings.http_max_sessions.into(),
+            // A slow client is dropped for a new one rather than the new one
+            // being refused
+            lru_purge_enable: true,
+            ..server_config
+        },
+        settings: &settings,
+        shared: shared.clone(),
+        config_store: config_store.clone(),
+        cert_store: Arc::new(Mutex::new(cert_store)),
+        identity_store,
+        mqtt_enabled,
+        #[cfg(feature = "sdcard")]
+        sdcard_mounted,
+    };
+    // Restarted by the monitor task when memory runs low
+    let mut server = Some(start_server(&server_context)?);
+    log::info!("HTTP Server awaiting connection");
+    if settings.coap_enabled {
+        let shared = shared.clone();
+        COAP_TASK.spawn(move || {
+            if let Err(e) = coap_server(&shared) {
+                log::error!("CoAP server stopped: {e:?}");
+            }
+        })?;
+    }
+    if settings.modbus_enabled {
+        let shared = shared.clone();
+        let unit_id = settings.modbus_unit_id;
+        let map = settings.modbus_registers.clone();
+        MODBUS_TASK.spawn(move || {
+            if let Err(e) = modbus_server(&shared, unit_id, &map) {
+                log::error!("Modbus server stopped: {e:?}");
+            }
+        })?;
+    }
+    if settings.snmp_enabled {
+        let shared = shared.clone();
+        let community = settings.snmp_community.clone();
+        let enterprise = settings.snmp_enterprise;
+        SNMP_TASK.spawn(move || {
+            if let Err(e) = snmp_agent(&shared, &community, enterprise) {
+                log::error!("SNMP agent stopped: {e:?}");
+            }
+        })?;
+    }
+
+    let notifier = if settings.ntfy_topic.is_empty() {
+        None
+    } else {
+        let station = shared.station().to_string();
+        let shared = shared.clone();
+        let (url, topic, token) = (
+            settings.ntfy_url.clone(),
+            settings.ntfy_topic.clone(),
+            settings.ntfy_token.clone(),
+        );
+        NTFY_TASK.spawn(move || loop {
+            let notification = block_on(shared.notifications.receive());
+            if let Err(e) = ntfy::send(&url, &topic, &token, &notification) {
+                log::warn!("Unable to publish the notification to ntfy: {e:?}");
+            }
+        })?;
+        Some(RefCell::new(Notifier::new(
+            station,
+            settings.ntfy_priorities,
+            Duration::from_secs(settings.ntfy_interval_secs.into()),
+        )))
+    };
+
+    let smtp_enabled = if let Some(account) = settings.smtp() {
+        let shared = shared.clone();
+        SMTP_TASK.spawn(move || loop {
+            let email = block_on(shared.emails.receive());
+            if let Err(e) = smtp::send(&account, &shared.hostname, &email) {
+                log::warn!("Unable to email {:?}: {e:?}", email.subject);
+            }
+        })?;
+        true
+    } else {
+        false
+    };
+    let telegram_enabled = !settings.telegram_token.is_empty();
+    if telegram_enabled {
+        let shared = shared.clone();
+        let bot = Bot::new(settings.telegram_token.clone());
+        let chat_id = settings.telegram_chat_id;
+        TELEGRAM_ALERTS_TASK.spawn({
+            let shared = shared.clone();
+            let bot = bot.clone();
+            move || loop {
+                let text = block_on(shared.telegram.receive());
+                if let Err(e) = bot.send(chat_id, &text) {
+                    log::warn!("Unable to send the Telegram alert: {e:?}");
+                }
+            }
+        })?;
+        TELEGRAM_TASK.spawn(move || telegram_bot(&shared, &bot, chat_id))?;
+    }
+    // Of the limits and safe mode
+    let alert = |alert: Email| {
+        if telegram_enabled
+            && shared
+                .telegram
+                .try_send(format!("{}\n{}", alert.subject, alert.body))
+                .is_err()
+        {
+            log::warn!("Telegram queue full, alert dropped");
+        }
+        if smtp_enabled && shared.emails.try_send(alert).is_err() {
+            log::warn!("SMTP queue full, email dropped");
+        }
+    };
+    if let Some(failures) = crash_counter.take_safe_mode().map_err(Error::Other)? {
+        alert(Email {
+            subject: format!("{}: recovered from safe mode", shared.station()),
+            body: format!(
+                "After {failures} consecutive failures, {} restarted in safe mode, \
+                 with its configuration portal on its own access point. It is back \
+                 on the network, running firmware {}.\n",
+                shared.station(),
+                BuildInfo::current()
+            ),
+        });
+    }
+
+    // Green!
+    led.set(GREEN, led_brightness).map_err(Error::Other)?;
+    // Wait...
+    timer.after(Duration::from_secs(1)).await?;
+
+    if !settings.webhook_url.is_empty() {
+        let shared = shared.clone();
+        let url = settings.webhook_url.clone();
+        WEBHOOK_TASK.spawn(move || loop {
+            let body = block_on(shared.webhooks.receive());
+            if let Err(e) = webhook::post(&url, &body) {
+                log::warn!("Unable to post the measurement to the webhook: {e:?}");
+            }
+        })?;
+    }
+    let sinks = RefCell::new(measurement_sinks(
+        &settings,
+        &shared,
+        network_kind,
+        display,
+        sdcard_mounted,
+    ));
+
+    // Called with the average of the sensors, shared by the measurement tasks
+    let last_save = Cell::new(Instant::now());
+    let rollover = RefCell::new(Rollover::default());
+    let limits = settings.limits();
+    let limit_alerts = RefCell::new(LimitAlerts::default());
+    let on_measurement = |latest: &Latest| {
+        let vals = &latest.vals;
+        let sample = Sample::new(vals, latest.measured_at);
+        let utc_offset = DateTime::from_timestamp(sample.timestamp.into(), 0)
+            .map_or(0, |now| clock::utc_offset(now).local_minus_utc().into());
+        let mut history = shared.history.lock().unwrap();
+        // Boosted measurements are published but only recorded at the usual
+        // interval, to keep the statistics and a day of history
+        let recent = history.iter().next_back().is_some_and(|last| {
+            u64::from(sample.timestamp.saturating_sub(last.timestamp)) < measure_interval.as_secs()
+        });
+        if !(shared.period() == Period::Boost && recent) {
+            // Before the oldest samples of the day are dropped by the new one
+            for (period, start) in rollover.borrow_mut().advance(sample.timestamp, utc_offset) {
+                let Some(stats) =
+                    stats::compute(history.iter(), period, start, measure_interval, &limits)
+                else {
+                    continue;
+                };
+                log::info!("{} statistics: {stats:?}", period.topic());
+                if mqtt_enabled {
+                    shared.stats.lock().unwrap().push(stats);
+                }
+            }
+            history.push(sample);
+            // Meaningless before the clock is set
+            if sample.timestamp != 0 {
+                let now = i64::from(sample.timestamp);
+                let exceedance =
+                    Exceedance::new(history.iter(), now, utc_offset, measure_interval, &limits);
+                for event in limit_alerts
+                    .borrow_mut()
+                    .check(now, utc_offset, &exceedance, &limits)
+                {
+                    log::warn!("{} limit exceeded: {event:?}", event.metric);
+                    alert(limit_alert(shared.station(), &event));
+                    if mqtt_enabled {
+                        shared.limit_events.lock().unwrap().push(event);
+                    }
+                }
+                *shared.exceedance.lock().unwrap() = exceedance;
+                *shared.trend.lock().unwrap() = Trend::compute(history.iter(), now);
+            }
+            if storage_mounted && last_save.get().elapsed() >= HISTORY_SAVE_INTERVAL {
+                match history.save(storage::HISTORY_PATH) {
+                    Ok(()) => last_save.set(Instant::now()),
+                    Err(e) => log::error!("Unable to save history: {e:?}"),
+                }
+            }
+        }
+        drop(history);
+        if let Err(e) = shared.update_relay(|relay| relay.update(&vals.readings(None, None))) {
+            log::error!("Unable to switch the relay: {e:?}");
+        }
+        if let Some(fan) = &shared.fan {
+            fan.lock()
+                .unwrap()
+                .set_pm25(reading::from_tenths(vals.pm25()));
+            shared.fan_changed.signal(());
+        }
+        if let Some(notifier) = &notifier {
+            let (pm25, pm10) = (
+                reading::from_tenths(vals.pm25()),
+                reading::from_tenths(vals.pm10()),
+            );
+            if let Some(notification) = notifier.borrow_mut().update(pm25, pm10, Instant::now()) {
+                log::info!("Notifying: {}", notification.title);
+                if shared.notifications.try_send(notification).is_err() {
+                    log::warn!("ntfy queue full, notification dropped");
+                }
+            }
+        }
+    };
+    let mqtt = async {
+        if mqtt_enabled {
+            let topics = Topics::new(
+                &root_topic,
+                settings.mqtt_tasmota.then_some(tasmota_device.as_str()),
+                &settings.mqtt_domoticz,
+                settings
+                    .mqtt_homie
+                    .then(|| format!("homie/{}", mqtt::homie_id(&shared.hostname))),
+                sensor_count,
+                shared.relay.is_some(),
+                shared.fan.is_some(),
+                shared.co2_kind.is_some(),
+            );
+            mqtt_task(
+                &settings,
+                &topics,
+                timer_service.timer_async()?,
+                timer_service.timer_async()?,
+                &shared,
+                &config_store,
+            )
+            .await
+        } else {
+            log::warn!("No MQTT broker configured, MQTT disabled");
+            core::future::pending().await
+        }
+    };
+
+    let sensor1 = async {
+        match sensor1.as_mut() {
+            Some(sensor) => {
+                measure_task(
+                    1,
+                    sensor,
+                    &mut sensor1_timer,
+                    measure_interval,
+                    settings.sensor_warmup,
+                    &shared,
+                    &on_measurement,
+                    &sinks,
+                )
+                .await
+            }
+            None => core::future::pending().await,
+        }
+    };
+
+    // The first task to fail stops the others and restarts the device
+    let tasks = select4(
+        measure_task(
+            0,
+            &mut sensor0,
+            &mut timer,
+            measure_interval,
+            settings.sensor_warmup,
+            &shared,
+            &on_measurement,
+            &sinks,
+        ),
+        sensor1,
+        blink_task(
+            led.as_mut(),
+            timer_service.timer_async()?,
+            &mut network,
+            &settings,
+            led_brightness,
+            &shared,
+            crash_counter,
+        ),
+        mqtt,
+    );
+    let monitor = monitor_task(
+        timer_service.timer_async()?,
+        &mut server,
+        &server_context,
+        settings.maintenance(),
+        storage_mounted,
+    );
+    let buzzer = buzzer_task(
+        buzzer.as_mut(),
+        timer_service.timer_async()?,
+        settings.alarm(),
+        &shared,
+    );
+    let fan = fan_task(fan.as_mut(), timer_service.timer_async()?, &shared);
+    let co2 = co2_task(
+        co2.as_mut(),
+        timer_service.timer_async()?,
+        measure_interval,
+        &shared,
+    );
+    let voc = voc_task(
+        voc.as_mut(),
+        timer_service.timer_async()?,
+        settings.voc_compensation(),
+        baseline_store,
+        &shared,
+    );
+    let dht22 = dht22_task(
+        dht22.as_mut(),
+        timer_service.timer_async()?,
+        measure_interval,
+        &shared,
+    );
+    match select4(tasks, monitor, buzzer, select4(fan, co2, voc, dht22)).await {
+        Either4::First(
+            Either4::First(result)
+            | Either4::Second(result)
+            | Either4::Third(result)
+            | Either4::Fourth(result),
+        )
+        | Either4::Second(result)
+        | Either4::Third(result)
+        | Either4::Fourth(
+            Either4::First(result)
+            | Either4::Second(result)
+            | Either4::Third(result)
+            | Either4::Fourth(result),
+        ) => result,
+    }
+}
+
+/// What the HTTP handlers need, kept to start the server again
+struct ServerContext<'a> {
+    configuration: Configuration,
+    settings: &'a Settings,
+    shared: Arc<Shared>,
+    config_store: Arc<Mutex<ConfigStore>>,
+    cert_store: Arc<Mutex<CertStore>>,
+    identity_store: SharedIdentityStore,
+    mqtt_enabled: bool,
+    #[cfg(feature = "sdcard")]
+    sdcard_mounted: bool,
+}
+
+/// Start the HTTP server and register all the handlers
+fn start_server(ctx: &ServerContext<'_>) -> Result<EspHttpServer<'static>> {
+    let shared = &ctx.shared;
+    let settings = ctx.settings;
+    let mqtt_enabled = ctx.mqtt_enabled;
+    let limits = settings.limits();
+    // The API key replaces `api_token` once provisioned, and protects the
+    // settings too
+    let api_token = shared.identity.token(&settings.api_token).to_string();
+    let write_token = shared.identity.write_token();
+    let mut server = EspHttpServer::new(&ctx.configuration)?;
+    let mut handlers = http::Handlers::new(&mut server, ctx.configuration.max_uri_handlers);
+    http::register_cors_handler(&mut handlers).map_err(Error::Other)?;
+    https::register_handlers(&mut handlers, ctx.cert_store.clone(), write_token.clone())
+        .map_err(Error::Other)?;
+    // http://<sta ip>/ handler
+    http::fn_handler(&mut handlers, "/", Method::Get, {
+        let shared = shared.clone();
+        let setting = settings.language.clone();
+        move |mut request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, 0) {
+                return http::write_error(request, status, message);
+            }
+            let language = Language::select(&setting, request.header("Accept-Language"));
+            let strings = language.strings();
+            let chart = http::query_param(request.uri(), "chart")
+                .and_then(Tier::from_name)
+                .unwrap_or(Tier::Raw);
+            let latest = *shared.measurement.lock().unwrap();
+            let (tile, summary) = match &latest {
+                Some(latest) => (
+                    aqi_tile(strings, latest, shared.max_age),
+                    latest_summary(strings, latest, shared.max_age),
+                ),
+                None => (format!("<p>{}</p>", strings.no_measurement), String::new()),
+            };
+            let refresh = DASHBOARD_REFRESH.as_secs().to_string();
+            let mut response = request.into_response(
+                200,
+                None,
+                &[
+                    ("Content-Type", "text/html; charset=utf-8"),
+                    ("Refresh", &refresh),
+                ],
+            )?;
+            // Sent section by section, each one built with its locks released
+            // before it is written and dropped once sent, rather than
+            // templated whole
+            let mut page = http::ChunkedWriter::new(&mut response);
+            let _ = page.write_str(&http::page_start(language));
+            if shared.is_warming_up() {
+                let _ = write!(page, "<p>{}</p>", strings.warming_up);
+            }
+            let _ = write!(
+                page,
+                r#"<div class="summary">{tile}<section class="details">{summary}"#
+            );
+            let mut section = co2_summary(strings, shared.co2_kind, *shared.co2.lock().unwrap());
+            let _ = page.write_str(&section);
+            section = voc_summary(strings, shared.voc_kind, *shared.voc.lock().unwrap());
+            let _ = page.write_str(&section);
+            section = climate_summary(strings, shared.climate.is_some(), shared.climate());
+            let _ = page.write_str(&section);
+            section = exceedance_summary(strings, &shared.exceedance.lock().unwrap(), &limits);
+            let _ = page.write_str(&section);
+            section = sensor_list(
+                strings,
+                &shared.sensors.lock().unwrap(),
+                &shared.readings.lock().unwrap(),
+                &shared.calibration.lock().unwrap(),
+            );
+            let _ = page.write_str(&section);
+            if !mqtt_enabled {
+                let _ = write!(page, "<p>{}</p>", strings.mqtt_disabled);
+            }
+            let uplink = shared.uplink();
+            if uplink.broker == Some(false) {
+                let _ = write!(page, "<p>{}</p>", strings.broker_unreachable);
+            }
+            if uplink.internet == Some(false) {
+                let _ = write!(page, "<p>{}</p>", strings.internet_unreachable);
+            }
+            let _ = page.write_str("</section></div>");
+            let values = chart_values(&shared.history.lock().unwrap(), chart);
+            let _ = write_history_chart(&mut page, strings, values.as_deref(), chart);
+            section = peers_comparison(strings, latest.as_ref(), &shared.peers.lock().unwrap());
+            let _ = page.write_str(&section);
+            let _ = page.write_str(http::PAGE_END);
+            page.finish()?;
+            Ok(())
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/measurement", Method::Get, {
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> {
+            let latest = *shared.measurement.lock().unwrap();
+            let json = latest.map(|latest| MeasurementJson::new(&latest, &shared));
+            http::write_json(request, &json)
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/pipeline", Method::Get, {
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> {
+            // Not locked while writing to the socket
+            let json = {
+                let pipeline = shared.pipeline.lock().unwrap();
+                PipelineJson {
+                    stages: pipeline.stages().to_vec(),
+                    last: pipeline.last().cloned(),
+                }
+            };
+            http::write_json(request, &json)
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/history", Method::Get, {
+        let shared = shared.clone();
+        move |mut request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, 0) {
+                return http::write_error(request, status, message);
+            }
+            let tier = http::query_param(request.uri(), "tier").map(Tier::from_name);
+            let tier = match tier {
+                None => Tier::Raw,
+                Some(Some(tier)) => tier,
+                Some(None) => {
+                    return http::write_error(request, 400, "Invalid tier, expected raw, 15m or 1h")
+                }
+            };
+            let samples: Vec<Sample> = shared.history.lock().unwrap().tier(tier).copied().collect();
+            http::write_json(request, &samples)
+        }
+    })?;
+    // Written a batch at a time, in chunks, not to copy the whole history
+    http::fn_handler(&mut handlers, "/api/export", Method::Get, {
+        let shared = shared.clone();
+        move |mut request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, 0) {
+                return http::write_error(request, status, message);
+            }
+            let uri = request.uri();
+            let query = export::Query::parse(
+                http::query_param(uri, "format"),
+                http::query_param(uri, "from"),
+                http::query_param(uri, "to"),
+            );
+            let Query { format, from, to } = match query {
+                Ok(query) => query,
+                Err(e) => return http::write_error(request, 400, format!("{e}")),
+            };
+            let mut response =
+                http::api_response(request, 200, &[("Content-Type", format.content_type())])?;
+            response.write_all(format.header().as_bytes())?;
+            let mut after = from.saturating_sub(1);
+            let mut first = true;
+            loop {
+                let batch = export::batch(shared.history.lock().unwrap().all(), after, to);
+                let Some(last) = batch.last() else {
+                    break;
+                };
+                after = last.timestamp;
+                let mut rows = String::new();
+                for sample in &batch {
+                    rows += &format.row(sample, first);
+                    first = false;
+                }
+                response.write_all(rows.as_bytes())?;
+            }
+            response.write_all(format.footer().as_bytes())?;
+            Ok(())
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/health", Method::Get, {
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> { http::write_json(request, &Health::new(&shared)) }
+    })?;
+    http::fn_handler(&mut handlers, "/metrics", Method::Get, {
+        let shared = shared.clone();
+        move |mut request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, 0) {
+                return http::write_error(request, status, message);
+            }
+            let uart: UartStats = shared.uart.lock().unwrap().iter().copied().sum();
+            let mut metrics = String::new();
+            shared.counters.write_metrics(&mut metrics);
+            for (name, help, value) in [
+                (
+                    "uart_crc_errors",
+                    "Frames with an invalid checksum",
+                    uart.crc_errors,
+                ),
+                (
+                    "uart_resyncs",
+                    "Bytes skipped to find a frame",
+                    uart.resyncs,
+                ),
+                (
+                    "uart_reinits",
+                    "Particle sensors initialized again",
+                    uart.reinits,
+                ),
+            ] {
+                counters::write_metric(&mut metrics, name, help, value);
+            }
+            let mut response = http::api_response(
+                request,
+                200,
+                &[("Content-Type", "text/plain; version=0.0.4")],
+            )?;
+            response.write_all(metrics.as_bytes())?;
+            Ok(())
+        }
+    })?;
+    http::fn_handler(
+        &mut handlers,
+        "/api/version",
+        Method::Get,
+        |request| -> anyhow::Result<()> { http::write_json(request, &BuildInfo::current()) },
+    )?;
+    http::fn_handler(&mut handlers, "/api/openapi.json", Method::Get, {
+        let document = api_document(ctx);
+        move |request| -> anyhow::Result<()> { http::write_json(request, &document) }
+    })?;
+    http::fn_handler(&mut handlers, "/api/peers", Method::Get, {
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> {
+            http::write_json(request, &*shared.peers.lock().unwrap())
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/sensors", Method::Get, {
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> {
+            http::write_json(request, &*shared.sensors.lock().unwrap())
+        }
+    })?;
+    // Scanned again, the VOC sensor waits for the bus meanwhile
+    http::fn_handler(&mut handlers, "/api/i2c/scan", Method::Get, {
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> {
+            let Some(bus) = &shared.i2c else {
+                return http::write_error(request, 404, "No I2C bus");
+            };
+            let devices = i2c_bus::scan(&mut bus.clone());
+            *shared.i2c_devices.lock().unwrap() = devices.clone();
+            shared.sensors_changed.signal(());
+            http::write_json(request, &devices)
+        }
+    })?;
+    // Queued for the measurement task, the result is seen on GET
+    http::fn_handler(&mut handlers, "/api/sensors", Method::Post, {
+        let shared = shared.clone();
+        let write_token = write_token.clone();
+        move |mut request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, http::MAX_COMMAND_LEN) {
+                return http::write_error(request, status, message);
+            }
+            if let Err((status, message)) = http::authorize_write(&request, write_token.as_deref())
+            {
+                return http::write_error(request, status, message);
+            }
+            let index = http::query_param(request.uri(), "index")
+                .unwrap_or("0")
+                .parse::<usize>();
+            let Some(commands) = index.ok().and_then(|index| shared.commands.get(index)) else {
+                return http::write_error(request, 404, "No such sensor");
+            };
+            let body = http::read_body(&mut request, http::MAX_COMMAND_LEN)?;
+            let command: SensorCommand = match serde_json::from_slice(&body) {
+                Ok(command) => command,
+                Err(e) => return http::write_error(request, 400, format!("{e}")),
+            };
+            if commands.try_send(command).is_err() {
+                return http::write_error(request, 503, "Too many pending commands");
+            }
+            http::api_response(request, 202, &[])?;
+            Ok(())
+        }
+    })?;
+    // Events pushed to the dashboards, commands as on the REST API
+    ws::register_handler(
+        &mut handlers,
+        "/ws",
+        shared.ws_clients.clone(),
+        {
+            let shared = shared.clone();
+            move || {
+                let mut events = vec![
+                    WsEvent::Status(Health::new(&shared)),
+                    WsEvent::sensors(&shared),
+                ];
+                let latest = *shared.measurement.lock().unwrap();
+                if let Some(latest) = latest {
+                    events.push(WsEvent::Measurement(MeasurementJson::new(&latest, &shared)));
+                }
+                events.iter().map(WsEvent::to_json).collect()
+            }
+        },
+        {
+            let api_token = api_token.clone();
+            let write_token = write_token.clone();
+            let shared = shared.clone();
+            move |message| {
+                ws_command(message, &shared, &api_token, write_token.as_deref()).to_json()
+            }
+        },
+    )
+    .map_err(Error::Other)?;
+    // Device control, authenticated by the API key or `api_token`
+    http::fn_handler(&mut handlers, "/api/restart", Method::Post, {
+        let api_token = api_token.clone();
+        move |request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::authorize(&request, &api_token) {
+                return http::write_error(request, status, message);
+            }
+            http::api_response(request, 202, &[])?;
+            schedule_restart();
+            Ok(())
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/identify", Method::Post, {
+        let api_token = api_token.clone();
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::authorize(&request, &api_token) {
+                return http::write_error(request, status, message);
+            }
+            shared.identify.signal(());
+            http::api_response(request, 202, &[])?;
+            Ok(())
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/loglevel", Method::Get, {
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> {
+            let levels = *shared.log_levels.lock().unwrap();
+            http::write_json(request, &loglevel::to_json(&levels))
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/loglevel", Method::Post, {
+        let api_token = api_token.clone();
+        let shared = shared.clone();
+        move |mut request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, http::MAX_COMMAND_LEN) {
+                return http::write_error(request, status, message);
+            }
+            if let Err((status, message)) = http::authorize(&request, &api_token) {
+                return http::write_error(request, status, message);
+            }
+            let body = http::read_body(&mut request, http::MAX_COMMAND_LEN)?;
+            let levels = match loglevel::parse(&body) {
+                Ok(levels) => levels,
+                Err(e) => return http::write_error(request, 400, format!("{e:#}")),
+            };
+            set_log_levels(&levels, &shared)?;
+            let levels = *shared.log_levels.lock().unwrap();
+            http::write_json(request, &loglevel::to_json(&levels))
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/measure", Method::Post, {
+        let api_token = api_token.clone();
+        let shared = shared.clone();
+        move |request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::authorize(&request, &api_token) {
+                return http::write_error(request, status, message);
+            }
+            shared.measure_now();
+            http::api_response(request, 202, &[])?;
+            Ok(())
+        }
+    })?;
+    // Cloned to another device, authenticated as the app embeds `cfg.toml`
+    if image::FACTORY_AVAILABLE {
+        http::fn_handler(&mut handlers, "/api/firmware", Method::Get, {
+            let api_token = api_token.clone();
+            move |request| -> anyhow::Result<()> {
+                if let Err((status, message)) = http::authorize(&request, &api_token) {
+                    return http::write_error(request, status, message);
+                }
+                let mut response = http::api_response(
+                    request,
+                    200,
+                    &[
+                        ("Content-Type", "application/octet-stream"),
+                        (
+                            "Content-Disposition",
+                            "attachment; filename=\"esp-particle-sensor-rs-factory.bin\"",
+                        ),
+                    ],
+                )?;
+                image::write_factory(|chunk| Ok(response.write_all(chunk)?))
+            }
+        })?;
+    }
+    if shared.relay.is_some() {
+        http::fn_handler(&mut handlers, "/api/relay", Method::Get, {
+            let shared = shared.clone();
+            move |request| -> anyhow::Result<()> { http::write_json(request, &shared.relay()) }
+        })?;
+        http::fn_handler(&mut handlers, "/api/relay", Method::Post, {
+            let api_token = api_token.clone();
+            let shared = shared.clone();
+            move |mut request| -> anyhow::Result<()> {
+                if let Err((status, message)) = http::admit(&mut request, http::MAX_COMMAND_LEN) {
+                    return http::write_error(request, status, message);
+                }
+                if let Err((status, message)) = http::authorize(&request, &api_token) {
+                    return http::write_error(request, status, message);
+                }
+                let body = http::read_body(&mut request, http::MAX_COMMAND_LEN)?;
+                let mode = match serde_json::from_slice::<RelayRequest>(&body) {
+                    Ok(RelayRequest { mode }) => mode,
+                    Err(e) => return http::write_error(request, 400, format!("{e}")),
+                };
+                shared.update_relay(|relay| relay.set_mode(mode))?;
+                http::write_json(request, &shared.relay())
+            }
+        })?;
+    }
+    if shared.fan.is_some() {
+        http::fn_handler(&mut handlers, "/api/fan", Method::Get, {
+            let shared = shared.clone();
+            move |request| -> anyhow::Result<()> { http::write_json(request, &shared.fan()) }
+        })?;
+        http::fn_handler(&mut handlers, "/api/fan", Method::Post, {
+            let api_token = api_token.clone();
+            let shared = shared.clone();
+            move |mut request| -> anyhow::Result<()> {
+                if let Err((status, message)) = http::admit(&mut request, http::MAX_COMMAND_LEN) {
+                    return http::write_error(request, status, message);
+                }
+                if let Err((status, message)) = http::authorize(&request, &api_token) {
+                    return http::write_error(request, status, message);
+                }
+                let body = http::read_body(&mut request, http::MAX_COMMAND_LEN)?;
+                let boost = match serde_json::from_slice::<FanRequest>(&body) {
+                    Ok(FanRequest { boost }) => boost,
+                    Err(e) => return http::write_error(request, 400, format!("{e}")),
+                };
+                shared.boost_fan(boost);
+                http::write_json(request, &shared.fan())
+            }
+        })?;
+    }
+    if shared.co2_kind.is_some() {
+        // Queued for the CO2 task
+        http::fn_handler(&mut handlers, "/api/co2", Method::Post, {
+            let api_token = api_token.clone();
+            let shared = shared.clone();
+            move |mut request| -> anyhow::Result<()> {
+                if let Err((status, message)) = http::admit(&mut request, http::MAX_COMMAND_LEN) {
+                    return http::write_error(request, status, message);
+                }
+                if let Err((status, message)) = http::authorize(&request, &api_token) {
+                    return http::write_error(request, status, message);
+                }
+                let body = http::read_body(&mut request, http::MAX_COMMAND_LEN)?;
+                let command: Co2Command = match serde_json::from_slice(&body) {
+                    Ok(command) => command,
+                    Err(e) => return http::write_error(request, 400, format!("{e}")),
+                };
+                if shared.co2_commands.try_send(command).is_err() {
+                    return http::write_error(request, 503, "Too many pending commands");
+                }
+                http::api_response(request, 202, &[])?;
+                Ok(())
+            }
+        })?;
+    }
+    // Rotated with the current token, applied on the next restart
+    http::fn_handler(&mut handlers, "/api/identity", Method::Post, {
+        let api_token = api_token.clone();
+        let identity_store = ctx.identity_store.clone();
+        move |mut request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, http::MAX_COMMAND_LEN) {
+                return http::write_error(request, status, message);
+            }
+            if let Err((status, message)) = http::authorize(&request, &api_token) {
+                return http::write_error(request, status, message);
+            }
+            let body = http::read_body(&mut request, http::MAX_COMMAND_LEN)?;
+            let result = serde_json::from_slice::<Identity>(&body)
+                .map_err(anyhow::Error::from)
+                .and_then(|identity| identity_store.lock().unwrap().save(&identity));
+            match result {
+                Ok(()) => {
+                    log::info!("Device identity updated, applied on next restart");
+                    http::api_response(request, 200, &[])?;
+                    Ok(())
+                }
+                Err(e) => http::write_error(request, 400, format!("{e}")),
+            }
+        }
+    })?;
+    http::fn_handler(&mut handlers, "/api/identity", Method::Delete, {
+        let api_token = api_token.clone();
+        let identity_store = ctx.identity_store.clone();
+        move |request| -> anyhow::Result<()> {
+            if let Err((status, message)) = http::authorize(&request, &api_token) {
+                return http::write_error(request, status, message);
+            }
+            identity_store.lock().unwrap().clear()?;
+            log::info!("Device identity removed, applied on next restart");
+            http::api_response(request, 200, &[])?;
+            Ok(())
+        }
+    })?;
+    portal::register_handlers(
+        &mut handlers,
+        "/config",
+        ctx.config_store.clone(),
+        false,
+        write_token,
+    )
+    .map_err(Error::Other)?;
+    #[cfg(feature = "sdcard")]
+    if ctx.sdcard_mounted {
+        http::fn_handler(
+            &mut handlers,
+            "/api/logs",
+            Method::Get,
+            |mut request| -> anyhow::Result<()> {
+                if let Err((status, message)) = http::admit(&mut request, 0) {
+                    return http::write_error(request, status, message);
+                }
+                let Some(name) = http::query_param(request.uri(), "file").map(str::to_string)
+                else {
+                    return http::write_json(request, &sdlog::list()?);
+                };
+                let mut file = match sdlog::open(&name) {
+                    Ok(file) => file,
+                    Err(e) => return http::write_error(request, 404, format!("{e}")),
+                };
+                let mut response =
+                    http::api_response(request, 200, &[("Content-Type", "text/csv")])?;
+                let mut buf = [0u8; 512];
+                loop {
+                    let len = std::io::Read::read(&mut file, &mut buf)?;
+                    if len == 0 {
+                        break;
+                    }
+                    response.write_all(&buf[..len])?;
+                }
+                Ok(())
+            },
+        )?;
+    }
+    log::info!(
+        "{} HTTP handlers registered, of {}",
+        handlers.count(),
+        ctx.configuration.max_uri_handlers
+    );
+    Ok(server)
+}
+
+/// Socket sending each measurement as a datagram on the LAN
+struct Announcer {
+    socket: UdpSocket,
+    to: SocketAddrV4,
+    shared: Arc<Shared>,
+}
+
+impl Announcer {
+    /// `None` unless `udp_port` is set
+    fn new(settings: &Settings, shared: &Arc<Shared>) -> anyhow::Result<Option<Self>> {
+        if settings.udp_port == 0 {
+            return Ok(None);
+        }
+        let address: Ipv4Addr = settings.udp_address.parse()?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        // Kept on the LAN
+        socket.set_multicast_ttl_v4(1)?;
+        socket.set_nonblocking(true)?;
+        Ok(Some(Self {
+            socket,
+            to: SocketAddrV4::new(address, settings.udp_port),
+            shared: shared.clone(),
+        }))
+    }
+}
+
+impl MeasurementSink<Latest> for Announcer {
+    fn kind(&self) -> SinkKind {
+        SinkKind::Announce
+    }
+
+    fn send(&mut self, latest: &Latest) -> anyhow::Result<()> {
+        let json = serde_json::to_vec(&Announcement::new(latest, &self.shared))?;
+        // Lost like any datagram, the next measurement follows
+        self.socket
+            .send_to(&json, self.to)
+            .with_context(|| format!("Unable to send to {}", self.to))?;
+        Ok(())
+    }
+}
+
+/// Sends the measurements as InfluxDB line protocol datagrams, for the
+/// UDP listeners of Telegraf or InfluxDB
+struct InfluxSender {
+    socket: UdpSocket,
+    to: SocketAddrV4,
+    measurement: String,
+    /// Unix timestamp of the last measurement sent, those of the history
+    /// taken since are replayed
+    delivered: u32,
+    shared: Arc<Shared>,
+}
+
+impl InfluxSender {
+    /// `None` unless `influx_address` is set. The measurements already in
+    /// the history are not replayed.
+    fn new(settings: &Settings, shared: &Arc<Shared>) -> anyhow::Result<Option<Self>> {
+        if settings.influx_address.is_empty() {
+            return Ok(None);
+        }
+        let address: Ipv4Addr = settings.influx_address.parse()?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_nonblocking(true)?;
+        Ok(Some(Self {
+            socket,
+            to: SocketAddrV4::new(address, settings.influx_port),
+            measurement: settings.influx_measurement.clone(),
+            delivered: shared.history.lock().unwrap().last_timestamp(),
+            shared: shared.clone(),
+        }))
+    }
+
+    fn line(&self, readings: &[Reading], timestamp: Option<DateTime<Utc>>) -> String {
+        let pm25 = reading::value(readings, Kind::Pm25).unwrap_or_default();
+        let pm10 = reading::value(readings, Kind::Pm10).unwrap_or_default();
+        influx::line(
+            &self.measurement,
+            &[
+                ("host", &self.shared.hostname),
+                ("name", &self.shared.name),
+                ("device_id", &self.shared.identity.device_id),
+            ],
+            readings,
+            aqi::us_epa(pm25, pm10),
+            timestamp,
+        )
+    }
+}
+
+impl MeasurementSink<Latest> for InfluxSender {
+    fn kind(&self) -> SinkKind {
+        SinkKind::Influx
+    }
+
+    /// Sent after the measurements of the history missed while the link was
+    /// down, with their original timestamps so that the series has no gap
+    fn send(&mut self, latest: &Latest) -> anyhow::Result<()> {
+        // Kept in the history until the link is up again
+        if !self.shared.link_up.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let timestamp = latest.measured_at.map_or(0, |at| at.timestamp() as u32);
+        // This one already in the history
+        let backlog: Vec<Sample> = self
+            .shared
+            .history
+            .lock()
+            .unwrap()
+            .since(self.delivered)
+            .filter(|sample| sample.timestamp < timestamp)
+            .take(INFLUX_REPLAY_MAX)
+            .copied()
+            .collect();
+        for sample in &backlog {
+            let line = self.line(&sample.readings(), sample.timestamp_utc());
+            self.socket
+                .send_to(line.as_bytes(), self.to)
+                .with_context(|| format!("Unable to replay the history to {}", self.to))?;
+            self.delivered = sample.timestamp;
+        }
+        if !backlog.is_empty() {
+            log::info!("Replayed {} measurements to InfluxDB", backlog.len());
+        }
+        let mut readings = latest.readings().to_vec();
+        readings.extend(self.shared.other_readings());
+        let line = self.line(&readings, latest.measured_at);
+        // Lost like any datagram, the next measurement follows
+        self.socket
+            .send_to(line.as_bytes(), self.to)
+            .with_context(|| format!("Unable to send to {}", self.to))?;
+        if backlog.len() < INFLUX_REPLAY_MAX && timestamp != 0 {
+            self.delivered = timestamp;
+        }
+        Ok(())
+    }
+}
+
+/// Queues each measurement for the webhook task
+struct WebhookSink {
+    shared: Arc<Shared>,
+}
+
+impl MeasurementSink<Latest> for WebhookSink {
+    fn kind(&self) -> SinkKind {
+        SinkKind::Webhook
+    }
+
+    fn send(&mut self, latest: &Latest) -> anyhow::Result<()> {
+        let body = serde_json::to_vec(&Announcement::new(latest, &self.shared))?;
+        self.shared
+            .webhooks
+            .try_send(body)
+            .map_err(|_| anyhow::anyhow!("Queue full, the webhook is not answering"))
+    }
+}
+
+/// Appends each measurement to the CSV log of the SD card
+#[cfg(feature = "sdcard")]
+struct SdCardSink;
+
+#[cfg(feature = "sdcard")]
+impl MeasurementSink<Latest> for SdCardSink {
+    fn kind(&self) -> SinkKind {
+        SinkKind::Sdcard
+    }
+
+    fn send(&mut self, latest: &Latest) -> anyhow::Result<()> {
+        sdlog::append(&latest.readings())
+    }
+}
+
+/// Pushed to the dashboards following the events on `/ws`
+struct WebsocketSink {
+    shared: Arc<Shared>,
+}
+
+impl MeasurementSink<Latest> for WebsocketSink {
+    fn kind(&self) -> SinkKind {
+        SinkKind::Websocket
+    }
+
+    fn send(&mut self, latest: &Latest) -> anyhow::Result<()> {
+        let json = MeasurementJson::new(latest, &self.shared);
+        self.shared
+            .ws_clients
+            .broadcast(&WsEvent::Measurement(json));
+        Ok(())
+    }
+}
+
+/// Hands each measurement over to the MQTT task, which publishes it
+struct MqttSink {
+    shared: Arc<Shared>,
+}
+
+impl MeasurementSink<Latest> for MqttSink {
+    fn kind(&self) -> SinkKind {
+        SinkKind::Mqtt
+    }
+
+    fn send(&mut self, latest: &Latest) -> anyhow::Result<()> {
+        self.shared.mqtt_measurements.signal(*latest);
+        Ok(())
+    }
+}
+
+/// Wakes up a task reading the latest measurement itself: the LoRaWAN
+/// uplinks or the e-paper display
+struct TaskSink {
+    kind: SinkKind,
+    shared: Arc<Shared>,
+    signal: fn(&Shared) -> &Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl MeasurementSink<Latest> for TaskSink {
+    fn kind(&self) -> SinkKind {
+        self.kind
+    }
+
+    fn send(&mut self, _latest: &Latest) -> anyhow::Result<()> {
+        (self.signal)(&self.shared).signal(());
+        Ok(())
+    }
+}
+
+/// Outputs of the measurements enabled in `settings`, each with its
+/// interval. `display` when the e-paper task is running.
+fn measurement_sinks(
+    settings: &Settings,
+    shared: &Arc<Shared>,
+    network_kind: NetworkKind,
+    display: bool,
+    #[cfg_attr(not(feature = "sdcard"), allow(unused_variables))] sdcard_mounted: bool,
+) -> Dispatcher<Latest> {
+    let mut sinks = Dispatcher::default();
+    if network_kind == NetworkKind::Lorawan {
+        sinks.register(
+            TaskSink {
+                kind: SinkKind::Lorawan,
+                shared: shared.clone(),
+                signal: |shared| &shared.new_measurement,
+            },
+            settings.sink_interval(SinkKind::Lorawan),
+        );
+    }
+    if display {
+        sinks.register(
+            TaskSink {
+                kind: SinkKind::Display,
+                shared: shared.clone(),
+                signal: |shared| &shared.display_changed,
+            },
+            settings.sink_interval(SinkKind::Display),
+        );
+    }
+    #[cfg(feature = "sdcard")]
+    if sdcard_mounted {
+        sinks.register(SdCardSink, settings.sink_interval(SinkKind::Sdcard));
+    }
+    // Without IP, with LoRaWAN or Zigbee
+    if network_kind.is_ip() {
+        if !settings.mqtt_broker_url.is_empty() {
+            sinks.register(
+                MqttSink {
+                    shared: shared.clone(),
+                },
+                settings.sink_interval(SinkKind::Mqtt),
+            );
+        }
+        sinks.register(
+            WebsocketSink {
+                shared: shared.clone(),
+            },
+            settings.sink_interval(SinkKind::Websocket),
+        );
+        match Announcer::new(settings, shared) {
+            Ok(Some(announcer)) => {
+                sinks.register(announcer, settings.sink_interval(SinkKind::Announce))
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Unable to announce the measurements over UDP: {e:?}"),
+        }
+        match InfluxSender::new(settings, shared) {
+            Ok(Some(influx)) => sinks.register(influx, settings.sink_interval(SinkKind::Influx)),
+            Ok(None) => {}
+            Err(e) => log::warn!("Unable to send the measurements to InfluxDB: {e:?}"),
+        }
+        if !settings.webhook_url.is_empty() {
+            sinks.register(
+                WebhookSink {
+                    shared: shared.clone(),
+                },
+                settings.sink_interval(SinkKind::Webhook),
+            );
+        }
+    }
+    let kinds: Vec<String> = sinks.kinds().map(|kind| kind.to_string()).collect();
+    log::info!("Measurements sent to: {}", kinds.join(", "));
+    sinks
+}
+
+/// Measurement with the identity of the station, datagram sent on the LAN
+/// and body of the webhook
+#[derive(Serialize)]
+struct Announcement<'a> {
+    hostname: &'a str,
+    /// Friendly name, `null` when not set
+    name: Option<&'a str>,
+    #[serde(flatten)]
+    measurement: MeasurementJson,
+}
+
+impl<'a> Announcement<'a> {
+    fn new(latest: &Latest, shared: &'a Shared) -> Self {
+        Self {
+            hostname: &shared.hostname,
+            name: (!shared.name.is_empty()).then_some(shared.name.as_str()),
+            measurement: MeasurementJson::new(latest, shared),
+        }
+    }
+}
+
+/// Serve `coap://<ip>/measurement` as `/api/measurement`, notifying its
+/// observers of each new measurement
+fn coap_server(shared: &Shared) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, coap::PORT))?;
+    socket.set_read_timeout(Some(COAP_POLL_INTERVAL))?;
+    // SAFETY: the hardware RNG can be read at any time
+    let mut server = coap::Server::new(unsafe { esp_random() } as u16);
+    let measured = || {
+        shared
+            .measurement
+            .lock()
+            .unwrap()
+            .and_then(|latest| latest.measured)
+    };
+    let json = || {
+        let latest = *shared.measurement.lock().unwrap();
+        serde_json::to_vec(&latest.map(|latest| MeasurementJson::new(&latest, shared)))
+    };
+    let mut notified = measured();
+    let mut buf = [0u8; coap::MAX_MESSAGE_LEN];
+    log::info!("CoAP server listening on port {}", coap::PORT);
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                let Some(request) = coap::Message::parse(&buf[..len]) else {
+                    continue;
+                };
+                if let Some(reply) = server.handle(from, &request, &json()?) {
+                    if let Err(e) = socket.send_to(&reply.encode(), from) {
+                        log::warn!("Unable to reply to the CoAP client {from}: {e}");
+                    }
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e.into()),
+        }
+        if measured() != notified {
+            notified = measured();
+            if server.observer_count() > 0 {
+                let json = json()?;
+                for (to, notification) in server.notify(&json) {
+                    // Gone clients are dropped when they reset a notification
+                    if let Err(e) = socket.send_to(&notification.encode(), to) {
+                        log::warn!("Unable to notify the CoAP observer {to}: {e}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Serve the values as Modbus TCP input registers, to a client at a time
+fn modbus_server(shared: &Shared, unit_id: u8, map: &[Register]) -> anyhow::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, modbus::PORT))?;
+    log::info!("Modbus server listening on port {}", modbus::PORT);
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Unable to accept a Modbus client: {e}");
+                continue;
+            }
+        };
+        stream.set_read_timeout(Some(MODBUS_IDLE_TIMEOUT))?;
+        if let Err(e) = modbus_connection(&mut stream, shared, unit_id, map) {
+            log::debug!("Modbus client disconnected: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Answer the requests of a client until it disconnects, or sends
+/// something else than Modbus
+fn modbus_connection(
+    stream: &mut TcpStream,
+    shared: &Shared,
+    unit_id: u8,
+    map: &[Register],
+) -> std::io::Result<()> {
+    loop {
+        let mut header = [0u8; modbus::HEADER_LEN];
+        std::io::Read::read_exact(stream, &mut header)?;
+        let Some(len) = modbus::pdu_len(&header) else {
+            return Ok(());
+        };
+        let mut pdu = vec![0u8; len];
+        std::io::Read::read_exact(stream, &mut pdu)?;
+        let registers = modbus_registers(shared, map);
+        if let Some(response) = modbus::respond(&header, &pdu, unit_id, &registers) {
+            std::io::Write::write_all(stream, &response)?;
+        }
+    }
+}
+
+/// Input registers of `map`, unavailable while the measurement is stale
+fn modbus_registers(shared: &Shared, map: &[Register]) -> Vec<u16> {
+    let latest = *shared.measurement.lock().unwrap();
+    let readings = latest
+        .filter(|latest| !latest.is_stale(shared.max_age))
+        .map(|latest| latest.readings());
+    let value = |kind| {
+        readings
+            .as_ref()
+            .and_then(|readings| reading::value(readings, kind))
+    };
+    let climate = shared.climate();
+    map.iter()
+        .map(|register| {
+            register.encode(match register {
+                Register::Pm25 => value(Kind::Pm25),
+                Register::Pm10 => value(Kind::Pm10),
+                Register::Aqi => value(Kind::Pm25)
+                    .zip(value(Kind::Pm10))
+                    .map(|(pm25, pm10)| f32::from(aqi::us_epa(pm25, pm10))),
+                Register::Temperature => climate.map(|climate| climate.temperature),
+                Register::Humidity => climate.map(|climate| climate.humidity),
+            })
+        })
+        .collect()
+}
+
+/// Alert of a 24 h mean above its limit
+fn limit_alert(station: &str, event: &LimitExceeded) -> Email {
+    Email {
+        subject: format!("{station}: {} limit exceeded", event.metric),
+        body: format!(
+            "The {metric} mean of the last 24 h is {:.1} µg/m³, above the limit of {} µg/m³.\n\
+             {metric} has been above it for {} minutes today.\n",
+            event.mean_24h,
+            event.limit,
+            event.minutes_today,
+            metric = event.metric,
+        ),
+    }
+}
+
+/// Answer the commands of the `chat_id` chat, the other chats are ignored
+fn telegram_bot(shared: &Shared, bot: &Bot, chat_id: i64) {
+    // Of the next update, confirming the previous ones. Those sent before
+    // the start are skipped, rather than answered after each restart.
+    let mut offset = loop {
+        match bot.next_offset() {
+            Ok(offset) => break offset,
+            Err(e) => {
+                log::warn!("Unable to skip the pending Telegram updates: {e:?}");
+                std::thread::sleep(TELEGRAM_RETRY);
+            }
+        }
+    };
+    loop {
+        let updates = match bot.updates(offset) {
+            Ok(updates) => updates,
+            Err(e) => {
+                log::warn!("Unable to poll the Telegram updates: {e:?}");
+                std::thread::sleep(TELEGRAM_RETRY);
+                continue;
+            }
+        };
+        for update in updates {
+            offset = update.update_id + 1;
+            let Some(message) = update.message.filter(|m| m.chat.id == chat_id) else {
+                continue;
+            };
+            let answer = match message.text.as_deref().and_then(BotCommand::parse) {
+                Some(BotCommand::Air) => telegram_summary(shared),
+                Some(BotCommand::Help) => telegram::HELP.to_string(),
+                None => continue,
+            };
+            if let Err(e) = bot.send(chat_id, &answer) {
+                log::warn!("Unable to answer on Telegram: {e:?}");
+            }
+        }
+    }
+}
+
+/// Answer to `/air`
+fn telegram_summary(shared: &Shared) -> String {
+    let Some(latest) = *shared.measurement.lock().unwrap() else {
+        return format!("{}: no measurement yet", shared.station());
+    };
+    let mut readings = latest.readings().to_vec();
+    readings.extend(shared.other_readings());
+    telegram::air_summary(shared.station(), &readings, latest.is_stale(shared.max_age))
+}
+
+/// Answer the SNMP requests of `community` with `snmp_mib`
+fn snmp_agent(shared: &Shared, community: &str, enterprise: u32) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, snmp::PORT))?;
+    let mut buf = [0u8; snmp::MAX_MESSAGE_LEN];
+    log::info!("SNMP agent listening on port {}", snmp::PORT);
+    loop {
+        let (len, from) = socket.recv_from(&mut buf)?;
+        let mut mib = snmp_mib(shared, enterprise);
+        if let Some(response) = snmp::respond(&buf[..len], community, &mut mib) {
+            if let Err(e) = socket.send_to(&response, from) {
+                log::warn!("Unable to answer the SNMP manager {from}: {e}");
+            }
+        }
+    }
+}
+
+/// Objects of the system group, and of `enterprises.<enterprise>`: `.1`
+/// the readings, `.2` the health of the device. Those unknown are missing.
+fn snmp_mib(shared: &Shared, enterprise: u32) -> snmp::Mib {
+    let scalar = |prefix: &[u32], id: u32| [prefix, &[id, 0]].concat();
+    let private = [&snmp::ENTERPRISES[..], &[enterprise]].concat();
+    let air = [&private[..], &[1]].concat();
+    let device = [&private[..], &[2]].concat();
+    // Hundredths of a second, wrapping after 497 days
+    let uptime = Value::TimeTicks((clock::uptime().as_millis() / 10) as u32);
+    let mut mib = vec![
+        (
+            scalar(&snmp::SYSTEM, 1),
+            Value::OctetString(
+                format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")).into_bytes(),
+            ),
+        ),
+        (scalar(&snmp::SYSTEM, 2), Value::ObjectId(private.clone())),
+        (scalar(&snmp::SYSTEM, 3), uptime.clone()),
+        (
+            scalar(&snmp::SYSTEM, 5),
+            Value::OctetString(shared.hostname.clone().into_bytes()),
+        ),
+    ];
+    if let Some(latest) = *shared.measurement.lock().unwrap() {
+        let (pm25, pm10) = (latest.vals.pm25(), latest.vals.pm10());
+        let aqi = aqi::us_epa(reading::from_tenths(pm25), reading::from_tenths(pm10));
+        mib.extend([
+            // Tenths of µg/m³
+            (scalar(&air, 1), Value::Gauge32(pm25.into())),
+            (scalar(&air, 2), Value::Gauge32(pm10.into())),
+            (scalar(&air, 3), Value::Gauge32(aqi.into())),
+            (
+                scalar(&air, 10),
+                Value::truth(latest.is_stale(shared.max_age)),
+            ),
+        ]);
+        if let Some(age) = latest.age() {
+            let age = u32::try_from(age.as_secs()).unwrap_or(u32::MAX);
+            mib.push((scalar(&air, 9), Value::Gauge32(age)));
+        }
+    }
+    if let Some(ppm) = *shared.co2.lock().unwrap() {
+        mib.push((scalar(&air, 4), Value::Gauge32(ppm.into())));
+    }
+    if let Some(index) = shared.voc(VocKind::Sgp40) {
+        mib.push((scalar(&air, 5), Value::Gauge32(index.into())));
+    }
+    if let Some(climate) = shared.climate() {
+        // Tenths of °C and of %
+        let temperature = (climate.temperature * reading::TENTHS).round() as i32;
+        mib.extend([
+            (scalar(&air, 6), Value::Integer(temperature)),
+            (
+                scalar(&air, 7),
+                Value::Gauge32(reading::to_tenths(climate.humidity).into()),
+            ),
+        ]);
+    }
+    if let Some(ppb) = shared.voc(VocKind::Sgp30) {
+        mib.push((scalar(&air, 8), Value::Gauge32(ppb.into())));
+    }
+    let memory = resources::memory();
+    let uart: UartStats = shared.uart.lock().unwrap().iter().copied().sum();
+    mib.extend([
+        (scalar(&device, 1), uptime),
+        (scalar(&device, 2), Value::Gauge32(memory.free_heap)),
+        (scalar(&device, 3), Value::Gauge32(memory.min_free_heap)),
+        (scalar(&device, 5), Value::Counter32(uart.crc_errors)),
+        (scalar(&device, 6), Value::Counter32(uart.resyncs)),
+        (scalar(&device, 7), Value::Counter32(uart.reinits)),
+        (
+            scalar(&device, 8),
+            Value::Counter32(shared.counters.get(Counter::HttpRestarts)),
+        ),
+        (
+            scalar(&device, 9),
+            Value::OctetString(env!("CARGO_PKG_VERSION").as_bytes().to_vec()),
+        ),
+        (scalar(&device, 10), Value::truth(shared.is_warming_up())),
+    ]);
+    if let Some(rssi) = shared.wifi.lock().unwrap().rssi {
+        mib.push((scalar(&device, 4), Value::Integer(rssi.into())));
+    }
+    mib
+}
+
+/// Join the LoRaWAN network, then send each new measurement as an uplink
+fn lorawan_uplinks(
+    mut radio: Sx1276,
+    keys: &Keys,
+    spreading_factor: u8,
+    shared: &Shared,
+) -> anyhow::Result<()> {
+    // Of the next transmission, within the duty cycle
+    let mut next = Instant::now();
+    let mut attempt = 0;
+    let mut session = loop {
+        // Reaching further every third attempt
+        let spreading_factor = spreading_factor.saturating_add(attempt / 3).min(12);
+        attempt = attempt.saturating_add(1);
+        // Random, the network rejects those already used
+        let dev_nonce = unsafe { esp_random() } as u16;
+        let (sent, frequency) = lorawan_transmit(
+            &mut radio,
+            spreading_factor,
+            &keys.join_request(dev_nonce),
+            &mut next,
+        )?;
+        let windows = [
+            (lorawan::JOIN_ACCEPT_DELAY, frequency, spreading_factor),
+            (
+                lorawan::JOIN_ACCEPT_DELAY + Duration::from_secs(1),
+                lorawan::RX2_FREQUENCY,
+                lorawan::RX2_SPREADING_FACTOR,
+            ),
+        ];
+        let mut accepted = None;
+        for (delay, frequency, spreading_factor) in windows {
+            let opening = (sent + delay).saturating_duration_since(Instant::now());
+            std::thread::sleep(opening.saturating_sub(LORAWAN_RX_MARGIN));
+            if let Some(frame) = radio.receive(frequency, spreading_factor)? {
+                accepted = keys.accept(&frame, dev_nonce);
+                if accepted.is_some() {
+                    break;
+                }
+            }
+        }
+        match accepted {
+            Some(session) => break session,
+            None => {
+                log::warn!("No LoRaWAN join accept at SF{spreading_factor}");
+                next = next.max(sent + LORAWAN_JOIN_RETRY);
+            }
+        }
+    };
+    log::info!("Joined the LoRaWAN network as {:08X}", session.dev_addr);
+    loop {
+        block_on(shared.new_measurement.wait());
+        // Sent when the duty cycle allows, with the latest values then
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+        let Some(latest) = *shared.measurement.lock().unwrap() else {
+            continue;
+        };
+        let payload = lorawan_payload(&latest, shared);
+        let fcnt = session.fcnt_up;
+        lorawan_transmit(
+            &mut radio,
+            spreading_factor,
+            &session.uplink(&payload),
+            &mut next,
+        )?;
+        log::info!("LoRaWAN uplink {fcnt} sent, {} bytes", payload.len());
+    }
+}
+
+/// Send `frame` on a random default channel once the duty cycle allows it,
+/// at `next`, which is then moved. Returns when it was sent and on which
+/// frequency.
+fn lorawan_transmit(
+    radio: &mut Sx1276,
+    spreading_factor: u8,
+    frame: &[u8],
+    next: &mut Instant,
+) -> anyhow::Result<(Instant, u32)> {
+    std::thread::sleep(next.saturating_duration_since(Instant::now()));
+    let channel = unsafe { esp_random() } as usize % lorawan::CHANNELS.len();
+    let frequency = lorawan::CHANNELS[channel];
+    radio.transmit(frequency, spreading_factor, frame)?;
+    let sent = Instant::now();
+    *next = sent + lorawan::time_on_air(spreading_factor, frame.len()) * (lorawan::DUTY_CYCLE - 1);
+    Ok((sent, frequency))
+}
+
+/// CayenneLPP payload of `latest` and of the other sensors, those unknown
+/// are left out
+fn lorawan_payload(latest: &Latest, shared: &Shared) -> Vec<u8> {
+    let mut payload = cayenne::Payload::default();
+    payload.analog_input(LPP_PM25, reading::from_tenths(latest.vals.pm25()));
+    payload.analog_input(LPP_PM10, reading::from_tenths(latest.vals.pm10()));
+    if let Some(climate) = shared.climate() {
+        payload.temperature(LPP_TEMPERATURE, climate.temperature);
+        payload.humidity(LPP_HUMIDITY, climate.humidity);
+    }
+    if let Some(ppm) = *shared.co2.lock().unwrap() {
+        payload.concentration(LPP_CO2, ppm);
+    }
+    if let Some(index) = shared.voc(VocKind::Sgp40) {
+        payload.analog_input(LPP_VOC_INDEX, index.into());
+    }
+    payload.into_bytes()
+}
+
+/// OpenAPI document of the endpoints registered by `start_server`, served
+/// on `/api/openapi.json`. The schemas are inferred from examples of the
+/// payloads, their optional fields set so that their type is known.
+fn api_document(ctx: &ServerContext<'_>) -> serde_json::Value {
+    let shared = &ctx.shared;
+    let measurement = MeasurementJson {
+        pm25: 0.0,
+        pm10: 0.0,
+        age_seconds: Some(0),
+        stale: false,
+        measured_at: Some(String::new()),
+        uptime_ms: Some(0),
+        warming_up: false,
+        trend: Some(Trend {
+            direction: Direction::Steady,
+            slope: 0.0,
+        }),
+        co2: Some(0),
+        voc_index: Some(0),
+        tvoc: Some(0),
+        temperature: Some(0.0),
+        humidity: Some(0.0),
+    };
+    let values = pipeline::Values {
+        pm25: 0.0,
+        pm10: 0.0,
+        aqi: Some(0),
+    };
+    let pipeline = PipelineJson {
+        stages: pipeline::DEFAULT_STAGES.to_vec(),
+        last: Some(Trace {
+            input: vec![Some(values)],
+            stages: vec![StageOutput {
+                stage: Stage::Aqi,
+                values: vec![Some(values)],
+            }],
+        }),
+    };
+    let health = Health {
+        name: Some(String::new()),
+        ..Health::new(shared)
+    };
+    let sample = Sample {
+        timestamp: 0,
+        pm25: 0,
+        pm10: 0,
+    };
+    let peer = Peer {
+        name: String::new(),
+        address: Some(String::new()),
+        measurement: Some(PeerMeasurement {
+            pm25: 0.0,
+            pm10: 0.0,
+            age_seconds: Some(0),
+            stale: false,
+        }),
+        error: Some(String::new()),
+    };
+    let sensor = SensorInfo {
+        model: SensorKind::Sds011,
+        device_id: Some(0),
+        firmware: Some(String::new()),
+        working_period: Some(0),
+    };
+    let device = Device {
+        address: 0,
+        model: Some(""),
+    };
+    // Only the types end up in the document, not the secrets of `cfg.toml`
+    let settings = Settings {
+        mqtt_measurement_qos: Some(0),
+        mqtt_measurement_retain: Some(false),
+        mqtt_sensor_qos: Some(0),
+        mqtt_sensor_retain: Some(false),
+        mqtt_domoticz: vec![DomoticzDevice {
+            kind: Kind::Pm25,
+            idx: 1,
+        }],
+        mqtt_profiles: vec![mqtt::Profile {
+            topic: "plc".to_string(),
+            encoding: Encoding::Tenths,
+        }],
+        cors_origins: vec![String::new()],
+        ..Settings::default()
+    };
+    let upload = https::Upload {
+        certificate: String::new(),
+        private_key: String::new(),
+    };
+    let log_levels = loglevel::to_json(&[LevelFilter::Info; 4]);
+    let identity = serde_json::json!({ "device_id": "office-3", "api_key": "" });
+    let provisioned = shared.identity.is_provisioned();
+    let mut endpoints = vec![
+        Endpoint::get(
+            "/api/measurement",
+            "Latest measurement, null before the first one",
+        )
+        .response(openapi::schema(&[measurement])),
+        Endpoint::get(
+            "/api/history",
+            "PM samples of the last hours, in tenths of µg/m³",
+        )
+        .query(
+            "tier",
+            "raw for the last 24h, the default, 15m or 1h for the averages of the last 7 or 30 days",
+        )
+        .response(openapi::schema(&[vec![sample]])),
+        Endpoint::get(
+            "/api/export",
+            "History of the measurements in µg/m³, for spreadsheets",
+        )
+        .query("format", "csv, the default, or json")
+        .query(
+            "from",
+            "Unix timestamp or RFC 3339 date, the oldest by default",
+        )
+        .query(
+            "to",
+            "Unix timestamp or RFC 3339 date, the latest by default",
+        )
+        .content("text/csv"),
+        Endpoint::get(
+            "/api/pipeline",
+            "Stages of the processing with the values of the last measurement after each one",
+        )
+        .response(openapi::schema(&[pipeline])),
+        Endpoint::get("/api/health", "State of the device").response(openapi::schema(&[health])),
+        Endpoint::get("/metrics", "Counters of the events since boot, for Prometheus")
+            .content("text/plain"),
+        Endpoint::get("/api/version", "Build of the firmware")
+            .response(openapi::schema(&[BuildInfo::current()])),
+        Endpoint::get("/api/openapi.json", "This document"),
+        Endpoint::get("/api/peers", "Stations found on the LAN")
+            .response(openapi::schema(&[vec![peer]])),
+        Endpoint::get("/api/sensors", "Particle sensors")
+            .response(openapi::schema(&[vec![sensor]])),
+        Endpoint::post("/api/sensors", "Queue a management command for a sensor")
+            .query("index", "Of the sensor, 0 by default")
+            .request(openapi::schema(&[
+                SensorCommand::SetDeviceId { id: 0 },
+                SensorCommand::SetWorkingPeriod { minutes: 0 },
+            ]))
+            .status(202)
+            .authenticated_if(provisioned),
+        Endpoint::get("/api/i2c/scan", "Scan the I2C bus")
+            .response(openapi::schema(&[vec![device]])),
+        Endpoint::post("/api/restart", "Restart the device")
+            .status(202)
+            .authenticated(),
+        Endpoint::post("/api/identify", "Blink the LED")
+            .status(202)
+            .authenticated(),
+        Endpoint::get("/api/loglevel", "Log level of each module")
+            .response(openapi::schema(&[&log_levels])),
+        Endpoint::post("/api/loglevel", "Change the log level of some modules")
+            .request(openapi::partial(openapi::schema(&[&log_levels])))
+            .response(openapi::schema(&[&log_levels]))
+            .authenticated(),
+        Endpoint::post("/api/measure", "Measure right away")
+            .status(202)
+            .authenticated(),
+        Endpoint::get("/api/config", "Settings").response(openapi::schema(&[&settings])),
+        Endpoint::post(
+            "/api/config",
+            "Update some settings, applied on next restart",
+        )
+        .request(openapi::partial(openapi::schema(&[&settings])))
+        .response(openapi::schema(&[&settings]))
+        .authenticated_if(provisioned),
+        Endpoint::post("/api/wifi/ca", "Set the WPA2-Enterprise CA certificate")
+            .request_text("application/x-pem-file")
+            .authenticated_if(provisioned),
+        Endpoint::delete("/api/wifi/ca", "Remove the WPA2-Enterprise CA certificate")
+            .authenticated_if(provisioned),
+        Endpoint::post("/api/https", "Set the HTTPS certificate and private key")
+            .request(openapi::schema(&[upload]))
+            .authenticated_if(provisioned),
+        Endpoint::delete("/api/https", "Revert to the self-signed HTTPS certificate")
+            .authenticated_if(provisioned),
+        Endpoint::post(
+            "/api/identity",
+            "Provision the device ID and API key, applied on next restart",
+        )
+        .request(openapi::schema(&[&identity]))
+        .authenticated(),
+        Endpoint::delete("/api/identity", "Forget the device ID and API key")
+            .authenticated(),
+    ];
+    if let Some(relay) = shared.relay() {
+        endpoints.extend([
+            Endpoint::get("/api/relay", "State of the relay").response(openapi::schema(&[relay])),
+            Endpoint::post("/api/relay", "Set the mode of the relay")
+                .request(openapi::schema(&[RelayRequest {
+                    mode: relay::Mode::Auto,
+                }]))
+                .response(openapi::schema(&[relay]))
+                .authenticated(),
+        ]);
+    }
+    if shared.fan.is_some() {
+        let fan = FanJson {
+            duty: 0,
+            boost_remaining_seconds: Some(0),
+        };
+        endpoints.extend([
+            Endpoint::get("/api/fan", "Speed of the fan").response(openapi::schema(&[&fan])),
+            Endpoint::post("/api/fan", "Start or end a boost of the fan")
+                .request(openapi::schema(&[FanRequest { boost: true }]))
+                .response(openapi::schema(&[&fan]))
+                .authenticated(),
+        ]);
+    }
+    if image::FACTORY_AVAILABLE {
+        endpoints.push(
+            Endpoint::get(
+                "/api/firmware",
+                "Factory image, to be flashed on another device",
+            )
+            .content("application/octet-stream")
+            .authenticated(),
+        );
+    }
+    if shared.co2_kind.is_some() {
+        endpoints.push(
+            Endpoint::post("/api/co2", "Queue a calibration command for the CO2 sensor")
+                .request(openapi::schema(&[
+                    Co2Command::SetAbc { enabled: true },
+                    Co2Command::CalibrateZero,
+                ]))
+                .status(202)
+                .authenticated(),
+        );
+    }
+    #[cfg(feature = "sdcard")]
+    if ctx.sdcard_mounted {
+        let file = sdlog::LogFile {
+            name: String::new(),
+            size: 0,
+        };
+        endpoints.push(
+            Endpoint::get(
+                "/api/logs",
+                "Log files of the SD card, or one of them as CSV",
+            )
+            .query("file", "Name of the file to download")
+            .response(openapi::schema(&[vec![file]])),
+        );
+    }
+    openapi::document(
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        &endpoints,
+    )
+}
+
+/// Measure every `interval`, the sensor sleeps in between. The PM1006 has
+/// no sleep command: it measures continuously as long as it is powered and
+/// is only read every `interval`, its fan duty cycle is up to whatever
+/// powers it, e.g. the VINDRIKTNING board.
+///
+/// The first `warmup` measurements are discarded, each one followed right
+/// away by the next. The averages of the sensors are recorded by
+/// `on_measurement` then fed to the `sinks`.
+async fn measure_task(
+    index: usize,
+    sensor: &mut Sensor,
+    timer: &mut EspAsyncTimer,
+    interval: Duration,
+    mut warmup: u8,
+    shared: &Shared,
+    on_measurement: &impl Fn(&Latest),
+    sinks: &RefCell<Dispatcher<Latest>>,
+) -> Result<()> {
+    let shutdown = shutdown::register(format!("Sensor {index}"));
+    let mut failures = 0;
+    loop {
+        // Already measuring
+        shared.measure_now[index].reset();
+        let awake = shared.stay_awake();
+        let measured = match select(sensor.measure(timer), shutdown.requested()).await {
+            Either::First(measured) => measured,
+            // Its fan would run until the restart
+            Either::Second(()) => return sleep_sensor(index, sensor, timer, &shutdown).await,
+        };
+        // The measure completes as soon as the frame is parsed
+        let sampled = clock::Timestamp::now();
+        let vals = match measured {
+            Ok(vals) => {
+                log::info!("Sensor {index} measured: {vals}");
+                failures = 0;
+                Some(vals)
+            }
+            Err(e) => {
+                log::error!("Unable to measure particles with sensor {index}: {e:?}");
+                shared.counters.increment(Counter::SensorErrors);
+                failures += 1;
+                None
+            }
+        };
+        // A sensor can get stuck in the middle of a frame, or the UART
+        // receive buffer overflow, until the line is reset
+        if failures >= SENSOR_REINIT_FAILURES {
+            log::warn!("Sensor {index} failed {failures} times, initializing it again");
+            failures = 0;
+            if let Err(e) = sensor.serial().clear_rx() {
+                log::error!("Unable to clear the UART of sensor {index}: {e:?}");
+            }
+            if let Err(e) = sensor.reinit(timer).await {
+                log::error!("Unable to initialize sensor {index} again: {e:?}");
+            }
+        }
+        drop(awake);
+        shared.uart.lock().unwrap()[index] = sensor.uart_stats();
+        if warmup > 0 {
+            if let Some(vals) = vals {
+                warmup -= 1;
+                log::info!("Sensor {index} warming up, discarded {vals}");
+                if warmup == 0 {
+                    log::info!("Sensor {index} warmed up");
+                    shared.warming_up[index].store(false, Ordering::Relaxed);
+                }
+                continue;
+            }
+        } else if let Some(latest) = shared.report(index, vals, sampled) {
+            log::info!("Particle sensors measured: {}", latest.vals);
+            on_measurement(&latest);
+            *shared.measurement.lock().unwrap() = Some(latest);
+            retained::save(&latest.vals, latest.measured_at);
+            sinks.borrow_mut().dispatch(&latest, Instant::now());
+        }
+        // Management commands are run while the sensor sleeps
+        let next_measure = Instant::now() + shared.measure_interval(interval);
+        loop {
+            let wait = next_measure.saturating_duration_since(Instant::now());
+            let command = match select4(
+                timer.after(wait),
+                shared.commands[index].receive(),
+                shared.measure_now[index].wait(),
+                shutdown.requested(),
+            )
+            .await
+            {
+                Either4::First(result) => break result?,
+                Either4::Second(command) => command,
+                Either4::Third(()) => {
+                    log::info!("Sensor {index} measuring now");
+                    break;
+                }
+                Either4::Fourth(()) => return sleep_sensor(index, sensor, timer, &shutdown).await,
+            };
+            log::info!("Sensor {index} command {command:?}");
+            let _awake = shared.stay_awake();
+            match sensor.command(command, timer).await {
+                Ok(()) => {
+                    log::info!("Sensor {index}: {sensor}");
+                    shared.sensors.lock().unwrap()[index] = sensor.info();
+                    shared.sensors_changed.signal(());
+                    shared.ws_clients.broadcast(&WsEvent::sensors(shared));
+                }
+                Err(e) => log::error!("Sensor {index} command failed: {e:?}"),
+            }
+        }
+    }
+}
+
+/// Put a sensor to sleep before the restart, then wait for it
+async fn sleep_sensor(
+    index: usize,
+    sensor: &mut Sensor,
+    timer: &mut EspAsyncTimer,
+    shutdown: &Participant,
+) -> Result<()> {
+    if let Err(e) = sensor.sleep(timer).await {
+        log::error!("Unable to put sensor {index} to sleep: {e:?}");
+    }
+    shutdown.done();
+    core::future::pending().await
+}
+
+/// Watch the free heap, and restart the HTTP server when it runs low rather
+/// than failing an allocation in the middle of a publication. Restart the
+/// whole device in its `maintenance` window, the history saved first.
+async fn monitor_task(
+    mut timer: EspAsyncTimer,
+    server: &mut Option<EspHttpServer<'static>>,
+    ctx: &ServerContext<'_>,
+    maintenance: Option<Maintenance>,
+    storage_mounted: bool,
+) -> Result<()> {
+    let mut last_restart: Option<Instant> = None;
+    loop {
+        timer.after(MONITOR_INTERVAL).await?;
+        if maintenance.is_some_and(|maintenance| maintenance.is_due(clock::now(), clock::uptime()))
+        {
+            log::info!(
+                "Maintenance restart after {} hours up",
+                clock::uptime().as_secs() / 3600
+            );
+            if storage_mounted {
+                if let Err(e) = ctx
+                    .shared
+                    .history
+                    .lock()
+                    .unwrap()
+                    .save(storage::HISTORY_PATH)
+                {
+                    log::error!("Unable to save history: {e:?}");
+                }
+            }
+            task::restart_after(Duration::ZERO);
+            return core::future::pending().await;
+        }
+        let memory = resources::memory();
+        if server.is_some() {
+            if memory.free_heap >= LOW_HEAP_THRESHOLD
+                || last_restart.is_some_and(|at| at.elapsed() < HTTP_RESTART_COOLDOWN)
+            {
+                continue;
+            }
+            log::warn!(
+                "Low memory, {} bytes free, restarting the HTTP server",
+                memory.free_heap
+            );
+            drop(server.take());
+            // Their senders refer to the stopped server
+            ctx.shared.ws_clients.clear();
+            last_restart = Some(Instant::now());
+            ctx.shared.counters.increment(Counter::HttpRestarts);
+        }
+        match start_server(ctx) {
+            Ok(started) => *server = Some(started),
+            // Retried on the next check
+            Err(e) => log::error!("Unable to start the HTTP server: {e:?}"),
+        }
+    }
+}
+
+/// Blink the LED with the color of the last measurement, and keep an eye on
+/// the network connection and the app stability.
+async fn blink_task(
+    led: &mut dyn StatusIndicator,
+    mut timer: EspAsyncTimer,
+    network: &mut Network,
+    settings: &Settings,
+    led_brightness: u8,
+    shared: &Shared,
+    crash_counter: &mut CrashCounter,
+) -> Result<()> {
+    let started = Instant::now();
+    let roam_interval = (settings.wifi_roam_interval_secs > 0)
+        .then(|| Duration::from_secs(settings.wifi_roam_interval_secs.into()));
+    let mut last_roam_check = Instant::now();
+    let mut connected = true;
+    let shutdown = shutdown::register("LED");
+    loop {
+        match select4(
+            timer.after(BLINK_INTERVAL),
+            shared.identify.wait(),
+            shared.provision.wait(),
+            shutdown.requested(),
+        )
+        .await
+        {
+            Either4::First(result) => result?,
+            Either4::Second(()) => {
+                identify(led, &mut timer).await?;
+                continue;
+            }
+            Either4::Third((ssid, password)) => {
+                let provisioned = match network {
+                    Network::Wifi(wifi) => wifi::provision(wifi, &ssid, &password).await,
+                    Network::Ethernet(_) => Err(anyhow::anyhow!("Connected by Ethernet")),
+                };
+                if let Ok(ip) = provisioned {
+                    *shared.url.lock().unwrap() = Some(improv::url(settings.https_enabled, ip));
+                }
+                shared
+                    .provisioned
+                    .signal(provisioned.map_err(|e| format!("{e:#}")));
+                continue;
+            }
+            Either4::Fourth(()) => {
+                led.set(BLACK, 0).map_err(Error::Other)?;
+                shutdown.done();
+                return core::future::pending().await;
+            }
+        }
+        if started.elapsed() >= recovery::STABLE_UPTIME {
+            crash_counter.reset().map_err(Error::Other)?;
+        }
+        match network {
+            Network::Wifi(wifi) => {
+                if !wifi.is_connected().unwrap_or(false) {
+                    if connected {
+                        shared.counters.increment(Counter::WifiDisconnects);
+                        connected = false;
+                    }
+                    log::warn!("Wi-Fi disconnected, reconnecting");
+                    // The access point chosen when roaming may be gone
+                    if let Err(e) = wifi::unpin_access_point(wifi) {
+                        log::error!("Unable to reset the Wi-Fi access point: {e:?}");
+                    }
+                    match wifi.connect().await {
+                        Ok(()) => {
+                            shared.counters.increment(Counter::WifiReconnects);
+                            connected = true;
+                        }
+                        Err(e) => log::error!("Unable to reconnect Wi-Fi: {e:?}"),
+                    }
+                } else if roam_interval
+                    .is_some_and(|interval| last_roam_check.elapsed() >= interval)
+                {
+                    last_roam_check = Instant::now();
+                    match wifi::roam(wifi).await {
+                        Ok(true) => shared.counters.increment(Counter::WifiRoams),
+                        Ok(false) => {}
+                        Err(e) => log::warn!("Unable to roam: {e:?}"),
+                    }
+                }
+                shared.wifi.lock().unwrap().update(wifi);
+            }
+            // The driver brings the link back up when the cable is plugged
+            // in again
+            Network::Ethernet(eth) => {
+                if eth.is_connected().unwrap_or(false) != connected {
+                    connected = !connected;
+                    log::warn!("Ethernet link {}", if connected { "up" } else { "down" });
+                }
+            }
+        }
+        shared.link_up.store(connected, Ordering::Relaxed);
+        if shared.period() == Period::Quiet {
+            continue;
+        }
+        if shared.is_warming_up() {
+            // No level yet, only the blue flash
+            led.set(BLUE, led_brightness).map_err(Error::Other)?;
+            timer.after(Duration::from_millis(50)).await?;
+            led.set(BLACK, 0).map_err(Error::Other)?;
+            continue;
+        }
+        let latest = *shared.measurement.lock().unwrap();
+        if !led.has_colors() {
+            blink_code(led, &mut timer, settings, latest, led_brightness, shared).await?;
+            continue;
+        }
+        let color = latest
+            .map(|latest| level_color(settings, &latest.readings()))
+            .unwrap_or(GREEN);
+        if shared
+            .trend
+            .lock()
+            .unwrap()
+            .is_some_and(|trend| trend.is_rising_fast())
+        {
+            // Rising fast: the color pulses instead of flashing
+            for level in PULSE_LEVELS {
+                let level = (u16::from(led_brightness) * u16::from(level) / 255) as u8;
+                led.set(color, level).map_err(Error::Other)?;
+                timer.after(Duration::from_millis(40)).await?;
+            }
+        } else {
+            led.set(color, led_brightness).map_err(Error::Other)?;
+            timer.after(Duration::from_millis(50)).await?;
+        }
+        if latest.is_some_and(|latest| latest.is_stale(shared.max_age)) {
+            // Stale data: the color blinks twice, without the blue
+            led.set(BLACK, 0).map_err(Error::Other)?;
+            timer.after(Duration::from_millis(200)).await?;
+            led.set(color, led_brightness).map_err(Error::Other)?;
+        } else {
+            match shared.uplink().connectivity() {
+                Connectivity::Online => led.set(BLUE, led_brightness).map_err(Error::Other)?,
+                // Without the blue
+                Connectivity::Offline => led.set(BLACK, 0).map_err(Error::Other)?,
+                // The blue flashes twice
+                Connectivity::Degraded => {
+                    led.set(BLUE, led_brightness).map_err(Error::Other)?;
+                    timer.after(Duration::from_millis(50)).await?;
+                    led.set(BLACK, 0).map_err(Error::Other)?;
+                    timer.after(Duration::from_millis(200)).await?;
+                    led.set(BLUE, led_brightness).map_err(Error::Other)?;
+                }
+            }
+        }
+        timer.after(Duration::from_millis(50)).await?;
+        led.set(BLACK, 0).map_err(Error::Other)?;
+    }
+}
+
+/// Flash a single color LED once per PM2.5 level, from once when it is low
+/// to 3 times, or once longer when the measurement is stale
+async fn blink_code(
+    led: &mut dyn StatusIndicator,
+    timer: &mut EspAsyncTimer,
+    settings: &Settings,
+    latest: Option<Latest>,
+    led_brightness: u8,
+    shared: &Shared,
+) -> Result<()> {
+    if latest.is_some_and(|latest| latest.is_stale(shared.max_age)) {
+        led.set(WHITE, led_brightness).map_err(Error::Other)?;
+        timer.after(LED_STALE_FLASH).await?;
+        led.set(BLACK, 0).map_err(Error::Other)?;
+        return Ok(());
+    }
+    let level = latest.map_or(0, |latest| led::level(settings, &latest.readings()));
+    for flash in 0..=level {
+        if flash > 0 {
+            timer.after(LED_CODE_GAP).await?;
+        }
+        led.set(WHITE, led_brightness).map_err(Error::Other)?;
+        timer.after(LED_CODE_FLASH).await?;
+        led.set(BLACK, 0).map_err(Error::Other)?;
+    }
+    Ok(())
+}
+
+/// Sound the buzzer while the measurement is above the alarm levels, again
+/// every `repeat` and never during its quiet hours or those of the schedule
+async fn buzzer_task(
+    buzzer: Option<&mut LedcDriver<'_>>,
+    mut timer: EspAsyncTimer,
+    alarm: Alarm,
+    shared: &Shared,
+) -> Result<()> {
+    let Some(buzzer) = buzzer else {
+        return core::future::pending().await;
+    };
+    let mut last_alarm: Option<Instant> = None;
+    loop {
+        timer.after(BUZZER_CHECK_INTERVAL).await?;
+        let latest = *shared.measurement.lock().unwrap();
+        let severe = latest.is_some_and(|latest| {
+            !latest.is_stale(shared.max_age) && alarm.is_severe(&latest.readings())
+        });
+        if !severe {
+            // Sound right away the next time
+            last_alarm = None;
+            continue;
+        }
+        if alarm.is_quiet(clock::now())
+            || shared.period() == Period::Quiet
+            || last_alarm.is_some_and(|at| at.elapsed() < alarm.repeat)
+        {
+            continue;
+        }
+        log::warn!("Severe pollution, sounding the buzzer");
+        last_alarm = Some(Instant::now());
+        for _ in 0..BUZZER_BEEPS {
+            buzzer.set_duty(buzzer.get_max_duty() / 2)?;
+            timer.after(Duration::from_millis(500)).await?;
+            buzzer.set_duty(0)?;
+            timer.after(Duration::from_millis(500)).await?;
+        }
+    }
+}
+
+/// Drive the fan at the duty cycle following PM2.5 and the boosts
+async fn fan_task(
+    driver: Option<&mut LedcDriver<'_>>,
+    mut timer: EspAsyncTimer,
+    shared: &Shared,
+) -> Result<()> {
+    let (Some(driver), Some(fan)) = (driver, &shared.fan) else {
+        return core::future::pending().await;
+    };
+    let mut current = None;
+    loop {
+        let duty = fan.lock().unwrap().duty(Instant::now());
+        if current != Some(duty) {
+            driver.set_duty(driver.get_max_duty() * u32::from(duty) / 100)?;
+            log::info!("Fan at {duty}%");
+            current = Some(duty);
+            shared.outputs_changed.signal(());
+        }
+        match select(timer.after(FAN_CHECK_INTERVAL), shared.fan_changed.wait()).await {
+            Either::First(result) => result?,
+            // The boost may have changed without changing the duty cycle
+            Either::Second(()) => shared.outputs_changed.signal(()),
+        }
+    }
+}
+
+/// Read the CO2 sensor every `interval`, running the calibration commands
+/// in between
+async fn co2_task(
+    sensor: Option<&mut Co2>,
+    mut timer: EspAsyncTimer,
+    interval: Duration,
+    shared: &Shared,
+) -> Result<()> {
+    let Some(sensor) = sensor else {
+        return core::future::pending().await;
+    };
+    loop {
+        let awake = shared.stay_awake();
+        let ppm = match sensor.measure(&mut timer).await {
+            Ok(ppm) => {
+                log::info!("CO2 sensor measured: {ppm} ppm");
+                Some(ppm)
+            }
+            Err(e) => {
+                log::error!("Unable to measure CO2: {e:?}");
+                shared.counters.increment(Counter::Co2Errors);
+                None
+            }
+        };
+        drop(awake);
+        *shared.co2.lock().unwrap() = ppm;
+        let next_measure = Instant::now() + shared.measure_interval(interval);
+        loop {
+            let wait = next_measure.saturating_duration_since(Instant::now());
+            let command = match select(timer.after(wait), shared.co2_commands.receive()).await {
+                Either::First(result) => break result?,
+                Either::Second(command) => command,
+            };
+            log::info!("CO2 sensor command {command:?}");
+            if let Err(e) = sensor.command(command, &mut timer).await {
+                log::error!("CO2 sensor command failed: {e:?}");
+            }
+        }
+    }
+}
+
+/// Measure the VOC sensor every second, compensated with the DHT22 values
+/// or `compensation` without them. Its baseline is restored once the clock
+/// tells whether the saved one is recent, and saved every hour.
+async fn voc_task(
+    sensor: Option<&mut Voc>,
+    mut timer: EspAsyncTimer,
+    compensation: Compensation,
+    mut store: BaselineStore,
+    shared: &Shared,
+) -> Result<()> {
+    let Some(sensor) = sensor else {
+        return core::future::pending().await;
+    };
+    let mut restored = false;
+    let mut last_save = Instant::now();
+    loop {
+        timer.after(VOC_INTERVAL).await?;
+        let compensation = shared.climate().unwrap_or(compensation);
+        let value = match sensor.measure(compensation, &mut timer).await {
+            Ok(value) => value,
+            Err(e) => {
+                log::error!("Unable to measure VOC: {e:?}");
+                shared.counters.increment(Counter::VocErrors);
+                None
+            }
+        };
+        *shared.voc.lock().unwrap() = value;
+        let Some(now) = clock::now().map(|now| now.timestamp()) else {
+            continue;
+        };
+        if !restored {
+            restored = true;
+            match store.load() {
+                Ok(Some((baseline, saved_at)))
+                    if now - saved_at <= voc::BASELINE_MAX_AGE.as_secs() as i64 =>
+                {
+                    match sensor.set_baseline(baseline, &mut timer).await {
+                        Ok(()) => log::info!("VOC baseline restored: {baseline:?}"),
+                        Err(e) => log::warn!("Unable to restore the VOC baseline: {e:?}"),
+                    }
+                }
+                Ok(_) => log::info!("No recent VOC baseline to restore"),
+                Err(e) => log::warn!("Unable to load the VOC baseline: {e:?}"),
+            }
+        }
+        if last_save.elapsed() >= VOC_BASELINE_SAVE_INTERVAL {
+            last_save = Instant::now();
+            match sensor.baseline(&mut timer).await {
+                Ok(Some(baseline)) => {
+                    if let Err(e) = store.save(baseline, now) {
+                        log::error!("Unable to save the VOC baseline: {e:?}");
+                    }
+                }
+                // Still learning
+                Ok(None) => {}
+                Err(e) => log::error!("Unable to read the VOC baseline: {e:?}"),
+            }
+        }
+    }
+}
+
+/// Read the DHT22 every `interval`, at most every 2 s. Interrupts are
+/// disabled while reading as the bits are told apart by their timing.
+async fn dht22_task(
+    sensor: Option<&mut Dht>,
+    mut timer: EspAsyncTimer,
+    interval: Duration,
+    shared: &Shared,
+) -> Result<()> {
+    let (Some(sensor), Some(climate)) = (sensor, &shared.climate) else {
+        return core::future::pending().await;
+    };
+    loop {
+        let values = match interrupt::free(|| sensor.read(&mut Ets)) {
+            Ok(values) => {
+                log::info!(
+                    "DHT22 measured: {} °C, {} %",
+                    values.temperature,
+                    values.humidity
+                );
+                Some(values)
+            }
+            Err(e) => {
+                log::error!("Unable to read the DHT22: {e:?}");
+                shared.counters.increment(Counter::Dht22Errors);
+                None
+            }
+        };
+        *climate.lock().unwrap() = values;
+        timer
+            .after(shared.measure_interval(interval).max(dht22::MIN_INTERVAL))
+            .await?;
+    }
+}
+
+/// Flash the red code of an error until `duration` elapsed, at least once,
+/// at full brightness even if the LED is disabled
+fn blink_error(led: &mut dyn StatusIndicator, blinks: usize, duration: Duration) {
+    let started = Instant::now();
+    loop {
+        for _ in 0..blinks {
+            let _ = led.set(RED, u8::MAX);
+            std::thread::sleep(ERROR_FLASH);
+            let _ = led.set(BLACK, 0);
+            std::thread::sleep(ERROR_GAP);
+        }
+        let remaining = duration.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        std::thread::sleep(remaining.min(ERROR_PAUSE));
+    }
+}
+
+/// Flash the LED white, at full brightness even if it is disabled
+async fn identify(led: &mut dyn StatusIndicator, timer: &mut EspAsyncTimer) -> Result<()> {
+    for _ in 0..IDENTIFY_BLINKS {
+        led.set(WHITE, u8::MAX).map_err(Error::Other)?;
+        timer.after(Duration::from_millis(200)).await?;
+        led.set(BLACK, 0).map_err(Error::Other)?;
+        timer.after(Duration::from_millis(200)).await?;
+    }
+    Ok(())
+}
+
+/// Stay connected to the primary broker, or to the secondary one while the
+/// primary one is unreachable, trying it again every
+/// [`MQTT_PRIMARY_RETRY`].
+async fn mqtt_task(
+    settings: &Settings,
+    topics: &Topics<'_>,
+    mut timer: EspAsyncTimer,
+    mut retry_timer: EspAsyncTimer,
+    shared: &Shared,
+    config_store: &SharedConfigStore,
+) -> Result<()> {
+    let failover = !settings.mqtt_secondary_url.is_empty();
+    let mut broker = mqtt::Broker::Primary;
+    loop {
+        *shared.mqtt_broker.lock().unwrap() = Some(broker);
+        let url = match broker {
+            mqtt::Broker::Primary => &settings.mqtt_broker_url,
+            mqtt::Broker::Secondary => &settings.mqtt_secondary_url,
+        };
+        let session = mqtt_session(
+            url,
+            failover,
+            settings,
+            topics,
+            &mut timer,
+            shared,
+            config_store,
+        );
+        let retry_primary = async {
+            match broker {
+                mqtt::Broker::Primary => core::future::pending().await,
+                mqtt::Broker::Secondary => retry_timer.after(MQTT_PRIMARY_RETRY).await,
+            }
+        };
+        match select(session, retry_primary).await {
+            Either::First(result) => {
+                result?;
+                log::warn!(
+                    "The {} MQTT broker is unreachable, switching to the {} one",
+                    broker.name(),
+                    broker.other().name()
+                );
+                shared.counters.increment(Counter::MqttFailovers);
+                broker = broker.other();
+            }
+            Either::Second(result) => {
+                result?;
+                log::info!("Trying the primary MQTT broker again");
+                broker = mqtt::Broker::Primary;
+            }
+        }
+    }
+}
+
+/// Publish each new measurement and receive the sensor commands on the
+/// broker at `url`, while logging the connection events. Returns once
+/// [`MQTT_FAILOVER_ATTEMPTS`] connections failed in a row, with `failover`.
+async fn mqtt_session(
+    url: &str,
+    failover: bool,
+    settings: &Settings,
+    topics: &Topics<'_>,
+    timer: &mut EspAsyncTimer,
+    shared: &Shared,
+    config_store: &SharedConfigStore,
+) -> Result<()> {
+    let lwt_topic = topics
+        .tasmota_device
+        .map(|device| format!("tele/{device}/LWT"));
+    // The credentials of the URL take precedence
+    let identity = Some(&shared.identity)
+        .filter(|identity| identity.is_provisioned())
+        .filter(|_| !mqtt::url_has_credentials(url));
+    let config = MqttClientConfiguration {
+        client_id: Some(&shared.hostname),
+        username: identity.map(|identity| identity.device_id.as_str()),
+        password: identity.map(|identity| identity.api_key.as_str()),
+        lwt: lwt_topic
+            .as_deref()
+            .map(|topic| (topic, mqtt::TASMOTA_OFFLINE))
+            .or(topics
+                .availability
+                .as_deref()
+                .map(|topic| (topic, topics.availability_payloads().2)))
+            .map(|(topic, payload)| LwtConfiguration {
+                topic,
+                payload: payload.as_bytes(),
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+        ..Default::default()
+    };
+    shared.mqtt_connected.store(false, Ordering::Relaxed);
+    let (mut client, mut connection) =
+        EspAsyncMqttClient::new(url, &config).map_err(Error::mqtt)?;
+    log::info!("MQTT client created, root topic {}", topics.root);
+    let connected = Signal::<CriticalSectionRawMutex, ()>::new();
+
+    // The connection must be polled for the client to make progress
+    let events = async {
+        // Since the last successful one
+        let mut failed_connections = 0;
+        while let Ok(event) = connection.next().await {
+            match event.payload() {
+                EventPayload::Connected(_) => {
+                    log::info!("MQTT connected");
+                    failed_connections = 0;
+                    shared.mqtt_connected.store(true, Ordering::Relaxed);
+                    connected.signal(());
+                }
+                EventPayload::Disconnected => {
+                    log::warn!("MQTT disconnected");
+                    shared.counters.increment(Counter::MqttDisconnects);
+                    shared.mqtt_connected.store(false, Ordering::Relaxed);
+                    // Also reported when a connection fails
+                    failed_connections += 1;
+                    if failover && failed_connections >= MQTT_FAILOVER_ATTEMPTS {
+                        return Ok(());
+                    }
+                }
+                EventPayload::Received {
+                    topic: Some(topic),
+                    data,
+                    ..
+                } => {
+                    if topic == topics.calibration {
+                        match set_calibration(data, config_store) {
+                            Ok(calibration) => {
+                                log::info!("Calibration set: {calibration:?}");
+                                *shared.calibration.lock().unwrap() = calibration;
+                            }
+                            Err(e) => log::warn!("Invalid calibration on {topic}: {e:#}"),
+                        }
+                        continue;
+                    }
+                    if topics.relay.as_deref() == Some(topic) {
+                        let mode = core::str::from_utf8(data)
+                            .map_err(anyhow::Error::from)
+                            .and_then(str::parse::<relay::Mode>);
+                        match mode {
+                            Ok(mode) => {
+                                if let Err(e) = shared.update_relay(|relay| relay.set_mode(mode)) {
+                                    log::error!("Unable to switch the relay: {e:?}");
+                                }
+                            }
+                            Err(e) => log::warn!("Invalid relay mode on {topic}: {e:#}"),
+                        }
+                        continue;
+                    }
+                    if topics.co2_command.as_deref() == Some(topic) {
+                        match serde_json::from_slice::<Co2Command>(data) {
+                            Ok(command) => {
+                                if shared.co2_commands.try_send(command).is_err() {
+                                    log::warn!("Too many pending commands for the CO2 sensor");
+                                }
+                            }
+                            Err(e) => log::warn!("Invalid command on {topic}: {e}"),
+                        }
+                        continue;
+                    }
+                    if topic == topics.log_level {
+                        let set = loglevel::parse(data)
+                            .and_then(|levels| set_log_levels(&levels, shared));
+                        if let Err(e) = set {
+                            log::warn!("Invalid log levels on {topic}: {e:#}");
+                        }
+                        continue;
+                    }
+                    if topics.fan_boost.as_deref() == Some(topic) {
+                        match data {
+                            b"on" => shared.boost_fan(true),
+                            b"off" => shared.boost_fan(false),
+                            _ => log::warn!("Invalid fan boost on {topic}, expected on or off"),
+                        }
+                        continue;
+                    }
+                    let Some(index) = topics.commands.iter().position(|t| t == topic) else {
+                        continue;
+                    };
+                    match serde_json::from_slice::<SensorCommand>(data) {
+                        Ok(command) => {
+                            if shared.commands[index].try_send(command).is_err() {
+                                log::warn!("Too many pending commands for sensor {index}");
+                            }
+                        }
+                        Err(e) => log::warn!("Invalid command on {topic}: {e}"),
+                    }
+                }
+                EventPayload::Error(e) => {
+                    log::warn!("MQTT error: {e:?}");
+                    shared.counters.increment(Counter::MqttErrors);
+                }
+                payload => log::debug!("MQTT event {payload:?}"),
+            }
+        }
+        Err(Error::mqtt(anyhow::anyhow!("MQTT connection closed")))
+    };
+    match select(
+        events,
+        publish_measurements(
+            &mut client,
+            topics,
+            lwt_topic.as_deref(),
+            &connected,
+            settings,
+            timer,
+            shared,
+        ),
+    )
+    .await
+    {
+        Either::First(result) | Either::Second(result) => result,
+    }
+}
+
+async fn publish_measurements(
+    client: &mut EspAsyncMqttClient,
+    topics: &Topics<'_>,
+    lwt_topic: Option<&str>,
+    connected: &Signal<CriticalSectionRawMutex, ()>,
+    settings: &Settings,
+    timer: &mut EspAsyncTimer,
+    shared: &Shared,
+) -> Result<()> {
+    let root_topic = topics.root;
+    let raw_root = format!("{root_topic}/raw");
+    let sensor_count = topics.commands.len();
+    let measurement_flags = mqtt_flags(settings, DataKind::Measurement)?;
+    let sensor_flags = mqtt_flags(settings, DataKind::Sensor)?;
+    let stats_flags = mqtt_flags(settings, DataKind::Stats)?;
+    let event_flags = mqtt_flags(settings, DataKind::Event)?;
+    let flags = |kind| match kind {
+        DataKind::Measurement => measurement_flags,
+        DataKind::Sensor => sensor_flags,
+        DataKind::Stats => stats_flags,
+        DataKind::Event => event_flags,
+    };
+    let min_interval = Duration::from_secs(settings.mqtt_min_interval_secs.into());
+    // Of the measurement in a batch, the latest one
+    let sampled = || {
+        let latest = (*shared.measurement.lock().unwrap())?;
+        Some(clock::Timestamp {
+            uptime: latest.measured?,
+            wall: latest.measured_at,
+        })
+    };
+    let mut batcher = mqtt::Batcher::new(MQTT_BATCH_WINDOW, min_interval);
+    let mut deadband = mqtt::Deadband::new(
+        settings.mqtt_deadband(),
+        Duration::from_secs(settings.mqtt_max_silence_secs.into()),
+    );
+    let encoding = settings.encoding();
+    let heartbeat = !settings.mqtt_fleet_topic.is_empty();
+    let mut next_heartbeat = Instant::now();
+    // Warming up when last published, `None` to publish it again
+    let mut warming_up = None;
+    let shutdown = shutdown::register("MQTT");
+    loop {
+        batcher.set_min_interval(
+            shared
+                .schedule
+                .mqtt_min_interval(shared.period(), min_interval),
+        );
+        let next_due = [batcher.next_due(), heartbeat.then_some(next_heartbeat)]
+            .into_iter()
+            .flatten()
+            .min();
+        let flush = async {
+            match next_due {
+                Some(due) => {
+                    timer
+                        .after(due.saturating_duration_since(Instant::now()))
+                        .await
+                }
+                None => core::future::pending().await,
+            }
+        };
+        let mut measurements = Vec::new();
+        let mut sensors = Vec::new();
+        let mut summaries = Vec::new();
+        let mut events = Vec::new();
+        let mut domoticz = Vec::new();
+        match select4(
+            shared.mqtt_measurements.wait(),
+            connected.wait(),
+            select(shared.sensors_changed.wait(), shared.outputs_changed.wait()),
+            select(flush, shutdown.requested()),
+        )
+        .await
+        {
+            Either4::First(latest) => {
+                let now = Instant::now();
+                sensors.push((
+                    format!("{root_topic}/telemetry"),
+                    serde_json::to_string(&Health::new(shared))?,
+                ));
+                for summary in shared.stats.lock().unwrap().drain(..) {
+                    summaries.push((
+                        format!("{root_topic}/stats/{}", summary.period.topic()),
+                        serde_json::to_string(&summary)?,
+                    ));
+                }
+                for event in shared.limit_events.lock().unwrap().drain(..) {
+                    events.push((
+                        format!("{root_topic}/exceedance/{}", event.metric.topic()),
+                        serde_json::to_string(&event)?,
+                    ));
+                }
+                if let (true, Some(trend)) = (topics.per_kind(), *shared.trend.lock().unwrap()) {
+                    measurements.push((
+                        format!("{root_topic}/trend"),
+                        serde_json::to_string(&trend)?,
+                    ));
+                }
+                let calibration = *shared.calibration.lock().unwrap();
+                // Locked in the same order as the HTTP handlers
+                let sensors = shared.sensors.lock().unwrap();
+                let readings = shared.readings.lock().unwrap();
+                // Timestamped when their frames were parsed
+                let measured_at = |i: usize| readings.sampled[i].and_then(|sampled| sampled.wall);
+                if let Some(device) = topics.tasmota_device {
+                    let values: Vec<(SensorKind, Vec<Reading>)> = sensors
+                        .iter()
+                        .zip(&readings.last)
+                        .enumerate()
+                        .filter_map(|(i, (info, vals))| {
+                            let vals = calibration.apply(&(*vals)?);
+                            Some((info.model, vals.readings(Some(i), measured_at(i)).to_vec()))
+                        })
+                        .collect();
+                    measurements.push(mqtt::tasmota_sensor_message(device, &values));
+                } else if topics.per_kind() && sensor_count > 1 {
+                    // With a single sensor its values are only published as the average
+                    for (i, vals) in readings.last.iter().enumerate() {
+                        if let Some(vals) = vals {
+                            let topic = mqtt::sensor_topic(root_topic, i, sensor_count);
+                            let readings =
+                                calibration.apply(vals).readings(Some(i), measured_at(i));
+                            let readings = deadband.filter(&topic, &readings, now);
+                            measurements.extend(mqtt::messages(&topic, &readings, encoding));
+                            if !calibration.is_identity() {
+                                let topic = mqtt::sensor_topic(&raw_root, i, sensor_count);
+                                let readings = vals.readings(Some(i), measured_at(i));
+                                let readings = deadband.filter(&topic, &readings, now);
+                                measurements.extend(mqtt::messages(&topic, &readings, encoding));
+                            }
+                        }
+                    }
+                }
+                drop((sensors, readings));
+                // Of the other sensors, not part of the Tasmota telemetry
+                let others = shared.other_readings();
+                let averages = || -> Vec<Reading> {
+                    latest
+                        .readings()
+                        .into_iter()
+                        .chain(others.iter().copied())
+                        .collect()
+                };
+                if !topics.domoticz.is_empty() {
+                    domoticz.extend(mqtt::domoticz_messages(topics.domoticz, &averages()));
+                } else if let Some(base) = &topics.homie {
+                    let readings = deadband.filter(base, &averages(), now);
+                    measurements.extend(mqtt::homie_messages(base, &readings));
+                } else if topics.tasmota_device.is_none() {
+                    measurements.extend(latest_messages(
+                        root_topic,
+                        &latest,
+                        &calibration,
+                        encoding,
+                        &mut deadband,
+                        now,
+                    ));
+                    let others = deadband.filter(root_topic, &others, now);
+                    measurements.extend(mqtt::messages(root_topic, &others, encoding));
+                    for profile in &settings.mqtt_profiles {
+                        let base = format!("{root_topic}/{}", profile.topic);
+                        let readings = deadband.filter(&base, &averages(), now);
+                        measurements.extend(mqtt::messages(&base, &readings, profile.encoding));
+                    }
+                }
+                // Otherwise replayed from the history once connected again
+                if let Some(measured_at) = latest.measured_at {
+                    if shared.mqtt_connected.load(Ordering::Relaxed) {
+                        shared
+                            .mqtt_delivered
+                            .store(measured_at.timestamp() as u32, Ordering::Relaxed);
+                    }
+                }
+            }
+            Either4::Second(()) => {
+                if let Some(lwt_topic) = lwt_topic {
+                    client
+                        .publish(
+                            lwt_topic,
+                            QoS::AtLeastOnce,
+                            true,
+                            mqtt::TASMOTA_ONLINE.as_bytes(),
+                        )
+                        .await
+                        .map_err(Error::mqtt)?;
+                }
+                let birth = serde_json::to_string(&Birth::new(settings, shared))?;
+                client
+                    .publish(
+                        &format!("{root_topic}/birth"),
+                        QoS::AtLeastOnce,
+                        true,
+                        birth.as_bytes(),
+                    )
+                    .await
+                    .map_err(Error::mqtt)?;
+                // Right away on each connection
+                next_heartbeat = Instant::now();
+                // Replaced by the LWT if the connection was lost
+                warming_up = None;
+                // The broker may have lost the retained values
+                deadband.clear();
+                if let Some(base) = &topics.homie {
+                    let name = if shared.name.is_empty() {
+                        &shared.hostname
+                    } else {
+                        &shared.name
+                    };
+                    for (topic, payload) in
+                        mqtt::homie_attributes(base, name, &measured_kinds(shared))
+                    {
+                        client
+                            .publish(&topic, QoS::AtLeastOnce, true, payload.as_bytes())
+                            .await
+                            .map_err(Error::mqtt)?;
+                    }
+                }
+                // How to read the values of the profiles
+                let kinds = measured_kinds(shared);
+                for profile in &settings.mqtt_profiles {
+                    let base = format!("{root_topic}/{}", profile.topic);
+                    for (topic, payload) in mqtt::scale_messages(&base, &kinds, profile.encoding) {
+                        client
+                            .publish(&topic, QoS::AtLeastOnce, true, payload.as_bytes())
+                            .await
+                            .map_err(Error::mqtt)?;
+                    }
+                }
+                // Subscriptions don't survive a reconnection
+                for topic in topics
+                    .commands
+                    .iter()
+                    .chain([&topics.calibration, &topics.log_level])
+                    .chain(&topics.relay)
+                    .chain(&topics.fan_boost)
+                    .chain(&topics.co2_command)
+                {
+                    client
+                        .subscribe(topic, QoS::AtLeastOnce)
+                        .await
+                        .map_err(Error::mqtt)?;
+                }
+                // Measured while disconnected, with their original time.
+                // Published right away as the batches only keep the last
+                // value of a topic, and never retained.
+                let delivered = shared.mqtt_delivered.load(Ordering::Relaxed);
+                let backlog: Vec<Sample> = shared
+                    .history
+                    .lock()
+                    .unwrap()
+                    .since(delivered)
+                    .copied()
+                    .collect();
+                for sample in &backlog {
+                    let (topic, payload) = mqtt::replay_message(
+                        root_topic,
+                        &sample.readings(),
+                        sample.timestamp,
+                        encoding,
+                    );
+                    client
+                        .publish(&topic, measurement_flags.0, false, payload.as_bytes())
+                        .await
+                        .map_err(Error::mqtt)?;
+                    shared
+                        .mqtt_delivered
+                        .store(sample.timestamp, Ordering::Relaxed);
+                }
+                if !backlog.is_empty() {
+                    log::info!("Replayed {} measurements to MQTT", backlog.len());
+                }
+                sensors.extend(sensor_messages(root_topic, shared));
+                sensors.extend(output_messages(root_topic, shared)?);
+                // In case the broker lost the retained values, unless they
+                // are too old to be of any use
+                let latest = *shared.measurement.lock().unwrap();
+                if let Some(latest) = latest.filter(|latest| !latest.is_stale(shared.max_age)) {
+                    let now = Instant::now();
+                    if let Some(base) = &topics.homie {
+                        let readings = deadband.filter(base, &latest.readings(), now);
+                        measurements.extend(mqtt::homie_messages(base, &readings));
+                    } else if topics.per_kind() {
+                        let calibration = *shared.calibration.lock().unwrap();
+                        measurements.extend(latest_messages(
+                            root_topic,
+                            &latest,
+                            &calibration,
+                            encoding,
+                            &mut deadband,
+                            now,
+                        ));
+                        for profile in &settings.mqtt_profiles {
+                            let base = format!("{root_topic}/{}", profile.topic);
+                            let readings = deadband.filter(&base, &latest.readings(), now);
+                            measurements.extend(mqtt::messages(&base, &readings, profile.encoding));
+                        }
+                    }
+                }
+            }
+            Either4::Third(Either::First(())) => {
+                sensors.extend(sensor_messages(root_topic, shared))
+            }
+            Either4::Third(Either::Second(())) => {
+                sensors.extend(output_messages(root_topic, shared)?)
+            }
+            Either4::Fourth(Either::Second(())) => {
+                // Lost while disconnected, like any other message
+                if shared.mqtt_connected.load(Ordering::Relaxed) {
+                    let flushed = async {
+                        let mut pending = batcher.take_all();
+                        if settings.mqtt_batch && !pending.is_empty() {
+                            pending = vec![mqtt::batch_message(root_topic, &pending, sampled())];
+                        }
+                        for (topic, payload, kind) in pending {
+                            let (qos, retain) = flags(kind);
+                            client
+                                .publish(&topic, qos, retain, payload.as_bytes())
+                                .await?;
+                        }
+                        // The last will is only sent by the broker when
+                        // the connection is lost
+                        let offline =
+                            lwt_topic
+                                .map(|topic| (topic, mqtt::TASMOTA_OFFLINE))
+                                .or(topics
+                                    .availability
+                                    .as_deref()
+                                    .map(|topic| (topic, topics.shutdown_payload())));
+                        if let Some((topic, payload)) = offline {
+                            client
+                                .publish(topic, QoS::AtLeastOnce, true, payload.as_bytes())
+                                .await?;
+                        }
+                        Ok::<_, EspError>(())
+                    };
+                    if let Err(e) = flushed.await {
+                        log::error!("Unable to flush MQTT before the restart: {e:?}");
+                    }
+                }
+                shutdown.done();
+                return core::future::pending().await;
+            }
+            Either4::Fourth(Either::First(result)) => {
+                result?;
+                let mut due = batcher.take_due(Instant::now());
+                if settings.mqtt_batch && !due.is_empty() {
+                    due = vec![mqtt::batch_message(root_topic, &due, sampled())];
+                }
+                log::debug!("publishing {} messages", due.len());
+                for (topic, payload, kind) in due {
+                    let (qos, retain) = flags(kind);
+                    client
+                        .publish(&topic, qos, retain, payload.as_bytes())
+                        .await
+                        .map_err(Error::mqtt)?;
+                }
+                if heartbeat && Instant::now() >= next_heartbeat {
+                    next_heartbeat = Instant::now() + HEARTBEAT_INTERVAL;
+                    let (topic, payload) = heartbeat_message(settings, shared);
+                    // Only worth it while fresh, neither retried nor retained
+                    client
+                        .publish(&topic, QoS::AtMostOnce, false, payload.as_bytes())
+                        .await
+                        .map_err(Error::mqtt)?;
+                }
+            }
+        }
+        // Before the first measurement, never batched
+        if let Some(topic) = &topics.availability {
+            let now_warming_up = shared.is_warming_up();
+            if shared.mqtt_connected.load(Ordering::Relaxed) && warming_up != Some(now_warming_up) {
+                let (online, warming_up_payload, _) = topics.availability_payloads();
+                let payload = if now_warming_up {
+                    warming_up_payload
+                } else {
+                    online
+                };
+                client
+                    .publish(topic, QoS::AtLeastOnce, true, payload.as_bytes())
+                    .await
+                    .map_err(Error::mqtt)?;
+                warming_up = Some(now_warming_up);
+            }
+        }
+        // Not batched, all the devices share the topic
+        for payload in domoticz {
+            client
+                .publish(
+                    mqtt::DOMOTICZ_TOPIC,
+                    measurement_flags.0,
+                    false,
+                    payload.as_bytes(),
+                )
+                .await
+                .map_err(Error::mqtt)?;
+        }
+        let now = Instant::now();
+        for (topic, payload) in measurements {
+            batcher.push(topic, payload, DataKind::Measurement, now);
+        }
+        for (topic, payload) in sensors {
+            batcher.push(topic, payload, DataKind::Sensor, now);
+        }
+        for (topic, payload) in summaries {
+            batcher.push(topic, payload, DataKind::Stats, now);
+        }
+        for (topic, payload) in events {
+            batcher.push(topic, payload, DataKind::Event, now);
+        }
+    }
+}
+
+/// Topic and payload of the heartbeat on the fleet topic
+fn heartbeat_message(settings: &Settings, shared: &Shared) -> (String, String) {
+    let latest = *shared.measurement.lock().unwrap();
+    let heartbeat = mqtt::Heartbeat {
+        mac: &shared.mac,
+        name: if shared.name.is_empty() {
+            &shared.hostname
+        } else {
+            &shared.name
+        },
+        version: env!("CARGO_PKG_VERSION"),
+        rssi: shared.wifi.lock().unwrap().rssi,
+        last_measure_age_secs: latest
+            .and_then(|latest| latest.age())
+            .map(|age| age.as_secs()),
+    };
+    heartbeat.message(&settings.mqtt_fleet_topic)
+}
+
+/// MQTT topics of the device
+struct Topics<'a> {
+    root: &'a str,
+    /// Management commands of each sensor
+    commands: Vec<String>,
+    /// Changes of the calibration
+    calibration: String,
+    /// Mode of the relay, `None` without a relay
+    relay: Option<String>,
+    /// Boosts of the fan, `None` without a fan
+    fan_boost: Option<String>,
+    /// Changes of the log levels
+    log_level: String,
+    /// Calibration commands of the CO2 sensor, `None` without one
+    co2_command: Option<String>,
+    /// Device name of the Tasmota compatibility mode
+    tasmota_device: Option<&'a str>,
+    /// Devices of the Domoticz mode, none when disabled
+    domoticz: &'a [DomoticzDevice],
+    /// `homie/<device>` in the Homie mode
+    homie: Option<String>,
+    /// Whether the sensors warmed up, `None` in the Tasmota compatibility
+    /// mode which has its own LWT topic. The `$state` of the Homie device.
+    availability: Option<String>,
+}
+
+impl<'a> Topics<'a> {
+    fn new(
+        root: &'a str,
+        tasmota_device: Option<&'a str>,
+        domoticz: &'a [DomoticzDevice],
+        homie: Option<String>,
+        sensor_count: usize,
+        relay: bool,
+        fan: bool,
+        co2: bool,
+    ) -> Self {
+        let commands = (0..sensor_count)
+            .map(|i| format!("{}/command", mqtt::sensor_topic(root, i, sensor_count)))
+            .collect();
+        Self {
+            root,
+            commands,
+            calibration: format!("{root}/calibration"),
+            relay: relay.then(|| format!("{root}/relay/set")),
+            fan_boost: fan.then(|| format!("{root}/fan/boost")),
+            log_level: format!("{root}/loglevel"),
+            co2_command: co2.then(|| format!("{root}/co2/command")),
+            tasmota_device,
+            domoticz,
+            availability: match (&homie, tasmota_device) {
+                (Some(base), _) => Some(format!("{base}/$state")),
+                (None, Some(_)) => None,
+                (None, None) => Some(format!("{root}/availability")),
+            },
+            homie,
+        }
+    }
+
+    /// Whether the values are published on their own topics, rather than
+    /// in the Tasmota, Domoticz or Homie format
+    fn per_kind(&self) -> bool {
+        self.tasmota_device.is_none() && self.domoticz.is_empty() && self.homie.is_none()
+    }
+
+    /// Payload of the availability topic on an intentional restart
+    fn shutdown_payload(&self) -> &'static str {
+        if self.homie.is_some() {
+            mqtt::HOMIE_DISCONNECTED
+        } else {
+            mqtt::OFFLINE
+        }
+    }
+
+    /// Payloads of the availability topic when online, warming up and
+    /// offline
+    fn availability_payloads(&self) -> (&'static str, &'static str, &'static str) {
+        if self.homie.is_some() {
+            (mqtt::HOMIE_READY, mqtt::HOMIE_INIT, mqtt::HOMIE_LOST)
+        } else {
+            (mqtt::ONLINE, mqtt::WARMING_UP, mqtt::OFFLINE)
+        }
+    }
+}
+
+/// Applied to the ESP-IDF tags and Rust targets of the modules, by
+/// `esp_log_level_set`
+fn set_log_levels(levels: &[(Module, LevelFilter)], shared: &Shared) -> anyhow::Result<()> {
+    let mut current = shared.log_levels.lock().unwrap();
+    for &(module, level) in levels {
+        for target in module.targets() {
+            esp_idf_svc::log::set_target_level(target, level)?;
+        }
+        current[module as usize] = level;
+        log::info!("Log level of the {} module set to {level}", module.name());
+    }
+    Ok(())
+}
+
+/// Redraws the e-paper display on each measurement, or once it becomes
+/// stale
+fn epaper_updates(mut display: Ssd1680, mut timer: EspAsyncTimer, shared: &Shared) -> ! {
+    loop {
+        let latest = *shared.measurement.lock().unwrap();
+        let history: Vec<u16> = shared
+            .history
+            .lock()
+            .unwrap()
+            .tier(Tier::Raw)
+            .map(|sample| sample.pm25)
+            .collect();
+        let screen = Screen {
+            station: shared.station(),
+            time: latest
+                .and_then(|latest| latest.measured_at)
+                .map(|measured_at| clock::local(measured_at).format("%H:%M").to_string()),
+            pm25: latest.map(|latest| reading::from_tenths(latest.vals.pm25())),
+            pm10: latest.map(|latest| reading::from_tenths(latest.vals.pm10())),
+            stale: latest.map_or(true, |latest| latest.is_stale(shared.max_age)),
+            history: &history,
+        };
+        if let Err(e) = display.show(&epaper::render(&screen)) {
+            log::warn!("Unable to update the e-paper display: {e:?}");
+        }
+        // Past the max age, so that the stale measurement is shown
+        block_on(select(
+            timer.after(shared.max_age + Duration::from_secs(1)),
+            shared.display_changed.wait(),
+        ));
+    }
+}
+
+/// Shows PM2.5, PM10 and CO2 in turn on the 7-segment display, each after
+/// its label, dimmed during the quiet hours
+fn segment_updates(mut display: Box<dyn Segments + Send>, brightness: u8, shared: &Shared) -> ! {
+    let mut failing = false;
+    loop {
+        for page in Page::cycle(shared.co2_kind.is_some()) {
+            let latest = *shared.measurement.lock().unwrap();
+            let latest = latest.filter(|latest| !latest.is_stale(shared.max_age));
+            let value = match page {
+                Page::Pm25 => latest.map(|latest| reading::from_tenths(latest.vals.pm25())),
+                Page::Pm10 => latest.map(|latest| reading::from_tenths(latest.vals.pm10())),
+                Page::Co2 => shared.co2.lock().unwrap().map(f32::from),
+            };
+            let brightness = match shared.period() {
+                Period::Quiet => segment::QUIET_BRIGHTNESS,
+                _ => brightness,
+            };
+            for (digits, duration) in [
+                (page.label(), segment::LABEL_DURATION),
+                (page.value(value), segment::VALUE_DURATION),
+            ] {
+                match display.show(digits, brightness) {
+                    Ok(()) => failing = false,
+                    // Logged once until it answers again
+                    Err(e) if !failing => {
+                        log::warn!("Unable to update the 7-segment display: {e:?}");
+                        failing = true;
+                    }
+                    Err(_) => {}
+                }
+                std::thread::sleep(duration);
+            }
+        }
+    }
+}
+
+/// Kinds of the values measured by the sensors wired, whether they gave one
+/// yet or not
+fn measured_kinds(shared: &Shared) -> Vec<Kind> {
+    let mut kinds = vec![Kind::Pm25, Kind::Pm10];
+    kinds.extend(shared.co2_kind.map(|_| Kind::Co2));
+    kinds.extend(shared.voc_kind.map(VocKind::reading_kind));
+    if shared.climate.is_some() {
+        kinds.extend([Kind::Temperature, Kind::Humidity]);
+    }
+    kinds
+}
+
+/// Topics and payloads of the average of the sensors, with the raw values
+/// under `raw/` when calibrated, those within the deadband left out
+fn latest_messages(
+    root_topic: &str,
+    latest: &Latest,
+    calibration: &Calibration,
+    encoding: Encoding,
+    deadband: &mut mqtt::Deadband,
+    now: Instant,
+) -> Vec<(String, String)> {
+    let readings = deadband.filter(root_topic, &latest.readings(), now);
+    let mut messages = mqtt::messages(root_topic, &readings, encoding);
+    if let (false, Some(raw)) = (calibration.is_identity(), latest.raw) {
+        let raw_topic = format!("{root_topic}/raw");
+        let readings = deadband.filter(&raw_topic, &raw.readings(None, latest.measured_at), now);
+        messages.extend(mqtt::messages(&raw_topic, &readings, encoding));
+    }
+    messages
+}
+
+/// Store the calibration settings of a JSON object, the other settings are
+/// refused
+fn set_calibration(patch: &[u8], config_store: &SharedConfigStore) -> anyhow::Result<Calibration> {
+    let settings: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(patch)?;
+    if let Some(key) = settings
+        .keys()
+        .find(|key| !calibration::SETTINGS.contains(&key.as_str()))
+    {
+        anyhow::bail!("{key} is not a calibration setting");
+    }
+    Ok(config_store
+        .lock()
+        .unwrap()
+        .import_json(patch)?
+        .calibration())
+}
+
+/// QoS and retain flag of a kind of data
+fn mqtt_flags(settings: &Settings, kind: DataKind) -> Result<(QoS, bool)> {
+    let (qos, retain) = settings.mqtt_flags(kind);
+    let qos = match qos {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        2 => QoS::ExactlyOnce,
+        qos => {
+            return Err(Error::config(anyhow::anyhow!(
+                "Invalid MQTT QoS {qos}, expected 0 to 2"
+            )))
+        }
+    };
+    Ok((qos, retain))
+}
+
+/// Model and details of each sensor, and the devices of the I2C bus if
+/// there is one, retained for late subscribers
+fn sensor_messages(root_topic: &str, shared: &Shared) -> Vec<(String, String)> {
+    let sensors = shared.sensors.lock().unwrap();
+    let mut messages: Vec<(String, String)> = sensors
+        .iter()
+        .enumerate()
+        .flat_map(|(i, info)| {
+            mqtt::sensor_info_messages(&mqtt::sensor_topic(root_topic, i, sensors.len()), info)
+        })
+        .collect();
+    if shared.i2c.is_some() {
+        messages.push(mqtt::i2c_message(
+            root_topic,
+            &shared.i2c_devices.lock().unwrap(),
+        ));
+    }
+    messages
+}
+
+/// State of the relay on `<root>/relay` and speed of the fan on
+/// `<root>/fan`, when they are wired
+fn output_messages(root_topic: &str, shared: &Shared) -> Result<Vec<(String, String)>> {
+    let mut messages = Vec::new();
+    if let Some(relay) = shared.relay() {
+        messages.push((
+            format!("{root_topic}/relay"),
+            serde_json::to_string(&relay)?,
+        ));
+    }
+    if let Some(fan) = shared.fan() {
+        messages.push((format!("{root_topic}/fan"), serde_json::to_string(&fan)?));
+    }
+    Ok(messages)
+}
+
+/// Served on `/api/pipeline`
+#[derive(Serialize)]
+struct PipelineJson {
+    stages: Vec<Stage>,
+    /// Values of the last measurement after each stage, `null` before the
+    /// first one
+    last: Option<Trace>,
+}
+
+/// Current measurement served on `/api/measurement`
+#[derive(Serialize)]
+struct MeasurementJson {
+    pm25: f32,
+    pm10: f32,
+    /// `None` if unknown, the clock was not synchronized before the restart
+    age_seconds: Option<u64>,
+    stale: bool,
+    /// RFC 3339, when the sensor frames were parsed, `None` if the clock was
+    /// not synchronized
+    measured_at: Option<String>,
+    /// Uptime when the sensor frames were parsed, `None` when restored from
+    /// before the restart
+    uptime_ms: Option<u64>,
+    /// The sensors discard their first measurements, this one is older
+    warming_up: bool,
+    /// Of PM2.5, `None` until there are enough recent samples
+    trend: Option<Trend>,
+    /// ppm, `None` without a CO2 sensor or when its last read failed
+    co2: Option<u16>,
+    /// Of an SGP40, `None` without one or while it warms up
+    voc_index: Option<u16>,
+    /// ppb, of an SGP30, `None` without one or while it warms up
+    tvoc: Option<u16>,
+    /// °C, `None` without a DHT22 or when its last read failed
+    temperature: Option<f32>,
+    /// %, `None` without a DHT22 or when its last read failed
+    humidity: Option<f32>,
+}
+
+impl MeasurementJson {
+    fn new(latest: &Latest, shared: &Shared) -> Self {
+        let readings = latest.readings();
+        Self {
+            pm25: reading::value(&readings, Kind::Pm25).unwrap_or_default(),
+            pm10: reading::value(&readings, Kind::Pm10).unwrap_or_default(),
+            age_seconds: latest.age().map(|age| age.as_secs()),
+            stale: latest.is_stale(shared.max_age),
+            measured_at: latest.measured_at.map(|at| at.to_rfc3339()),
+            uptime_ms: latest.measured.map(|uptime| uptime.as_millis() as u64),
+            warming_up: shared.is_warming_up(),
+            trend: *shared.trend.lock().unwrap(),
+            co2: *shared.co2.lock().unwrap(),
+            voc_index: shared.voc(VocKind::Sgp40),
+            tvoc: shared.voc(VocKind::Sgp30),
+            temperature: shared.climate().map(|climate| climate.temperature),
+            humidity: shared.climate().map(|climate| climate.humidity),
+        }
+    }
+}
+
+/// Snapshot of the device retained on `<root>/birth` on each connection to
+/// the broker, for the consumers subscribing late
+#[derive(Serialize)]
+struct Birth {
+    hostname: String,
+    /// Friendly name, `null` when not set
+    name: Option<String>,
+    /// `null` until provisioned
+    device_id: Option<String>,
+    build: BuildInfo,
+    config: SettingsSummary,
+    /// As `/api/sensors`
+    sensors: Vec<SensorInfo>,
+    /// As `/api/measurement`, `null` before the first measurement
+    measurement: Option<MeasurementJson>,
+}
+
+impl Birth {
+    fn new(settings: &Settings, shared: &Shared) -> Self {
+        let latest = *shared.measurement.lock().unwrap();
+        Self {
+            hostname: shared.hostname.clone(),
+            name: (!shared.name.is_empty()).then(|| shared.name.clone()),
+            device_id: shared
+                .identity
+                .is_provisioned()
+                .then(|| shared.identity.device_id.clone()),
+            build: BuildInfo::current(),
+            config: settings.summary(),
+            sensors: shared.sensors.lock().unwrap().clone(),
+            measurement: latest.map(|latest| MeasurementJson::new(&latest, shared)),
+        }
+    }
+}
+
+/// State of the device, served on `/api/health` and published on
+/// `<root>/telemetry`
+#[derive(Serialize)]
+struct Health {
+    hostname: String,
+    /// Friendly name, `null` when not set
+    name: Option<String>,
+    /// `null` until provisioned
+    device_id: Option<String>,
+    build: BuildInfo,
+    uptime_seconds: u64,
+    #[serde(flatten)]
+    memory: Memory,
+    /// Stack high-water marks of the tasks
+    stacks: Vec<TaskStack>,
+    /// Events since boot, by name
+    counters: BTreeMap<&'static str, u32>,
+    /// Of all the particle sensors
+    uart_crc_errors: u32,
+    uart_resyncs: u32,
+    uart_reinits: u32,
+    wifi: WifiStats,
+    /// Subsystems enabled but not detected, or detected but disabled
+    subsystems: Vec<Mismatch>,
+    /// Of the schedule
+    period: Period,
+    /// Until the particle sensors discarded their first measurements
+    warming_up: bool,
+    /// Of the MQTT client, `null` when disabled
+    mqtt_broker: Option<mqtt::Broker>,
+    /// `offline`, `degraded` or `online`, from `uplink`
+    connectivity: Connectivity,
+    uplink: Uplink,
+    /// Automatic light sleep between the activities
+    light_sleep: bool,
+    /// Whether the settings and identity are stored encrypted
+    nvs_encrypted: bool,
+    /// Assumed average current between the measurements, sensors apart
+    idle_current_ma: f32,
+}
+
+impl Health {
+    fn new(shared: &Shared) -> Self {
+        let uart: UartStats = shared.uart.lock().unwrap().iter().copied().sum();
+        Self {
+            hostname: shared.hostname.clone(),
+            name: (!shared.name.is_empty()).then(|| shared.name.clone()),
+            device_id: shared
+                .identity
+                .is_provisioned()
+                .then(|| shared.identity.device_id.clone()),
+            build: BuildInfo::current(),
+            uptime_seconds: clock::uptime().as_secs(),
+            memory: resources::memory(),
+            stacks: resources::task_stacks(),
+            counters: shared.counters.snapshot(),
+            uart_crc_errors: uart.crc_errors,
+            uart_resyncs: uart.resyncs,
+            uart_reinits: uart.reinits,
+            wifi: shared.wifi.lock().unwrap().clone(),
+            subsystems: subsystem::mismatches(&shared.enabled, |subsystem| {
+                shared
+                    .wired
+                    .get(subsystem)
+                    .then(|| shared.detected(subsystem))
+            }),
+            period: shared.period(),
+            warming_up: shared.is_warming_up(),
+            mqtt_broker: *shared.mqtt_broker.lock().unwrap(),
+            connectivity: shared.uplink().connectivity(),
+            uplink: shared.uplink(),
+            light_sleep: power::light_sleep_enabled(),
+            nvs_encrypted: cfg!(esp_idf_nvs_encryption),
+            idle_current_ma: power::idle_current_ma(),
+        }
+    }
+}
+
+/// Message sent to the `/ws` clients, tagged by `event`
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum WsEvent {
+    Measurement(MeasurementJson),
+    Sensors {
+        sensors: Vec<SensorInfo>,
+    },
+    Status(Health),
+    /// Reply to a command which succeeded
+    Done,
+    /// Reply to a command which failed
+    Error {
+        message: String,
+    },
+}
+
+impl WsEvent {
+    fn sensors(shared: &Shared) -> Self {
+        Self::Sensors {
+            sensors: shared.sensors.lock().unwrap().clone(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Command received on `/ws`, tagged by `command`. The device control
+/// commands need the `token` of the message to match the API key or
+/// `api_token`, the sensor commands too once provisioned.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum WsCommand {
+    Status,
+    Restart,
+    Identify,
+    Measure,
+    /// Management command of a sensor, as `POST /api/sensors`
+    Sensor {
+        #[serde(default)]
+        index: usize,
+        request: SensorCommand,
+    },
+}
+
+/// Speed of the fan, on `/api/fan` and `<root>/fan`
+#[derive(Serialize)]
+struct FanJson {
+    /// Percent
+    duty: u8,
+    /// `None` when not boosted
+    boost_remaining_seconds: Option<u64>,
+}
+
+/// Body of `POST /api/fan`
+#[derive(Serialize, Deserialize)]
+struct FanRequest {
+    boost: bool,
+}
+
+/// Body of `POST /api/relay`
+#[derive(Serialize, Deserialize)]
+struct RelayRequest {
+    mode: relay::Mode,
+}
+
+#[derive(Deserialize)]
+struct WsMessage {
+    #[serde(default)]
+    token: String,
+    #[serde(flatten)]
+    command: WsCommand,
+}
+
+/// Run a command received on `/ws`, giving the event to reply with
+fn ws_command(
+    message: &[u8],
+    shared: &Shared,
+    api_token: &str,
+    write_token: Option<&str>,
+) -> WsEvent {
+    let message: WsMessage = match serde_json::from_slice(message) {
+        Ok(message) => message,
+        Err(e) => {
+            return WsEvent::Error {
+                message: format!("{e}"),
+            }
+        }
+    };
+    if matches!(
+        message.command,
+        WsCommand::Restart | WsCommand::Identify | WsCommand::Measure
+    ) {
+        if let Err((_, error)) = http::check_token(&message.token, api_token) {
+            return WsEvent::Error {
+                message: error.to_string(),
+            };
+        }
+    }
+    if let (WsCommand::Sensor { .. }, Some(write_token)) = (&message.command, write_token) {
+        if let Err((_, error)) = http::check_token(&message.token, write_token) {
+            return WsEvent::Error {
+                message: error.to_string(),
+            };
+        }
+    }
+    match message.command {
+        WsCommand::Status => return WsEvent::Status(Health::new(shared)),
+        WsCommand::Restart => schedule_restart(),
+        WsCommand::Identify => shared.identify.signal(()),
+        WsCommand::Measure => shared.measure_now(),
+        WsCommand::Sensor { index, request } => {
+            let Some(commands) = shared.commands.get(index) else {
+                return WsEvent::Error {
+                    message: "No such sensor".to_string(),
+                };
+            };
+            if commands.try_send(request).is_err() {
+                return WsEvent::Error {
+                    message: "Too many pending commands".to_string(),
+                };
+            }
+        }
+    }
+    WsEvent::Done
+}
+
+/// Run the commands typed on the USB serial port
+#[cfg(not(esp32))]
+fn start_console(
+    shared: Arc<Shared>,
+    config_store: SharedConfigStore,
+    identity_store: SharedIdentityStore,
+    mqtt_enabled: bool,
+) -> anyhow::Result<()> {
+    let mut console = UsbConsole::new()?;
+    CONSOLE_TASK.spawn(move || loop {
+        let line = match console.read() {
+            Input::Line(line) => line,
+            Input::Improv(rpc) => {
+                let device = ConsoleDevice {
+                    shared: &shared,
+                    config_store: &config_store,
+                };
+                improv::answer(rpc, &device, |packet| console.write_bytes(packet));
+                continue;
+            }
+        };
+        // Terminals may end lines with CR LF
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = line
+            .parse()
+            .and_then(|command| {
+                console_command(
+                    command,
+                    &shared,
+                    &config_store,
+                    &identity_store,
+                    mqtt_enabled,
+                )
+            })
+            .unwrap_or_else(|e| format!("{e:#}"));
+        console.write(&format!("{reply}\n"));
+    })?;
+    Ok(())
+}
+
+/// Provisioned already, joins another network received over Improv
+#[cfg(not(esp32))]
+struct ConsoleDevice<'a> {
+    shared: &'a Shared,
+    config_store: &'a SharedConfigStore,
+}
+
+#[cfg(not(esp32))]
+impl improv::Device for ConsoleDevice<'_> {
+    fn url(&self) -> Option<String> {
+        self.shared.url.lock().unwrap().clone()
+    }
+
+    fn info(&self) -> [String; 4] {
+        let name = if self.shared.name.is_empty() {
+            &self.shared.hostname
+        } else {
+            &self.shared.name
+        };
+        improv::device_info(board::chip(), name)
+    }
+
+    fn provision(&self, ssid: &str, password: &str) -> anyhow::Result<String> {
+        let settings = self.config_store.lock().unwrap().load()?;
+        if !settings.wifi_eap_username.is_empty() {
+            anyhow::bail!("WPA2-Enterprise is configured");
+        }
+        self.shared.provisioned.reset();
+        self.shared
+            .provision
+            .signal((ssid.to_string(), password.to_string()));
+        // Polled, this thread can't await the blink task
+        let started = Instant::now();
+        let ip = loop {
+            if let Some(provisioned) = self.shared.provisioned.try_take() {
+                break provisioned.map_err(anyhow::Error::msg)?;
+            }
+            if started.elapsed() >= PROVISION_TIMEOUT {
+                anyhow::bail!("Timeout");
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        };
+        self.config_store
+            .lock()
+            .unwrap()
+            .set_wifi_network(ssid, password)?;
+        Ok(improv::url(settings.https_enabled, ip))
+    }
+
+    /// Would drop the connection
+    fn scan(&self) -> Option<Vec<improv::Network>> {
+        None
+    }
+}
+
+/// Reply to a command of the serial console
+#[cfg(not(esp32))]
+fn console_command(
+    command: Command,
+    shared: &Shared,
+    config_store: &SharedConfigStore,
+    identity_store: &SharedIdentityStore,
+    mqtt_enabled: bool,
+) -> anyhow::Result<String> {
+    Ok(match command {
+        Command::Help => console::HELP.to_string(),
+        Command::Measure => {
+            shared.measure_now();
+            "Measuring".to_string()
+        }
+        Command::WifiStatus => serde_json::to_string_pretty(&*shared.wifi.lock().unwrap())?,
+        Command::MqttStatus => {
+            let broker = shared
+                .mqtt_broker
+                .lock()
+                .unwrap()
+                .map_or("", mqtt::Broker::name);
+            match (mqtt_enabled, shared.mqtt_connected.load(Ordering::Relaxed)) {
+                (false, _) => "MQTT disabled, no broker configured".to_string(),
+                (true, true) => format!("MQTT connected to the {broker} broker"),
+                (true, false) => format!("MQTT disconnected from the {broker} broker"),
+            }
+        }
+        Command::Health => serde_json::to_string_pretty(&Health::new(shared))?,
+        Command::ConfigGet(key) => {
+            let settings = serde_json::to_value(config_store.lock().unwrap().load()?.redacted())?;
+            match key {
+                Some(key) => settings
+                    .get(&key)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown setting {key}"))?
+                    .to_string(),
+                None => serde_json::to_string_pretty(&settings)?,
+            }
+        }
+        Command::ConfigSet { key, value } => {
+            config_store
+                .lock()
+                .unwrap()
+                .import_json(&Command::config_patch(&key, &value))?;
+            format!("{key} set, applied on the next restart")
+        }
+        Command::Provision { device_id, api_key } => {
+            identity_store
+                .lock()
+                .unwrap()
+                .save(&Identity { device_id, api_key })?;
+            "Provisioned, applied on the next restart".to_string()
+        }
+        Command::Unprovision => {
+            identity_store.lock().unwrap().clear()?;
+            "Identity removed, applied on the next restart".to_string()
+        }
+        Command::Restart => {
+            schedule_restart();
+            "Restarting".to_string()
+        }
+    })
+}
+
+/// Restart once the response to the request has gone out
+fn schedule_restart() {
+    log::info!("Restart requested");
+    task::restart_after(Duration::from_secs(1));
+}
+
+/// AQI of the measurement in large, on the color of its band, with the
+/// particle concentrations
+fn aqi_tile(strings: &Strings, latest: &Latest, max_age: Duration) -> String {
+    let readings = latest.readings();
+    let pm25 = reading::value(&readings, Kind::Pm25).unwrap_or_default();
+    let pm10 = reading::value(&readings, Kind::Pm10).unwrap_or_default();
+    let aqi = aqi::us_epa(pm25, pm10);
+    let band = Band::from_aqi(aqi);
+    format!(
+        r#"<div class="tile band-{}{}"><span class="aqi">{aqi}</span><span class="band">{}</span><span>PM2.5 {pm25:.1} µg/m³ · PM10 {pm10:.1} µg/m³</span></div>"#,
+        band as usize,
+        if latest.is_stale(max_age) {
+            " stale"
+        } else {
+            ""
+        },
+        strings.band(band)
+    )
+}
+
+/// Values and age of the measurement, grayed out when stale
+fn latest_summary(strings: &Strings, latest: &Latest, max_age: Duration) -> String {
+    let when = match latest.measured {
+        Some(_) => strings.measured,
+        None => strings.measured_before_restart,
+    };
+    let at = latest
+        .measured_at
+        .map(|at| {
+            let at = clock::local(at).format("%Y-%m-%d %H:%M:%S");
+            format!(" {}", i18n::fill(strings.measured_at, &[&at]))
+        })
+        .unwrap_or_default();
+    let age = latest
+        .age()
+        .map(|age| format!(", {}", i18n::fill(strings.ago, &[&age.as_secs()])))
+        .unwrap_or_default();
+    if latest.is_stale(max_age) {
+        format!(
+            r#"<p class="stale">{} ({}, {when}{at}{age})</p>"#,
+            latest.vals, strings.stale
+        )
+    } else {
+        format!("<p>{} ({when}{at}{age})</p>", latest.vals)
+    }
+}
+
+/// Last CO2 value, nothing without a CO2 sensor
+fn co2_summary(strings: &Strings, kind: Option<Co2Kind>, ppm: Option<u16>) -> String {
+    match (kind, ppm) {
+        (Some(kind), Some(ppm)) => format!("<p>CO2: {ppm} ppm ({kind})</p>"),
+        (Some(kind), None) => format!("<p>CO2: {} ({kind})</p>", strings.no_measure),
+        (None, _) => String::new(),
+    }
+}
+
+/// VOC index or TVOC, nothing without a VOC sensor
+fn voc_summary(strings: &Strings, kind: Option<VocKind>, value: Option<u16>) -> String {
+    match (kind, value) {
+        (Some(VocKind::Sgp40), Some(index)) => {
+            format!("<p>{}: {index} (SGP40)</p>", strings.voc_index)
+        }
+        (Some(VocKind::Sgp30), Some(ppb)) => format!("<p>TVOC: {ppb} ppb (SGP30)</p>"),
+        (Some(kind), None) => format!("<p>{}: {} ({kind})</p>", strings.voc, strings.no_measure),
+        (None, _) => String::new(),
+    }
+}
+
+/// Temperature and humidity, nothing without a DHT22
+fn climate_summary(strings: &Strings, wired: bool, climate: Option<Compensation>) -> String {
+    match (wired, climate) {
+        (true, Some(climate)) => format!(
+            "<p>{} (DHT22)</p>",
+            i18n::fill(
+                strings.climate,
+                &[
+                    &format!("{:.1}", climate.temperature),
+                    &format!("{:.1}", climate.humidity)
+                ]
+            )
+        ),
+        (true, None) => format!("<p>{} (DHT22)</p>", strings.climate_no_measure),
+        (false, _) => String::new(),
+    }
+}
+
+/// Time spent above the limits today
+fn exceedance_summary(strings: &Strings, exceedance: &Exceedance, limits: &Limits) -> String {
+    let summary = i18n::fill(
+        strings.exceedance,
+        &[
+            &exceedance.pm25_minutes,
+            &limits.pm25,
+            &exceedance.pm10_minutes,
+            &limits.pm10,
+        ],
+    );
+    format!("<p>{summary}</p>")
+}
+
+/// Details and values of each sensor
+fn sensor_list(
+    strings: &Strings,
+    sensors: &[SensorInfo],
+    readings: &Readings,
+    calibration: &Calibration,
+) -> String {
+    let items: Vec<String> = sensors
+        .iter()
+        .zip(&readings.last)
+        .enumerate()
+        .map(|(i, (info, vals))| {
+            let sensor = i18n::fill(strings.sensor, &[&i]);
+            match vals {
+                Some(vals) => format!("<li>{sensor} ({info}): {}</li>", calibration.apply(vals)),
+                None => format!("<li>{sensor} ({info}): {}</li>", strings.no_measure),
+            }
+        })
+        .collect();
+    format!("<ul>{}</ul>", items.concat())
+}
+
+/// PM2.5 of the samples of `tier`, copied so that the history isn't locked
+/// while the chart is sent, `None` until there are two samples
+fn chart_values(history: &History, tier: Tier) -> Option<Vec<u16>> {
+    (history.len() >= 2).then(|| history.tier(tier).map(|s| s.pm25).collect())
+}
+
+/// Inline SVG polyline of the PM2.5 history of `tier`, with the links to
+/// the other tiers, written point by point
+fn write_history_chart(
+    out: &mut impl fmt::Write,
+    strings: &Strings,
+    values: Option<&[u16]>,
+    tier: Tier,
+) -> fmt::Result {
+    const WIDTH: usize = 600;
+    const HEIGHT: u16 = 150;
+    let Some(values) = values else {
+        return Ok(());
+    };
+    let write_nav = |out: &mut dyn fmt::Write| -> fmt::Result {
+        out.write_str(r#"<nav class="periods">"#)?;
+        for other in Tier::ALL {
+            let period = strings.periods[other as usize];
+            if other == tier {
+                write!(out, "<strong>{period}</strong>")?;
+            } else {
+                write!(out, r#"<a href="/?chart={}">{period}</a>"#, other.name())?;
+            }
+        }
+        out.write_str("</nav>")
+    };
+    if values.len() < 2 {
+        write!(out, "<p>{}</p>", strings.no_measurement)?;
+        return write_nav(out);
+    }
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    let step = WIDTH as f32 / (values.len() - 1) as f32;
+    write!(
+        out,
+        r#"<h2>{}</h2>
+<svg width="{WIDTH}" height="{HEIGHT}"><polyline fill="none" stroke="currentColor" points=""#,
+        i18n::fill(
+            strings.history[tier as usize],
+            &[&reading::from_tenths(max)]
+        )
+    )?;
+    for (i, value) in values.iter().enumerate() {
+        let y = HEIGHT as f32 - (*value as f32 * HEIGHT as f32 / max as f32);
+        let separator = if i == 0 { "" } else { " " };
+        write!(out, "{separator}{:.1},{:.1}", i as f32 * step, y)?;
+    }
+    out.write_str(r#""/></svg>"#)?;
+    write_nav(out)
+}
+
+/// Table and bar chart comparing the PM2.5 of this station and its peers,
+/// nothing when not aggregating
+fn peers_comparison(strings: &Strings, own: Option<&Latest>, peers: &[Peer]) -> String {
+    const WIDTH: f32 = 400.0;
+    const BAR_HEIGHT: usize = 20;
+    if peers.is_empty() {
+        return String::new();
+    }
+    let own = own.map(Latest::readings);
+    let own_value = |kind| own.and_then(|readings| reading::value(&readings, kind));
+    let mut rows = vec![format!(
+        "<tr><td>{}</td><td>{}</td><td>{}</td><td></td></tr>",
+        strings.this_station,
+        own_value(Kind::Pm25)
+            .map(|value| format!("{value:.1}"))
+            .unwrap_or_default(),
+        own_value(Kind::Pm10)
+            .map(|value| format!("{value:.1}"))
+            .unwrap_or_default(),
+    )];
+    let mut bars = vec![(strings.this_station.to_string(), own_value(Kind::Pm25))];
+    for peer in peers {
+        // Both come from the network
+        let name = http::escape(&peer.name);
+        let (pm25, pm10) = match &peer.measurement {
+            Some(m) => (format!("{:.1}", m.pm25), format!("{:.1}", m.pm10)),
+            None => Default::default(),
+        };
+        let status = match (&peer.error, &peer.measurement) {
+            (Some(error), _) => http::escape(error),
+            (None, Some(m)) if m.stale => strings.stale.to_string(),
+            (None, Some(_)) => String::new(),
+            (None, None) => strings.no_measure.to_string(),
+        };
+        rows.push(format!(
+            "<tr><td>{name}</td><td>{pm25}</td><td>{pm10}</td><td>{status}</td></tr>"
+        ));
+        bars.push((name, peer.measurement.map(|m| m.pm25)));
+    }
+    let max = bars
+        .iter()
+        .filter_map(|(_, pm25)| *pm25)
+        .fold(1.0, f32::max);
+    let rects: Vec<String> = bars
+        .iter()
+        .enumerate()
+        .map(|(i, (name, pm25))| {
+            let y = i * BAR_HEIGHT;
+            let width = pm25.unwrap_or(0.0) * WIDTH / max;
+            format!(
+                r#"<rect x="150" y="{y}" width="{width:.1}" height="{}"/><text x="0" y="{}">{name}</text>"#,
+                BAR_HEIGHT - 4,
+                y + BAR_HEIGHT - 6
+            )
+        })
+        .collect();
+    format!(
+        r#"<h2>{}</h2>
+<div class="scroll"><table><tr><th>{}</th><th>PM2.5</th><th>PM10</th><th></th></tr>{}</table></div>
+<svg width="{}" height="{}">{}</svg>"#,
+        strings.stations,
+        strings.station,
+        rows.concat(),
+        150.0 + WIDTH,
+        bars.len() * BAR_HEIGHT,
+        rects.concat()
+    )
+}
@@ -0,0 +1,326 @@
+/// The algorithm expects one sample per second
+pub const SAMPLING_INTERVAL: f32 = 1.0;
+/// Samples ignored while the sensor heats up
+const INITIAL_BLACKOUT: f32 = 45.0;
+const INDEX_GAIN: f32 = 230.0;
+const SRAW_STD_INITIAL: f32 = 50.0;
+const SRAW_STD_BONUS: f32 = 220.0;
+const SRAW_MINIMUM: f32 = 20_000.0;
+const INDEX_OFFSET: f32 = 100.0;
+const TAU_MEAN_HOURS: f32 = 12.0;
+const TAU_VARIANCE_HOURS: f32 = 12.0;
+const TAU_INITIAL_MEAN: f32 = 20.0;
+const INIT_DURATION_MEAN: f32 = 3600.0 * 0.75;
+const INIT_TRANSITION_MEAN: f32 = 0.01;
+const TAU_INITIAL_VARIANCE: f32 = 2500.0;
+const INIT_DURATION_VARIANCE: f32 = 3600.0 * 1.45;
+const INIT_TRANSITION_VARIANCE: f32 = 0.01;
+const GATING_THRESHOLD: f32 = 340.0;
+const GATING_THRESHOLD_INITIAL: f32 = 510.0;
+const GATING_THRESHOLD_TRANSITION: f32 = 0.09;
+const GATING_MAX_DURATION_MINUTES: f32 = 60.0 * 3.0;
+const GATING_MAX_RATIO: f32 = 0.3;
+const SIGMOID_L: f32 = 500.0;
+const SIGMOID_K: f32 = -0.0065;
+const SIGMOID_X0: f32 = 213.0;
+const LP_TAU_FAST: f32 = 20.0;
+const LP_TAU_SLOW: f32 = 500.0;
+const LP_ALPHA: f32 = -0.2;
+/// Uptime given to restored states, past the initial learning
+const PERSISTENCE_UPTIME_GAMMA: f32 = 3.0 * 3600.0;
+const GAMMA_SCALING: f32 = 64.0;
+const ADDITIONAL_GAMMA_MEAN_SCALING: f32 = 8.0;
+/// Bound of the uptimes, the fixed point range of the reference code
+const UPTIME_LIMIT: f32 = 32767.0 - SAMPLING_INTERVAL;
+
+/// Learned baseline of the raw signal, to be restored after a restart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct States {
+    pub mean: f32,
+    pub std: f32,
+}
+
+/// Sensirion's VOC index algorithm (gas index algorithm 3.2, VOC mode),
+/// turning the raw signal of an SGP40 sampled every second into an index
+/// from 1 to 500, 100 being the average of the last 24 hours
+#[derive(Debug, Clone)]
+pub struct GasIndex {
+    uptime: f32,
+    sraw: f32,
+    gas_index: f32,
+    estimator: MeanVarianceEstimator,
+    lowpass: AdaptiveLowpass,
+}
+
+impl Default for GasIndex {
+    fn default() -> Self {
+        Self {
+            uptime: 0.0,
+            sraw: 0.0,
+            gas_index: 0.0,
+            estimator: MeanVarianceEstimator::new(),
+            lowpass: AdaptiveLowpass::default(),
+        }
+    }
+}
+
+impl GasIndex {
+    /// VOC index of a raw sample, 0 during the blackout
+    pub fn process(&mut self, sraw: u16) -> u16 {
+        if self.uptime <= INITIAL_BLACKOUT {
+            self.uptime += SAMPLING_INTERVAL;
+            return 0;
+        }
+        if sraw > 0 && sraw < 65_000 {
+            let sraw = f32::from(sraw).clamp(SRAW_MINIMUM + 1.0, SRAW_MINIMUM + 32767.0);
+            self.sraw = sraw - SRAW_MINIMUM;
+            self.gas_index = sigmoid_scaled(self.mox_model(self.sraw));
+        } else {
+            self.gas_index = INDEX_OFFSET;
+        }
+        self.gas_index = self.lowpass.process(self.gas_index).max(0.5);
+        if self.sraw > 0.0 {
+            self.estimator.process(self.sraw, self.gas_index);
+        }
+        (self.gas_index + 0.5) as u16
+    }
+
+    /// `None` until the baseline is meaningful, after 3 hours
+    pub fn states(&self) -> Option<States> {
+        (self.estimator.uptime_gamma >= PERSISTENCE_UPTIME_GAMMA).then_some(States {
+            mean: self.estimator.mean + self.estimator.sraw_offset,
+            std: self.estimator.std,
+        })
+    }
+
+    pub fn set_states(&mut self, states: States) {
+        self.estimator.set_states(states);
+        self.sraw = states.mean;
+    }
+
+    /// Scaled deviation of the sample from the learned mean
+    fn mox_model(&self, sraw: f32) -> f32 {
+        let mean = self.estimator.mean + self.estimator.sraw_offset;
+        (sraw - mean) / -(self.estimator.std + SRAW_STD_BONUS) * INDEX_GAIN
+    }
+}
+
+/// Index from the scaled deviation, 100 at the mean
+fn sigmoid_scaled(sample: f32) -> f32 {
+    let x = SIGMOID_K * (sample - SIGMOID_X0);
+    if x < -50.0 {
+        SIGMOID_L
+    } else if x > 50.0 {
+        0.0
+    } else if sample >= 0.0 {
+        let shift = (SIGMOID_L - 5.0 * INDEX_OFFSET) / 4.0;
+        (SIGMOID_L + shift) / (1.0 + x.exp()) - shift
+    } else {
+        SIGMOID_L / (1.0 + x.exp())
+    }
+}
+
+/// Logistic function going from 1 to 0 around `x0`
+#[derive(Debug, Clone, Copy)]
+struct Sigmoid {
+    x0: f32,
+    k: f32,
+}
+
+impl Sigmoid {
+    fn process(&self, sample: f32) -> f32 {
+        let x = self.k * (sample - self.x0);
+        if x < -50.0 {
+            1.0
+        } else if x > 50.0 {
+            0.0
+        } else {
+            1.0 / (1.0 + x.exp())
+        }
+    }
+}
+
+/// Mean and standard deviation of the raw signal, learned quickly at first
+/// then over 12 hours, and not while the index is high
+#[derive(Debug, Clone)]
+struct MeanVarianceEstimator {
+    initialized: bool,
+    mean: f32,
+    sraw_offset: f32,
+    std: f32,
+    gamma_mean: f32,
+    gamma_variance: f32,
+    gamma_initial_mean: f32,
+    gamma_initial_variance: f32,
+    uptime_gamma: f32,
+    uptime_gating: f32,
+    gating_duration_minutes: f32,
+}
+
+impl MeanVarianceEstimator {
+    fn new() -> Self {
+        let hours = SAMPLING_INTERVAL / 3600.0;
+        Self {
+            initialized: false,
+            mean: 0.0,
+            sraw_offset: 0.0,
+            std: SRAW_STD_INITIAL,
+            gamma_mean: ADDITIONAL_GAMMA_MEAN_SCALING * GAMMA_SCALING * hours
+                / (TAU_MEAN_HOURS + hours),
+            gamma_variance: GAMMA_SCALING * hours / (TAU_VARIANCE_HOURS + hours),
+            gamma_initial_mean: ADDITIONAL_GAMMA_MEAN_SCALING * GAMMA_SCALING * SAMPLING_INTERVAL
+                / (TAU_INITIAL_MEAN + SAMPLING_INTERVAL),
+            gamma_initial_variance: GAMMA_SCALING * SAMPLING_INTERVAL
+                / (TAU_INITIAL_VARIANCE + SAMPLING_INTERVAL),
+            uptime_gamma: 0.0,
+            uptime_gating: 0.0,
+            gating_duration_minutes: 0.0,
+        }
+    }
+
+    fn set_states(&mut self, states: States) {
+        self.mean = states.mean;
+        self.sraw_offset = 0.0;
+        self.std = states.std;
+        self.uptime_gamma = PERSISTENCE_UPTIME_GAMMA;
+        self.initialized = true;
+    }
+
+    /// Learning rates of the mean and variance for this sample
+    fn gammas(&mut self, gas_index: f32) -> (f32, f32) {
+        if self.uptime_gamma < UPTIME_LIMIT {
+            self.uptime_gamma += SAMPLING_INTERVAL;
+        }
+        if self.uptime_gating < UPTIME_LIMIT {
+            self.uptime_gating += SAMPLING_INTERVAL;
+        }
+        let init_mean = Sigmoid {
+            x0: INIT_DURATION_MEAN,
+            k: INIT_TRANSITION_MEAN,
+        };
+        let sigmoid_gamma_mean = init_mean.process(self.uptime_gamma);
+        let gamma_mean =
+            self.gamma_mean + (self.gamma_initial_mean - self.gamma_mean) * sigmoid_gamma_mean;
+        let gating_mean = Sigmoid {
+            x0: GATING_THRESHOLD
+                + (GATING_THRESHOLD_INITIAL - GATING_THRESHOLD)
+                    * init_mean.process(self.uptime_gating),
+            k: GATING_THRESHOLD_TRANSITION,
+        };
+        let sigmoid_gating_mean = gating_mean.process(gas_index);
+
+        let init_variance = Sigmoid {
+            x0: INIT_DURATION_VARIANCE,
+            k: INIT_TRANSITION_VARIANCE,
+        };
+        let sigmoid_gamma_variance = init_variance.process(self.uptime_gamma);
+        let gamma_variance = self.gamma_variance
+            + (self.gamma_initial_variance - self.gamma_variance)
+                * (sigmoid_gamma_variance - sigmoid_gamma_mean);
+        let gating_variance = Sigmoid {
+            x0: GATING_THRESHOLD
+                + (GATING_THRESHOLD_INITIAL - GATING_THRESHOLD)
+                    * init_variance.process(self.uptime_gating),
+            k: GATING_THRESHOLD_TRANSITION,
+        };
+        let sigmoid_gating_variance = gating_variance.process(gas_index);
+
+        // Stuck gated for too long: learn again from the current level
+        self.gating_duration_minutes += (SAMPLING_INTERVAL / 60.0)
+            * ((1.0 - sigmoid_gating_mean) * (1.0 + GATING_MAX_RATIO) - GATING_MAX_RATIO);
+        self.gating_duration_minutes = self.gating_duration_minutes.max(0.0);
+        if self.gating_duration_minutes > GATING_MAX_DURATION_MINUTES {
+            self.uptime_gating = 0.0;
+        }
+        (
+            sigmoid_gating_mean * gamma_mean,
+            sigmoid_gating_variance * gamma_variance,
+        )
+    }
+
+    fn process(&mut self, sraw: f32, gas_index: f32) {
+        if !self.initialized {
+            self.initialized = true;
+            self.sraw_offset = sraw;
+            self.mean = 0.0;
+            return;
+        }
+        // Keeps the mean small, for the precision of f32
+        if self.mean >= 100.0 || self.mean <= -100.0 {
+            self.sraw_offset += self.mean;
+            self.mean = 0.0;
+        }
+        let sraw = sraw - self.sraw_offset;
+        let (gamma_mean, gamma_variance) = self.gammas(gas_index);
+        let delta = (sraw - self.mean) / GAMMA_SCALING;
+        let c = self.std + delta.abs();
+        let additional_scaling = if c > 1440.0 {
+            (c / 1440.0) * (c / 1440.0)
+        } else {
+            1.0
+        };
+        self.std = (additional_scaling * (GAMMA_SCALING - gamma_variance)).sqrt()
+            * (self.std * (self.std / (GAMMA_SCALING * additional_scaling))
+                + gamma_variance * delta / additional_scaling * delta)
+                .sqrt();
+        self.mean += gamma_mean * delta / ADDITIONAL_GAMMA_MEAN_SCALING;
+    }
+}
+
+/// Low pass filter, faster when the index moves a lot
+#[derive(Debug, Clone, Default)]
+struct AdaptiveLowpass {
+    /// `None` before the first sample
+    state: Option<(f32, f32, f32)>,
+}
+
+impl AdaptiveLowpass {
+    fn process(&mut self, sample: f32) -> f32 {
+        let a1 = SAMPLING_INTERVAL / (LP_TAU_FAST + SAMPLING_INTERVAL);
+        let a2 = SAMPLING_INTERVAL / (LP_TAU_SLOW + SAMPLING_INTERVAL);
+        let (x1, x2, x3) = self.state.unwrap_or((sample, sample, sample));
+        let x1 = (1.0 - a1) * x1 + a1 * sample;
+        let x2 = (1.0 - a2) * x2 + a2 * sample;
+        let f1 = (LP_ALPHA * (x1 - x2).abs()).exp();
+        let tau = (LP_TAU_SLOW - LP_TAU_FAST) * f1 + LP_TAU_FAST;
+        let a3 = SAMPLING_INTERVAL / (SAMPLING_INTERVAL + tau);
+        let x3 = (1.0 - a3) * x3 + a3 * sample;
+        self.state = Some((x1, x2, x3));
+        x3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blackout_then_baseline() {
+        let mut gas_index = GasIndex::default();
+        for _ in 0..=INITIAL_BLACKOUT as usize {
+            assert_eq!(gas_index.process(30_000), 0);
+        }
+        let mut index = 0;
+        for _ in 0..3600 {
+            index = gas_index.process(30_000);
+        }
+        assert!((95..=105).contains(&index), "{index}");
+        assert!(gas_index.states().is_none());
+        // The raw signal drops with VOCs
+        for _ in 0..60 {
+            index = gas_index.process(28_000);
+        }
+        assert!(index > 150, "{index}");
+    }
+
+    #[test]
+    fn states_restored() {
+        let mut gas_index = GasIndex::default();
+        let states = States {
+            mean: 10_000.0,
+            std: 40.0,
+        };
+        gas_index.set_states(states);
+        assert_eq!(gas_index.states(), Some(states));
+    }
+}
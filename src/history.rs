@@ -0,0 +1,411 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::reading::Reading;
+use crate::sensor::Measurement;
+
+/// Size of a serialized [`Sample`]
+const SAMPLE_LEN: usize = 8;
+/// Of the 15 min averages, kept for 7 days
+const QUARTER: u32 = 15 * 60;
+const QUARTERS: usize = 7 * 24 * 4;
+/// Of the hourly averages, kept for 30 days
+const HOUR: u32 = 60 * 60;
+const HOURS: usize = 30 * 24;
+
+/// A measurement as stored in the history
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Sample {
+    /// Unix timestamp, 0 when taken before SNTP synchronization
+    pub timestamp: u32,
+    /// PM2.5 in tenths of µg/m³
+    pub pm25: u16,
+    /// PM10 in tenths of µg/m³
+    pub pm10: u16,
+}
+
+impl Sample {
+    /// `measured_at` is `None` if the clock was not synchronized
+    pub fn new(vals: &Measurement, measured_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            timestamp: measured_at.map(|t| t.timestamp() as u32).unwrap_or(0),
+            pm25: vals.pm25(),
+            pm10: vals.pm10(),
+        }
+    }
+
+    /// `None` when taken before the clock was set
+    pub fn timestamp_utc(&self) -> Option<DateTime<Utc>> {
+        (self.timestamp != 0)
+            .then(|| DateTime::from_timestamp(self.timestamp.into(), 0))
+            .flatten()
+    }
+
+    /// The PM values, timestamped unless taken before the clock was set
+    pub fn readings(&self) -> [Reading; 2] {
+        Measurement::new(self.pm25, self.pm10).readings(None, self.timestamp_utc())
+    }
+
+    fn to_bytes(self) -> [u8; SAMPLE_LEN] {
+        let mut bytes = [0u8; SAMPLE_LEN];
+        bytes[0..4].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.pm25.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.pm10.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            timestamp: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            pm25: u16::from_le_bytes([bytes[4], bytes[5]]),
+            pm10: u16::from_le_bytes([bytes[6], bytes[7]]),
+        }
+    }
+}
+
+pub type SharedHistory = Arc<Mutex<History>>;
+
+/// Resolution of the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    /// Every measurement of the last 24h
+    Raw,
+    /// 15 min averages of the last 7 days
+    Quarter,
+    /// Hourly averages of the last 30 days
+    Hour,
+}
+
+impl Tier {
+    pub const ALL: [Self; 3] = [Self::Raw, Self::Quarter, Self::Hour];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Quarter => "15m",
+            Self::Hour => "1h",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|tier| tier.name() == name)
+    }
+}
+
+/// Ring buffers of the last 24h of measurements and of their averages over
+/// the last 7 and 30 days, about 23 kB at most, in RAM and on flash.
+pub struct History {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+    quarters: Averages,
+    hours: Averages,
+}
+
+impl History {
+    /// History holding 24h of samples taken every `measure_interval`.
+    pub fn new(measure_interval: Duration) -> Self {
+        let capacity = (24 * 3600 / measure_interval.as_secs().max(1)).clamp(1, 1440) as usize;
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            quarters: Averages::new(QUARTER, QUARTERS),
+            hours: Averages::new(HOUR, HOURS),
+        }
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        push(&mut self.samples, self.capacity, sample);
+        // Samples taken before the clock is set belong to no period
+        if sample.timestamp != 0 {
+            self.quarters.add(sample);
+            self.hours.add(sample);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter()
+    }
+
+    /// Measurements taken after the `after` Unix timestamp, oldest first,
+    /// without those taken before the clock was set
+    pub fn since(&self, after: u32) -> impl Iterator<Item = &Sample> {
+        self.samples
+            .iter()
+            .filter(move |sample| sample.timestamp > after)
+    }
+
+    /// Unix timestamp of the last measurement, 0 without any or when taken
+    /// before the clock was set
+    pub fn last_timestamp(&self) -> u32 {
+        self.samples.back().map_or(0, |sample| sample.timestamp)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Samples of `tier`, oldest first, without the average of the current
+    /// period
+    pub fn tier(&self, tier: Tier) -> std::collections::vec_deque::Iter<'_, Sample> {
+        match tier {
+            Tier::Raw => self.samples.iter(),
+            Tier::Quarter => self.quarters.samples.iter(),
+            Tier::Hour => self.hours.samples.iter(),
+        }
+    }
+
+    /// The whole history, oldest first: the hourly averages until the 15 min
+    /// ones start, then those until the measurements start
+    pub fn all(&self) -> impl Iterator<Item = &Sample> {
+        let raw_start = self.samples.iter().map(|s| s.timestamp).find(|t| *t != 0);
+        let quarters_start = self.quarters.samples.front().map(|s| s.timestamp);
+        let before = |start: Option<u32>| {
+            move |sample: &&Sample| start.map_or(true, |start| sample.timestamp < start)
+        };
+        self.hours
+            .samples
+            .iter()
+            .filter(before(quarters_start.or(raw_start)))
+            .chain(self.quarters.samples.iter().filter(before(raw_start)))
+            .chain(self.samples.iter())
+    }
+
+    /// Restore samples previously written by [`History::save`], keeping the
+    /// most recent ones if the files hold more than the capacity. The
+    /// averages are missing from the files of older versions.
+    pub fn load(&mut self, path: &str) -> Result<()> {
+        for sample in read(path)? {
+            push(&mut self.samples, self.capacity, sample);
+        }
+        for (averages, path) in [
+            (&mut self.quarters, quarters_path(path)),
+            (&mut self.hours, hours_path(path)),
+        ] {
+            for sample in read(&path).unwrap_or_default() {
+                push(&mut averages.samples, averages.capacity, sample);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the measurements to `path` and the averages next to it. The
+    /// average of the current periods is lost on restart.
+    pub fn save(&self, path: &str) -> Result<()> {
+        write(path, &self.samples)?;
+        write(&quarters_path(path), &self.quarters.samples)?;
+        write(&hours_path(path), &self.hours.samples)
+    }
+}
+
+/// Averages over fixed periods, timestamped with their start
+struct Averages {
+    period: u32,
+    capacity: usize,
+    samples: VecDeque<Sample>,
+    /// Sums of the samples of the current period
+    current: Option<Sums>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sums {
+    start: u32,
+    pm25: u32,
+    pm10: u32,
+    count: u32,
+}
+
+impl Averages {
+    fn new(period: u32, capacity: usize) -> Self {
+        Self {
+            period,
+            capacity,
+            samples: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    /// Add `sample` to the sums of its period, first averaging those of the
+    /// previous period if it is a new one
+    fn add(&mut self, sample: Sample) {
+        let start = sample.timestamp - sample.timestamp % self.period;
+        match &mut self.current {
+            Some(sums) if sums.start == start => {
+                sums.pm25 += u32::from(sample.pm25);
+                sums.pm10 += u32::from(sample.pm10);
+                sums.count += 1;
+            }
+            current => {
+                if let Some(sums) = current.take() {
+                    let average = |sum: u32| (sum / sums.count) as u16;
+                    let average = Sample {
+                        timestamp: sums.start,
+                        pm25: average(sums.pm25),
+                        pm10: average(sums.pm10),
+                    };
+                    push(&mut self.samples, self.capacity, average);
+                }
+                self.current = Some(Sums {
+                    start,
+                    pm25: sample.pm25.into(),
+                    pm10: sample.pm10.into(),
+                    count: 1,
+                });
+            }
+        }
+    }
+}
+
+fn push(samples: &mut VecDeque<Sample>, capacity: usize, sample: Sample) {
+    if samples.len() == capacity {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+fn quarters_path(path: &str) -> String {
+    format!("{path}.15m")
+}
+
+fn hours_path(path: &str) -> String {
+    format!("{path}.1h")
+}
+
+fn read(path: &str) -> Result<Vec<Sample>> {
+    let bytes = fs::read(path).or_else(|_| fs::read(format!("{path}.tmp")))?;
+    Ok(bytes
+        .chunks_exact(SAMPLE_LEN)
+        .map(Sample::from_bytes)
+        .collect())
+}
+
+/// Write `samples` to `path`, through a temporary file so that a reset while
+/// writing doesn't corrupt the previous copy. SPIFFS can't rename over an
+/// existing file, [`read`] falls back to the temporary file if the reset
+/// happens in between.
+fn write(path: &str, samples: &VecDeque<Sample>) -> Result<()> {
+    let mut bytes = Vec::with_capacity(samples.len() * SAMPLE_LEN);
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_bytes());
+    }
+    let tmp_path = format!("{path}.tmp");
+    fs::write(&tmp_path, bytes)?;
+    let _ = fs::remove_file(path);
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u32, pm25: u16) -> Sample {
+        Sample {
+            timestamp,
+            pm25,
+            pm10: pm25 * 2,
+        }
+    }
+
+    #[test]
+    fn oldest_dropped_when_full() {
+        // 24 samples a day
+        let mut history = History::new(Duration::from_secs(3600));
+        for i in 0..30 {
+            history.push(sample(1_700_000_000 + i * 3600, i as u16));
+        }
+        assert_eq!(history.len(), 24);
+        assert_eq!(history.iter().next().map(|s| s.pm25), Some(6));
+        assert_eq!(history.iter().last().map(|s| s.pm25), Some(29));
+    }
+
+    #[test]
+    fn since_skips_unsynchronized() {
+        let mut history = History::new(Duration::from_secs(60));
+        history.push(sample(0, 1));
+        history.push(sample(1_000, 2));
+        history.push(sample(2_000, 3));
+        let since: Vec<u16> = history.since(1_000).map(|s| s.pm25).collect();
+        assert_eq!(since, [3]);
+        assert_eq!(history.since(0).count(), 2);
+        assert_eq!(history.last_timestamp(), 2_000);
+        history.push(sample(0, 4));
+        assert_eq!(history.last_timestamp(), 0);
+    }
+
+    #[test]
+    fn quarter_averages() {
+        let mut history = History::new(Duration::from_secs(60));
+        let start = 1_700_000_100 - 1_700_000_100 % QUARTER;
+        history.push(sample(start, 10));
+        history.push(sample(start + 60, 20));
+        history.push(sample(start + 120, 31));
+        // Not averaged until the next period starts
+        assert_eq!(history.tier(Tier::Quarter).count(), 0);
+        history.push(sample(start + QUARTER, 40));
+        let quarters: Vec<Sample> = history.tier(Tier::Quarter).copied().collect();
+        assert_eq!(quarters.len(), 1);
+        assert_eq!(quarters[0].timestamp, start);
+        // Truncated
+        assert_eq!(quarters[0].pm25, 20);
+        assert_eq!(quarters[0].pm10, 40);
+    }
+
+    #[test]
+    fn unsynchronized_not_averaged() {
+        let mut history = History::new(Duration::from_secs(60));
+        history.push(sample(0, 10));
+        history.push(sample(QUARTER * 10, 10));
+        assert_eq!(history.tier(Tier::Quarter).count(), 0);
+        assert_eq!(history.tier(Tier::Raw).count(), 2);
+    }
+
+    #[test]
+    fn sample_timestamp() {
+        assert_eq!(sample(0, 1).timestamp_utc(), None);
+        let utc = sample(1_700_000_000, 1).timestamp_utc();
+        assert_eq!(utc.map(|t| t.timestamp()), Some(1_700_000_000));
+        let [pm25, pm10] = sample(1_700_000_000, 123).readings();
+        assert_eq!((pm25.value, pm10.value), (12.3, 24.6));
+        assert_eq!(pm25.timestamp, utc);
+    }
+
+    #[test]
+    fn saved_and_loaded() {
+        let path = std::env::temp_dir().join(format!("history-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut history = History::new(Duration::from_secs(60));
+        for i in 0..5 {
+            history.push(sample(1_700_000_000 + i * QUARTER, i as u16));
+        }
+        history.save(path).unwrap();
+        let mut loaded = History::new(Duration::from_secs(60));
+        loaded.load(path).unwrap();
+        for path in [path.to_string(), quarters_path(path), hours_path(path)] {
+            let _ = fs::remove_file(path);
+        }
+        let pm25 = |history: &History, tier| {
+            history
+                .tier(tier)
+                .map(|s| (s.timestamp, s.pm25))
+                .collect::<Vec<_>>()
+        };
+        for tier in Tier::ALL {
+            assert_eq!(pm25(&loaded, tier), pm25(&history, tier));
+        }
+        assert_eq!(loaded.len(), 5);
+    }
+
+    #[test]
+    fn tier_names() {
+        for tier in Tier::ALL {
+            assert_eq!(Tier::from_name(tier.name()), Some(tier));
+        }
+        assert_eq!(Tier::from_name("1d"), None);
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use embassy_futures::block_on;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::build_info::BuildInfo;
+use crate::co2::{Co2Kind, Co2Sensor};
+use crate::config::Settings;
+use crate::dht22::Dht22;
+use crate::history::{History, Sample};
+use crate::i2c_bus;
+use crate::reading::{Kind, Reading};
+use crate::sensor::{Measurement, Sensor};
+use crate::sim::{FakeDht22, FakeMhz19, FakePms5003, FakeSds011, FakeSgp40};
+use crate::trend::Trend;
+use crate::voc::VocSensor;
+use crate::{clock, led, mqtt};
+
+const ROOT_TOPIC: &str = "esp32/simulated";
+/// A minute of VOC samples per measurement cycle
+const VOC_SAMPLES_PER_CYCLE: usize = 60;
+
+/// The simulated sensor answers immediately
+struct NoDelay;
+
+impl DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _n: u32) {}
+}
+
+impl embedded_hal::delay::DelayNs for NoDelay {
+    fn delay_ns(&mut self, _n: u32) {}
+}
+
+/// Run the measurement pipeline on the host: an SDS011, a PMS5003, an
+/// MH-Z19, an SGP40 found by scanning the I2C bus and a DHT22 are simulated, the LED color and MQTT messages are
+/// logged. Takes an optional number of measurement cycles, runs forever
+/// otherwise.
+pub fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let cycles = std::env::args().nth(1).and_then(|arg| arg.parse().ok());
+    if let Err(e) = block_on(run(cycles)) {
+        log::error!("Error in simulation {e:?}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cycles: Option<u32>) -> Result<()> {
+    let settings = Settings::default();
+    let encoding = settings.encoding();
+    log::info!("Firmware {}", BuildInfo::current());
+
+    // Same setup as two sensors detected on the device
+    let mut sensor0 = Sensor::init(None, FakeSds011::new(0xC0DE), &mut NoDelay).await?;
+    log::info!("Sensor 0: {sensor0}");
+    let mut sensor1 = Sensor::init(None, FakePms5003::new(), &mut NoDelay).await?;
+    log::info!("Sensor 1: {sensor1}");
+    let mut co2_sensor = Co2Sensor::new(Co2Kind::Mhz19, FakeMhz19::new());
+    log::info!("CO2 sensor: {co2_sensor}");
+    let mut i2c = FakeSgp40::new();
+    let devices = i2c_bus::scan(&mut i2c);
+    for device in &devices {
+        log::info!("I2C device {device}");
+    }
+    let (topic, payload) = mqtt::i2c_message(ROOT_TOPIC, &devices);
+    log::info!("MQTT publish {topic}: {payload}");
+    let voc_kind = i2c_bus::voc_kind(&devices).context("No VOC sensor on the I2C bus")?;
+    let mut voc_sensor = VocSensor::init(voc_kind, i2c, &mut NoDelay).await?;
+    log::info!("VOC sensor: {voc_sensor}");
+    let mut dht22 = Dht22::new(FakeDht22::new())?;
+    for (i, info) in [sensor0.info(), sensor1.info()].iter().enumerate() {
+        for (topic, payload) in
+            mqtt::sensor_info_messages(&mqtt::sensor_topic(ROOT_TOPIC, i, 2), info)
+        {
+            log::info!("MQTT publish {topic}: {payload}");
+        }
+    }
+
+    for _ in 0..settings.sensor_warmup {
+        let vals = sensor0.measure(&mut NoDelay).await?;
+        log::info!("Sensor 0 warming up, discarded {vals}");
+        let vals = sensor1.measure(&mut NoDelay).await?;
+        log::info!("Sensor 1 warming up, discarded {vals}");
+    }
+
+    let mut history = History::new(Duration::from_secs(settings.measure_interval_secs.into()));
+    let mut cycle = 0;
+    while cycles.map_or(true, |cycles| cycle < cycles) {
+        let measurements = [
+            sensor0.measure(&mut NoDelay).await?,
+            sensor1.measure(&mut NoDelay).await?,
+        ];
+        for (i, vals) in measurements.iter().enumerate() {
+            log::info!("Sensor {i} measured: {vals}");
+            let readings = vals.readings(Some(i), None);
+            for (topic, payload) in
+                mqtt::messages(&mqtt::sensor_topic(ROOT_TOPIC, i, 2), &readings, encoding)
+            {
+                log::info!("MQTT publish {topic}: {payload}");
+            }
+        }
+        let vals = settings
+            .calibration()
+            .apply(&Measurement::average(&measurements).unwrap());
+        log::info!("Particle sensors measured: {vals}");
+        history.push(Sample::new(&vals, clock::now()));
+        if let Some(now) = clock::now() {
+            if let Some(trend) = Trend::compute(history.iter(), now.timestamp()) {
+                log::info!("Trend: {trend}");
+            }
+        }
+        let readings = vals.readings(None, None);
+        log::info!("LED color: {:?}", led::level_color(&settings, &readings));
+        for (topic, payload) in mqtt::messages(ROOT_TOPIC, &readings, encoding) {
+            log::info!("MQTT publish {topic}: {payload}");
+        }
+        let co2 = co2_sensor.measure(&mut NoDelay).await?;
+        let co2 = [Reading::new(Kind::Co2, f32::from(co2), None, None)];
+        for (topic, payload) in mqtt::messages(ROOT_TOPIC, &co2, encoding) {
+            log::info!("MQTT publish {topic}: {payload}");
+        }
+        let climate = dht22.read(&mut NoDelay)?;
+        let climate_readings = [
+            Reading::new(Kind::Temperature, climate.temperature, None, None),
+            Reading::new(Kind::Humidity, climate.humidity, None, None),
+        ];
+        for (topic, payload) in mqtt::messages(ROOT_TOPIC, &climate_readings, encoding) {
+            log::info!("MQTT publish {topic}: {payload}");
+        }
+        // Sampled every second, past its warm up within a cycle
+        let mut voc = None;
+        for _ in 0..VOC_SAMPLES_PER_CYCLE {
+            voc = voc_sensor.measure(climate, &mut NoDelay).await?;
+        }
+        if let Some(voc) = voc {
+            let kind = voc_sensor.kind().reading_kind();
+            let voc = [Reading::new(kind, f32::from(voc), None, None)];
+            for (topic, payload) in mqtt::messages(ROOT_TOPIC, &voc, encoding) {
+                log::info!("MQTT publish {topic}: {payload}");
+            }
+        }
+        let heartbeat = mqtt::Heartbeat {
+            mac: "simulated",
+            name: "simulated",
+            version: env!("CARGO_PKG_VERSION"),
+            rssi: None,
+            last_measure_age_secs: Some(0),
+        };
+        let (topic, payload) = heartbeat.message(&settings.mqtt_fleet_topic);
+        log::info!("MQTT publish {topic}: {payload}");
+        cycle += 1;
+        if cycles.is_none() {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+    log::info!("{} samples in history", history.len());
+    Ok(())
+}
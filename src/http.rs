@@ -0,0 +1,399 @@
+use core::fmt::{Debug, Display};
+use std::mem::ManuallyDrop;
+use std::net::TcpStream;
+use std::os::fd::FromRawFd;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use esp_idf_svc::http::server::{EspHttpConnection, EspHttpServer, Request, Response};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::{EspIOError, Read, Write};
+use esp_idf_svc::sys::{self, EspError};
+use serde::Serialize;
+
+use crate::i18n::Language;
+use crate::resources;
+
+pub const MAX_BODY_LEN: usize = 2048;
+/// Of the small JSON commands, e.g. `POST /api/fan`
+pub const MAX_COMMAND_LEN: usize = 512;
+/// Below it requests are turned away, before the server gets restarted for
+/// lack of memory
+const MIN_FREE_HEAP: u32 = 32 * 1024;
+/// Largest allocation a request needs besides its body
+const MIN_FREE_BLOCK: usize = 4 * 1024;
+/// Suggested to the clients turned away
+const RETRY_AFTER_SECS: &str = "10";
+
+/// Origins allowed to call the API from a browser, set once at startup
+static CORS_ORIGINS: OnceLock<Vec<String>> = OnceLock::new();
+/// Title of the pages, set once at startup
+static TITLE: OnceLock<String> = OnceLock::new();
+/// Send and receive timeout of the clients, set once at startup
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// Read the whole request body, failing if it is larger than `limit`.
+pub fn read_body(reader: &mut impl Read<Error = EspIOError>, limit: usize) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        if body.len() + len > limit {
+            bail!("Request body too large");
+        }
+        body.extend_from_slice(&buf[..len]);
+    }
+    Ok(body)
+}
+
+/// Value of the `key` query parameter of the given uri, not url-decoded.
+pub fn query_param<'a>(uri: &'a str, key: &str) -> Option<&'a str> {
+    let (_, query) = uri.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+/// Allow cross-origin API requests from `origins`, `*` allowing any. Only
+/// the first call has an effect.
+pub fn set_cors_origins(origins: Vec<String>) {
+    let _ = CORS_ORIGINS.set(origins);
+}
+
+/// Title the pages with the station name. Only the first call has an
+/// effect.
+pub fn set_title(title: String) {
+    let _ = TITLE.set(title);
+}
+
+pub fn set_timeout(timeout: Duration) {
+    let _ = TIMEOUT.set(timeout);
+}
+
+/// Let in a request whose body is at most `limit` bytes, unless memory runs
+/// low, and bound the time its client can hold the server task. Gives the
+/// status and message of the error response otherwise.
+pub fn admit(
+    request: &mut Request<&mut EspHttpConnection>,
+    limit: usize,
+) -> Result<(), (u16, &'static str)> {
+    if let Some(timeout) = TIMEOUT.get() {
+        set_socket_timeout(request, *timeout);
+    }
+    let length = request
+        .header("Content-Length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > limit {
+        return Err((413, "Request body too large"));
+    }
+    let memory = resources::memory();
+    if memory.free_heap < MIN_FREE_HEAP
+        || (memory.largest_free_block as usize) < MIN_FREE_BLOCK + limit
+    {
+        return Err((503, "Low on memory, retry later"));
+    }
+    Ok(())
+}
+
+/// Replace the timeouts the server set on the socket when accepting it
+fn set_socket_timeout(request: &mut Request<&mut EspHttpConnection>, timeout: Duration) {
+    let Ok(raw) = request.connection().raw_connection() else {
+        return;
+    };
+    // SAFETY: the request is being handled, so its socket is open
+    let fd = unsafe { esp_idf_svc::sys::httpd_req_to_sockfd(raw.handle()) };
+    if fd < 0 {
+        return;
+    }
+    // SAFETY: the socket stays open meanwhile, and is not closed on drop
+    let socket = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(fd) });
+    if let Err(e) = socket
+        .set_read_timeout(Some(timeout))
+        .and_then(|()| socket.set_write_timeout(Some(timeout)))
+    {
+        log::warn!("Unable to set the timeout of an HTTP client: {e}");
+    }
+}
+
+/// `Access-Control-Allow-Origin` value for the request's origin, if allowed
+fn allowed_origin(request: &Request<&mut EspHttpConnection>) -> Option<String> {
+    let origins = CORS_ORIGINS.get()?;
+    let origin = request.header("Origin")?;
+    if origins.iter().any(|allowed| allowed == "*") {
+        Some("*".to_string())
+    } else {
+        origins
+            .iter()
+            .any(|allowed| allowed == origin)
+            .then(|| origin.to_string())
+    }
+}
+
+/// Start an API response, with the CORS headers when the request comes from
+/// an allowed origin.
+pub fn api_response<'a, 'b>(
+    request: Request<&'a mut EspHttpConnection<'b>>,
+    status: u16,
+    headers: &[(&str, &str)],
+) -> Result<Response<&'a mut EspHttpConnection<'b>>> {
+    let origin = allowed_origin(&request);
+    let mut headers = headers.to_vec();
+    if let Some(origin) = &origin {
+        headers.push(("Access-Control-Allow-Origin", origin));
+        headers.push(("Vary", "Origin"));
+    }
+    Ok(request.into_response(status, None, &headers)?)
+}
+
+/// Answer the CORS preflight requests of the whole `/api/` tree, the server
+/// must match URIs with wildcards.
+pub fn register_cors_handler(handlers: &mut Handlers) -> Result<()> {
+    fn_handler(
+        handlers,
+        "/api/*",
+        Method::Options,
+        |request| -> Result<()> {
+            api_response(
+                request,
+                204,
+                &[
+                    ("Access-Control-Allow-Methods", "GET, POST, DELETE"),
+                    (
+                        "Access-Control-Allow-Headers",
+                        "Content-Type, Authorization",
+                    ),
+                    ("Access-Control-Max-Age", "86400"),
+                ],
+            )?;
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+/// Server whose handlers are counted as they are registered, ESP-IDF
+/// keeping them in a table of `max_uri_handlers` slots
+pub struct Handlers<'a> {
+    server: &'a mut EspHttpServer<'static>,
+    count: usize,
+    max: usize,
+}
+
+impl<'a> Handlers<'a> {
+    /// `max` being the `max_uri_handlers` of the configuration of `server`
+    pub fn new(server: &'a mut EspHttpServer<'static>, max: usize) -> Self {
+        Self {
+            server,
+            count: 0,
+            max,
+        }
+    }
+
+    /// Registered so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Register the handler of `what` with `register`, in one of the slots
+    /// left, logging which one fails
+    pub fn register<E: From<EspError> + Display>(
+        &mut self,
+        what: &str,
+        register: impl FnOnce(&mut EspHttpServer<'static>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.count >= self.max {
+            log::error!(
+                "Unable to register the HTTP handler of {what}: all {} slots are taken",
+                self.max
+            );
+            return Err(EspError::from_infallible::<{ sys::ESP_ERR_NO_MEM }>().into());
+        }
+        if let Err(e) = register(self.server) {
+            log::error!("Unable to register the HTTP handler of {what}: {e}");
+            return Err(e);
+        }
+        self.count += 1;
+        Ok(())
+    }
+}
+
+/// Register `handler` for `method` on `uri`
+pub fn fn_handler<E, F>(
+    handlers: &mut Handlers,
+    uri: &str,
+    method: Method,
+    handler: F,
+) -> Result<(), EspError>
+where
+    F: for<'r> Fn(Request<&mut EspHttpConnection<'r>>) -> Result<(), E> + Send + 'static,
+    E: Debug,
+{
+    handlers.register(&format!("{method:?} {uri}"), |server| {
+        server.fn_handler(uri, method, handler)?;
+        Ok(())
+    })
+}
+
+/// Check the `Authorization: Bearer <token>` header of a device control
+/// request, these endpoints being disabled while `token` is empty. Gives the
+/// status and message of the error response otherwise.
+pub fn authorize(
+    request: &Request<&mut EspHttpConnection>,
+    token: &str,
+) -> Result<(), (u16, &'static str)> {
+    let given = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    check_token(given, token)
+}
+
+/// Check the token of a request changing the settings, open while `token`
+/// is `None`, i.e. until the device is provisioned with its API key
+pub fn authorize_write(
+    request: &Request<&mut EspHttpConnection>,
+    token: Option<&str>,
+) -> Result<(), (u16, &'static str)> {
+    token.map_or(Ok(()), |token| authorize(request, token))
+}
+
+/// Compare a token given by a client to `token`, see [`authorize`]
+pub fn check_token(given: &str, token: &str) -> Result<(), (u16, &'static str)> {
+    if token.is_empty() {
+        return Err((403, "Set api_token to enable the control endpoints"));
+    }
+    // Same time whatever the first wrong byte
+    let matching = given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+    if matching {
+        Ok(())
+    } else {
+        Err((401, "Invalid or missing token"))
+    }
+}
+
+pub fn write_json(request: Request<&mut EspHttpConnection>, value: &impl Serialize) -> Result<()> {
+    let json = serde_json::to_string(value)?;
+    let mut response = api_response(request, 200, &[("Content-Type", "application/json")])?;
+    response.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+pub fn write_error(
+    request: Request<&mut EspHttpConnection>,
+    status: u16,
+    message: impl AsRef<str>,
+) -> Result<()> {
+    let headers: &[_] = if status == 503 {
+        &[("Retry-After", RETRY_AFTER_SECS)]
+    } else {
+        &[]
+    };
+    let mut response = api_response(request, status, headers)?;
+    response.write_all(message.as_ref().as_bytes())?;
+    Ok(())
+}
+
+/// Escape text coming from outside before putting it in a page
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Page in `language` around `content`
+pub fn templated(language: Language, content: impl AsRef<str>) -> String {
+    format!("{}{}{PAGE_END}", page_start(language), content.as_ref())
+}
+
+/// Start of a page in `language`, up to its content
+pub fn page_start(language: Language) -> String {
+    format!(
+        r#"
+<!DOCTYPE html>
+<html lang="{}">
+    <head>
+        <meta charset="utf-8">
+        <meta name="viewport" content="width=device-width, initial-scale=1">
+        <title>{}</title>
+        <link rel="stylesheet" href="/assets/style.css">
+    </head>
+    <body>
+        "#,
+        language.code(),
+        escape(TITLE.get().map_or("esp-rs web server", String::as_str)),
+    )
+}
+
+/// End of a page, after its content
+pub const PAGE_END: &str = "
+    </body>
+</html>
+";
+
+/// Size of the chunks sent by [`ChunkedWriter`]
+const CHUNK_LEN: usize = 1024;
+
+/// Sends a page in chunks of [`CHUNK_LEN`] bytes while it is templated with
+/// `write!`, so that a page is never held whole in memory. The first write
+/// error is kept and returned by [`ChunkedWriter::finish`].
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    error: Option<W::Error>,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(CHUNK_LEN),
+            error: None,
+        }
+    }
+
+    /// Send the last chunk
+    pub fn finish(mut self) -> Result<(), W::Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        if !self.buf.is_empty() {
+            self.inner.write_all(&self.buf)?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> core::fmt::Write for ChunkedWriter<W> {
+    fn write_str(&mut self, text: &str) -> core::fmt::Result {
+        if self.error.is_some() {
+            return Err(core::fmt::Error);
+        }
+        let mut bytes = text.as_bytes();
+        while !bytes.is_empty() {
+            let len = bytes.len().min(CHUNK_LEN - self.buf.len());
+            self.buf.extend_from_slice(&bytes[..len]);
+            bytes = &bytes[len..];
+            if self.buf.len() == CHUNK_LEN {
+                if let Err(error) = self.inner.write_all(&self.buf) {
+                    self.error = Some(error);
+                    return Err(core::fmt::Error);
+                }
+                self.buf.clear();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use core::ffi::{c_int, c_uchar, c_void, CStr};
+use std::ffi::CString;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Write;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::tls::X509;
+use serde::{Deserialize, Serialize};
+
+use crate::http;
+
+const NAMESPACE: &str = "https";
+const KEY_CERT: &str = "cert";
+const KEY_PRIVATE_KEY: &str = "key";
+const KEY_SELF_SIGNED_CERT: &str = "self_cert";
+const KEY_SELF_SIGNED_KEY: &str = "self_key";
+/// Certificate chains are larger than the settings
+const MAX_UPLOAD_LEN: usize = 16 * 1024;
+
+const SELF_SIGNED_SUBJECT: &CStr = c"CN=esp-particle-sensor";
+/// Checked by the clients against their own clock, the device may not know
+/// the time yet
+const SELF_SIGNED_NOT_BEFORE: &CStr = c"20240101000000";
+const SELF_SIGNED_NOT_AFTER: &CStr = c"20491231235959";
+/// The PEM of a P-256 certificate is about 600 bytes
+const PEM_BUF_LEN: usize = 2048;
+
+/// Certificate and private key uploaded by the user, as PEM
+#[derive(Serialize, Deserialize)]
+pub struct Upload {
+    pub certificate: String,
+    pub private_key: String,
+}
+
+/// Web server certificate, persisted in the `https` NVS namespace.
+pub struct CertStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+pub type SharedCertStore = Arc<Mutex<CertStore>>;
+
+impl CertStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, NAMESPACE, true)?,
+        })
+    }
+
+    /// Server configuration with the uploaded certificate, or the
+    /// self-signed one of the device if none was uploaded
+    pub fn server_configuration(&mut self) -> Result<Configuration> {
+        let (cert, private_key) = match (self.get(KEY_CERT)?, self.get(KEY_PRIVATE_KEY)?) {
+            (Some(cert), Some(private_key)) => {
+                log::info!("Using the uploaded HTTPS certificate");
+                (cert, private_key)
+            }
+            _ => self.self_signed()?,
+        };
+        // The server keeps them for its whole life, which is the app's, and
+        // this is only called once
+        let (cert, private_key) = (&*Vec::leak(cert), &*Vec::leak(private_key));
+        Ok(Configuration {
+            server_certificate: Some(X509::pem_until_nul(cert)),
+            private_key: Some(X509::pem_until_nul(private_key)),
+            ..Default::default()
+        })
+    }
+
+    /// Certificate of the device, generated on the first start with HTTPS
+    /// and kept since, the NVS partition never leaving the device
+    fn self_signed(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+        if let (Some(cert), Some(private_key)) = (
+            self.get(KEY_SELF_SIGNED_CERT)?,
+            self.get(KEY_SELF_SIGNED_KEY)?,
+        ) {
+            return Ok((cert, private_key));
+        }
+        log::info!("Generating the self-signed HTTPS certificate");
+        let (cert, private_key) = generate_self_signed()?;
+        self.set(KEY_SELF_SIGNED_KEY, &private_key)?;
+        self.set(KEY_SELF_SIGNED_CERT, &cert)?;
+        let nul_terminated = |pem: String| {
+            let mut bytes = pem.into_bytes();
+            bytes.push(0);
+            bytes
+        };
+        Ok((nul_terminated(cert), nul_terminated(private_key)))
+    }
+
+    /// Store a certificate and its private key, as PEM, if the key is the
+    /// one of the certificate
+    pub fn save(&mut self, cert: &str, private_key: &str) -> Result<()> {
+        check_pair(cert, private_key)?;
+        self.set(KEY_CERT, cert)?;
+        self.set(KEY_PRIVATE_KEY, private_key)
+    }
+
+    /// Go back to the self-signed certificate
+    pub fn clear(&mut self) -> Result<()> {
+        self.nvs.remove(KEY_CERT)?;
+        self.nvs.remove(KEY_PRIVATE_KEY)?;
+        Ok(())
+    }
+
+    /// The PEM with its NUL terminator
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let Some(len) = self.nvs.blob_len(key)? else {
+            return Ok(None);
+        };
+        let mut buf = vec![0u8; len];
+        Ok(self.nvs.get_blob(key, &mut buf)?.map(<[u8]>::to_vec))
+    }
+
+    fn set(&mut self, key: &str, pem: &str) -> Result<()> {
+        let mut blob = pem.as_bytes().to_vec();
+        blob.push(0);
+        Ok(self.nvs.set_blob(key, &blob)?)
+    }
+}
+
+/// `POST /api/https` stores the certificate and private key given as JSON
+/// (`{"certificate": "<PEM>", "private_key": "<PEM>"}`), `DELETE` reverts to
+/// the self-signed one. Applied on the next restart. Both need `write_token` as
+/// a bearer token when given.
+pub fn register_handlers(
+    handlers: &mut http::Handlers,
+    cert_store: SharedCertStore,
+    write_token: Option<String>,
+) -> Result<()> {
+    http::fn_handler(handlers, "/api/https", Method::Post, {
+        let cert_store = cert_store.clone();
+        let write_token = write_token.clone();
+        move |mut request| -> Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, MAX_UPLOAD_LEN) {
+                return http::write_error(request, status, message);
+            }
+            if let Err((status, message)) = http::authorize_write(&request, write_token.as_deref())
+            {
+                return http::write_error(request, status, message);
+            }
+            let body = http::read_body(&mut request, MAX_UPLOAD_LEN)?;
+            let upload: Upload = match serde_json::from_slice(&body) {
+                Ok(upload) => upload,
+                Err(e) => return http::write_error(request, 400, format!("{e}")),
+            };
+            let result = cert_store
+                .lock()
+                .unwrap()
+                .save(&upload.certificate, &upload.private_key);
+            match result {
+                Ok(()) => {
+                    log::info!("HTTPS certificate updated, applied on next restart");
+                    http::api_response(request, 200, &[])?;
+                    Ok(())
+                }
+                Err(e) => http::write_error(request, 400, format!("{e}")),
+            }
+        }
+    })?;
+    http::fn_handler(
+        handlers,
+        "/api/https",
+        Method::Delete,
+        move |request| -> Result<()> {
+            if let Err((status, message)) = http::authorize_write(&request, write_token.as_deref())
+            {
+                return http::write_error(request, status, message);
+            }
+            cert_store.lock().unwrap().clear()?;
+            log::info!("HTTPS certificate removed, applied on next restart");
+            http::api_response(request, 200, &[])?;
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+/// Parse the PEM certificate chain and private key as the server will, and
+/// check that the key matches the first certificate
+fn check_pair(cert: &str, private_key: &str) -> Result<()> {
+    use esp_idf_svc::sys;
+
+    let (Ok(cert), Ok(private_key)) = (CString::new(cert), CString::new(private_key)) else {
+        bail!("Expected a PEM encoded certificate and private key");
+    };
+    // SAFETY: plain C structs, initialized below before any other use
+    let mut chain: sys::mbedtls_x509_crt = unsafe { core::mem::zeroed() };
+    let mut key: sys::mbedtls_pk_context = unsafe { core::mem::zeroed() };
+    // SAFETY: the contexts are initialized first and freed last, the PEM
+    // strings outlive the calls
+    unsafe {
+        sys::mbedtls_x509_crt_init(&mut chain);
+        sys::mbedtls_pk_init(&mut key);
+        let result = parse_pair(&mut chain, &mut key, &cert, &private_key);
+        sys::mbedtls_pk_free(&mut key);
+        sys::mbedtls_x509_crt_free(&mut chain);
+        result
+    }
+}
+
+/// # Safety
+///
+/// `chain` and `key` must be initialized
+unsafe fn parse_pair(
+    chain: &mut esp_idf_svc::sys::mbedtls_x509_crt,
+    key: &mut esp_idf_svc::sys::mbedtls_pk_context,
+    cert: &CStr,
+    private_key: &CStr,
+) -> Result<()> {
+    use esp_idf_svc::sys;
+
+    let check = |ret: c_int, what: &str| -> Result<()> {
+        if ret != 0 {
+            bail!("Invalid {what}, mbedtls error -0x{:04x}", -ret);
+        }
+        Ok(())
+    };
+    // The PEM lengths count the NUL terminator
+    let cert = cert.to_bytes_with_nul();
+    let ret = sys::mbedtls_x509_crt_parse(chain, cert.as_ptr(), cert.len());
+    if ret > 0 {
+        bail!("Invalid certificate chain, {ret} certificates could not be parsed");
+    }
+    check(ret, "certificate")?;
+    let private_key = private_key.to_bytes_with_nul();
+    check(
+        sys::mbedtls_pk_parse_key(
+            key,
+            private_key.as_ptr(),
+            private_key.len(),
+            core::ptr::null(),
+            0,
+            Some(random),
+            core::ptr::null_mut(),
+        ),
+        "private key",
+    )?;
+    check(
+        sys::mbedtls_pk_check_pair(&chain.private_pk, key, Some(random), core::ptr::null_mut()),
+        "private key for the certificate",
+    )
+}
+
+/// A P-256 private key and a certificate signed with it, as PEM, from the
+/// hardware random number generator
+fn generate_self_signed() -> Result<(String, String)> {
+    use esp_idf_svc::sys;
+
+    // SAFETY: plain C structs, initialized below before any other use
+    let mut key: sys::mbedtls_pk_context = unsafe { core::mem::zeroed() };
+    let mut cert: sys::mbedtls_x509write_cert = unsafe { core::mem::zeroed() };
+    let mut cert_pem = vec![0u8; PEM_BUF_LEN];
+    let mut key_pem = vec![0u8; PEM_BUF_LEN];
+    // SAFETY: the contexts are initialized first and freed last, the
+    // buffers outlive the calls
+    let result = unsafe {
+        sys::mbedtls_pk_init(&mut key);
+        sys::mbedtls_x509write_crt_init(&mut cert);
+        let result = write_self_signed(&mut key, &mut cert, &mut cert_pem, &mut key_pem);
+        sys::mbedtls_x509write_crt_free(&mut cert);
+        sys::mbedtls_pk_free(&mut key);
+        result
+    };
+    result?;
+    let pem = |buf: &[u8]| -> Result<String> {
+        Ok(CStr::from_bytes_until_nul(buf)?.to_str()?.to_string())
+    };
+    Ok((pem(&cert_pem)?, pem(&key_pem)?))
+}
+
+/// # Safety
+///
+/// `key` and `cert` must be initialized
+unsafe fn write_self_signed(
+    key: &mut esp_idf_svc::sys::mbedtls_pk_context,
+    cert: &mut esp_idf_svc::sys::mbedtls_x509write_cert,
+    cert_pem: &mut [u8],
+    key_pem: &mut [u8],
+) -> Result<()> {
+    use esp_idf_svc::sys;
+
+    let check = |ret: c_int, what: &str| -> Result<()> {
+        if ret != 0 {
+            bail!(
+                "Unable to {what} of the certificate, mbedtls error -0x{:04x}",
+                -ret
+            );
+        }
+        Ok(())
+    };
+    let mut serial = [0u8; 16];
+    sys::esp_fill_random(serial.as_mut_ptr().cast(), serial.len());
+    // Positive
+    serial[0] &= 0x7f;
+    check(
+        sys::mbedtls_pk_setup(
+            key,
+            sys::mbedtls_pk_info_from_type(sys::mbedtls_pk_type_t_MBEDTLS_PK_ECKEY),
+        ),
+        "set up the key",
+    )?;
+    check(
+        sys::mbedtls_ecp_gen_key(
+            sys::mbedtls_ecp_group_id_MBEDTLS_ECP_DP_SECP256R1,
+            // What the inline `mbedtls_pk_ec` returns
+            key.private_pk_ctx.cast(),
+            Some(random),
+            core::ptr::null_mut(),
+        ),
+        "generate the key",
+    )?;
+    sys::mbedtls_x509write_crt_set_version(cert, sys::MBEDTLS_X509_CRT_VERSION_3 as c_int);
+    sys::mbedtls_x509write_crt_set_md_alg(cert, sys::mbedtls_md_type_t_MBEDTLS_MD_SHA256);
+    sys::mbedtls_x509write_crt_set_subject_key(cert, key);
+    sys::mbedtls_x509write_crt_set_issuer_key(cert, key);
+    check(
+        sys::mbedtls_x509write_crt_set_subject_name(cert, SELF_SIGNED_SUBJECT.as_ptr()),
+        "set the subject",
+    )?;
+    check(
+        sys::mbedtls_x509write_crt_set_issuer_name(cert, SELF_SIGNED_SUBJECT.as_ptr()),
+        "set the issuer",
+    )?;
+    check(
+        sys::mbedtls_x509write_crt_set_serial_raw(cert, serial.as_mut_ptr(), serial.len()),
+        "set the serial number",
+    )?;
+    check(
+        sys::mbedtls_x509write_crt_set_validity(
+            cert,
+            SELF_SIGNED_NOT_BEFORE.as_ptr(),
+            SELF_SIGNED_NOT_AFTER.as_ptr(),
+        ),
+        "set the validity",
+    )?;
+    check(
+        sys::mbedtls_x509write_crt_set_basic_constraints(cert, 0, -1),
+        "set the constraints",
+    )?;
+    check(
+        sys::mbedtls_x509write_crt_pem(
+            cert,
+            cert_pem.as_mut_ptr(),
+            cert_pem.len(),
+            Some(random),
+            core::ptr::null_mut(),
+        ),
+        "sign",
+    )?;
+    check(
+        sys::mbedtls_pk_write_key_pem(key, key_pem.as_mut_ptr(), key_pem.len()),
+        "write the key",
+    )
+}
+
+/// Random generator of mbedtls, filled by the hardware one, random once the
+/// radio is on
+unsafe extern "C" fn random(_: *mut c_void, buf: *mut c_uchar, len: usize) -> c_int {
+    esp_idf_svc::sys::esp_fill_random(buf.cast(), len);
+    0
+}
+
+/// Plain HTTP server redirecting everything to HTTPS
+pub fn redirect_server() -> Result<EspHttpServer<'static>> {
+    let mut server = EspHttpServer::new(&Configuration {
+        // Not the default one, used by the HTTPS server
+        ctrl_port: 32769,
+        uri_match_wildcard: true,
+        ..Default::default()
+    })?;
+    for method in [Method::Get, Method::Post, Method::Delete] {
+        server.fn_handler("/*", method, |request| -> Result<()> {
+            let Some(host) = request.header("Host").map(str::to_string) else {
+                return http::write_error(request, 400, "Missing Host header");
+            };
+            let location = format!("https://{host}{}", request.uri());
+            let mut response =
+                request.into_response(301, None, &[("Location", location.as_str())])?;
+            response.write_all(b"Use HTTPS")?;
+            Ok(())
+        })?;
+    }
+    Ok(server)
+}
@@ -0,0 +1,247 @@
+use std::fmt::Display;
+
+use crate::aqi::Band;
+
+/// Languages of the web pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    En,
+    Fr,
+    De,
+}
+
+impl Language {
+    pub const ALL: [Self; 3] = [Self::En, Self::Fr, Self::De];
+
+    /// ISO 639-1 code, of the `lang` attribute of the pages
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Fr => "fr",
+            Self::De => "de",
+        }
+    }
+
+    /// Language of a code such as `fr` or `fr-CH`, whatever the case
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split('-').next().unwrap_or_default();
+        Self::ALL
+            .into_iter()
+            .find(|language| primary.eq_ignore_ascii_case(language.code()))
+    }
+
+    /// The `setting` code unless empty, else the language preferred by the
+    /// browser in its `Accept-Language` header, English if none is known
+    pub fn select(setting: &str, accept_language: Option<&str>) -> Self {
+        Self::from_code(setting)
+            .or_else(|| accept_language.and_then(preferred))
+            .unwrap_or(Self::En)
+    }
+
+    pub fn strings(self) -> &'static Strings {
+        match self {
+            Self::En => &EN,
+            Self::Fr => &FR,
+            Self::De => &DE,
+        }
+    }
+}
+
+/// Texts of the pages, `{}` being replaced by [`fill`]
+#[derive(Debug)]
+pub struct Strings {
+    pub warming_up: &'static str,
+    pub no_measurement: &'static str,
+    /// Of a single value or sensor
+    pub no_measure: &'static str,
+    pub measured: &'static str,
+    pub measured_before_restart: &'static str,
+    pub measured_at: &'static str,
+    pub ago: &'static str,
+    pub stale: &'static str,
+    pub voc_index: &'static str,
+    pub voc: &'static str,
+    pub climate: &'static str,
+    pub climate_no_measure: &'static str,
+    pub exceedance: &'static str,
+    pub sensor: &'static str,
+    pub mqtt_disabled: &'static str,
+    pub broker_unreachable: &'static str,
+    pub internet_unreachable: &'static str,
+    /// Titles of the chart of each history tier
+    pub history: [&'static str; 3],
+    pub periods: [&'static str; 3],
+    pub stations: &'static str,
+    pub station: &'static str,
+    pub this_station: &'static str,
+    pub configuration: &'static str,
+    pub save: &'static str,
+    pub saved: &'static str,
+    /// Names of the AQI bands, from `Good` to `Hazardous`
+    pub bands: [&'static str; 6],
+}
+
+impl Strings {
+    pub fn band(&self, band: Band) -> &'static str {
+        self.bands[band as usize]
+    }
+}
+
+const EN: Strings = Strings {
+    warming_up: "Sensors warming up",
+    no_measurement: "No measure",
+    no_measure: "no measure",
+    measured: "measured",
+    measured_before_restart: "measured before the restart",
+    measured_at: "at {}",
+    ago: "{}s ago",
+    stale: "stale",
+    voc_index: "VOC index",
+    voc: "VOC",
+    climate: "Temperature: {} °C, humidity: {} %",
+    climate_no_measure: "Temperature and humidity: no measure",
+    exceedance: "Above the limits today: {} min for PM2.5 ({} µg/m³), {} min for PM10 ({} µg/m³)",
+    sensor: "Sensor {}",
+    mqtt_disabled: "MQTT disabled",
+    broker_unreachable: "MQTT broker unreachable",
+    internet_unreachable: "Connected to the local network, but not to the internet",
+    history: [
+        "PM2.5, last 24h (max {} µg/m³)",
+        "PM2.5, 15 min averages of the last 7 days (max {} µg/m³)",
+        "PM2.5, hourly averages of the last 30 days (max {} µg/m³)",
+    ],
+    periods: ["24 h", "7 days", "30 days"],
+    stations: "Stations",
+    station: "Station",
+    this_station: "This station",
+    configuration: "Configuration",
+    save: "Save",
+    saved: "Saved",
+    bands: [
+        "Good",
+        "Moderate",
+        "Unhealthy for sensitive groups",
+        "Unhealthy",
+        "Very unhealthy",
+        "Hazardous",
+    ],
+};
+
+const FR: Strings = Strings {
+    warming_up: "Capteurs en préchauffage",
+    no_measurement: "Aucune mesure",
+    no_measure: "aucune mesure",
+    measured: "mesuré",
+    measured_before_restart: "mesuré avant le redémarrage",
+    measured_at: "le {}",
+    ago: "il y a {} s",
+    stale: "périmé",
+    voc_index: "Indice COV",
+    voc: "COV",
+    climate: "Température : {} °C, humidité : {} %",
+    climate_no_measure: "Température et humidité : aucune mesure",
+    exceedance: "Au-delà des limites aujourd'hui : {} min pour les PM2.5 ({} µg/m³), {} min pour les PM10 ({} µg/m³)",
+    sensor: "Capteur {}",
+    mqtt_disabled: "MQTT désactivé",
+    broker_unreachable: "Broker MQTT injoignable",
+    internet_unreachable: "Connecté au réseau local, mais pas à internet",
+    history: [
+        "PM2.5, dernières 24 h (max {} µg/m³)",
+        "PM2.5, moyennes sur 15 min des 7 derniers jours (max {} µg/m³)",
+        "PM2.5, moyennes horaires des 30 derniers jours (max {} µg/m³)",
+    ],
+    periods: ["24 h", "7 jours", "30 jours"],
+    stations: "Stations",
+    station: "Station",
+    this_station: "Cette station",
+    configuration: "Configuration",
+    save: "Enregistrer",
+    saved: "Enregistré",
+    bands: [
+        "Bon",
+        "Modéré",
+        "Mauvais pour les personnes sensibles",
+        "Mauvais",
+        "Très mauvais",
+        "Dangereux",
+    ],
+};
+
+const DE: Strings = Strings {
+    warming_up: "Sensoren wärmen sich auf",
+    no_measurement: "Keine Messung",
+    no_measure: "keine Messung",
+    measured: "gemessen",
+    measured_before_restart: "gemessen vor dem Neustart",
+    measured_at: "am {}",
+    ago: "vor {} s",
+    stale: "veraltet",
+    voc_index: "VOC-Index",
+    voc: "VOC",
+    climate: "Temperatur: {} °C, Luftfeuchtigkeit: {} %",
+    climate_no_measure: "Temperatur und Luftfeuchtigkeit: keine Messung",
+    exceedance:
+        "Heute über den Grenzwerten: {} min für PM2.5 ({} µg/m³), {} min für PM10 ({} µg/m³)",
+    sensor: "Sensor {}",
+    mqtt_disabled: "MQTT deaktiviert",
+    broker_unreachable: "MQTT-Broker nicht erreichbar",
+    internet_unreachable: "Mit dem lokalen Netzwerk verbunden, aber nicht mit dem Internet",
+    history: [
+        "PM2.5, letzte 24 h (max {} µg/m³)",
+        "PM2.5, 15-Minuten-Mittel der letzten 7 Tage (max {} µg/m³)",
+        "PM2.5, Stundenmittel der letzten 30 Tage (max {} µg/m³)",
+    ],
+    periods: ["24 h", "7 Tage", "30 Tage"],
+    stations: "Stationen",
+    station: "Station",
+    this_station: "Diese Station",
+    configuration: "Konfiguration",
+    save: "Speichern",
+    saved: "Gespeichert",
+    bands: [
+        "Gut",
+        "Mäßig",
+        "Ungesund für empfindliche Gruppen",
+        "Ungesund",
+        "Sehr ungesund",
+        "Gefährlich",
+    ],
+};
+
+/// `text` with its `{}` replaced by the `args`, in order
+pub fn fill(text: &str, args: &[&dyn Display]) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut args = args.iter();
+    let mut parts = text.split("{}");
+    filled += parts.next().unwrap_or_default();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            filled += &arg.to_string();
+        }
+        filled += part;
+    }
+    filled
+}
+
+/// Known language of the highest quality in an `Accept-Language` header,
+/// such as `fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5`, the first one on a tie
+fn preferred(accept_language: &str) -> Option<Language> {
+    let mut best: Option<(Language, f32)> = None;
+    for range in accept_language.split(',') {
+        let mut params = range.split(';');
+        let Some(language) = params
+            .next()
+            .and_then(|code| Language::from_code(code.trim()))
+        else {
+            continue;
+        };
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality > best.map_or(0.0, |(_, best)| best) {
+            best = Some((language, quality));
+        }
+    }
+    best.map(|(language, _)| language)
+}
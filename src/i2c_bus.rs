@@ -0,0 +1,89 @@
+use core::fmt::{self, Display, Formatter};
+use core::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+use embedded_hal::i2c::{ErrorType, I2c, Operation};
+use serde::Serialize;
+
+use crate::voc::VocKind;
+
+/// 7-bit addresses, without the reserved ones
+const ADDRESSES: RangeInclusive<u8> = 0x08..=0x77;
+/// Models told by their address, only the VOC sensors have a driver
+const KNOWN: &[(u8, &str)] = &[
+    (0x3C, "SSD1306"),
+    (0x58, "SGP30"),
+    (0x59, "SGP40"),
+    (0x62, "SCD4x"),
+    (0x76, "BME280"),
+    (0x77, "BME280"),
+];
+
+/// A device answering on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Device {
+    pub address: u8,
+    /// Guessed from the address, `None` if unknown
+    pub model: Option<&'static str>,
+}
+
+impl Display for Device {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:02X}", self.address)?;
+        match self.model {
+            Some(model) => write!(f, " ({model})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The devices acknowledging an empty write
+pub fn scan(bus: &mut impl I2c) -> Vec<Device> {
+    ADDRESSES
+        .filter(|address| bus.write(*address, &[]).is_ok())
+        .map(|address| Device {
+            address,
+            model: KNOWN
+                .iter()
+                .find(|(known, _)| *known == address)
+                .map(|(_, model)| *model),
+        })
+        .collect()
+}
+
+/// VOC sensor among the devices, the SGP40 first as it gives the VOC index
+pub fn voc_kind(devices: &[Device]) -> Option<VocKind> {
+    [(0x59, VocKind::Sgp40), (0x58, VocKind::Sgp30)]
+        .into_iter()
+        .find(|(address, _)| devices.iter().any(|device| device.address == *address))
+        .map(|(_, kind)| kind)
+}
+
+/// Bus shared by the sensors and the scans, locked for each transaction
+pub struct SharedBus<I2C>(Arc<Mutex<I2C>>);
+
+impl<I2C> SharedBus<I2C> {
+    pub fn new(bus: I2C) -> Self {
+        Self(Arc::new(Mutex::new(bus)))
+    }
+}
+
+impl<I2C> Clone for SharedBus<I2C> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<I2C: I2c> ErrorType for SharedBus<I2C> {
+    type Error = I2C::Error;
+}
+
+impl<I2C: I2c> I2c for SharedBus<I2C> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.0.lock().unwrap().transaction(address, operations)
+    }
+}
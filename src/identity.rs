@@ -0,0 +1,107 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use serde::{Deserialize, Serialize};
+
+const NAMESPACE: &str = "identity";
+const KEY_DEVICE_ID: &str = "device_id";
+const KEY_API_KEY: &str = "api_key";
+const MAX_DEVICE_ID_LEN: usize = 32;
+const MIN_API_KEY_LEN: usize = 16;
+const MAX_API_KEY_LEN: usize = 64;
+
+/// Identity of the device in a fleet, provisioned once per device. Kept apart
+/// from the settings: neither exported by `/api/config` nor reset with them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    /// Tags the data pushed by the device, e.g. `office-3`
+    pub device_id: String,
+    /// Secret of this device only, replaces `api_token` once provisioned
+    #[serde(skip_serializing)]
+    pub api_key: String,
+}
+
+impl Identity {
+    pub fn is_provisioned(&self) -> bool {
+        !self.api_key.is_empty()
+    }
+
+    /// Token of the device control endpoints: the API key once provisioned,
+    /// `api_token` otherwise
+    pub fn token<'a>(&'a self, api_token: &'a str) -> &'a str {
+        if self.is_provisioned() {
+            &self.api_key
+        } else {
+            api_token
+        }
+    }
+
+    /// Token required by the endpoints changing the settings, which stay open
+    /// until the device is provisioned
+    pub fn write_token(&self) -> Option<String> {
+        self.is_provisioned().then(|| self.api_key.clone())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let id_chars = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if self.device_id.is_empty()
+            || self.device_id.len() > MAX_DEVICE_ID_LEN
+            || !self.device_id.chars().all(id_chars)
+        {
+            bail!("device_id must be 1 to {MAX_DEVICE_ID_LEN} letters, digits, - or _");
+        }
+        if !(MIN_API_KEY_LEN..=MAX_API_KEY_LEN).contains(&self.api_key.len())
+            || !self.api_key.chars().all(|c| c.is_ascii_graphic())
+        {
+            bail!("api_key must be {MIN_API_KEY_LEN} to {MAX_API_KEY_LEN} printable characters without spaces");
+        }
+        Ok(())
+    }
+}
+
+/// Device identity, persisted in the `identity` NVS namespace.
+pub struct IdentityStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+pub type SharedIdentityStore = Arc<Mutex<IdentityStore>>;
+
+impl IdentityStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, NAMESPACE, true)?,
+        })
+    }
+
+    /// Empty until provisioned
+    pub fn load(&self) -> Result<Identity> {
+        Ok(Identity {
+            device_id: self.get(KEY_DEVICE_ID)?,
+            api_key: self.get(KEY_API_KEY)?,
+        })
+    }
+
+    pub fn save(&mut self, identity: &Identity) -> Result<()> {
+        identity.validate()?;
+        self.nvs.set_str(KEY_DEVICE_ID, &identity.device_id)?;
+        self.nvs.set_str(KEY_API_KEY, &identity.api_key)?;
+        Ok(())
+    }
+
+    /// Back to `api_token`, e.g. before handing the device over
+    pub fn clear(&mut self) -> Result<()> {
+        self.nvs.remove(KEY_DEVICE_ID)?;
+        self.nvs.remove(KEY_API_KEY)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<String> {
+        let mut buf = [0u8; MAX_API_KEY_LEN + 1];
+        Ok(self
+            .nvs
+            .get_str(key, &mut buf)?
+            .unwrap_or_default()
+            .to_string())
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::{bail, Result};
+
+/// First byte of an ESP-IDF app image
+const MAGIC: u8 = 0xE9;
+/// Common and extended headers
+const HEADER_LEN: usize = 24;
+/// Load address and length
+const SEGMENT_HEADER_LEN: usize = 8;
+/// ESP-IDF refuses more
+const MAX_SEGMENTS: u8 = 16;
+/// SHA-256 after the checksum
+const HASH_LEN: u32 = 32;
+/// Read from the flash and streamed at once
+#[cfg(target_os = "espidf")]
+const CHUNK_LEN: usize = 1024;
+
+/// Whether the flash can be served as a factory image: encrypted, it reads
+/// back as ciphertext of the key of this device, which boots nowhere else
+#[cfg(target_os = "espidf")]
+pub const FACTORY_AVAILABLE: bool = !cfg!(esp_idf_secure_flash_enc_enabled);
+
+/// Length of the app image at the start of a partition, `read` filling the
+/// buffer from an offset. The partition is larger than the image.
+pub fn app_len(mut read: impl FnMut(u32, &mut [u8]) -> Result<()>) -> Result<u32> {
+    let mut header = [0u8; HEADER_LEN];
+    read(0, &mut header)?;
+    let segments = header[1];
+    if header[0] != MAGIC || segments > MAX_SEGMENTS {
+        bail!("No app image");
+    }
+    let hash_appended = header[23] == 1;
+    let mut offset = HEADER_LEN as u32;
+    for _ in 0..segments {
+        let mut segment = [0u8; SEGMENT_HEADER_LEN];
+        read(offset, &mut segment)?;
+        let len = u32::from_le_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        offset = offset
+            .checked_add(SEGMENT_HEADER_LEN as u32 + len)
+            .ok_or_else(|| anyhow::anyhow!("Invalid segment length"))?;
+    }
+    // The checksum byte ends a 16 bytes block
+    let len = (offset + 1).next_multiple_of(16);
+    Ok(if hash_appended { len + HASH_LEN } else { len })
+}
+
+/// Stream the flash as a factory image, written at 0 on another device: the
+/// bootloader, partition table and running app. NVS, holding the settings
+/// and credentials, is left blank.
+#[cfg(target_os = "espidf")]
+pub fn write_factory(mut write: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    // Bootloader and partition table
+    let tables_end = esp_idf_svc::sys::CONFIG_PARTITION_TABLE_OFFSET + 0x1000;
+    // SAFETY: the running partition is never freed
+    let app = unsafe { &*esp_idf_svc::sys::esp_ota_get_running_partition() };
+    let app_len = app_len(|offset, buf| read_flash(app.address + offset, buf))?;
+    copy(0, tables_end, &mut write)?;
+    // Like erased flash
+    let blank = [0xFFu8; CHUNK_LEN];
+    for address in (tables_end..app.address).step_by(CHUNK_LEN) {
+        write(&blank[..(app.address - address).min(CHUNK_LEN as u32) as usize])?;
+    }
+    copy(app.address, app.address + app_len, &mut write)
+}
+
+#[cfg(target_os = "espidf")]
+fn copy(start: u32, end: u32, write: &mut impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    let mut buf = [0u8; CHUNK_LEN];
+    for address in (start..end).step_by(CHUNK_LEN) {
+        let chunk = &mut buf[..(end - address).min(CHUNK_LEN as u32) as usize];
+        read_flash(address, chunk)?;
+        write(chunk)?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "espidf")]
+fn read_flash(address: u32, buf: &mut [u8]) -> Result<()> {
+    use esp_idf_svc::sys;
+
+    // SAFETY: the buffer outlives the call, the default chip is used
+    sys::esp!(unsafe {
+        sys::esp_flash_read(
+            core::ptr::null_mut(),
+            buf.as_mut_ptr().cast(),
+            address,
+            buf.len() as u32,
+        )
+    })?;
+    Ok(())
+}
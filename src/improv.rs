@@ -0,0 +1,316 @@
+use std::net::Ipv4Addr;
+
+use anyhow::Result;
+
+use crate::build_info::BuildInfo;
+
+/// Start of every packet
+pub const HEADER: &[u8; 6] = b"IMPROV";
+const VERSION: u8 = 1;
+/// Header, version, type and length
+const PREFIX_LEN: usize = HEADER.len() + 3;
+
+const TYPE_CURRENT_STATE: u8 = 0x01;
+const TYPE_ERROR_STATE: u8 = 0x02;
+const TYPE_RPC: u8 = 0x03;
+const TYPE_RPC_RESULT: u8 = 0x04;
+
+const RPC_WIFI_SETTINGS: u8 = 0x01;
+const RPC_CURRENT_STATE: u8 = 0x02;
+const RPC_DEVICE_INFO: u8 = 0x03;
+const RPC_SCAN_NETWORKS: u8 = 0x04;
+
+/// Provisioning state, sent on changes and when asked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Waiting for the Wi-Fi settings
+    Ready = 0x02,
+    Provisioning = 0x03,
+    Provisioned = 0x04,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorState {
+    None = 0x00,
+    InvalidRpc = 0x01,
+    UnknownRpc = 0x02,
+    UnableToConnect = 0x03,
+    Unknown = 0xFF,
+}
+
+/// Command sent by the installer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rpc {
+    WifiSettings { ssid: String, password: String },
+    CurrentState,
+    DeviceInfo,
+    ScanNetworks,
+}
+
+impl Rpc {
+    fn id(&self) -> u8 {
+        match self {
+            Self::WifiSettings { .. } => RPC_WIFI_SETTINGS,
+            Self::CurrentState => RPC_CURRENT_STATE,
+            Self::DeviceInfo => RPC_DEVICE_INFO,
+            Self::ScanNetworks => RPC_SCAN_NETWORKS,
+        }
+    }
+}
+
+/// Outcome of [`parse`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed {
+    /// The start of a packet, more bytes are needed
+    Incomplete,
+    NotImprov,
+    /// RPC or the error to report, and the length of the packet
+    Packet(Result<Rpc, ErrorState>, usize),
+}
+
+/// Packet at the start of `bytes`, received on the serial port
+pub fn parse(bytes: &[u8]) -> Parsed {
+    let header_len = bytes.len().min(HEADER.len());
+    if bytes[..header_len] != HEADER[..header_len] {
+        return Parsed::NotImprov;
+    }
+    if bytes.len() < PREFIX_LEN {
+        return Parsed::Incomplete;
+    }
+    let len = PREFIX_LEN + usize::from(bytes[PREFIX_LEN - 1]) + 1;
+    if bytes.len() < len {
+        return Parsed::Incomplete;
+    }
+    let (packet, checksum) = (&bytes[..len - 1], bytes[len - 1]);
+    let rpc = if checksum != self::checksum(packet) || packet[HEADER.len() + 1] != TYPE_RPC {
+        Err(ErrorState::InvalidRpc)
+    } else {
+        parse_rpc(&packet[PREFIX_LEN..])
+    };
+    Parsed::Packet(rpc, len)
+}
+
+/// Command, length of its data and the data
+fn parse_rpc(data: &[u8]) -> Result<Rpc, ErrorState> {
+    let [command, len, data @ ..] = data else {
+        return Err(ErrorState::InvalidRpc);
+    };
+    if data.len() != usize::from(*len) {
+        return Err(ErrorState::InvalidRpc);
+    }
+    match *command {
+        RPC_WIFI_SETTINGS => {
+            let mut strings = Strings(data);
+            match (strings.next(), strings.next(), strings.0) {
+                (Some(ssid), Some(password), []) => Ok(Rpc::WifiSettings { ssid, password }),
+                _ => Err(ErrorState::InvalidRpc),
+            }
+        }
+        RPC_CURRENT_STATE => Ok(Rpc::CurrentState),
+        RPC_DEVICE_INFO => Ok(Rpc::DeviceInfo),
+        RPC_SCAN_NETWORKS => Ok(Rpc::ScanNetworks),
+        _ => Err(ErrorState::UnknownRpc),
+    }
+}
+
+/// Strings prefixed by their length
+struct Strings<'a>(&'a [u8]);
+
+impl Iterator for Strings<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let (len, rest) = self.0.split_first()?;
+        let string = rest.get(..usize::from(*len))?;
+        self.0 = &rest[string.len()..];
+        Some(String::from_utf8_lossy(string).into_owned())
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn packet(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = HEADER.to_vec();
+    packet.extend([VERSION, kind, data.len() as u8]);
+    packet.extend_from_slice(data);
+    packet.push(checksum(&packet));
+    // Some installers read lines
+    packet.push(b'\n');
+    packet
+}
+
+pub fn state_packet(state: State) -> Vec<u8> {
+    packet(TYPE_CURRENT_STATE, &[state as u8])
+}
+
+pub fn error_packet(error: ErrorState) -> Vec<u8> {
+    packet(TYPE_ERROR_STATE, &[error as u8])
+}
+
+/// Result of `command`, strings prefixed by their length. Longer strings
+/// are truncated to fit in a packet.
+pub fn result_packet(command: u8, strings: &[&str]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for string in strings {
+        let bytes = &string.as_bytes()[..string.len().min(64)];
+        data.push(bytes.len() as u8);
+        data.extend_from_slice(bytes);
+    }
+    let mut rpc = vec![command, data.len() as u8];
+    rpc.extend(data);
+    packet(TYPE_RPC_RESULT, &rpc)
+}
+
+/// A network found by a scan
+pub struct Network {
+    pub ssid: String,
+    /// dBm
+    pub rssi: i8,
+    pub secured: bool,
+}
+
+/// Answer to the device info RPC, `chip` as named by ESP-IDF
+pub fn device_info(chip: &str, name: &str) -> [String; 4] {
+    let build = BuildInfo::current();
+    [
+        env!("CARGO_PKG_NAME").to_string(),
+        format!("{} ({})", build.version, build.git_hash),
+        chip.to_uppercase(),
+        name.to_string(),
+    ]
+}
+
+/// Of the dashboard, opened by the installer once provisioned
+pub fn url(https: bool, ip: Ipv4Addr) -> String {
+    format!("{}://{ip}/", if https { "https" } else { "http" })
+}
+
+/// What the device does for the RPCs
+pub trait Device {
+    /// URL of the dashboard, `None` while not connected
+    fn url(&self) -> Option<String>;
+    /// Firmware name, version, chip and device name
+    fn info(&self) -> [String; 4];
+    /// Join the network and keep its settings, returning the URL of the
+    /// dashboard
+    fn provision(&self, ssid: &str, password: &str) -> Result<String>;
+    /// Networks in range, `None` when scanning is not possible
+    fn scan(&self) -> Option<Vec<Network>>;
+}
+
+/// Answer an RPC, the packets being sent by `write`
+pub fn answer(rpc: Result<Rpc, ErrorState>, device: &impl Device, mut write: impl FnMut(&[u8])) {
+    let rpc = match rpc {
+        Ok(rpc) => rpc,
+        Err(error) => return write(&error_packet(error)),
+    };
+    let id = rpc.id();
+    match rpc {
+        Rpc::CurrentState => match device.url() {
+            Some(url) => {
+                write(&state_packet(State::Provisioned));
+                write(&result_packet(id, &[&url]));
+            }
+            None => write(&state_packet(State::Ready)),
+        },
+        Rpc::DeviceInfo => {
+            let info = device.info();
+            let info: Vec<&str> = info.iter().map(String::as_str).collect();
+            write(&result_packet(id, &info));
+        }
+        Rpc::ScanNetworks => match device.scan() {
+            Some(networks) => {
+                for network in networks {
+                    let rssi = network.rssi.to_string();
+                    let secured = if network.secured { "YES" } else { "NO" };
+                    write(&result_packet(id, &[&network.ssid, &rssi, secured]));
+                }
+                // End of the list
+                write(&result_packet(id, &[]));
+            }
+            None => write(&error_packet(ErrorState::UnknownRpc)),
+        },
+        Rpc::WifiSettings { ssid, password } => {
+            write(&error_packet(ErrorState::None));
+            write(&state_packet(State::Provisioning));
+            match device.provision(&ssid, &password) {
+                Ok(url) => {
+                    log::info!("Provisioned on {ssid} over Improv");
+                    write(&state_packet(State::Provisioned));
+                    write(&result_packet(id, &[&url]));
+                }
+                Err(e) => {
+                    log::warn!("Unable to join {ssid} over Improv: {e:#}");
+                    write(&error_packet(ErrorState::UnableToConnect));
+                    write(&state_packet(State::Ready));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc(command: u8, data: &[u8]) -> Vec<u8> {
+        let mut rpc = vec![command, data.len() as u8];
+        rpc.extend(data);
+        let mut packet = packet(TYPE_RPC, &rpc);
+        // Without the newline
+        packet.pop();
+        packet
+    }
+
+    #[test]
+    fn wifi_settings() {
+        let packet = rpc(RPC_WIFI_SETTINGS, b"\x04home\x06secret");
+        let rpc = Rpc::WifiSettings {
+            ssid: "home".to_string(),
+            password: "secret".to_string(),
+        };
+        assert_eq!(parse(&packet), Parsed::Packet(Ok(rpc), packet.len()));
+        assert_eq!(parse(&packet[..8]), Parsed::Incomplete);
+        assert_eq!(parse(&packet[..packet.len() - 1]), Parsed::Incomplete);
+        assert_eq!(parse(b"IMP"), Parsed::Incomplete);
+        assert_eq!(parse(b"I (123) wifi"), Parsed::NotImprov);
+    }
+
+    #[test]
+    fn invalid() {
+        let mut packet = rpc(RPC_WIFI_SETTINGS, b"\x04home");
+        let len = packet.len();
+        assert_eq!(
+            parse(&packet),
+            Parsed::Packet(Err(ErrorState::InvalidRpc), len)
+        );
+        packet = rpc(0x7F, &[]);
+        assert_eq!(
+            parse(&packet),
+            Parsed::Packet(Err(ErrorState::UnknownRpc), 12)
+        );
+        // Bad checksum
+        packet[11] ^= 1;
+        assert_eq!(
+            parse(&packet),
+            Parsed::Packet(Err(ErrorState::InvalidRpc), 12)
+        );
+    }
+
+    #[test]
+    fn packets() {
+        assert_eq!(
+            state_packet(State::Ready),
+            [b'I', b'M', b'P', b'R', b'O', b'V', 1, 1, 1, 2, 0xE2, b'\n']
+        );
+        let result = result_packet(RPC_CURRENT_STATE, &["http://x/"]);
+        assert_eq!(&result[9..12], [RPC_CURRENT_STATE, 10, 9]);
+        assert_eq!(&result[12..21], b"http://x/");
+        assert_eq!(
+            url(false, Ipv4Addr::new(192, 168, 1, 2)),
+            "http://192.168.1.2/"
+        );
+    }
+}
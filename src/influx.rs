@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+
+use crate::reading::{Kind, Reading};
+
+/// Of the UDP listeners of Telegraf and InfluxDB 1.x
+pub const DEFAULT_PORT: u16 = 8089;
+pub const DEFAULT_MEASUREMENT: &str = "air_quality";
+
+/// Line protocol record of the readings and the AQI, tagged with the `tags` pairs,
+/// without timestamp when the clock is not synchronized: the server then
+/// uses its own time of reception
+pub fn line(
+    measurement: &str,
+    tags: &[(&str, &str)],
+    readings: &[Reading],
+    aqi: u16,
+    timestamp: Option<DateTime<Utc>>,
+) -> String {
+    let mut line = escape(measurement, &[',', ' ']);
+    for (key, value) in tags {
+        // Empty tag values are not allowed
+        if !value.is_empty() {
+            line += &format!(
+                ",{}={}",
+                escape(key, &[',', '=', ' ']),
+                escape(value, &[',', '=', ' '])
+            );
+        }
+    }
+    let mut fields: Vec<String> = readings
+        .iter()
+        .map(|reading| {
+            format!(
+                "{}={:.*}",
+                field(reading.kind),
+                usize::from(reading.kind.decimals()),
+                reading.value
+            )
+        })
+        .collect();
+    fields.push(format!("aqi={aqi}i"));
+    line += " ";
+    line += &fields.join(",");
+    if let Some(nanos) = timestamp.and_then(|timestamp| timestamp.timestamp_nanos_opt()) {
+        line += &format!(" {nanos}");
+    }
+    line
+}
+
+/// Key of the field of a kind, as in the JSON
+fn field(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Pm25 => "pm25",
+        Kind::Pm10 => "pm10",
+        Kind::Co2 => "co2",
+        Kind::Voc => "voc_index",
+        Kind::Tvoc => "tvoc",
+        Kind::Temperature => "temperature",
+        Kind::Humidity => "humidity",
+    }
+}
+
+/// Backslash before the `special` characters, newlines can't be escaped
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars().filter(|c| *c != '\n') {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
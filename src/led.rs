@@ -0,0 +1,168 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use embedded_hal::digital::{OutputPin, PinState};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::hal::ledc::LedcDriver;
+use smart_leds::RGB8;
+#[cfg(target_os = "espidf")]
+use smart_leds::{brightness, SmartLedsWrite};
+#[cfg(target_os = "espidf")]
+use ws2812_esp32_rmt_driver::Ws2812Esp32Rmt;
+
+use crate::config::Settings;
+use crate::reading::{self, Kind, Reading};
+
+// The WS2812 expects GRB: `RGB8::new(g, r, b)`
+pub const BLUE: RGB8 = RGB8::new(0, 0, 50);
+pub const GREEN: RGB8 = RGB8::new(100, 0, 0);
+pub const BLACK: RGB8 = RGB8::new(0, 0, 0);
+pub const RED: RGB8 = RGB8::new(0, 100, 0);
+pub const ORANGE: RGB8 = RGB8::new(100, 255, 0);
+pub const WHITE: RGB8 = RGB8::new(100, 100, 100);
+
+/// Of the PM2.5 levels, from below `pm25_warn` to above `pm25_alert`
+const LEVEL_COLORS: [RGB8; 3] = [GREEN, ORANGE, RED];
+
+/// Status LED of the board, selected by `led_kind` in `cfg.toml`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedKind {
+    Ws2812,
+    /// Single color LED on a GPIO
+    Gpio,
+    /// RGB LED on three LEDC channels
+    Rgb,
+}
+
+impl FromStr for LedKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ws2812" => Ok(Self::Ws2812),
+            "gpio" => Ok(Self::Gpio),
+            "rgb" => Ok(Self::Rgb),
+            _ => bail!("Unknown LED {s}, expected ws2812, gpio or rgb"),
+        }
+    }
+}
+
+/// Status LED, given the colors in the GRB order of the WS2812
+pub trait StatusIndicator {
+    /// Light `color` dimmed to `brightness` out of 255, [`BLACK`] to turn
+    /// it off
+    fn set(&mut self, color: RGB8, brightness: u8) -> Result<()>;
+
+    /// A single color LED tells the levels by blink codes instead
+    fn has_colors(&self) -> bool {
+        true
+    }
+}
+
+/// Level of PM2.5 against the configured thresholds, 0 to 2, 0 without
+/// value
+pub fn level(settings: &Settings, readings: &[Reading]) -> usize {
+    match reading::value(readings, Kind::Pm25) {
+        Some(pm25) if pm25 >= settings.pm25_alert => 2,
+        Some(pm25) if pm25 >= settings.pm25_warn => 1,
+        _ => 0,
+    }
+}
+
+/// LED color matching the PM2.5 level against the configured thresholds
+pub fn level_color(settings: &Settings, readings: &[Reading]) -> RGB8 {
+    LEVEL_COLORS[level(settings, readings)]
+}
+
+/// Single color LED, on whatever the color unless it is black or dimmed to 0
+pub struct GpioLed<P> {
+    pin: P,
+    /// Wired to 3.3 V, lit by a low output
+    active_low: bool,
+}
+
+impl<P> GpioLed<P>
+where
+    P: OutputPin,
+    P::Error: std::error::Error + Send + Sync + 'static,
+{
+    pub fn new(pin: P, active_low: bool) -> Result<Self> {
+        let mut led = Self { pin, active_low };
+        led.set(BLACK, 0)?;
+        Ok(led)
+    }
+}
+
+impl<P> StatusIndicator for GpioLed<P>
+where
+    P: OutputPin,
+    P::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn set(&mut self, color: RGB8, brightness: u8) -> Result<()> {
+        let lit = brightness > 0 && color != BLACK;
+        self.pin.set_state(PinState::from(lit != self.active_low))?;
+        Ok(())
+    }
+
+    fn has_colors(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl StatusIndicator for Ws2812Esp32Rmt<'_> {
+    fn set(&mut self, color: RGB8, level: u8) -> Result<()> {
+        self.write(brightness([color].into_iter(), level))?;
+        Ok(())
+    }
+}
+
+/// RGB LED, each color on a LEDC channel
+#[cfg(target_os = "espidf")]
+pub struct RgbLed {
+    red: LedcDriver<'static>,
+    green: LedcDriver<'static>,
+    blue: LedcDriver<'static>,
+    /// Common anode, lit by a low duty
+    active_low: bool,
+}
+
+#[cfg(target_os = "espidf")]
+impl RgbLed {
+    pub fn new(
+        red: LedcDriver<'static>,
+        green: LedcDriver<'static>,
+        blue: LedcDriver<'static>,
+        active_low: bool,
+    ) -> Result<Self> {
+        let mut led = Self {
+            red,
+            green,
+            blue,
+            active_low,
+        };
+        led.set(BLACK, 0)?;
+        Ok(led)
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl StatusIndicator for RgbLed {
+    fn set(&mut self, color: RGB8, brightness: u8) -> Result<()> {
+        let active_low = self.active_low;
+        // GRB
+        for (channel, value) in [
+            (&mut self.red, color.g),
+            (&mut self.green, color.r),
+            (&mut self.blue, color.b),
+        ] {
+            let max = channel.get_max_duty();
+            let mut duty = max * u32::from(value) / 255 * u32::from(brightness) / 255;
+            if active_low {
+                duty = max - duty;
+            }
+            channel.set_duty(duty)?;
+        }
+        Ok(())
+    }
+}
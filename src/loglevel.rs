@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use log::LevelFilter;
+use serde_json::Value;
+
+/// Levels compiled in, see `CONFIG_LOG_MAXIMUM_LEVEL`
+const LEVELS: [LevelFilter; 5] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+];
+
+/// Parts of the firmware whose log level can be changed at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Module {
+    Wifi,
+    Sensor,
+    Mqtt,
+    Http,
+}
+
+impl Module {
+    pub const ALL: [Self; 4] = [Self::Wifi, Self::Sensor, Self::Mqtt, Self::Http];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Wifi => "wifi",
+            Self::Sensor => "sensor",
+            Self::Mqtt => "mqtt",
+            Self::Http => "http",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|module| module.name() == name)
+    }
+
+    /// Tags of the ESP-IDF components and targets of the Rust modules
+    pub fn targets(self) -> &'static [&'static str] {
+        match self {
+            Self::Wifi => &[
+                "wifi",
+                "esp_netif_handlers",
+                "esp_idf_svc::wifi",
+                "esp_particle_sensor_rs::wifi",
+                "esp_particle_sensor_rs::eth",
+            ],
+            Self::Sensor => &[
+                "esp_particle_sensor_rs::sensor",
+                "esp_particle_sensor_rs::pms5003",
+                "esp_particle_sensor_rs::pm1006",
+                "esp_particle_sensor_rs::co2",
+                "esp_particle_sensor_rs::voc",
+                "esp_particle_sensor_rs::dht22",
+            ],
+            Self::Mqtt => &[
+                "mqtt_client",
+                "esp_idf_svc::mqtt::client",
+                "esp_particle_sensor_rs::mqtt",
+            ],
+            Self::Http => &[
+                "httpd",
+                "httpd_uri",
+                "httpd_txrx",
+                "httpd_parse",
+                "esp_idf_svc::http::server",
+                "esp_particle_sensor_rs::http",
+                "esp_particle_sensor_rs::portal",
+                "esp_particle_sensor_rs::ws",
+            ],
+        }
+    }
+}
+
+/// Levels of some modules in an object such as `{"wifi": "debug"}`, as
+/// received on `POST /api/loglevel` and MQTT
+pub fn parse(json: &[u8]) -> Result<Vec<(Module, LevelFilter)>> {
+    let levels: BTreeMap<String, String> = serde_json::from_slice(json)?;
+    let mut parsed = Vec::with_capacity(levels.len());
+    for (name, level) in levels {
+        let Some(module) = Module::from_name(&name) else {
+            bail!("Unknown module {name}, expected wifi, sensor, mqtt or http");
+        };
+        let filter = level.parse().ok().filter(|filter| LEVELS.contains(filter));
+        let Some(filter) = filter else {
+            bail!("Invalid log level {level}, expected off, error, warn, info or debug");
+        };
+        parsed.push((module, filter));
+    }
+    Ok(parsed)
+}
+
+/// Level of each module, in the order of [`Module::ALL`]
+pub fn to_json(levels: &[LevelFilter; 4]) -> Value {
+    let levels = Module::ALL
+        .into_iter()
+        .zip(levels)
+        .map(|(module, level)| {
+            (
+                module.name().to_string(),
+                level.as_str().to_lowercase().into(),
+            )
+        })
+        .collect();
+    Value::Object(levels)
+}
@@ -0,0 +1,386 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+/// EU868 channels every device knows before joining, in Hz
+pub const CHANNELS: [u32; 3] = [868_100_000, 868_300_000, 868_500_000];
+/// Of the second receive window, in Hz
+pub const RX2_FREQUENCY: u32 = 869_525_000;
+/// Of the second receive window, DR0
+pub const RX2_SPREADING_FACTOR: u8 = 12;
+/// From the end of the join request to the first receive window, the
+/// second one opens a second later
+pub const JOIN_ACCEPT_DELAY: Duration = Duration::from_secs(5);
+/// The default channels may only be used 1% of the time
+pub const DUTY_CYCLE: u32 = 100;
+
+/// FPort of the measurement uplinks
+const PORT: u8 = 1;
+
+const MHDR_JOIN_REQUEST: u8 = 0x00;
+const MHDR_JOIN_ACCEPT: u8 = 0x20;
+const MHDR_UNCONFIRMED_UP: u8 = 0x40;
+const MIC_LEN: usize = 4;
+
+/// Root keys of the over-the-air activation
+pub struct Keys {
+    /// Little endian, as sent
+    dev_eui: [u8; 8],
+    /// Little endian, as sent
+    join_eui: [u8; 8],
+    app_key: [u8; 16],
+}
+
+impl Keys {
+    /// From hexadecimal, most significant byte first as the network
+    /// servers show them
+    pub fn parse(dev_eui: &str, join_eui: &str, app_key: &str) -> Result<Self> {
+        let mut dev_eui: [u8; 8] = hex(dev_eui).context("Invalid DevEUI")?;
+        let mut join_eui: [u8; 8] = hex(join_eui).context("Invalid JoinEUI")?;
+        dev_eui.reverse();
+        join_eui.reverse();
+        Ok(Self {
+            dev_eui,
+            join_eui,
+            app_key: hex(app_key).context("Invalid AppKey")?,
+        })
+    }
+
+    /// Join request of `dev_nonce`, which must not be reused
+    pub fn join_request(&self, dev_nonce: u16) -> Vec<u8> {
+        let mut frame = vec![MHDR_JOIN_REQUEST];
+        frame.extend(self.join_eui);
+        frame.extend(self.dev_eui);
+        frame.extend(dev_nonce.to_le_bytes());
+        let mic = cmac(&self.app_key, &frame);
+        frame.extend(&mic[..MIC_LEN]);
+        frame
+    }
+
+    /// Session of the join accept answering the request of `dev_nonce`,
+    /// `None` if `frame` is something else. The channels and settings it
+    /// may carry are ignored.
+    pub fn accept(&self, frame: &[u8], dev_nonce: u16) -> Option<Session> {
+        let (&mhdr, encrypted) = frame.split_first()?;
+        // Without or with the list of channels
+        if mhdr != MHDR_JOIN_ACCEPT || ![16, 32].contains(&encrypted.len()) {
+            return None;
+        }
+        // Encrypted by the network with AES decryption, so that devices
+        // only need encryption
+        let mut signed = vec![mhdr];
+        for block in encrypted.chunks_exact(16) {
+            signed.extend(aes128(&self.app_key, block.try_into().ok()?));
+        }
+        let mic = signed.split_off(signed.len() - MIC_LEN);
+        if cmac(&self.app_key, &signed)[..MIC_LEN] != mic {
+            return None;
+        }
+        // AppNonce and NetID, then DevAddr
+        let nonces = &signed[1..7];
+        let dev_addr = u32::from_le_bytes(signed[7..11].try_into().ok()?);
+        let session_key = |kind: u8| {
+            let mut block = [0; 16];
+            block[0] = kind;
+            block[1..7].copy_from_slice(nonces);
+            block[7..9].copy_from_slice(&dev_nonce.to_le_bytes());
+            aes128(&self.app_key, &block)
+        };
+        Some(Session {
+            dev_addr,
+            nwk_s_key: session_key(0x01),
+            app_s_key: session_key(0x02),
+            fcnt_up: 0,
+        })
+    }
+}
+
+/// Joined to the network
+pub struct Session {
+    pub dev_addr: u32,
+    nwk_s_key: [u8; 16],
+    app_s_key: [u8; 16],
+    /// Of the next uplink
+    pub fcnt_up: u32,
+}
+
+impl Session {
+    /// Unconfirmed data uplink of `payload`, which the network does not
+    /// acknowledge. Without ADR, there is no downlink to listen for.
+    pub fn uplink(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![MHDR_UNCONFIRMED_UP];
+        frame.extend(self.dev_addr.to_le_bytes());
+        // FCtrl: no ADR, no MAC commands
+        frame.push(0x00);
+        // Only the 16 least significant bits are sent
+        frame.extend((self.fcnt_up as u16).to_le_bytes());
+        frame.push(PORT);
+        for (i, chunk) in payload.chunks(16).enumerate() {
+            let stream = aes128(&self.app_s_key, &self.block(0x01, i as u8 + 1));
+            frame.extend(chunk.iter().zip(stream).map(|(byte, key)| byte ^ key));
+        }
+        let mut signed = self.block(0x49, frame.len() as u8).to_vec();
+        signed.extend(&frame);
+        let mic = cmac(&self.nwk_s_key, &signed);
+        frame.extend(&mic[..MIC_LEN]);
+        self.fcnt_up = self.fcnt_up.wrapping_add(1);
+        frame
+    }
+
+    /// Block of the uplink encryption and MIC, ending with `last`
+    fn block(&self, first: u8, last: u8) -> [u8; 16] {
+        let mut block = [0; 16];
+        block[0] = first;
+        // Direction at 5 is 0 for uplinks
+        block[6..10].copy_from_slice(&self.dev_addr.to_le_bytes());
+        block[10..14].copy_from_slice(&self.fcnt_up.to_le_bytes());
+        block[15] = last;
+        block
+    }
+}
+
+/// Of a `len` bytes frame with 125 kHz LoRa, coding rate 4/5, an explicit
+/// header, a CRC and 8 preamble symbols
+pub fn time_on_air(spreading_factor: u8, len: usize) -> Duration {
+    let sf = i64::from(spreading_factor);
+    // 2^SF / 125 kHz
+    let symbol = Duration::from_micros(8 << spreading_factor);
+    // Low data rate optimization from SF11
+    let low_data_rate = i64::from(spreading_factor >= 11);
+    let bits = 8 * len as i64 - 4 * sf + 28 + 16;
+    let blocks = (bits.max(0) as u64).div_ceil((4 * (sf - 2 * low_data_rate)) as u64);
+    let payload_symbols = 8 + blocks as u32 * 5;
+    // 8 preamble symbols and 4.25 of synchronization
+    symbol * 49 / 4 + symbol * payload_symbols
+}
+
+fn hex<const N: usize>(text: &str) -> Result<[u8; N]> {
+    let text = text.trim();
+    if text.len() != 2 * N {
+        bail!("Expected {} hexadecimal digits, got {}", 2 * N, text.len());
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(2 * i..2 * i + 2).context("Not ASCII")?, 16)?;
+    }
+    Ok(bytes)
+}
+
+/// AES-128 encryption of one block
+fn aes128(key: &[u8; 16], block: &[u8; 16]) -> [u8; 16] {
+    let sbox = sbox();
+    // Round keys, expanded a word at a time
+    let mut words = [[0u8; 4]; 44];
+    for (i, word) in key.chunks_exact(4).enumerate() {
+        words[i].copy_from_slice(word);
+    }
+    let mut rcon = 1u8;
+    for i in 4..44 {
+        let mut word = words[i - 1];
+        if i % 4 == 0 {
+            word.rotate_left(1);
+            word = word.map(|byte| sbox[usize::from(byte)]);
+            word[0] ^= rcon;
+            rcon = xtime(rcon);
+        }
+        for (byte, previous) in word.iter_mut().zip(words[i - 4]) {
+            *byte ^= previous;
+        }
+        words[i] = word;
+    }
+    let add_round_key = |state: &mut [u8; 16], round: usize| {
+        for (i, byte) in state.iter_mut().enumerate() {
+            *byte ^= words[4 * round + i / 4][i % 4];
+        }
+    };
+    // Column major, as the input bytes
+    let mut state = *block;
+    add_round_key(&mut state, 0);
+    for round in 1..=10 {
+        let substituted = state.map(|byte| sbox[usize::from(byte)]);
+        // Row r shifted left by r
+        for (i, byte) in state.iter_mut().enumerate() {
+            let (column, row) = (i / 4, i % 4);
+            *byte = substituted[(column + row) % 4 * 4 + row];
+        }
+        if round < 10 {
+            for column in state.chunks_exact_mut(4) {
+                let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+                let all = a ^ b ^ c ^ d;
+                column[0] ^= all ^ xtime(a ^ b);
+                column[1] ^= all ^ xtime(b ^ c);
+                column[2] ^= all ^ xtime(c ^ d);
+                column[3] ^= all ^ xtime(d ^ a);
+            }
+        }
+        add_round_key(&mut state, round);
+    }
+    state
+}
+
+/// Multiplication by 2 in the AES field
+fn xtime(byte: u8) -> u8 {
+    byte << 1 ^ if byte & 0x80 != 0 { 0x1B } else { 0 }
+}
+
+/// AES substitution box, computed rather than spelled out
+fn sbox() -> [u8; 256] {
+    let mut sbox = [0x63; 256];
+    // `p` goes through all the non-zero elements as powers of 3, `inverse`
+    // through their inverses
+    let (mut p, mut inverse) = (1u8, 1u8);
+    loop {
+        p ^= xtime(p);
+        inverse ^= inverse << 1;
+        inverse ^= inverse << 2;
+        inverse ^= inverse << 4;
+        if inverse & 0x80 != 0 {
+            inverse ^= 0x09;
+        }
+        sbox[usize::from(p)] = inverse
+            ^ inverse.rotate_left(1)
+            ^ inverse.rotate_left(2)
+            ^ inverse.rotate_left(3)
+            ^ inverse.rotate_left(4)
+            ^ 0x63;
+        if p == 1 {
+            return sbox;
+        }
+    }
+}
+
+/// AES-CMAC, RFC 4493
+fn cmac(key: &[u8; 16], message: &[u8]) -> [u8; 16] {
+    let double = |block: [u8; 16]| {
+        let mut doubled = (u128::from_be_bytes(block) << 1).to_be_bytes();
+        if block[0] & 0x80 != 0 {
+            doubled[15] ^= 0x87;
+        }
+        doubled
+    };
+    let k1 = double(aes128(key, &[0; 16]));
+    let k2 = double(k1);
+    let count = message.len().div_ceil(16).max(1);
+    let mut mac = [0; 16];
+    for i in 0..count {
+        let chunk = &message[16 * i..message.len().min(16 * i + 16)];
+        let mut block = [0; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+        if i == count - 1 {
+            let subkey = if chunk.len() == 16 {
+                k1
+            } else {
+                block[chunk.len()] = 0x80;
+                k2
+            };
+            for (byte, key) in block.iter_mut().zip(subkey) {
+                *byte ^= key;
+            }
+        }
+        for (byte, previous) in block.iter_mut().zip(mac) {
+            *byte ^= previous;
+        }
+        mac = aes128(key, &block);
+    }
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Key of the examples of RFC 4493
+    const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+
+    fn bytes(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn aes_fips_197() {
+        let key = hex("000102030405060708090a0b0c0d0e0f").unwrap();
+        let block = hex("00112233445566778899aabbccddeeff").unwrap();
+        assert_eq!(
+            aes128(&key, &block).to_vec(),
+            bytes("69c4e0d86a7b0430d8cdb78070b4c55a")
+        );
+        let sbox = sbox();
+        assert_eq!((sbox[0x00], sbox[0x01], sbox[0x53]), (0x63, 0x7C, 0xED));
+    }
+
+    #[test]
+    fn cmac_rfc_4493() {
+        let key = hex(KEY).unwrap();
+        for (message, mac) in [
+            ("", "bb1d6929e95937287fa37d129b756746"),
+            (
+                "6bc1bee22e409f96e93d7e117393172a",
+                "070a16b46b4d4144f79bdd9dd04a287c",
+            ),
+            (
+                "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411",
+                "dfa66747de9ae63030ca32611497c827",
+            ),
+        ] {
+            assert_eq!(
+                cmac(&key, &bytes(message)).to_vec(),
+                bytes(mac),
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn join() {
+        let keys = Keys::parse("0004A30B001C0530", "70B3D57ED0000001", KEY).unwrap();
+        assert_eq!(
+            keys.join_request(0x1234),
+            bytes("00010000d07ed5b37030051c000ba3040034124f5ca349")
+        );
+        // AppNonce 010203, NetID 000013, DevAddr 26011BDA, RX1 delay 1 s
+        let accept = bytes("203513ef54c0ce6b650451db4f49f61db7");
+        let session = keys.accept(&accept, 0x1234).unwrap();
+        assert_eq!(session.dev_addr, 0x2601_1BDA);
+        assert_eq!(
+            session.nwk_s_key.to_vec(),
+            bytes("63da50bad282447cace5b70f71f10f9e")
+        );
+        assert_eq!(
+            session.app_s_key.to_vec(),
+            bytes("1d042021f571984e269cc93270f034aa")
+        );
+        // Another nonce gives other keys, a bad MIC nothing
+        assert!(keys.accept(&accept, 0x1235).unwrap().nwk_s_key != session.nwk_s_key);
+        let mut tampered = accept.clone();
+        tampered[5] ^= 1;
+        assert!(keys.accept(&tampered, 0x1234).is_none());
+        assert!(keys.accept(&accept[..16], 0x1234).is_none());
+        assert!(Keys::parse("0004A30B001C05", "70B3D57ED0000001", KEY).is_err());
+    }
+
+    #[test]
+    fn uplink() {
+        // The example of the lora-packet library
+        let mut session = Session {
+            dev_addr: 0x49BE_7DF1,
+            nwk_s_key: hex("44024241ed4ce9a68c6a8bc055233fd3").unwrap(),
+            app_s_key: hex("ec925802ae430ca77fd3dd73cb2cc588").unwrap(),
+            fcnt_up: 2,
+        };
+        assert_eq!(
+            session.uplink(b"test"),
+            bytes("40f17dbe4900020001954378762b11ff0d")
+        );
+        assert_eq!(session.fcnt_up, 3);
+    }
+
+    #[test]
+    fn airtime() {
+        assert_eq!(time_on_air(7, 13), Duration::from_micros(46_336));
+        // With the low data rate optimization
+        assert_eq!(time_on_air(12, 13), Duration::from_micros(1_155_072));
+    }
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+/// Default Modbus TCP port
+pub const PORT: u16 = 502;
+/// MBAP header, up to the unit ID
+pub const HEADER_LEN: usize = 7;
+/// Of the PDU following the header
+const MAX_PDU_LEN: usize = 253;
+/// Per request
+const MAX_REGISTERS: u16 = 125;
+
+const FUNCTION_READ_HOLDING_REGISTERS: u8 = 0x03;
+const FUNCTION_READ_INPUT_REGISTERS: u8 = 0x04;
+/// Set on the function code of an exception response
+const EXCEPTION: u8 = 0x80;
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+/// Value of a register when unknown
+pub const UNAVAILABLE: u16 = 0xFFFF;
+/// Of the signed registers
+pub const UNAVAILABLE_SIGNED: u16 = i16::MIN as u16;
+
+/// Content of an input register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Register {
+    /// Tenths of µg/m³
+    Pm25,
+    /// Tenths of µg/m³
+    Pm10,
+    /// US EPA index
+    Aqi,
+    /// Tenths of °C, signed
+    Temperature,
+    /// Tenths of %
+    Humidity,
+}
+
+impl Register {
+    /// Register of `value` in the unit of the register, `None` if unknown
+    pub fn encode(self, value: Option<f32>) -> u16 {
+        match (self, value) {
+            (Self::Temperature, Some(value)) => {
+                ((value * 10.0).round().clamp(-32767.0, 32767.0) as i16) as u16
+            }
+            (Self::Temperature, None) => UNAVAILABLE_SIGNED,
+            (Self::Aqi, Some(value)) => value.round().clamp(0.0, 65534.0) as u16,
+            (_, Some(value)) => (value * 10.0).round().clamp(0.0, 65534.0) as u16,
+            (_, None) => UNAVAILABLE,
+        }
+    }
+}
+
+/// Length of the PDU announced by an MBAP header, `None` if it is not a
+/// Modbus request
+pub fn pdu_len(header: &[u8; HEADER_LEN]) -> Option<usize> {
+    let protocol = u16::from_be_bytes([header[2], header[3]]);
+    // The length counts the unit ID
+    let len = usize::from(u16::from_be_bytes([header[4], header[5]])).checked_sub(1)?;
+    (protocol == 0 && (1..=MAX_PDU_LEN).contains(&len)).then_some(len)
+}
+
+/// Response to the request of `header` and `pdu`, reading `registers`
+/// from address 0. `None` when the request is for another unit.
+pub fn respond(
+    header: &[u8; HEADER_LEN],
+    pdu: &[u8],
+    unit_id: u8,
+    registers: &[u16],
+) -> Option<Vec<u8>> {
+    if header[6] != unit_id {
+        return None;
+    }
+    let response = match read(pdu, registers) {
+        Ok(values) => {
+            let mut response = vec![pdu[0], (values.len() * 2) as u8];
+            response.extend(values.iter().flat_map(|value| value.to_be_bytes()));
+            response
+        }
+        Err(exception) => vec![
+            pdu.first().copied().unwrap_or_default() | EXCEPTION,
+            exception,
+        ],
+    };
+    let mut frame = Vec::with_capacity(HEADER_LEN + response.len());
+    // Same transaction and protocol IDs
+    frame.extend(&header[..4]);
+    frame.extend((response.len() as u16 + 1).to_be_bytes());
+    frame.push(unit_id);
+    frame.extend(response);
+    Some(frame)
+}
+
+/// Values of a read request, or the exception code. The input registers
+/// are also served as holding registers, as some clients only read those.
+fn read<'a>(pdu: &[u8], registers: &'a [u16]) -> Result<&'a [u16], u8> {
+    let [function, address_high, address_low, count_high, count_low] = *pdu else {
+        return Err(ILLEGAL_FUNCTION);
+    };
+    if ![
+        FUNCTION_READ_INPUT_REGISTERS,
+        FUNCTION_READ_HOLDING_REGISTERS,
+    ]
+    .contains(&function)
+    {
+        return Err(ILLEGAL_FUNCTION);
+    }
+    let address = usize::from(u16::from_be_bytes([address_high, address_low]));
+    let count = u16::from_be_bytes([count_high, count_low]);
+    if !(1..=MAX_REGISTERS).contains(&count) {
+        return Err(ILLEGAL_DATA_VALUE);
+    }
+    registers
+        .get(address..address + usize::from(count))
+        .ok_or(ILLEGAL_DATA_ADDRESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTERS: [u16; 3] = [123, 456, 789];
+
+    fn header(len: u16) -> [u8; HEADER_LEN] {
+        let [high, low] = len.to_be_bytes();
+        [0x00, 0x07, 0x00, 0x00, high, low, 1]
+    }
+
+    #[test]
+    fn read_input_registers() {
+        let header = header(6);
+        assert_eq!(pdu_len(&header), Some(5));
+        let response = respond(&header, &[0x04, 0x00, 0x01, 0x00, 0x02], 1, &REGISTERS);
+        assert_eq!(
+            response.unwrap(),
+            [0x00, 0x07, 0x00, 0x00, 0x00, 0x07, 1, 0x04, 4, 0x01, 0xC8, 0x03, 0x15]
+        );
+        assert!(respond(&header, &[0x04, 0x00, 0x01, 0x00, 0x02], 2, &REGISTERS).is_none());
+    }
+
+    #[test]
+    fn exceptions() {
+        let header = header(6);
+        let exception = |pdu: &[u8]| respond(&header, pdu, 1, &REGISTERS).unwrap()[7..].to_vec();
+        assert_eq!(exception(&[0x06, 0x00, 0x00, 0x00, 0x01]), [0x86, 0x01]);
+        assert_eq!(exception(&[0x03, 0x00, 0x02, 0x00, 0x02]), [0x83, 0x02]);
+        assert_eq!(exception(&[0x04, 0x00, 0x00, 0x00, 0x00]), [0x84, 0x03]);
+        assert_eq!(exception(&[]), [0x80, 0x01]);
+        // Another protocol
+        let mut other = header;
+        other[2] = 1;
+        assert!(pdu_len(&other).is_none());
+        assert!(pdu_len(&self::header(1)).is_none());
+    }
+
+    #[test]
+    fn register_encoding() {
+        assert_eq!(Register::Pm25.encode(Some(12.34)), 123);
+        assert_eq!(Register::Pm25.encode(None), UNAVAILABLE);
+        assert_eq!(Register::Aqi.encode(Some(57.6)), 58);
+        assert_eq!(Register::Temperature.encode(Some(-5.5)), (-55i16) as u16);
+        assert_eq!(Register::Temperature.encode(None), UNAVAILABLE_SIGNED);
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "espidf")]
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::aqi::{self, Band};
+
+/// Public ntfy server, the default
+pub const DEFAULT_URL: &str = "https://ntfy.sh";
+/// Of the bands, from `Good` to `Hazardous`
+pub const DEFAULT_PRIORITIES: [u8; 6] = [2, 3, 3, 4, 5, 5];
+/// Of ntfy, from min to max
+pub const PRIORITIES: std::ops::RangeInclusive<u8> = 1..=5;
+
+#[cfg(target_os = "espidf")]
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Message published to the topic
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub title: String,
+    pub message: String,
+    pub priority: u8,
+    pub tags: Vec<&'static str>,
+}
+
+/// Notifies the changes of AQI band, at most once per `interval`
+#[derive(Debug)]
+pub struct Notifier {
+    /// Title of the notifications
+    station: String,
+    /// Of each band, from `Good` to `Hazardous`
+    priorities: [u8; 6],
+    interval: Duration,
+    /// Band of the last notification, the air is assumed to be good at
+    /// startup
+    notified: Band,
+    sent: Option<Instant>,
+}
+
+impl Notifier {
+    pub fn new(station: String, priorities: [u8; 6], interval: Duration) -> Self {
+        Self {
+            station,
+            priorities,
+            interval,
+            notified: Band::Good,
+            sent: None,
+        }
+    }
+
+    /// Notification of the band of the measurement at `now` if it differs
+    /// from the last one notified. Within the interval of the last
+    /// notification, the change is only notified once it elapsed, if the
+    /// band still differs.
+    pub fn update(&mut self, pm25: f32, pm10: f32, now: Instant) -> Option<Notification> {
+        let aqi = aqi::us_epa(pm25, pm10);
+        let band = Band::from_aqi(aqi);
+        if band == self.notified
+            || self
+                .sent
+                .is_some_and(|sent| now.duration_since(sent) < self.interval)
+        {
+            return None;
+        }
+        self.notified = band;
+        self.sent = Some(now);
+        Some(Notification {
+            title: format!("{}: {}", self.station, band.name()),
+            message: format!("AQI {aqi}, PM2.5 {pm25:.1} µg/m³, PM10 {pm10:.1} µg/m³"),
+            priority: self.priorities[band as usize],
+            tags: vec![tag(band)],
+        })
+    }
+}
+
+/// Publish `notification` to `topic` on the server at `url`, with a bearer
+/// `token` unless it is empty
+#[cfg(target_os = "espidf")]
+pub fn send(url: &str, topic: &str, token: &str, notification: &Notification) -> Result<()> {
+    use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+    use esp_idf_svc::http::Method;
+    use esp_idf_svc::io::Write;
+
+    let mut body = serde_json::to_value(notification)?;
+    body["topic"] = topic.into();
+    let body = serde_json::to_vec(&body)?;
+    let mut connection = EspHttpConnection::new(&Configuration {
+        timeout: Some(HTTP_TIMEOUT),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let len = body.len().to_string();
+    let authorization = format!("Bearer {token}");
+    let mut headers = vec![
+        ("Content-Type", "application/json"),
+        ("Content-Length", len.as_str()),
+    ];
+    if !token.is_empty() {
+        headers.push(("Authorization", &authorization));
+    }
+    // JSON publishing is on the root, the topic is in the body
+    connection.initiate_request(Method::Post, url.trim_end_matches('/'), &headers)?;
+    connection.write_all(&body)?;
+    connection.initiate_response()?;
+    if connection.status() != 200 {
+        bail!("HTTP status {}", connection.status());
+    }
+    Ok(())
+}
+
+/// ntfy tag shown as an emoji, of the color of the EPA category
+fn tag(band: Band) -> &'static str {
+    match band {
+        Band::Good => "green_circle",
+        Band::Moderate => "yellow_circle",
+        Band::UnhealthyForSensitiveGroups => "orange_circle",
+        Band::Unhealthy => "red_circle",
+        Band::VeryUnhealthy => "purple_circle",
+        Band::Hazardous => "brown_circle",
+    }
+}
@@ -0,0 +1,213 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/// JSON Schema of the values serialized like `examples`, inferred from what
+/// serde makes of the Rust types. Only the types are kept, never the values.
+/// Several examples give the variants of an enum, a field `null` in all of
+/// them is nullable of unknown type.
+pub fn schema<T: Serialize>(examples: &[T]) -> Value {
+    let schemas: Vec<Value> = examples
+        .iter()
+        .map(|example| infer(&serde_json::to_value(example).unwrap_or(Value::Null)))
+        .collect();
+    match <[Value; 1]>::try_from(schemas) {
+        Ok([schema]) => schema,
+        Err(schemas) => json!({ "oneOf": schemas }),
+    }
+}
+
+/// The same schema with all the properties optional, for a patch
+pub fn partial(mut schema: Value) -> Value {
+    if let Value::Object(schema) = &mut schema {
+        schema.remove("required");
+    }
+    schema
+}
+
+fn infer(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({ "nullable": true }),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(number) if number.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(items) => json!({
+            "type": "array",
+            "items": items.first().map_or_else(|| json!({}), infer),
+        }),
+        Value::Object(fields) => {
+            let properties: Map<String, Value> = fields
+                .iter()
+                .map(|(name, value)| {
+                    let mut schema = infer(value);
+                    if let (Value::Object(schema), false) = (&mut schema, value.is_null()) {
+                        // An `Option` which happens to be set in the example
+                        schema.insert("nullable".to_string(), Value::Bool(true));
+                    }
+                    (name.clone(), schema)
+                })
+                .collect();
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": fields.keys().collect::<Vec<_>>(),
+            })
+        }
+    }
+}
+
+/// An endpoint of the REST API
+pub struct Endpoint {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    /// `(name, description)` of the query parameters, all optional
+    query: Vec<(&'static str, &'static str)>,
+    /// Content type and schema of the body
+    request: Option<(&'static str, Value)>,
+    status: u16,
+    /// Of the JSON response, `None` when empty or not JSON
+    response: Option<Value>,
+    /// Type of a response which is not JSON
+    content_type: Option<&'static str>,
+    /// Needs the API key of the device, or `api_token` until provisioned, as
+    /// a bearer token
+    authenticated: bool,
+}
+
+impl Endpoint {
+    pub fn get(path: &'static str, summary: &'static str) -> Self {
+        Self::new("get", path, summary)
+    }
+
+    pub fn post(path: &'static str, summary: &'static str) -> Self {
+        Self::new("post", path, summary)
+    }
+
+    pub fn delete(path: &'static str, summary: &'static str) -> Self {
+        Self::new("delete", path, summary)
+    }
+
+    fn new(method: &'static str, path: &'static str, summary: &'static str) -> Self {
+        Self {
+            method,
+            path,
+            summary,
+            query: Vec::new(),
+            request: None,
+            status: 200,
+            response: None,
+            content_type: None,
+            authenticated: false,
+        }
+    }
+
+    pub fn query(mut self, name: &'static str, description: &'static str) -> Self {
+        self.query.push((name, description));
+        self
+    }
+
+    /// A JSON body
+    pub fn request(mut self, schema: Value) -> Self {
+        self.request = Some(("application/json", schema));
+        self
+    }
+
+    /// A body which is not JSON, e.g. a PEM certificate
+    pub fn request_text(mut self, content_type: &'static str) -> Self {
+        self.request = Some((content_type, json!({ "type": "string" })));
+        self
+    }
+
+    pub fn response(mut self, schema: Value) -> Self {
+        self.response = Some(schema);
+        self
+    }
+
+    /// A response which is not JSON, e.g. `text/csv`
+    pub fn content(mut self, content_type: &'static str) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn authenticated(mut self) -> Self {
+        self.authenticated = true;
+        self
+    }
+
+    /// For the endpoints changing the settings, open until the device is
+    /// provisioned
+    pub fn authenticated_if(mut self, provisioned: bool) -> Self {
+        self.authenticated |= provisioned;
+        self
+    }
+
+    fn operation(&self) -> Value {
+        let body = |schema: &Value| json!({ "application/json": { "schema": schema } });
+        let mut response = json!({ "description": self.summary });
+        match (&self.response, self.content_type) {
+            (Some(schema), _) => response["content"] = body(schema),
+            (None, Some(content_type)) => response["content"] = json!({ content_type: {} }),
+            (None, None) => {}
+        }
+        let mut responses = json!({ self.status.to_string(): response });
+        if self.request.is_some() {
+            responses["400"] = json!({ "description": "Invalid request body" });
+        }
+        let mut operation = json!({ "summary": self.summary, "responses": responses });
+        if let Some((content_type, schema)) = &self.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { *content_type: { "schema": schema } },
+            });
+        }
+        if !self.query.is_empty() {
+            let parameters: Vec<Value> = self
+                .query
+                .iter()
+                .map(|(name, description)| {
+                    json!({
+                        "name": name,
+                        "in": "query",
+                        "description": description,
+                        "schema": { "type": "string" },
+                    })
+                })
+                .collect();
+            operation["parameters"] = Value::Array(parameters);
+        }
+        if self.authenticated {
+            operation["security"] = json!([{ "token": [] }]);
+            operation["responses"]["401"] = json!({ "description": "Invalid or missing token" });
+            operation["responses"]["403"] =
+                json!({ "description": "Neither api_token nor an API key is set" });
+        }
+        operation
+    }
+}
+
+/// OpenAPI 3.0 document of `endpoints`
+pub fn document(title: &str, version: &str, endpoints: &[Endpoint]) -> Value {
+    let mut paths = Map::new();
+    for endpoint in endpoints {
+        let path = paths
+            .entry(endpoint.path)
+            .or_insert_with(|| Value::Object(Map::new()));
+        path[endpoint.method] = endpoint.operation();
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": title, "version": version },
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "token": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::mdns::{EspMdns, Interface, Protocol, QueryResult};
+use serde::{Deserialize, Serialize};
+
+use crate::http;
+
+/// mDNS service advertised by every station
+const SERVICE_TYPE: &str = "_particle";
+const SERVICE_PROTO: &str = "_tcp";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_PEERS: usize = 8;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Measurement of a peer, as served on its `/api/measurement`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PeerMeasurement {
+    pub pm25: f32,
+    pub pm10: f32,
+    pub age_seconds: Option<u64>,
+    pub stale: bool,
+}
+
+/// Station found on the LAN, and what it answered
+#[derive(Debug, Clone, Serialize)]
+pub struct Peer {
+    pub name: String,
+    /// `host:port`, `None` if the name could not be resolved
+    pub address: Option<String>,
+    /// `None` if the peer has not measured yet, or could not be polled
+    pub measurement: Option<PeerMeasurement>,
+    pub error: Option<String>,
+}
+
+/// Advertise this station as `hostname.local`, for the aggregators, under
+/// its friendly `name` if it has one
+pub fn advertise(hostname: &str, name: &str, port: u16, https: bool) -> Result<EspMdns> {
+    let instance_name = if name.is_empty() { hostname } else { name };
+    let mut mdns = EspMdns::take()?;
+    mdns.set_hostname(hostname)?;
+    mdns.set_instance_name(instance_name)?;
+    mdns.add_service(
+        Some(instance_name),
+        SERVICE_TYPE,
+        SERVICE_PROTO,
+        port,
+        &[("https", if https { "1" } else { "0" })],
+    )?;
+    Ok(mdns)
+}
+
+/// Find the other stations and read their measurement, blocking for a few
+/// seconds per peer at most
+pub fn poll(mdns: &EspMdns, own_hostname: &str) -> Result<Vec<Peer>> {
+    let empty = QueryResult {
+        instance_name: None,
+        hostname: None,
+        port: 0,
+        txt: Vec::new(),
+        addr: Vec::new(),
+        interface: Interface::STA,
+        ip_protocol: Protocol::V4,
+    };
+    let mut results = vec![empty; MAX_PEERS];
+    let found = mdns.query_ptr(
+        SERVICE_TYPE,
+        SERVICE_PROTO,
+        QUERY_TIMEOUT,
+        MAX_PEERS,
+        &mut results,
+    )?;
+    let mut peers: Vec<Peer> = results[..found]
+        .iter()
+        // Names may be shared, host names are unique on the LAN
+        .filter(|result| result.hostname.as_deref() != Some(own_hostname))
+        .map(|result| {
+            let name = result
+                .instance_name
+                .clone()
+                .or_else(|| result.hostname.clone())
+                .unwrap_or_default();
+            let https = result.txt.iter().any(|(k, v)| k == "https" && v == "1");
+            let Some(addr) = result.addr.iter().find(|addr| addr.is_ipv4()) else {
+                return Peer {
+                    name,
+                    address: None,
+                    measurement: None,
+                    error: Some("No IPv4 address".to_string()),
+                };
+            };
+            let address = format!("{addr}:{}", result.port);
+            let (measurement, error) = if https {
+                // Self-signed certificates can't be verified
+                (None, Some("Served over HTTPS, not polled".to_string()))
+            } else {
+                match fetch(&address) {
+                    Ok(measurement) => (measurement, None),
+                    Err(e) => (None, Some(format!("{e}"))),
+                }
+            };
+            Peer {
+                name,
+                address: Some(address),
+                measurement,
+                error,
+            }
+        })
+        .collect();
+    peers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(peers)
+}
+
+fn fetch(address: &str) -> Result<Option<PeerMeasurement>> {
+    let mut connection = EspHttpConnection::new(&Configuration {
+        timeout: Some(HTTP_TIMEOUT),
+        ..Default::default()
+    })?;
+    let url = format!("http://{address}/api/measurement");
+    connection.initiate_request(Method::Get, &url, &[])?;
+    connection.initiate_response()?;
+    if connection.status() != 200 {
+        bail!("HTTP status {}", connection.status());
+    }
+    let body = http::read_body(&mut connection, http::MAX_BODY_LEN)?;
+    Ok(serde_json::from_slice(&body)?)
+}
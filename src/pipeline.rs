@@ -0,0 +1,345 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aqi;
+use crate::calibration::Calibration;
+use crate::reading;
+use crate::sensor::Measurement;
+
+/// Last values of a sensor a new one is compared to by the outlier filter
+const OUTLIER_WINDOW: usize = 5;
+/// Values needed in the window before filtering
+const OUTLIER_MIN_SAMPLES: usize = 3;
+/// Distance from the median, in tenths of µg/m³, under which a value is
+/// never an outlier, the median absolute deviation of steady air being 0
+const OUTLIER_MIN_DEVIATION: f32 = 50.0;
+/// Distance from the median, in standard deviations estimated from the
+/// median absolute deviation, from which a value is an outlier
+const OUTLIER_THRESHOLD: f32 = 3.0 * 1.4826;
+/// Hygroscopicity of the particles in the κ-Köhler correction, of a mixed
+/// urban aerosol (Crilley et al., 2018)
+const KAPPA: f32 = 0.24;
+/// Percent, the growth of the particles diverging towards saturation
+const MAX_HUMIDITY: f32 = 95.0;
+
+/// Step of the processing of the values of the particle sensors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Linear corrections against a reference station
+    Calibration,
+    /// A value far from the median of the last ones of its sensor is
+    /// replaced by that median, until it lasts
+    OutlierFilter,
+    /// Removes the growth of the particles with the humidity measured by a
+    /// DHT22, none without one
+    HumidityCorrection,
+    /// Average of the sensors, done last when not listed
+    Averaging,
+    /// AQI of the values at that point, only reported
+    Aqi,
+}
+
+pub const DEFAULT_STAGES: [Stage; 5] = [
+    Stage::Calibration,
+    Stage::OutlierFilter,
+    Stage::HumidityCorrection,
+    Stage::Averaging,
+    Stage::Aqi,
+];
+
+/// What the stages use besides the values
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    pub calibration: Calibration,
+    /// Percent, `None` without a DHT22 or when its last read failed
+    pub humidity: Option<f32>,
+}
+
+/// PM values in µg/m³
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Values {
+    pub pm25: f32,
+    pub pm10: f32,
+    /// US EPA, only computed by the AQI stage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aqi: Option<u16>,
+}
+
+impl From<&Measurement> for Values {
+    fn from(vals: &Measurement) -> Self {
+        Self {
+            pm25: reading::from_tenths(vals.pm25()),
+            pm10: reading::from_tenths(vals.pm10()),
+            aqi: None,
+        }
+    }
+}
+
+/// Values after a stage: of each sensor, `null` for a failed one, until
+/// averaged then a single one
+#[derive(Debug, Clone, Serialize)]
+pub struct StageOutput {
+    pub stage: Stage,
+    pub values: Vec<Option<Values>>,
+}
+
+/// Values of the last measurement through the pipeline
+#[derive(Debug, Clone, Serialize)]
+pub struct Trace {
+    /// Raw values of each sensor
+    pub input: Vec<Option<Values>>,
+    pub stages: Vec<StageOutput>,
+}
+
+/// Stages run in order on the values of the sensors, ending with their
+/// average
+#[derive(Debug)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    /// Of each sensor then of the average, for the outlier filter
+    recent: Vec<VecDeque<Measurement>>,
+    last: Option<Trace>,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<Stage>, sensor_count: usize) -> Self {
+        Self {
+            stages,
+            recent: vec![VecDeque::with_capacity(OUTLIER_WINDOW); sensor_count + 1],
+            last: None,
+        }
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// `None` before the first measurement
+    pub fn last(&self) -> Option<&Trace> {
+        self.last.as_ref()
+    }
+
+    /// Processed average of `sensors`, `None` if they all failed
+    pub fn run(
+        &mut self,
+        sensors: &[Option<Measurement>],
+        context: &Context,
+    ) -> Option<Measurement> {
+        let mut values = sensors.to_vec();
+        let mut averaged = false;
+        let mut trace = Trace {
+            input: snapshot(&values),
+            stages: Vec::with_capacity(self.stages.len()),
+        };
+        for &stage in &self.stages {
+            let mut output = None;
+            match stage {
+                Stage::Calibration => {
+                    for vals in values.iter_mut().flatten() {
+                        *vals = context.calibration.apply(vals);
+                    }
+                }
+                Stage::OutlierFilter => {
+                    for (i, vals) in values.iter_mut().enumerate() {
+                        let recent = match averaged {
+                            true => self.recent.last_mut(),
+                            false => self.recent.get_mut(i),
+                        };
+                        if let (Some(vals), Some(recent)) = (vals.as_mut(), recent) {
+                            *vals = filter_outlier(recent, *vals);
+                        }
+                    }
+                }
+                Stage::HumidityCorrection => {
+                    if let Some(humidity) = context.humidity {
+                        for vals in values.iter_mut().flatten() {
+                            *vals = correct_humidity(vals, humidity);
+                        }
+                    }
+                }
+                Stage::Averaging => {
+                    values = vec![Measurement::average(values.iter().flatten())];
+                    averaged = true;
+                }
+                Stage::Aqi => {
+                    let mut with_aqi = snapshot(&values);
+                    for values in with_aqi.iter_mut().flatten() {
+                        values.aqi = Some(aqi::us_epa(values.pm25, values.pm10));
+                    }
+                    output = Some(with_aqi);
+                }
+            }
+            trace.stages.push(StageOutput {
+                stage,
+                values: output.unwrap_or_else(|| snapshot(&values)),
+            });
+        }
+        self.last = Some(trace);
+        Measurement::average(values.iter().flatten())
+    }
+}
+
+fn snapshot(values: &[Option<Measurement>]) -> Vec<Option<Values>> {
+    values
+        .iter()
+        .map(|vals| vals.as_ref().map(Values::from))
+        .collect()
+}
+
+/// `vals`, or the median of the `recent` values of each metric from which
+/// it is too far. A lasting change is accepted once it is most of the
+/// window.
+fn filter_outlier(recent: &mut VecDeque<Measurement>, vals: Measurement) -> Measurement {
+    let filter = |metric: fn(&Measurement) -> u16| {
+        let value = metric(&vals);
+        if recent.len() < OUTLIER_MIN_SAMPLES {
+            return value;
+        }
+        let center = median(recent.iter().map(|vals| f32::from(metric(vals))).collect());
+        let deviation = median(
+            recent
+                .iter()
+                .map(|vals| (f32::from(metric(vals)) - center).abs())
+                .collect(),
+        );
+        let limit = (OUTLIER_THRESHOLD * deviation).max(OUTLIER_MIN_DEVIATION);
+        match (f32::from(value) - center).abs() > limit {
+            true => center.round() as u16,
+            false => value,
+        }
+    };
+    let filtered = Measurement::new(filter(Measurement::pm25), filter(Measurement::pm10));
+    if recent.len() == OUTLIER_WINDOW {
+        recent.pop_front();
+    }
+    recent.push_back(vals);
+    filtered
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let middle = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[middle - 1] + values[middle]) / 2.0,
+        _ => values[middle],
+    }
+}
+
+/// κ-Köhler correction: the particles measured wet are `1 + κ / 1.65 /
+/// (100 / RH - 1)` times the dry mass
+fn correct_humidity(vals: &Measurement, humidity: f32) -> Measurement {
+    if humidity <= 0.0 {
+        return *vals;
+    }
+    let humidity = humidity.min(MAX_HUMIDITY);
+    let growth = 1.0 + KAPPA / 1.65 / (100.0 / humidity - 1.0);
+    let correct = |tenths: u16| reading::to_tenths(reading::from_tenths(tenths) / growth);
+    Measurement::new(correct(vals.pm25()), correct(vals.pm10()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::Correction;
+
+    fn context(humidity: Option<f32>) -> Context {
+        Context {
+            calibration: Calibration {
+                pm25: Correction {
+                    offset: 0.0,
+                    slope: 0.5,
+                },
+                pm10: Correction::IDENTITY,
+            },
+            humidity,
+        }
+    }
+
+    #[test]
+    fn default_stages() {
+        let mut pipeline = Pipeline::new(DEFAULT_STAGES.to_vec(), 2);
+        let sensors = [
+            Some(Measurement::new(100, 200)),
+            Some(Measurement::new(140, 200)),
+        ];
+        let vals = pipeline.run(&sensors, &context(None));
+        assert_eq!(vals, Some(Measurement::new(60, 200)));
+        let trace = pipeline.last().unwrap();
+        assert_eq!(trace.input.len(), 2);
+        let stages: Vec<Stage> = trace.stages.iter().map(|output| output.stage).collect();
+        assert_eq!(stages, DEFAULT_STAGES);
+        let aqi = &trace.stages[4].values;
+        assert_eq!(aqi.len(), 1);
+        assert_eq!(
+            aqi[0].and_then(|values| values.aqi),
+            Some(aqi::us_epa(6.0, 20.0))
+        );
+    }
+
+    #[test]
+    fn failed_sensors() {
+        let mut pipeline = Pipeline::new(DEFAULT_STAGES.to_vec(), 2);
+        let sensors = [None, Some(Measurement::new(100, 200))];
+        assert_eq!(
+            pipeline.run(&sensors, &context(None)),
+            Some(Measurement::new(50, 200))
+        );
+        assert!(pipeline.last().unwrap().input[0].is_none());
+        assert_eq!(pipeline.run(&[None, None], &context(None)), None);
+    }
+
+    #[test]
+    fn averaged_without_the_stage() {
+        let mut pipeline = Pipeline::new(vec![], 2);
+        let sensors = [
+            Some(Measurement::new(100, 200)),
+            Some(Measurement::new(300, 400)),
+        ];
+        assert_eq!(
+            pipeline.run(&sensors, &context(None)),
+            Some(Measurement::new(200, 300))
+        );
+    }
+
+    #[test]
+    fn outlier_replaced_until_it_lasts() {
+        let mut pipeline = Pipeline::new(vec![Stage::OutlierFilter], 1);
+        let mut run = |pm25| {
+            pipeline
+                .run(&[Some(Measurement::new(pm25, 200))], &context(None))
+                .map(|vals| vals.pm25())
+        };
+        for pm25 in [100, 102, 98] {
+            assert_eq!(run(pm25), Some(pm25));
+        }
+        // Within the minimum deviation
+        assert_eq!(run(140), Some(140));
+        assert_eq!(run(900), Some(101));
+        assert_eq!(run(900), Some(102));
+        assert_eq!(run(900), Some(140));
+        // Most of the window
+        assert_eq!(run(900), Some(900));
+    }
+
+    #[test]
+    fn humidity_corrected() {
+        let mut pipeline = Pipeline::new(vec![Stage::HumidityCorrection], 1);
+        let sensors = [Some(Measurement::new(100, 200))];
+        assert_eq!(
+            pipeline.run(&sensors, &context(None)),
+            Some(Measurement::new(100, 200))
+        );
+        let dry = pipeline.run(&sensors, &context(Some(0.0)));
+        assert_eq!(dry, Some(Measurement::new(100, 200)));
+        // 1 + 0.24 / 1.65 / (100 / 80 - 1) = 1.58
+        let humid = pipeline.run(&sensors, &context(Some(80.0)));
+        assert_eq!(humid, Some(Measurement::new(63, 126)));
+        // Capped at 95 %
+        assert_eq!(
+            pipeline.run(&sensors, &context(Some(100.0))),
+            pipeline.run(&sensors, &context(Some(95.0)))
+        );
+    }
+}
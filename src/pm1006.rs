@@ -0,0 +1,99 @@
+use anyhow::{anyhow, bail, Result};
+use embassy_futures::select::{select, Either};
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Read, ReadExactError, Write};
+
+use crate::sensor::{self, Measurement, UartStats};
+
+/// Read request, as sent by the VINDRIKTNING board
+pub const REQUEST: [u8; 5] = [0x11, 0x02, 0x0B, 0x01, 0xE1];
+/// Header, length and command of the replies
+pub const REPLY_START: [u8; 3] = [0x16, 0x11, 0x0B];
+/// Header, length, command, 16 data bytes and the checksum
+pub const REPLY_LEN: usize = 20;
+const READ_TIMEOUT_MS: u32 = 2000;
+
+/// Cubic PM1006, the sensor of the Ikea VINDRIKTNING. It can't sleep: it
+/// measures continuously as long as it is powered, and only answers read
+/// requests. It only measures PM2.5.
+pub struct Pm1006<RW> {
+    serial: RW,
+    stats: UartStats,
+}
+
+impl<RW> Pm1006<RW>
+where
+    RW: Read + Write,
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    pub fn new(serial: RW) -> Self {
+        Self {
+            serial,
+            stats: UartStats::default(),
+        }
+    }
+
+    pub fn serial(&self) -> &RW {
+        &self.serial
+    }
+
+    pub fn stats(&self) -> UartStats {
+        self.stats
+    }
+
+    /// Nothing to set up, only the pending bytes are discarded
+    pub async fn reinit(&mut self, delay: &mut impl DelayNs) -> Result<()> {
+        self.stats.reinits += 1;
+        sensor::drain(&mut self.serial, delay).await
+    }
+
+    /// PM10 is not measured, it is given as the PM2.5 it includes
+    pub async fn measure(&mut self, delay: &mut impl DelayNs) -> Result<Measurement> {
+        self.serial.write_all(&REQUEST).await?;
+        self.serial.flush().await?;
+        let reply = match select(self.read_reply(), delay.delay_ms(READ_TIMEOUT_MS)).await {
+            Either::First(reply) => reply.map_err(|e| anyhow!("Unable to read reply: {e:?}"))?,
+            Either::Second(()) => bail!("No reply from the PM1006"),
+        };
+        if checksum(&reply[..REPLY_LEN - 1]) != reply[REPLY_LEN - 1] {
+            self.stats.crc_errors += 1;
+            bail!("Invalid reply from the PM1006");
+        }
+        // DF3 and DF4, in µg/m³
+        let pm25 = u16::from_be_bytes([reply[5], reply[6]]);
+        Ok(Measurement::from_micrograms(pm25, pm25))
+    }
+
+    /// Skip the bytes until the start of a reply
+    async fn read_reply(&mut self) -> Result<[u8; REPLY_LEN], ReadExactError<RW::Error>> {
+        let mut reply = [0u8; REPLY_LEN];
+        let mut matched = 0;
+        let mut skipped = false;
+        while matched < REPLY_START.len() {
+            self.serial
+                .read_exact(&mut reply[matched..=matched])
+                .await?;
+            if reply[matched] == REPLY_START[matched] {
+                matched += 1;
+            } else {
+                skipped = true;
+                matched = usize::from(reply[matched] == REPLY_START[0]);
+            }
+        }
+        if skipped {
+            self.stats.resyncs += 1;
+        }
+        self.serial
+            .read_exact(&mut reply[REPLY_START.len()..])
+            .await?;
+        Ok(reply)
+    }
+}
+
+/// Makes the sum of the frame bytes 0
+pub fn checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |acc, b| acc.wrapping_add(*b))
+        .wrapping_neg()
+}
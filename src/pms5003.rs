@@ -0,0 +1,194 @@
+use core::fmt::{self, Debug, Display, Formatter};
+
+use embassy_futures::select::{select, Either};
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Read, ReadExactError, Write};
+
+use crate::sensor::{self, Measurement, UartStats};
+
+/// Start of the frames in both directions
+pub const START: [u8; 2] = [0x42, 0x4D];
+/// Length field of a data frame: 13 values and the checksum
+const DATA_LEN: usize = 28;
+/// Data 0 for passive, 1 for active
+pub const CMD_MODE: u8 = 0xE1;
+const CMD_READ: u8 = 0xE2;
+/// Data 0 to sleep, 1 to wake up
+pub const CMD_SLEEP: u8 = 0xE4;
+/// Frames not matching a read request skipped before giving up
+const MAX_SKIPPED_FRAMES: usize = 4;
+const READ_TIMEOUT_MS: u32 = 2000;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Serial(E),
+    /// The serial line ended in the middle of a frame
+    UnexpectedEof,
+    Checksum,
+    /// No data frame received in time
+    Timeout,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Self::Serial(e)
+    }
+}
+
+impl<E> From<ReadExactError<E>> for Error<E> {
+    fn from(e: ReadExactError<E>) -> Self {
+        match e {
+            ReadExactError::UnexpectedEof => Self::UnexpectedEof,
+            ReadExactError::Other(e) => Self::Serial(e),
+        }
+    }
+}
+
+impl<E: Debug> Display for Error<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serial(e) => write!(f, "serial error: {e:?}"),
+            Self::UnexpectedEof => write!(f, "truncated frame"),
+            Self::Checksum => write!(f, "invalid checksum"),
+            Self::Timeout => write!(f, "no answer from the sensor"),
+        }
+    }
+}
+
+impl<E: Debug> std::error::Error for Error<E> {}
+
+pub fn command(cmd: u8, data: u8) -> [u8; 7] {
+    let mut frame = [START[0], START[1], cmd, 0, data, 0, 0];
+    let checksum = frame[..5].iter().map(|b| u16::from(*b)).sum::<u16>();
+    frame[5..].copy_from_slice(&checksum.to_be_bytes());
+    frame
+}
+
+/// Plantower PMS5003 (and PMS7003) in passive mode: the sensor sleeps
+/// between measurements and only answers read requests.
+pub struct Pms5003<RW> {
+    serial: RW,
+    /// Time for the fan to spin up before reading
+    warmup_ms: u32,
+    stats: UartStats,
+}
+
+impl<RW> Pms5003<RW>
+where
+    RW: Read + Write,
+{
+    pub fn new(serial: RW, warmup_ms: u32) -> Self {
+        Self {
+            serial,
+            warmup_ms,
+            stats: UartStats::default(),
+        }
+    }
+
+    pub fn serial(&self) -> &RW {
+        &self.serial
+    }
+
+    pub fn stats(&self) -> UartStats {
+        self.stats
+    }
+
+    /// Switch to passive mode and put the sensor to sleep
+    pub async fn init(&mut self) -> Result<(), Error<RW::Error>> {
+        self.command(CMD_MODE, 0).await?;
+        self.command(CMD_SLEEP, 0).await
+    }
+
+    pub async fn sleep(&mut self) -> Result<(), Error<RW::Error>> {
+        self.command(CMD_SLEEP, 0).await
+    }
+
+    /// Discard what is left of the frames and initialize the sensor again
+    pub async fn reinit(&mut self, delay: &mut impl DelayNs) -> anyhow::Result<()>
+    where
+        RW::Error: std::error::Error + Send + Sync + 'static,
+    {
+        self.stats.reinits += 1;
+        sensor::drain(&mut self.serial, delay).await?;
+        Ok(self.init().await?)
+    }
+
+    pub async fn measure(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<Measurement, Error<RW::Error>> {
+        self.command(CMD_SLEEP, 1).await?;
+        delay.delay_ms(self.warmup_ms).await;
+        // The sensor wakes up in active mode
+        self.command(CMD_MODE, 0).await?;
+        self.command(CMD_READ, 0).await?;
+        let res = match select(self.read_data(), delay.delay_ms(READ_TIMEOUT_MS)).await {
+            Either::First(res) => res,
+            Either::Second(()) => Err(Error::Timeout),
+        };
+        self.command(CMD_SLEEP, 0).await?;
+        res
+    }
+
+    async fn command(&mut self, cmd: u8, data: u8) -> Result<(), Error<RW::Error>> {
+        self.serial.write_all(&command(cmd, data)).await?;
+        self.serial.flush().await?;
+        Ok(())
+    }
+
+    /// Read frames until a data frame, skipping the command acknowledgments
+    /// and the corrupted frames
+    async fn read_data(&mut self) -> Result<Measurement, Error<RW::Error>> {
+        let mut checksum_failed = false;
+        for _ in 0..MAX_SKIPPED_FRAMES {
+            let mut byte = [0u8];
+            let mut previous = None;
+            let mut skipped = false;
+            loop {
+                self.serial.read_exact(&mut byte).await?;
+                if previous.is_some_and(|previous| [previous, byte[0]] == START) {
+                    break;
+                }
+                skipped |= previous.is_some();
+                previous = Some(byte[0]);
+            }
+            if skipped {
+                self.stats.resyncs += 1;
+            }
+            let mut len = [0u8; 2];
+            self.serial.read_exact(&mut len).await?;
+            let len = usize::from(u16::from_be_bytes(len));
+            if !(2..=DATA_LEN).contains(&len) {
+                // Not a frame start, resynchronize
+                self.stats.resyncs += 1;
+                continue;
+            }
+            let mut body = [0u8; DATA_LEN];
+            let body = &mut body[..len];
+            self.serial.read_exact(body).await?;
+            let (data, checksum) = body.split_at(len - 2);
+            let sum = START
+                .iter()
+                .chain(&(len as u16).to_be_bytes())
+                .chain(data.iter())
+                .map(|b| u16::from(*b))
+                .fold(0u16, u16::wrapping_add);
+            if sum != u16::from_be_bytes([checksum[0], checksum[1]]) {
+                // Look for the next frame
+                self.stats.crc_errors += 1;
+                checksum_failed = true;
+                continue;
+            }
+            if len == DATA_LEN {
+                let value = |i: usize| u16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+                // Atmospheric environment values, in µg/m³
+                return Ok(Measurement::from_micrograms(value(4), value(5)));
+            }
+        }
+        Err(if checksum_failed {
+            Error::Checksum
+        } else {
+            Error::Timeout
+        })
+    }
+}
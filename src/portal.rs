@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Write;
+
+use crate::assets;
+use crate::config::SharedConfigStore;
+use crate::http;
+use crate::i18n::{Language, Strings};
+use crate::task;
+
+/// The form is built by `assets/config.js` from the `/api/config` JSON
+fn config_page(strings: &Strings) -> String {
+    format!(
+        r#"
+<h1>{}</h1>
+<form id="config"></form>
+<button onclick="save()">{}</button>
+<p id="status" data-saved="{}"></p>
+<script src="/assets/config.js"></script>
+"#,
+        strings.configuration, strings.save, strings.saved
+    )
+}
+
+/// CA certificates are larger than the settings
+const MAX_CA_CERT_LEN: usize = 8 * 1024;
+
+/// Register the configuration page on `page_uri`, the `/api/config` JSON
+/// endpoints, `/api/wifi/ca` and the static assets the page uses. Changes
+/// need `write_token` as a bearer token when given.
+pub fn register_handlers(
+    handlers: &mut http::Handlers,
+    page_uri: &str,
+    config_store: SharedConfigStore,
+    restart_on_save: bool,
+    write_token: Option<String>,
+) -> Result<()> {
+    assets::register_handlers(handlers)?;
+    http::fn_handler(handlers, page_uri, Method::Get, {
+        let config_store = config_store.clone();
+        move |mut request| -> Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, 0) {
+                return http::write_error(request, status, message);
+            }
+            let setting = config_store.lock().unwrap().load()?.language;
+            let language = Language::select(&setting, request.header("Accept-Language"));
+            let page = http::templated(language, config_page(language.strings()));
+            let mut response = request.into_ok_response()?;
+            response.write_all(page.as_bytes())?;
+            Ok(())
+        }
+    })?;
+    http::fn_handler(handlers, "/api/config", Method::Get, {
+        let config_store = config_store.clone();
+        move |mut request| -> Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, 0) {
+                return http::write_error(request, status, message);
+            }
+            let settings = config_store.lock().unwrap().load()?;
+            http::write_json(request, &settings.redacted())
+        }
+    })?;
+    http::fn_handler(handlers, "/api/config", Method::Post, {
+        let config_store = config_store.clone();
+        let write_token = write_token.clone();
+        move |mut request| -> Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, http::MAX_BODY_LEN) {
+                return http::write_error(request, status, message);
+            }
+            if let Err((status, message)) = http::authorize_write(&request, write_token.as_deref())
+            {
+                return http::write_error(request, status, message);
+            }
+            let body = http::read_body(&mut request, http::MAX_BODY_LEN)?;
+            let result = config_store.lock().unwrap().import_json(&body);
+            match result {
+                Ok(settings) => {
+                    http::write_json(request, &settings.redacted())?;
+                    if restart_on_save {
+                        log::info!("Settings updated, restarting");
+                        task::restart_after(Duration::from_secs(1));
+                    } else {
+                        log::info!("Settings updated, applied on next restart");
+                    }
+                    Ok(())
+                }
+                Err(e) => http::write_error(request, 400, format!("{e}")),
+            }
+        }
+    })?;
+    // WPA2-Enterprise CA certificate, as PEM, too large for the settings
+    http::fn_handler(handlers, "/api/wifi/ca", Method::Post, {
+        let config_store = config_store.clone();
+        let write_token = write_token.clone();
+        move |mut request| -> Result<()> {
+            if let Err((status, message)) = http::admit(&mut request, MAX_CA_CERT_LEN) {
+                return http::write_error(request, status, message);
+            }
+            if let Err((status, message)) = http::authorize_write(&request, write_token.as_deref())
+            {
+                return http::write_error(request, status, message);
+            }
+            let body = http::read_body(&mut request, MAX_CA_CERT_LEN)?;
+            let pem = String::from_utf8_lossy(&body);
+            let result = config_store.lock().unwrap().set_eap_ca_cert(Some(&pem));
+            match result {
+                Ok(()) => {
+                    log::info!("Wi-Fi CA certificate updated, applied on next restart");
+                    http::api_response(request, 200, &[])?;
+                    Ok(())
+                }
+                Err(e) => http::write_error(request, 400, format!("{e}")),
+            }
+        }
+    })?;
+    http::fn_handler(
+        handlers,
+        "/api/wifi/ca",
+        Method::Delete,
+        move |request| -> Result<()> {
+            if let Err((status, message)) = http::authorize_write(&request, write_token.as_deref())
+            {
+                return http::write_error(request, status, message);
+            }
+            config_store.lock().unwrap().set_eap_ca_cert(None)?;
+            log::info!("Wi-Fi CA certificate removed, applied on next restart");
+            http::api_response(request, 200, &[])?;
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::ffi::CStr;
+
+use esp_idf_svc::sys::{self, esp, EspError};
+
+/// Assumed average current of an ESP32 devkit between the measurements, the
+/// sensors apart, with the access point sending a DTIM beacon every 102.4
+/// ms: Wi-Fi modem sleep only, the default
+pub const IDLE_CURRENT_MA: f32 = 30.0;
+/// The same with automatic light sleep, the chip waking up for each beacon
+pub const LIGHT_SLEEP_IDLE_CURRENT_MA: f32 = 3.0;
+
+/// Let the chip enter light sleep whenever all the tasks are idle, down to
+/// the crystal frequency when awake, and the Wi-Fi radio sleep between the
+/// DTIM beacons. The connection and the timers survive the light sleep.
+pub fn enable_light_sleep() -> Result<(), EspError> {
+    let config = sys::esp_pm_config_t {
+        max_freq_mhz: sys::CONFIG_ESP_DEFAULT_CPU_FREQ_MHZ as i32,
+        min_freq_mhz: sys::CONFIG_XTAL_FREQ as i32,
+        light_sleep_enable: true,
+    };
+    // SAFETY: the configuration is copied
+    esp!(unsafe { sys::esp_pm_configure(core::ptr::addr_of!(config).cast()) })?;
+    // SAFETY: called once the Wi-Fi is started
+    esp!(unsafe { sys::esp_wifi_set_ps(sys::wifi_ps_type_t_WIFI_PS_MIN_MODEM) })
+}
+
+/// Whether [`enable_light_sleep`] succeeded
+pub fn light_sleep_enabled() -> bool {
+    let mut config = sys::esp_pm_config_t::default();
+    // SAFETY: `config` has the type of the current chip
+    let got =
+        esp!(unsafe { sys::esp_pm_get_configuration(core::ptr::addr_of_mut!(config).cast()) });
+    got.is_ok() && config.light_sleep_enable
+}
+
+/// Average current between the measurements, see [`IDLE_CURRENT_MA`]
+pub fn idle_current_ma() -> f32 {
+    if light_sleep_enabled() {
+        LIGHT_SLEEP_IDLE_CURRENT_MA
+    } else {
+        IDLE_CURRENT_MA
+    }
+}
+
+/// Keeps the chip out of light sleep while acquired, e.g. while a sensor
+/// answers on a UART which would lose the bytes received asleep
+pub struct NoLightSleep(sys::esp_pm_lock_handle_t);
+
+// SAFETY: the power management locks are thread safe
+unsafe impl Send for NoLightSleep {}
+unsafe impl Sync for NoLightSleep {}
+
+impl NoLightSleep {
+    pub fn new(name: &'static CStr) -> Result<Self, EspError> {
+        let mut handle = core::ptr::null_mut();
+        // SAFETY: `name` is kept by the lock, hence static
+        esp!(unsafe {
+            sys::esp_pm_lock_create(
+                sys::esp_pm_lock_type_t_ESP_PM_NO_LIGHT_SLEEP,
+                0,
+                name.as_ptr(),
+                &mut handle,
+            )
+        })?;
+        Ok(Self(handle))
+    }
+
+    /// Counted, several tasks can hold it at the same time
+    pub fn acquire(&self) -> Awake<'_> {
+        // SAFETY: the handle lives as long as `self`
+        if let Err(e) = esp!(unsafe { sys::esp_pm_lock_acquire(self.0) }) {
+            log::warn!("Unable to prevent the light sleep: {e}");
+        }
+        Awake(self)
+    }
+}
+
+/// Released when dropped
+pub struct Awake<'a>(&'a NoLightSleep);
+
+impl Drop for Awake<'_> {
+    fn drop(&mut self) {
+        // SAFETY: acquired in `NoLightSleep::acquire`, releasing a lock which
+        // failed to be acquired only returns an error
+        let _ = unsafe { sys::esp_pm_lock_release(self.0 .0) };
+    }
+}
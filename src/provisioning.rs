@@ -0,0 +1,72 @@
+use std::ffi::CString;
+use std::ptr;
+
+use anyhow::Result;
+use esp_idf_svc::sys::{self, esp};
+
+/// Espressif BLE provisioning service, as used by the ESP BLE Provisioning
+/// apps. The manager joins the network received itself.
+pub struct BleProvisioning {
+    // Kept by reference by the manager
+    _service_name: CString,
+    _pop: CString,
+}
+
+impl BleProvisioning {
+    /// Advertise as `service_name`, the apps only list the names starting
+    /// with `PROV_`. `pop` is the proof of possession asked by the apps,
+    /// none when empty.
+    pub fn start(service_name: &str, pop: &str) -> Result<Self> {
+        let config = sys::wifi_prov_mgr_config_t {
+            // SAFETY: a constant of the component
+            scheme: unsafe { sys::wifi_prov_scheme_ble },
+            ..Default::default()
+        };
+        esp!(unsafe { sys::wifi_prov_mgr_init(config) })?;
+        let provisioning = Self {
+            _service_name: CString::new(service_name)?,
+            _pop: CString::new(pop)?,
+        };
+        let pop = if pop.is_empty() {
+            ptr::null()
+        } else {
+            provisioning._pop.as_ptr().cast()
+        };
+        // SAFETY: the strings live as long as the manager
+        esp!(unsafe {
+            sys::wifi_prov_mgr_start_provisioning(
+                sys::wifi_prov_security_WIFI_PROV_SECURITY_1,
+                pop,
+                provisioning._service_name.as_ptr(),
+                ptr::null(),
+            )
+        })?;
+        // The manager switches to station only, bring the access point of
+        // the safe mode back
+        esp!(unsafe { sys::esp_wifi_set_mode(sys::wifi_mode_t_WIFI_MODE_APSTA) })?;
+        Ok(provisioning)
+    }
+
+    /// Block until a network has been received and joined, returns its SSID
+    /// and password
+    pub fn wait(self) -> Result<(String, String)> {
+        unsafe { sys::wifi_prov_mgr_wait() };
+        let mut config = sys::wifi_config_t::default();
+        esp!(unsafe { sys::esp_wifi_get_config(sys::wifi_interface_t_WIFI_IF_STA, &mut config) })?;
+        // SAFETY: the station configuration was asked for
+        let sta = unsafe { config.sta };
+        Ok((nul_terminated(&sta.ssid)?, nul_terminated(&sta.password)?))
+    }
+}
+
+impl Drop for BleProvisioning {
+    fn drop(&mut self) {
+        // Releases the BLE stack too
+        unsafe { sys::wifi_prov_mgr_deinit() };
+    }
+}
+
+fn nul_terminated(bytes: &[u8]) -> Result<String> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Ok(std::str::from_utf8(&bytes[..len])?.to_string())
+}
@@ -0,0 +1,268 @@
+use core::fmt::{self, Display, Formatter};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The particle values are kept in tenths of µg/m³, the resolution of the
+/// sensors
+pub const TENTHS: f32 = 10.0;
+
+/// Quantity measured by a sensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Pm25,
+    Pm10,
+    Co2,
+    /// Sensirion VOC index, from 1 to 500
+    Voc,
+    Tvoc,
+    Temperature,
+    /// Relative
+    Humidity,
+}
+
+impl Kind {
+    pub fn unit(self) -> Unit {
+        match self {
+            Self::Pm25 | Self::Pm10 => Unit::MicrogramsPerCubicMeter,
+            Self::Co2 => Unit::PartsPerMillion,
+            Self::Voc => Unit::Index,
+            Self::Tvoc => Unit::PartsPerBillion,
+            Self::Temperature => Unit::Celsius,
+            Self::Humidity => Unit::Percent,
+        }
+    }
+
+    /// Digits after the decimal point of the values, 1 for those known in
+    /// tenths of their unit
+    pub fn decimals(self) -> u8 {
+        match self {
+            Self::Pm25 | Self::Pm10 | Self::Temperature | Self::Humidity => 1,
+            Self::Co2 | Self::Voc | Self::Tvoc => 0,
+        }
+    }
+
+    /// Last part of the MQTT topics
+    pub fn topic(self) -> &'static str {
+        match self {
+            Self::Pm25 => "PM25",
+            Self::Pm10 => "PM10",
+            Self::Co2 => "CO2",
+            Self::Voc => "VOC",
+            Self::Tvoc => "TVOC",
+            Self::Temperature => "Temperature",
+            Self::Humidity => "Humidity",
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pm25 => write!(f, "PM2.5"),
+            Self::Pm10 => write!(f, "PM10"),
+            Self::Co2 => write!(f, "CO2"),
+            Self::Voc => write!(f, "VOC"),
+            Self::Tvoc => write!(f, "TVOC"),
+            Self::Temperature => write!(f, "Temperature"),
+            Self::Humidity => write!(f, "Humidity"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Unit {
+    #[serde(rename = "µg/m³")]
+    MicrogramsPerCubicMeter,
+    #[serde(rename = "ppm")]
+    PartsPerMillion,
+    #[serde(rename = "ppb")]
+    PartsPerBillion,
+    /// No unit
+    #[serde(rename = "index")]
+    Index,
+    #[serde(rename = "°C")]
+    Celsius,
+    #[serde(rename = "%")]
+    Percent,
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MicrogramsPerCubicMeter => write!(f, "µg/m³"),
+            Self::PartsPerMillion => write!(f, "ppm"),
+            Self::PartsPerBillion => write!(f, "ppb"),
+            Self::Index => write!(f, "index"),
+            Self::Celsius => write!(f, "°C"),
+            Self::Percent => write!(f, "%"),
+        }
+    }
+}
+
+/// How the values are published
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// In their unit, e.g. `12.3`
+    Decimal,
+    /// Integers in tenths of their unit for the values having a decimal,
+    /// e.g. `123`
+    #[serde(rename = "integer_tenths")]
+    Tenths,
+}
+
+impl Encoding {
+    /// Of the payloads of `kind`, giving the value in its unit
+    pub fn divisor(self, kind: Kind) -> u32 {
+        match self {
+            Self::Tenths if kind.decimals() == 1 => 10,
+            _ => 1,
+        }
+    }
+}
+
+/// A single value, whatever the sensor it comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub value: f32,
+    pub unit: Unit,
+    pub kind: Kind,
+    /// Index of the sensor, `None` for the average of the sensors
+    pub sensor_id: Option<usize>,
+    /// `None` if the clock was not synchronized
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl Reading {
+    pub fn new(
+        kind: Kind,
+        value: f32,
+        sensor_id: Option<usize>,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            value,
+            unit: kind.unit(),
+            kind,
+            sensor_id,
+            timestamp,
+        }
+    }
+
+    /// The value as published, rounded to the decimals of its kind, e.g.
+    /// `12.3` rather than `12.34567` for an average
+    pub fn payload(&self, encoding: Encoding) -> String {
+        let scale = 10f32.powi(self.kind.decimals().into());
+        let scaled = (self.value * scale).round();
+        match encoding {
+            Encoding::Tenths if self.kind.decimals() == 1 => (scaled as i32).to_string(),
+            _ => (scaled / scale).to_string(),
+        }
+    }
+}
+
+impl Display for Reading {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} {}", self.kind, self.value, self.unit)
+    }
+}
+
+/// Value of `tenths` of the unit
+pub fn from_tenths(tenths: u16) -> f32 {
+    f32::from(tenths) / TENTHS
+}
+
+/// `value` in tenths of the unit, rounded and never negative
+pub fn to_tenths(value: f32) -> u16 {
+    (value * TENTHS).round().clamp(0.0, u16::MAX as f32) as u16
+}
+
+/// Value of the first reading of `kind`
+pub fn value(readings: &[Reading], kind: Kind) -> Option<f32> {
+    readings
+        .iter()
+        .find(|reading| reading.kind == kind)
+        .map(|reading| reading.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        assert_eq!(Kind::Pm25.unit(), Unit::MicrogramsPerCubicMeter);
+        assert_eq!(Kind::Co2.unit(), Unit::PartsPerMillion);
+        assert_eq!(Kind::Humidity.unit().to_string(), "%");
+        let reading = Reading::new(Kind::Pm10, 20.1, None, None);
+        assert_eq!(reading.to_string(), "PM10: 20.1 µg/m³");
+    }
+
+    #[test]
+    fn divisor() {
+        assert_eq!(Encoding::Decimal.divisor(Kind::Pm25), 1);
+        assert_eq!(Encoding::Tenths.divisor(Kind::Pm25), 10);
+        assert_eq!(Encoding::Tenths.divisor(Kind::Temperature), 10);
+        assert_eq!(Encoding::Tenths.divisor(Kind::Co2), 1);
+    }
+
+    #[test]
+    fn tenths() {
+        assert_eq!(from_tenths(0), 0.0);
+        assert_eq!(from_tenths(123), 12.3);
+        assert_eq!(from_tenths(u16::MAX), 6553.5);
+        assert_eq!(to_tenths(0.0), 0);
+        assert_eq!(to_tenths(12.3), 123);
+        // Rounded to the nearest
+        assert_eq!(to_tenths(12.34), 123);
+        assert_eq!(to_tenths(12.36), 124);
+        // Never negative, saturated
+        assert_eq!(to_tenths(-1.0), 0);
+        assert_eq!(to_tenths(6553.5), u16::MAX);
+        assert_eq!(to_tenths(100_000.0), u16::MAX);
+        for tenths in [0, 1, 9, 10, 123, 999, u16::MAX] {
+            assert_eq!(to_tenths(from_tenths(tenths)), tenths);
+        }
+    }
+
+    #[test]
+    fn decimal_payload() {
+        let payload =
+            |kind, value| Reading::new(kind, value, None, None).payload(Encoding::Decimal);
+        assert_eq!(payload(Kind::Pm25, 0.0), "0");
+        assert_eq!(payload(Kind::Pm25, 12.34), "12.3");
+        assert_eq!(payload(Kind::Pm25, 12.35), "12.4");
+        assert_eq!(payload(Kind::Pm25, 20.0), "20");
+        assert_eq!(payload(Kind::Pm25, from_tenths(u16::MAX)), "6553.5");
+        assert_eq!(payload(Kind::Temperature, -3.25), "-3.3");
+        // No decimal
+        assert_eq!(payload(Kind::Co2, 650.5), "651");
+        assert_eq!(payload(Kind::Voc, 0.0), "0");
+    }
+
+    #[test]
+    fn tenths_payload() {
+        let payload = |kind, value| Reading::new(kind, value, None, None).payload(Encoding::Tenths);
+        assert_eq!(payload(Kind::Pm25, 0.0), "0");
+        assert_eq!(payload(Kind::Pm25, 12.34), "123");
+        assert_eq!(payload(Kind::Pm25, 12.36), "124");
+        assert_eq!(payload(Kind::Pm25, from_tenths(u16::MAX)), "65535");
+        assert_eq!(payload(Kind::Temperature, -3.2), "-32");
+        // Values without a decimal stay in their unit
+        assert_eq!(payload(Kind::Co2, 650.4), "650");
+    }
+
+    #[test]
+    fn first_value_of_kind() {
+        let readings = [
+            Reading::new(Kind::Pm25, 1.0, Some(0), None),
+            Reading::new(Kind::Pm25, 2.0, Some(1), None),
+            Reading::new(Kind::Co2, 400.0, None, None),
+        ];
+        assert_eq!(value(&readings, Kind::Pm25), Some(1.0));
+        assert_eq!(value(&readings, Kind::Co2), Some(400.0));
+        assert_eq!(value(&readings, Kind::Voc), None);
+    }
+}
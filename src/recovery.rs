@@ -0,0 +1,372 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::modem::Modem;
+use esp_idf_svc::hal::peripheral::Peripheral;
+use esp_idf_svc::hal::reset::restart;
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{
+    esp_reset_reason, esp_reset_reason_t_ESP_RST_INT_WDT, esp_reset_reason_t_ESP_RST_PANIC,
+    esp_reset_reason_t_ESP_RST_TASK_WDT, esp_reset_reason_t_ESP_RST_WDT,
+};
+#[cfg(not(esp32))]
+use esp_idf_svc::wifi::EspWifi;
+
+use crate::config::ConfigStore;
+use crate::error::Error;
+use crate::identity::IdentityStore;
+use crate::provisioning::BleProvisioning;
+use crate::task::{self, Task};
+#[cfg(not(esp32))]
+use crate::{
+    board, improv,
+    usb_console::{Input, UsbConsole},
+};
+use crate::{http, portal, wifi};
+
+const NAMESPACE: &str = "recovery";
+const KEY_FAILURES: &str = "failures";
+/// Failures which led to the last safe mode, until reported
+const KEY_SAFE_MODE: &str = "safe_mode";
+/// Invalid setting which led to the safe mode, until it starts
+const KEY_CONFIG_ERROR: &str = "config_error";
+/// Of the stored configuration error
+const MAX_CONFIG_ERROR_LEN: usize = 255;
+
+/// Consecutive failures after which the device boots in safe mode
+pub const SAFE_MODE_THRESHOLD: u32 = 5;
+/// Uptime after which the app is considered healthy and the counter reset
+pub const STABLE_UPTIME: Duration = Duration::from_secs(10 * 60);
+/// Safe mode gives up and retries a normal boot after this delay
+const SAFE_MODE_DURATION: Duration = Duration::from_secs(30 * 60);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+/// Delay before retrying after a sensor failure, which safe mode can't fix
+const SENSOR_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+const SAFE_MODE_SSID: &str = "esp-particle-sensor";
+/// Waits for the BLE provisioning
+const BLE_PROVISIONING_TASK: Task = Task {
+    name: c"ble_prov",
+    stack_size: 4 * 1024,
+};
+/// Scans and joins networks
+#[cfg(not(esp32))]
+const IMPROV_TASK: Task = Task {
+    name: c"improv",
+    stack_size: 8 * 1024,
+};
+
+/// Number of consecutive failed boots, persisted in NVS.
+pub struct CrashCounter {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl CrashCounter {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, NAMESPACE, true)?,
+        })
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.nvs.get_u32(KEY_FAILURES).ok().flatten().unwrap_or(0)
+    }
+
+    pub fn record_failure(&mut self) -> Result<u32> {
+        let failures = self.failures().saturating_add(1);
+        self.nvs.set_u32(KEY_FAILURES, failures)?;
+        Ok(failures)
+    }
+
+    /// Boot in safe mode next time
+    pub fn force_safe_mode(&mut self) -> Result<()> {
+        self.nvs.set_u32(KEY_FAILURES, SAFE_MODE_THRESHOLD)?;
+        Ok(())
+    }
+
+    /// The failures which led to safe mode since the last call, `None` if
+    /// it was not entered, to report it once back on the network
+    pub fn take_safe_mode(&mut self) -> Result<Option<u32>> {
+        let failures = self.nvs.get_u32(KEY_SAFE_MODE)?;
+        if failures.is_some() {
+            self.nvs.remove(KEY_SAFE_MODE)?;
+        }
+        Ok(failures)
+    }
+
+    /// Reported by the next safe mode
+    pub fn set_config_error(&mut self, error: &str) -> Result<()> {
+        let mut len = error.len().min(MAX_CONFIG_ERROR_LEN);
+        while !error.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.nvs.set_str(KEY_CONFIG_ERROR, &error[..len])?;
+        Ok(())
+    }
+
+    /// The configuration error since the last call, `None` if safe mode
+    /// was entered for other failures
+    pub fn take_config_error(&mut self) -> Result<Option<String>> {
+        let mut buf = [0u8; MAX_CONFIG_ERROR_LEN + 1];
+        let error = self
+            .nvs
+            .get_str(KEY_CONFIG_ERROR, &mut buf)?
+            .map(str::to_string);
+        if error.is_some() {
+            self.nvs.remove(KEY_CONFIG_ERROR)?;
+        }
+        Ok(error)
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        if self.failures() != 0 {
+            log::info!("App is stable, resetting crash counter");
+            self.nvs.set_u32(KEY_FAILURES, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the previous boot ended with a panic or a watchdog reset.
+pub fn crashed_on_last_boot() -> bool {
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_reset_reason() } {
+        esp_reset_reason_t_ESP_RST_PANIC
+        | esp_reset_reason_t_ESP_RST_INT_WDT
+        | esp_reset_reason_t_ESP_RST_TASK_WDT
+        | esp_reset_reason_t_ESP_RST_WDT => true,
+        _ => false,
+    }
+}
+
+/// Delay before restarting after `failures` consecutive failures: 1s, 2s,
+/// 4s... up to 10 minutes.
+pub fn backoff(failures: u32) -> Duration {
+    Duration::from_secs(1 << failures.saturating_sub(1).min(10)).min(MAX_BACKOFF)
+}
+
+/// Recovery strategy for an error of the firmware core: how long to wait
+/// before restarting, updating the crash counter accordingly.
+///
+/// - sensor errors are retried after a fixed delay and don't lead to safe
+///   mode, the configuration portal can't fix hardware
+/// - configuration errors go straight to safe mode
+/// - network, MQTT and other errors are retried with an exponential backoff
+///   until safe mode kicks in
+pub fn restart_delay(error: &Error, crash_counter: &mut CrashCounter) -> Duration {
+    match error {
+        Error::Sensor(_) => SENSOR_RETRY_DELAY,
+        Error::Config(error) => {
+            log::error!("Invalid configuration, to be fixed in safe mode: {error:#}");
+            if let Err(e) = crash_counter.force_safe_mode() {
+                log::error!("Unable to force safe mode: {e:?}");
+            }
+            if let Err(e) = crash_counter.set_config_error(&format!("{error:#}")) {
+                log::error!("Unable to save the configuration error: {e:?}");
+            }
+            Duration::from_secs(1)
+        }
+        Error::Network(_) | Error::Mqtt(_) | Error::Other(_) => {
+            let failures = crash_counter.record_failure().unwrap_or(1);
+            log::info!("{failures} consecutive failures");
+            backoff(failures)
+        }
+    }
+}
+
+/// Only start an access point serving the configuration portal, the BLE
+/// provisioning and Improv Wi-Fi on the USB serial port. Saving the configuration restarts the
+/// device, as does reaching the end of the safe mode period.
+pub fn safe_mode(
+    modem: impl Peripheral<P = Modem> + 'static,
+    sysloop: EspSystemEventLoop,
+    nvs_partition: EspDefaultNvsPartition,
+    crash_counter: &mut CrashCounter,
+) -> Result<()> {
+    let failures = crash_counter.failures();
+    log::warn!("{failures} consecutive failures, starting in safe mode");
+    crash_counter.nvs.set_u32(KEY_SAFE_MODE, failures)?;
+    let config_error = crash_counter.take_config_error()?;
+    if let Some(error) = &config_error {
+        log::warn!("Safe mode for an invalid configuration: {error}");
+    }
+    // Give the normal mode another chance after this one, whatever happens
+    crash_counter.reset()?;
+
+    // Still protecting the settings once provisioned
+    let identity = IdentityStore::new(nvs_partition.clone())?.load()?;
+    let config_store = Arc::new(Mutex::new(ConfigStore::new(nvs_partition)?));
+    let wifi = Arc::new(Mutex::new(wifi::access_point(
+        SAFE_MODE_SSID,
+        modem,
+        sysloop.clone(),
+    )?));
+
+    #[cfg(not(esp32))]
+    if let Err(e) = start_improv(SafeModeDevice {
+        wifi: wifi.clone(),
+        sysloop,
+        config_store: config_store.clone(),
+    }) {
+        log::warn!("Unable to start Improv Wi-Fi: {e:?}");
+    }
+
+    // Without valid settings, it is still offered
+    let settings = config_store.lock().unwrap().load().ok();
+    if settings
+        .as_ref()
+        .map_or(true, |settings| settings.ble_provisioning)
+    {
+        let mac = wifi.lock().unwrap().sta_netif().get_mac()?;
+        // Named like the examples of ESP-IDF, the apps look for this prefix
+        let service_name = format!("PROV_{:02X}{:02X}{:02X}", mac[3], mac[4], mac[5]);
+        let api_token = settings
+            .map(|settings| settings.api_token)
+            .unwrap_or_default();
+        let pop = identity.token(&api_token);
+        if let Err(e) = start_ble_provisioning(&service_name, pop, config_store.clone()) {
+            log::warn!("Unable to start the BLE provisioning: {e:?}");
+        }
+    }
+
+    let configuration = Configuration::default();
+    let mut server = EspHttpServer::new(&configuration)?;
+    let mut handlers = http::Handlers::new(&mut server, configuration.max_uri_handlers);
+    portal::register_handlers(
+        &mut handlers,
+        "/",
+        config_store,
+        true,
+        identity.write_token(),
+    )?;
+    // Networks offered by the configuration page
+    http::fn_handler(
+        &mut handlers,
+        "/api/wifi/scan",
+        Method::Get,
+        move |request| -> Result<()> {
+            let networks = wifi::scan(&mut wifi.lock().unwrap())?;
+            http::write_json(request, &networks)
+        },
+    )?;
+    // Why the device is in safe mode
+    http::fn_handler(
+        &mut handlers,
+        "/api/health",
+        Method::Get,
+        move |request| -> Result<()> {
+            let health = serde_json::json!({
+                "safe_mode": true,
+                "failures": failures,
+                "config_error": config_error,
+            });
+            http::write_json(request, &health)
+        },
+    )?;
+    log::info!("Safe mode: configuration portal available on access point {SAFE_MODE_SSID}");
+
+    std::thread::sleep(SAFE_MODE_DURATION);
+    log::info!("Leaving safe mode");
+    Ok(())
+}
+
+/// Save the network received over BLE, joined by the provisioning manager,
+/// and restart
+fn start_ble_provisioning(
+    service_name: &str,
+    pop: &str,
+    config_store: Arc<Mutex<ConfigStore>>,
+) -> Result<()> {
+    let provisioning = BleProvisioning::start(service_name, pop)?;
+    log::info!("Safe mode: BLE provisioning available as {service_name}");
+    BLE_PROVISIONING_TASK.spawn(move || {
+        let saved = provisioning.wait().and_then(|(ssid, password)| {
+            config_store
+                .lock()
+                .unwrap()
+                .set_wifi_network(&ssid, &password)
+        });
+        match saved {
+            Ok(_) => {
+                log::info!("Wi-Fi settings received over BLE, restarting");
+                restart();
+            }
+            Err(e) => log::error!("Unable to save the network received over BLE: {e:?}"),
+        }
+    })?;
+    Ok(())
+}
+
+/// Joins the network received over Improv with the station of the access
+/// point, then saves it and restarts
+#[cfg(not(esp32))]
+struct SafeModeDevice {
+    wifi: Arc<Mutex<Box<EspWifi<'static>>>>,
+    sysloop: EspSystemEventLoop,
+    config_store: Arc<Mutex<ConfigStore>>,
+}
+
+#[cfg(not(esp32))]
+impl improv::Device for SafeModeDevice {
+    fn url(&self) -> Option<String> {
+        None
+    }
+
+    fn info(&self) -> [String; 4] {
+        improv::device_info(board::chip(), SAFE_MODE_SSID)
+    }
+
+    fn provision(&self, ssid: &str, password: &str) -> Result<String> {
+        let ip = wifi::join(
+            &mut self.wifi.lock().unwrap(),
+            self.sysloop.clone(),
+            ssid,
+            password,
+        )?;
+        let settings = self
+            .config_store
+            .lock()
+            .unwrap()
+            .set_wifi_network(ssid, password)?;
+        log::info!("Wi-Fi settings updated, restarting");
+        // Once the installer got the result
+        task::restart_after(Duration::from_secs(2));
+        Ok(improv::url(settings.https_enabled, ip))
+    }
+
+    fn scan(&self) -> Option<Vec<improv::Network>> {
+        let networks = wifi::scan(&mut self.wifi.lock().unwrap()).ok()?;
+        Some(
+            networks
+                .into_iter()
+                .map(|network| improv::Network {
+                    ssid: network.ssid,
+                    rssi: network.rssi,
+                    secured: network.secured,
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Answer the Improv RPCs received on the USB serial port, the lines typed
+/// are ignored in safe mode
+#[cfg(not(esp32))]
+fn start_improv(device: SafeModeDevice) -> Result<()> {
+    let mut console = UsbConsole::new()?;
+    IMPROV_TASK.spawn(move || loop {
+        match console.read() {
+            Input::Improv(rpc) => {
+                improv::answer(rpc, &device, |packet| console.write_bytes(packet))
+            }
+            // Terminals may end lines with CR LF
+            Input::Line(line) if line.trim().is_empty() => {}
+            Input::Line(_) => console.write("Safe mode, only Improv Wi-Fi is available\n"),
+        }
+    })?;
+    Ok(())
+}
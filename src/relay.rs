@@ -0,0 +1,135 @@
+use core::str::FromStr;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::reading::{self, Kind, Reading};
+
+/// How the relay is driven, set over HTTP or MQTT and lost on restart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Follows the PM2.5 level
+    #[default]
+    Auto,
+    /// Manual override
+    On,
+    Off,
+}
+
+/// Plain text payloads of MQTT
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim() {
+            "auto" => Ok(Self::Auto),
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            mode => bail!("Unknown relay mode {mode}, expected auto, on or off"),
+        }
+    }
+}
+
+/// PM2.5 levels (µg/m³) switching the relay, `on` above `off`
+#[derive(Debug, Clone, Copy)]
+pub struct Hysteresis {
+    pub on: f32,
+    pub off: f32,
+}
+
+/// State of a relay driving an air purifier or a fan
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Relay {
+    pub mode: Mode,
+    /// Whether the output is on
+    pub on: bool,
+    /// The last state decided by the PM2.5 level, kept while overridden
+    #[serde(skip)]
+    auto_on: bool,
+    #[serde(skip)]
+    hysteresis: Hysteresis,
+}
+
+impl Relay {
+    pub fn new(hysteresis: Hysteresis) -> Self {
+        Self {
+            mode: Mode::Auto,
+            on: false,
+            auto_on: false,
+            hysteresis,
+        }
+    }
+
+    /// Follow a new measurement, the state is kept between the two levels.
+    /// Returns true when the output changed.
+    pub fn update(&mut self, readings: &[Reading]) -> bool {
+        if let Some(pm25) = reading::value(readings, Kind::Pm25) {
+            if pm25 >= self.hysteresis.on {
+                self.auto_on = true;
+            } else if pm25 <= self.hysteresis.off {
+                self.auto_on = false;
+            }
+        }
+        self.apply()
+    }
+
+    /// Returns true when the output changed
+    pub fn set_mode(&mut self, mode: Mode) -> bool {
+        self.mode = mode;
+        self.apply()
+    }
+
+    fn apply(&mut self) -> bool {
+        let on = match self.mode {
+            Mode::Auto => self.auto_on,
+            Mode::On => true,
+            Mode::Off => false,
+        };
+        let changed = on != self.on;
+        self.on = on;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pm25(value: f32) -> [Reading; 1] {
+        [Reading::new(Kind::Pm25, value, None, None)]
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut relay = Relay::new(Hysteresis {
+            on: 35.0,
+            off: 25.0,
+        });
+        assert!(!relay.update(&pm25(30.0)));
+        assert!(relay.update(&pm25(40.0)));
+        assert!(relay.on);
+        assert!(!relay.update(&pm25(30.0)));
+        assert!(relay.on);
+        // Without PM2.5 the state is kept
+        assert!(!relay.update(&[]));
+        assert!(relay.update(&pm25(20.0)));
+        assert!(!relay.on);
+    }
+
+    #[test]
+    fn manual_override() {
+        let mut relay = Relay::new(Hysteresis {
+            on: 35.0,
+            off: 25.0,
+        });
+        assert!(relay.set_mode("on".parse().unwrap()));
+        assert!(!relay.update(&pm25(10.0)));
+        assert!(relay.on);
+        relay.update(&pm25(40.0));
+        assert!(!relay.set_mode(Mode::Auto));
+        assert!(relay.set_mode(Mode::Off));
+        assert!(" auto\n".parse::<Mode>().is_ok());
+        assert!("toggle".parse::<Mode>().is_err());
+    }
+}
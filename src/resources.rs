@@ -0,0 +1,66 @@
+use std::ffi::CStr;
+
+use esp_idf_svc::sys;
+use serde::Serialize;
+
+/// Heap usage, in bytes
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Memory {
+    pub free_heap: u32,
+    /// Lowest `free_heap` since boot
+    pub min_free_heap: u32,
+    /// Largest allocation which can succeed, lower than `free_heap` when the
+    /// heap is fragmented
+    pub largest_free_block: u32,
+}
+
+/// Stack left to a FreeRTOS task
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskStack {
+    pub name: String,
+    /// Bytes never used since the task started
+    pub free: u32,
+}
+
+pub fn memory() -> Memory {
+    // SAFETY: no precondition
+    unsafe {
+        Memory {
+            free_heap: sys::esp_get_free_heap_size(),
+            min_free_heap: sys::esp_get_minimum_free_heap_size(),
+            largest_free_block: sys::heap_caps_get_largest_free_block(sys::MALLOC_CAP_8BIT) as u32,
+        }
+    }
+}
+
+/// Stack high-water marks of every task, the least free first. Needs
+/// `CONFIG_FREERTOS_USE_TRACE_FACILITY`.
+pub fn task_stacks() -> Vec<TaskStack> {
+    // SAFETY: no precondition
+    let count = unsafe { sys::uxTaskGetNumberOfTasks() };
+    // A few spare entries in case tasks are created meanwhile
+    let mut statuses: Vec<sys::TaskStatus_t> = Vec::with_capacity(count as usize + 4);
+    // SAFETY: fills at most `capacity` entries, which are then initialized
+    unsafe {
+        let filled = sys::uxTaskGetSystemState(
+            statuses.as_mut_ptr(),
+            statuses.capacity() as _,
+            core::ptr::null_mut(),
+        );
+        statuses.set_len(filled as usize);
+    }
+    let mut stacks: Vec<TaskStack> = statuses
+        .iter()
+        .map(|status| TaskStack {
+            // SAFETY: NUL terminated, in the control block of a task, and the
+            // tasks of this app are never deleted
+            name: unsafe { CStr::from_ptr(status.pcTaskName) }
+                .to_string_lossy()
+                .into_owned(),
+            // StackType_t is a byte on ESP-IDF
+            free: status.usStackHighWaterMark,
+        })
+        .collect();
+    stacks.sort_by_key(|stack| stack.free);
+    stacks
+}
@@ -0,0 +1,75 @@
+use core::mem::MaybeUninit;
+
+use chrono::{DateTime, Utc};
+
+use crate::sensor::Measurement;
+
+/// Marks a valid record, RTC memory holds garbage after a power on
+const MAGIC: u32 = 0x504d_3235;
+
+/// Last measurement, kept in RTC memory which survives software and
+/// watchdog resets but not a power loss
+#[derive(Debug, Clone, Copy)]
+pub struct Retained {
+    pub vals: Measurement,
+    /// `None` if the clock was not synchronized
+    pub measured_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Record {
+    magic: u32,
+    pm25: u16,
+    pm10: u16,
+    /// Unix timestamp, 0 if the clock was not synchronized
+    timestamp: i64,
+    checksum: u32,
+}
+
+impl Record {
+    fn checksum(&self) -> u32 {
+        let [ts_lo, ts_hi] = [self.timestamp as u32, (self.timestamp >> 32) as u32];
+        self.magic
+            .rotate_left(5)
+            .wrapping_add((u32::from(self.pm25) << 16) | u32::from(self.pm10))
+            .rotate_left(5)
+            .wrapping_add(ts_lo)
+            .rotate_left(5)
+            .wrapping_add(ts_hi)
+    }
+}
+
+// Not initialized at boot, unlike `.rtc.data`
+#[link_section = ".rtc_noinit"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+/// `measured_at` is `None` if the clock was not synchronized
+pub fn save(vals: &Measurement, measured_at: Option<DateTime<Utc>>) {
+    let mut record = Record {
+        magic: MAGIC,
+        pm25: vals.pm25(),
+        pm10: vals.pm10(),
+        timestamp: measured_at.map_or(0, |at| at.timestamp()),
+        checksum: 0,
+    };
+    record.checksum = record.checksum();
+    // SAFETY: only accessed from the measurement tasks, all on the main thread
+    unsafe { core::ptr::addr_of_mut!(RECORD).write_volatile(MaybeUninit::new(record)) };
+}
+
+/// The measurement saved before the restart, if any
+pub fn restore() -> Option<Retained> {
+    // SAFETY: called once at startup before the tasks run. Every bit pattern
+    // is a valid `Record`, garbage is rejected by the magic and checksum.
+    let record = unsafe { core::ptr::addr_of!(RECORD).read_volatile().assume_init() };
+    if record.magic != MAGIC || record.checksum != record.checksum() {
+        return None;
+    }
+    Some(Retained {
+        vals: Measurement::new(record.pm25, record.pm10),
+        measured_at: (record.timestamp != 0)
+            .then(|| DateTime::from_timestamp(record.timestamp, 0))
+            .flatten(),
+    })
+}
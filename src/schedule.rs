@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Timelike, Utc};
+use serde::Serialize;
+
+use crate::clock;
+
+/// Local hours, from `start` included to `end` excluded, none when equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hours {
+    pub start: u8,
+    pub end: u8,
+}
+
+impl Hours {
+    pub fn contains(&self, hour: u8) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&hour)
+        } else {
+            // Over midnight
+            hour >= self.start || hour < self.end
+        }
+    }
+}
+
+/// Hour of the local time, `None` when the clock is not synchronized
+pub fn local_hour(now: Option<DateTime<Utc>>) -> Option<u8> {
+    Some(clock::local(now?).hour() as u8)
+}
+
+/// Part of the day, following the schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    Normal,
+    /// LED and buzzer off, MQTT publications slowed down
+    Quiet,
+    /// Faster measurements
+    Boost,
+}
+
+/// Daily quiet and boost hours, from the settings
+#[derive(Debug, Clone, Copy)]
+pub struct Schedule {
+    pub quiet: Hours,
+    pub boost: Hours,
+    /// Between two measurements while boosted, when shorter than the usual
+    /// interval
+    pub boost_interval: Duration,
+    /// Between two publications on the same topic while quiet, when longer
+    /// than the usual one
+    pub quiet_mqtt_interval: Duration,
+}
+
+impl Schedule {
+    /// The quiet hours win over the boost ones. Always normal when the clock
+    /// is not synchronized.
+    pub fn period(&self, now: Option<DateTime<Utc>>) -> Period {
+        match local_hour(now) {
+            Some(hour) if self.quiet.contains(hour) => Period::Quiet,
+            Some(hour) if self.boost.contains(hour) => Period::Boost,
+            _ => Period::Normal,
+        }
+    }
+
+    /// Delay between two measurements during `period`
+    pub fn measure_interval(&self, period: Period, interval: Duration) -> Duration {
+        match period {
+            Period::Boost => interval.min(self.boost_interval),
+            Period::Normal | Period::Quiet => interval,
+        }
+    }
+
+    /// Delay between two publications on the same topic during `period`
+    pub fn mqtt_min_interval(&self, period: Period, min_interval: Duration) -> Duration {
+        match period {
+            Period::Quiet => min_interval.max(self.quiet_mqtt_interval),
+            Period::Normal | Period::Boost => min_interval,
+        }
+    }
+}
+
+/// Length of the maintenance window, longer than the checks' interval
+const MAINTENANCE_WINDOW_MINUTES: u16 = 10;
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Daily window during which a device up for long enough restarts, against
+/// the slow fragmentation of its heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Maintenance {
+    /// Start of the window, in minutes since the local midnight
+    pub start: u16,
+    pub min_uptime: Duration,
+}
+
+impl Maintenance {
+    /// Never due when the clock is not synchronized
+    pub fn is_due(&self, now: Option<DateTime<Utc>>, uptime: Duration) -> bool {
+        let Some(now) = now else {
+            return false;
+        };
+        let local = clock::local(now);
+        let minute = (local.hour() * 60 + local.minute()) as u16;
+        let elapsed = (minute + MINUTES_PER_DAY - self.start) % MINUTES_PER_DAY;
+        uptime >= self.min_uptime && elapsed < MAINTENANCE_WINDOW_MINUTES
+    }
+}
+
+/// Minutes since midnight of a `HH:MM` time, e.g. `03:30`
+pub fn parse_time(time: &str) -> Option<u16> {
+    let (hour, minute) = time.split_once(':')?;
+    if hour.len() != 2 || minute.len() != 2 {
+        return None;
+    }
+    let (hour, minute) = (hour.parse::<u16>().ok()?, minute.parse::<u16>().ok()?);
+    (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::PoisonError;
+
+    use super::*;
+
+    /// 2023-11-14T22:13:20Z
+    const NOW: i64 = 1_700_000_000;
+
+    fn schedule() -> Schedule {
+        Schedule {
+            quiet: Hours { start: 22, end: 7 },
+            boost: Hours { start: 17, end: 23 },
+            boost_interval: Duration::from_secs(60),
+            quiet_mqtt_interval: Duration::from_secs(600),
+        }
+    }
+
+    #[test]
+    fn hours() {
+        let overnight = Hours { start: 22, end: 7 };
+        assert!(overnight.contains(23) && overnight.contains(0) && overnight.contains(6));
+        assert!(!overnight.contains(7) && !overnight.contains(12));
+        assert!(Hours { start: 8, end: 12 }.contains(8));
+        assert!(!Hours { start: 8, end: 8 }.contains(8));
+    }
+
+    #[test]
+    fn periods_in_local_time() {
+        let _local_time = clock::LOCAL_TIME
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let schedule = schedule();
+        let now = DateTime::from_timestamp(NOW, 0);
+        clock::set_local_time("", 0);
+        assert_eq!(schedule.period(now), Period::Quiet);
+        // 21:13
+        clock::set_local_time("", -60);
+        assert_eq!(schedule.period(now), Period::Boost);
+        clock::set_local_time("", 0);
+        assert_eq!(schedule.period(None), Period::Normal);
+        let interval = Duration::from_secs(300);
+        assert_eq!(
+            schedule.measure_interval(Period::Boost, interval),
+            Duration::from_secs(60)
+        );
+        assert_eq!(schedule.measure_interval(Period::Quiet, interval), interval);
+        assert_eq!(
+            schedule.mqtt_min_interval(Period::Quiet, interval),
+            Duration::from_secs(600)
+        );
+    }
+
+    #[test]
+    fn maintenance_window() {
+        let _local_time = clock::LOCAL_TIME
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        clock::set_local_time("", 0);
+        let maintenance = Maintenance {
+            start: parse_time("22:05").unwrap(),
+            min_uptime: Duration::from_secs(3600),
+        };
+        let now = DateTime::from_timestamp(NOW, 0);
+        let day = Duration::from_secs(24 * 3600);
+        assert!(maintenance.is_due(now, day));
+        assert!(!maintenance.is_due(now, Duration::from_secs(60)));
+        assert!(!maintenance.is_due(None, day));
+        let late = Maintenance {
+            start: parse_time("22:20").unwrap(),
+            ..maintenance
+        };
+        assert!(!late.is_due(now, day));
+        // Over midnight
+        let midnight = Maintenance {
+            start: parse_time("23:55").unwrap(),
+            ..maintenance
+        };
+        let after = DateTime::from_timestamp(NOW + 6400, 0);
+        assert!(midnight.is_due(after, day));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("03:30"), Some(210));
+        assert_eq!(parse_time("23:59"), Some(1439));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("3:30"), None);
+        assert_eq!(parse_time("0330"), None);
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use esp_idf_svc::hal::gpio::{OutputPin, Pin};
+use esp_idf_svc::hal::peripheral::Peripheral;
+use esp_idf_svc::hal::spi::SpiDriver;
+use esp_idf_svc::sys::{
+    esp, esp_vfs_fat_mount_config_t, esp_vfs_fat_sdcard_unmount, esp_vfs_fat_sdspi_mount,
+    sdmmc_card_t, sdmmc_host_t, sdspi_device_config_t, sdspi_host_do_transaction,
+    sdspi_host_get_real_freq, sdspi_host_init, sdspi_host_io_int_enable, sdspi_host_io_int_wait,
+    sdspi_host_remove_device, sdspi_host_set_card_clk, SDMMC_FREQ_DEFAULT,
+    SDMMC_HOST_FLAG_DEINIT_ARG, SDMMC_HOST_FLAG_SPI,
+};
+use serde::Serialize;
+
+use crate::clock;
+use crate::reading::{self, Kind, Reading};
+
+pub const MOUNT_POINT: &str = "/sdcard";
+
+/// Measurements taken before SNTP synchronization can't be assigned to a day.
+const UNSYNCED_FILE: &str = "UNSYNCED.CSV";
+
+/// A mounted SD card, unmounted on drop.
+///
+/// `esp_idf_svc::fs::Fat` releases the SPI bus as soon as the card is
+/// mounted, so the card is mounted here with the ESP-IDF API while the SPI
+/// driver is kept alive. The bus may be shared with the Ethernet module.
+pub struct SdCard {
+    card: *mut sdmmc_card_t,
+    _spi: Arc<SpiDriver<'static>>,
+}
+
+impl Drop for SdCard {
+    fn drop(&mut self) {
+        unsafe {
+            esp_vfs_fat_sdcard_unmount(c"/sdcard".as_ptr(), self.card);
+        }
+    }
+}
+
+/// Mount a FAT formatted SD card connected to the `spi` bus on
+/// [`MOUNT_POINT`].
+pub fn mount(
+    spi: Arc<SpiDriver<'static>>,
+    cs: impl Peripheral<P = impl OutputPin> + 'static,
+) -> Result<SdCard> {
+    // SDSPI_HOST_DEFAULT()
+    let mut host: sdmmc_host_t = unsafe { core::mem::zeroed() };
+    host.flags = SDMMC_HOST_FLAG_SPI | SDMMC_HOST_FLAG_DEINIT_ARG;
+    host.slot = spi.host() as i32;
+    host.max_freq_khz = SDMMC_FREQ_DEFAULT as i32;
+    host.io_voltage = 3.3;
+    host.init = Some(sdspi_host_init);
+    host.set_card_clk = Some(sdspi_host_set_card_clk);
+    host.do_transaction = Some(sdspi_host_do_transaction);
+    host.__bindgen_anon_1.deinit_p = Some(sdspi_host_remove_device);
+    host.io_int_enable = Some(sdspi_host_io_int_enable);
+    host.io_int_wait = Some(sdspi_host_io_int_wait);
+    host.get_real_freq = Some(sdspi_host_get_real_freq);
+
+    // SDSPI_DEVICE_CONFIG_DEFAULT()
+    let mut device: sdspi_device_config_t = unsafe { core::mem::zeroed() };
+    device.host_id = spi.host();
+    device.gpio_cs = cs.into_ref().pin();
+    device.gpio_cd = -1;
+    device.gpio_wp = -1;
+    device.gpio_int = -1;
+
+    let mut mount_config: esp_vfs_fat_mount_config_t = unsafe { core::mem::zeroed() };
+    mount_config.max_files = 4;
+    mount_config.allocation_unit_size = 16 * 1024;
+
+    let mut card: *mut sdmmc_card_t = core::ptr::null_mut();
+    esp!(unsafe {
+        esp_vfs_fat_sdspi_mount(
+            c"/sdcard".as_ptr(),
+            &host,
+            &device,
+            &mount_config,
+            &mut card,
+        )
+    })?;
+    Ok(SdCard { card, _spi: spi })
+}
+
+/// Append a CSV row to the file of the (UTC) day of the readings.
+pub fn append(readings: &[Reading]) -> Result<()> {
+    let measured_at = readings.iter().find_map(|reading| reading.timestamp);
+    let (file_name, timestamp) = match measured_at {
+        Some(now) => (
+            format!("{}.CSV", now.format("%Y%m%d")),
+            now.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        ),
+        None => (
+            UNSYNCED_FILE.to_string(),
+            format!("uptime+{}", clock::uptime().as_secs()),
+        ),
+    };
+    let path = format!("{MOUNT_POINT}/{file_name}");
+    let new_file = !Path::new(&path).exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if new_file {
+        writeln!(file, "timestamp,pm25,pm10")?;
+    }
+    let value = |kind| reading::value(readings, kind).map(|value| value.to_string());
+    writeln!(
+        file,
+        "{timestamp},{},{}",
+        value(Kind::Pm25).unwrap_or_default(),
+        value(Kind::Pm10).unwrap_or_default()
+    )?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct LogFile {
+    pub name: String,
+    pub size: u64,
+}
+
+pub fn list() -> Result<Vec<LogFile>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(MOUNT_POINT)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.to_ascii_uppercase().ends_with(".CSV") {
+            files.push(LogFile {
+                name,
+                size: entry.metadata()?.len(),
+            });
+        }
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+pub fn open(name: &str) -> Result<File> {
+    if name.contains('/') || name.contains("..") {
+        bail!("Invalid log file name {name}");
+    }
+    Ok(File::open(format!("{MOUNT_POINT}/{name}"))?)
+}
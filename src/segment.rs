@@ -0,0 +1,308 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+
+/// Of a value on the display, after its label
+pub const VALUE_DURATION: Duration = Duration::from_secs(4);
+pub const LABEL_DURATION: Duration = Duration::from_secs(1);
+/// Of the quiet hours
+pub const QUIET_BRIGHTNESS: u8 = 0;
+pub const MAX_BRIGHTNESS: u8 = 7;
+
+/// Half period of the TM1637 clock, it runs up to 250 kHz
+const TM1637_BIT_US: u32 = 5;
+const TM1637_DATA_AUTO_INCREMENT: u8 = 0x40;
+const TM1637_ADDRESS_0: u8 = 0xC0;
+/// Or'ed with the brightness
+const TM1637_DISPLAY_ON: u8 = 0x88;
+const MAX7219_DIGIT_0: u8 = 0x01;
+const MAX7219_DECODE_MODE: u8 = 0x09;
+const MAX7219_INTENSITY: u8 = 0x0A;
+const MAX7219_SCAN_LIMIT: u8 = 0x0B;
+const MAX7219_SHUTDOWN: u8 = 0x0C;
+const MAX7219_DISPLAY_TEST: u8 = 0x0F;
+
+/// Segments of a digit, `A` in the low bit to `G`, then the decimal point
+const DOT: u8 = 0x80;
+
+/// Controller of the 4-digit display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// Two wires, CLK and DIO
+    Tm1637,
+    /// SPI like, CLK, DIN and CS
+    Max7219,
+}
+
+impl FromStr for SegmentKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tm1637" => Ok(Self::Tm1637),
+            "max7219" => Ok(Self::Max7219),
+            _ => bail!("Unknown 7-segment display {s}, expected tm1637 or max7219"),
+        }
+    }
+}
+
+/// Values shown in turn, each after its label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    Pm25,
+    Pm10,
+    Co2,
+}
+
+impl Page {
+    /// CO2 only with its sensor
+    pub fn cycle(co2: bool) -> &'static [Self] {
+        if co2 {
+            &[Self::Pm25, Self::Pm10, Self::Co2]
+        } else {
+            &[Self::Pm25, Self::Pm10]
+        }
+    }
+
+    pub fn label(self) -> [u8; 4] {
+        segments(match self {
+            Self::Pm25 => "P2.5",
+            Self::Pm10 => "P10",
+            Self::Co2 => "CO2",
+        })
+    }
+
+    /// `----` without value
+    pub fn value(self, value: Option<f32>) -> [u8; 4] {
+        let text = match value {
+            None => "----".to_string(),
+            // PM with a decimal while it fits
+            Some(value) if self != Self::Co2 && value < 100.0 => format!("{value:.1}"),
+            Some(value) => format!("{:.0}", value.clamp(0.0, 9999.0)),
+        };
+        segments(&text)
+    }
+}
+
+/// Segments of `text` right-aligned on 4 digits, a `.` lighting the point
+/// of the previous character. Characters without glyph are blank.
+pub fn segments(text: &str) -> [u8; 4] {
+    let mut digits = Vec::with_capacity(4);
+    for c in text.chars() {
+        match (c, digits.last_mut()) {
+            ('.', Some(last)) if *last & DOT == 0 => *last |= DOT,
+            ('.', _) => digits.push(DOT),
+            (c, _) => digits.push(glyph(c)),
+        }
+    }
+    let mut segments = [0; 4];
+    for (segment, digit) in segments.iter_mut().rev().zip(digits.into_iter().rev()) {
+        *segment = digit;
+    }
+    segments
+}
+
+fn glyph(c: char) -> u8 {
+    match c.to_ascii_uppercase() {
+        '0' | 'O' => 0x3F,
+        '1' => 0x06,
+        '2' => 0x5B,
+        '3' => 0x4F,
+        '4' => 0x66,
+        '5' | 'S' => 0x6D,
+        '6' => 0x7D,
+        '7' => 0x07,
+        '8' => 0x7F,
+        '9' => 0x6F,
+        'A' => 0x77,
+        'C' => 0x39,
+        'E' => 0x79,
+        'F' => 0x71,
+        'H' => 0x76,
+        'L' => 0x38,
+        'P' => 0x73,
+        'U' => 0x3E,
+        '-' => 0x40,
+        _ => 0x00,
+    }
+}
+
+/// 4-digit 7-segment display
+pub trait Segments {
+    /// `brightness` from 0 to [`MAX_BRIGHTNESS`], the display stays on
+    fn show(&mut self, digits: [u8; 4], brightness: u8) -> Result<()>;
+}
+
+/// TM1637, bit-banged on an open drain DIO line with a pull-up, as on the
+/// usual modules
+pub struct Tm1637<C, D, T> {
+    clk: C,
+    dio: D,
+    delay: T,
+}
+
+impl<C, D, T> Tm1637<C, D, T>
+where
+    C: OutputPin,
+    D: InputPin + OutputPin,
+    T: DelayNs,
+    C::Error: std::error::Error + Send + Sync + 'static,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    pub fn new(mut clk: C, mut dio: D, delay: T) -> Result<Self> {
+        // Idle
+        clk.set_high()?;
+        dio.set_high()?;
+        Ok(Self { clk, dio, delay })
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.dio.set_low()?;
+        self.delay.delay_us(TM1637_BIT_US);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.clk.set_low()?;
+        self.dio.set_low()?;
+        self.delay.delay_us(TM1637_BIT_US);
+        self.clk.set_high()?;
+        self.delay.delay_us(TM1637_BIT_US);
+        self.dio.set_high()?;
+        self.delay.delay_us(TM1637_BIT_US);
+        Ok(())
+    }
+
+    /// Least significant bit first, then the acknowledgement of the
+    /// controller pulling DIO low
+    fn write(&mut self, byte: u8) -> Result<()> {
+        for bit in 0..8 {
+            self.clk.set_low()?;
+            if byte & (1 << bit) != 0 {
+                self.dio.set_high()?;
+            } else {
+                self.dio.set_low()?;
+            }
+            self.delay.delay_us(TM1637_BIT_US);
+            self.clk.set_high()?;
+            self.delay.delay_us(TM1637_BIT_US);
+        }
+        self.clk.set_low()?;
+        self.dio.set_high()?;
+        self.delay.delay_us(TM1637_BIT_US);
+        self.clk.set_high()?;
+        self.delay.delay_us(TM1637_BIT_US);
+        let acked = self.dio.is_low()?;
+        self.clk.set_low()?;
+        if !acked {
+            bail!("No acknowledgement from the TM1637");
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, bytes: &[u8]) -> Result<()> {
+        self.start()?;
+        let written = bytes.iter().try_for_each(|byte| self.write(*byte));
+        // Released even on error, for the next command
+        self.stop()?;
+        written
+    }
+}
+
+impl<C, D, T> Segments for Tm1637<C, D, T>
+where
+    C: OutputPin,
+    D: InputPin + OutputPin,
+    T: DelayNs,
+    C::Error: std::error::Error + Send + Sync + 'static,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn show(&mut self, digits: [u8; 4], brightness: u8) -> Result<()> {
+        self.command(&[TM1637_DATA_AUTO_INCREMENT])?;
+        let mut data = [TM1637_ADDRESS_0, 0, 0, 0, 0];
+        data[1..].copy_from_slice(&digits);
+        self.command(&data)?;
+        self.command(&[TM1637_DISPLAY_ON | brightness.min(MAX_BRIGHTNESS)])
+    }
+}
+
+/// MAX7219 in no-decode mode, its first 4 digits from the right as on the
+/// usual 8-digit modules, bit-banged
+pub struct Max7219<C, D, S> {
+    clk: C,
+    din: D,
+    cs: S,
+}
+
+impl<C, D, S> Max7219<C, D, S>
+where
+    C: OutputPin,
+    D: OutputPin,
+    S: OutputPin,
+    C::Error: std::error::Error + Send + Sync + 'static,
+    D::Error: std::error::Error + Send + Sync + 'static,
+    S::Error: std::error::Error + Send + Sync + 'static,
+{
+    pub fn new(mut clk: C, mut din: D, mut cs: S) -> Result<Self> {
+        clk.set_low()?;
+        din.set_low()?;
+        cs.set_high()?;
+        let mut display = Self { clk, din, cs };
+        display.write(MAX7219_DISPLAY_TEST, 0)?;
+        display.write(MAX7219_DECODE_MODE, 0)?;
+        display.write(MAX7219_SCAN_LIMIT, 3)?;
+        display.write(MAX7219_SHUTDOWN, 1)?;
+        Ok(display)
+    }
+
+    /// Register address then data, most significant bit first, latched when
+    /// CS rises. The chip is slow enough for the GPIOs without delay.
+    fn write(&mut self, register: u8, data: u8) -> Result<()> {
+        self.cs.set_low()?;
+        for bit in (0..16).rev() {
+            if u16::from_be_bytes([register, data]) & (1 << bit) != 0 {
+                self.din.set_high()?;
+            } else {
+                self.din.set_low()?;
+            }
+            self.clk.set_high()?;
+            self.clk.set_low()?;
+        }
+        self.cs.set_high()?;
+        Ok(())
+    }
+}
+
+impl<C, D, S> Segments for Max7219<C, D, S>
+where
+    C: OutputPin,
+    D: OutputPin,
+    S: OutputPin,
+    C::Error: std::error::Error + Send + Sync + 'static,
+    D::Error: std::error::Error + Send + Sync + 'static,
+    S::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn show(&mut self, digits: [u8; 4], brightness: u8) -> Result<()> {
+        // 16 levels
+        let intensity = brightness.min(MAX_BRIGHTNESS) * 2 + 1;
+        self.write(MAX7219_INTENSITY, intensity)?;
+        for (i, digit) in digits.iter().rev().enumerate() {
+            self.write(MAX7219_DIGIT_0 + i as u8, max7219_segments(*digit))?;
+        }
+        Ok(())
+    }
+}
+
+/// The MAX7219 has the decimal point in the high bit then `A` to `G`
+fn max7219_segments(digit: u8) -> u8 {
+    let mut segments = digit & DOT;
+    for segment in 0..7 {
+        if digit & (1 << segment) != 0 {
+            segments |= 0x40 >> segment;
+        }
+    }
+    segments
+}
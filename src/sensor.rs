@@ -0,0 +1,590 @@
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use embassy_futures::select::{select, Either};
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Read, ReadExactError, Write};
+use sds011::sensor_state::Polling;
+use sds011::{SDS011Error, SDS011};
+use serde::{Deserialize, Serialize};
+
+use crate::pm1006::{self, Pm1006};
+use crate::pms5003::{self, Pms5003};
+use crate::reading::{self, Kind, Reading};
+
+/// Time for the PMS5003 fan to spin up before reading, the SDS011 driver
+/// also waits 30s
+const PMS5003_WARMUP_MS: u32 = 30_000;
+/// Time to wait for an answer when probing the sensor model
+const DETECT_TIMEOUT_MS: u32 = 3000;
+/// Quiet time on the serial line after which no more frame is expected
+const DRAIN_TIMEOUT_MS: u32 = 200;
+/// Data 1, 0 to set the active mode, 1, 1 the query mode
+const SDS011_CMD_REPORTING_MODE: u8 = 2;
+/// Data bytes 11 and 12 are the new ID
+const SDS011_CMD_SET_ID: u8 = 5;
+/// Data 1, 0 to sleep, 1, 1 to work
+const SDS011_CMD_WORK: u8 = 6;
+/// Data 0 to query, 1, n to work 30 s every n minutes, 0 is continuous
+const SDS011_CMD_WORKING_PERIOD: u8 = 8;
+/// Same as the driver, the sensor needs some time between sleep and work
+const SDS011_WAKE_DELAY_MS: u32 = 500;
+const SDS011_REPLY_TIMEOUT_MS: u32 = 1000;
+
+/// A measurement of PM2.5 and PM10, whatever the sensor, in the compact
+/// form kept in memory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pm25: u16,
+    pm10: u16,
+}
+
+impl Measurement {
+    /// Values in tenths of µg/m³
+    pub fn new(pm25: u16, pm10: u16) -> Self {
+        Self { pm25, pm10 }
+    }
+
+    /// Values in µg/m³, as sent by the PMS5003 and PM1006
+    pub fn from_micrograms(pm25: u16, pm10: u16) -> Self {
+        Self::new(pm25.saturating_mul(10), pm10.saturating_mul(10))
+    }
+
+    /// PM2.5 in tenths of µg/m³
+    pub fn pm25(&self) -> u16 {
+        self.pm25
+    }
+
+    /// PM10 in tenths of µg/m³
+    pub fn pm10(&self) -> u16 {
+        self.pm10
+    }
+
+    /// The values with their unit, for the sensor `sensor_id` or the
+    /// average when `None`
+    pub fn readings(
+        &self,
+        sensor_id: Option<usize>,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> [Reading; 2] {
+        [
+            Reading::new(
+                Kind::Pm25,
+                reading::from_tenths(self.pm25),
+                sensor_id,
+                timestamp,
+            ),
+            Reading::new(
+                Kind::Pm10,
+                reading::from_tenths(self.pm10),
+                sensor_id,
+                timestamp,
+            ),
+        ]
+    }
+
+    pub fn average<'a>(measurements: impl IntoIterator<Item = &'a Measurement>) -> Option<Self> {
+        let (count, pm25, pm10) =
+            measurements
+                .into_iter()
+                .fold((0u32, 0u32, 0u32), |(count, pm25, pm10), vals| {
+                    (
+                        count + 1,
+                        pm25 + u32::from(vals.pm25),
+                        pm10 + u32::from(vals.pm10),
+                    )
+                });
+        (count > 0).then(|| Self::new((pm25 / count) as u16, (pm10 / count) as u16))
+    }
+}
+
+impl From<sds011::Measurement> for Measurement {
+    fn from(vals: sds011::Measurement) -> Self {
+        Self::new(vals.pm25(), vals.pm10())
+    }
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let [pm25, pm10] = self.readings(None, None);
+        write!(f, "{pm25}, {pm10}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SensorKind {
+    /// Nova Fitness SDS011 or SDS021
+    Sds011,
+    /// Plantower PMS5003 or PMS7003
+    Pms5003,
+    /// Cubic PM1006, from an Ikea VINDRIKTNING
+    Pm1006,
+}
+
+impl Display for SensorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sds011 => write!(f, "SDS011"),
+            Self::Pms5003 => write!(f, "PMS5003"),
+            Self::Pm1006 => write!(f, "PM1006"),
+        }
+    }
+}
+
+impl SensorKind {
+    /// Model from the configuration, `None` to detect it
+    pub fn from_config(model: &str) -> Result<Option<Self>> {
+        match model {
+            "auto" => Ok(None),
+            model => Ok(Some(model.parse()?)),
+        }
+    }
+}
+
+impl FromStr for SensorKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sds011" => Ok(Self::Sds011),
+            "pms5003" => Ok(Self::Pms5003),
+            "pm1006" => Ok(Self::Pm1006),
+            _ => bail!("Unknown sensor {s}, expected sds011, pms5003 or pm1006"),
+        }
+    }
+}
+
+/// Management command of a sensor, e.g.
+/// `{"command": "set_working_period", "minutes": 5}`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum SensorCommand {
+    /// Change the device ID of an SDS011
+    SetDeviceId { id: u16 },
+    /// SDS011 working period in minutes, 0 to work continuously. The sensor
+    /// then wakes up on its own every period.
+    SetWorkingPeriod { minutes: u8 },
+}
+
+/// What is known about a sensor, read at startup and refreshed by the
+/// management commands
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SensorInfo {
+    pub model: SensorKind,
+    /// SDS011 device ID
+    pub device_id: Option<u16>,
+    /// SDS011 firmware date
+    pub firmware: Option<String>,
+    /// SDS011 working period in minutes, 0 is continuous
+    pub working_period: Option<u8>,
+}
+
+impl Display for SensorInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.model)?;
+        if let Some(id) = self.device_id {
+            write!(f, ", ID: {id:04X}")?;
+        }
+        if let Some(firmware) = &self.firmware {
+            write!(f, ", firmware: {firmware}")?;
+        }
+        match self.working_period {
+            Some(0) => write!(f, ", continuous"),
+            Some(minutes) => write!(f, ", working period: {minutes} min"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Errors on the serial line of a sensor, since startup
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct UartStats {
+    /// Frames with an invalid checksum
+    pub crc_errors: u32,
+    /// Bytes skipped to find the start of a frame
+    pub resyncs: u32,
+    /// Sensor initialized again after repeated failures
+    pub reinits: u32,
+}
+
+impl core::iter::Sum for UartStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |sum, stats| Self {
+            crc_errors: sum.crc_errors + stats.crc_errors,
+            resyncs: sum.resyncs + stats.resyncs,
+            reinits: sum.reinits + stats.reinits,
+        })
+    }
+}
+
+/// A particle sensor on a serial line
+pub enum Sensor<RW> {
+    /// The SDS011 driver can't give the serial line back, it only borrows
+    /// it so that the management commands can use it too
+    Sds011 {
+        serial: RW,
+        id: u16,
+        firmware: String,
+        working_period: u8,
+        stats: UartStats,
+    },
+    Pms5003(Pms5003<RW>),
+    Pm1006(Pm1006<RW>),
+}
+
+impl<RW> Sensor<RW>
+where
+    RW: Read + Write,
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    /// Initialize the sensor, its model is detected when `kind` is `None`
+    pub async fn init(
+        kind: Option<SensorKind>,
+        mut serial: RW,
+        delay: &mut impl DelayNs,
+    ) -> Result<Self> {
+        let kind = match kind {
+            Some(kind) => kind,
+            None => {
+                let kind = probe(&mut serial, delay).await?;
+                log::info!("Detected a {kind} sensor");
+                kind
+            }
+        };
+        Ok(match kind {
+            SensorKind::Sds011 => {
+                // In active mode the sensor sends data frames every second,
+                // which the driver would take as its command replies. Query
+                // mode is persisted by the sensor, the driver sets it again.
+                serial
+                    .write_all(&sds011_command(SDS011_CMD_REPORTING_MODE, &[1, 1]))
+                    .await?;
+                serial.flush().await?;
+                drain(&mut serial, delay).await?;
+                let sds011 = sds011_driver(&mut serial, delay).await?;
+                let (id, firmware) = (sds011.id(), sds011.version().to_string());
+                let reply =
+                    sds011_request(&mut serial, delay, SDS011_CMD_WORKING_PERIOD, &[0, 0]).await?;
+                Self::Sds011 {
+                    serial,
+                    id,
+                    firmware,
+                    working_period: reply[4],
+                    stats: UartStats::default(),
+                }
+            }
+            SensorKind::Pms5003 => {
+                let mut pms5003 = Pms5003::new(serial, PMS5003_WARMUP_MS);
+                pms5003.init().await?;
+                Self::Pms5003(pms5003)
+            }
+            SensorKind::Pm1006 => {
+                // Replies to the probe would be taken as measurements
+                drain(&mut serial, delay).await?;
+                Self::Pm1006(Pm1006::new(serial))
+            }
+        })
+    }
+
+    pub fn kind(&self) -> SensorKind {
+        match self {
+            Self::Sds011 { .. } => SensorKind::Sds011,
+            Self::Pms5003(_) => SensorKind::Pms5003,
+            Self::Pm1006(_) => SensorKind::Pm1006,
+        }
+    }
+
+    pub fn info(&self) -> SensorInfo {
+        match self {
+            Self::Sds011 {
+                id,
+                firmware,
+                working_period,
+                ..
+            } => SensorInfo {
+                model: SensorKind::Sds011,
+                device_id: Some(*id),
+                firmware: Some(firmware.clone()),
+                working_period: Some(*working_period),
+            },
+            Self::Pms5003(_) | Self::Pm1006(_) => SensorInfo {
+                model: self.kind(),
+                device_id: None,
+                firmware: None,
+                working_period: None,
+            },
+        }
+    }
+
+    pub fn serial(&self) -> &RW {
+        match self {
+            Self::Sds011 { serial, .. } => serial,
+            Self::Pms5003(pms5003) => pms5003.serial(),
+            Self::Pm1006(pm1006) => pm1006.serial(),
+        }
+    }
+
+    pub fn uart_stats(&self) -> UartStats {
+        match self {
+            Self::Sds011 { stats, .. } => *stats,
+            Self::Pms5003(pms5003) => pms5003.stats(),
+            Self::Pm1006(pm1006) => pm1006.stats(),
+        }
+    }
+
+    pub async fn measure(&mut self, delay: &mut impl DelayNs) -> Result<Measurement> {
+        Ok(match self {
+            Self::Sds011 { serial, stats, .. } => {
+                let mut sds011 = sds011_driver(serial, delay).await?;
+                match sds011.measure(delay).await {
+                    Ok(vals) => vals.into(),
+                    Err(e) => {
+                        if matches!(e, SDS011Error::ParseError(_)) {
+                            stats.crc_errors += 1;
+                        }
+                        // After a corrupted or unexpected frame the next
+                        // replies would be shifted
+                        if matches!(e, SDS011Error::ParseError(_) | SDS011Error::UnexpectedType) {
+                            stats.resyncs += 1;
+                            drain(serial, delay).await?;
+                        }
+                        return Err(e.into());
+                    }
+                }
+            }
+            Self::Pms5003(pms5003) => pms5003.measure(delay).await?,
+            Self::Pm1006(pm1006) => pm1006.measure(delay).await?,
+        })
+    }
+
+    /// Initialize the sensor again, of the same model, when it stopped
+    /// answering properly. Only the counters are kept.
+    pub async fn reinit(&mut self, delay: &mut impl DelayNs) -> Result<()> {
+        match self {
+            Self::Sds011 {
+                serial,
+                id,
+                firmware,
+                stats,
+                ..
+            } => {
+                stats.reinits += 1;
+                drain(serial, delay).await?;
+                let sds011 = sds011_driver(serial, delay).await?;
+                *id = sds011.id();
+                *firmware = sds011.version().to_string();
+            }
+            Self::Pms5003(pms5003) => pms5003.reinit(delay).await?,
+            Self::Pm1006(pm1006) => pm1006.reinit(delay).await?,
+        }
+        Ok(())
+    }
+
+    /// Stop the fan, e.g. in the middle of a measurement. The PM1006 has no
+    /// sleep command.
+    pub async fn sleep(&mut self, delay: &mut impl DelayNs) -> Result<()> {
+        match self {
+            Self::Sds011 { serial, .. } => {
+                sds011_exchange(serial, delay, SDS011_CMD_WORK, &[1, 0]).await?;
+            }
+            Self::Pms5003(pms5003) => pms5003.sleep().await?,
+            Self::Pm1006(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Run a management command, the sensor is left asleep
+    pub async fn command(
+        &mut self,
+        command: SensorCommand,
+        delay: &mut impl DelayNs,
+    ) -> Result<()> {
+        let Self::Sds011 {
+            serial,
+            id,
+            working_period,
+            ..
+        } = self
+        else {
+            bail!("{command:?} is not supported by the {}", self.kind());
+        };
+        match command {
+            SensorCommand::SetDeviceId { id: new_id } => {
+                let mut data = [0u8; 12];
+                data[10..].copy_from_slice(&new_id.to_be_bytes());
+                let reply = sds011_request(serial, delay, SDS011_CMD_SET_ID, &data).await?;
+                *id = u16::from_be_bytes([reply[6], reply[7]]);
+            }
+            SensorCommand::SetWorkingPeriod { minutes } => {
+                if minutes > 30 {
+                    bail!("Invalid working period {minutes}, expected 0 to 30 minutes");
+                }
+                let reply =
+                    sds011_request(serial, delay, SDS011_CMD_WORKING_PERIOD, &[1, minutes]).await?;
+                *working_period = reply[4];
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<RW> Display for Sensor<RW>
+where
+    RW: Read + Write,
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.info())
+    }
+}
+
+/// SDS011 driver on the borrowed serial line, in the state the sensor is
+/// left in: asleep in query mode
+async fn sds011_driver<'a, RW>(
+    serial: &'a mut RW,
+    delay: &mut impl DelayNs,
+) -> Result<SDS011<&'a mut RW, Polling>>
+where
+    RW: Read + Write,
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    Ok(SDS011::new(serial, sds011::Config::default())
+        .init(delay)
+        .await?)
+}
+
+/// Wake the SDS011 up, send a command and put it back to sleep. Returns the
+/// reply to the command.
+async fn sds011_request<RW>(
+    serial: &mut RW,
+    delay: &mut impl DelayNs,
+    cmd: u8,
+    data: &[u8],
+) -> Result<[u8; 10]>
+where
+    RW: Read + Write,
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    delay.delay_ms(SDS011_WAKE_DELAY_MS).await;
+    sds011_exchange(serial, delay, SDS011_CMD_WORK, &[1, 1]).await?;
+    let reply = sds011_exchange(serial, delay, cmd, data).await;
+    sds011_exchange(serial, delay, SDS011_CMD_WORK, &[1, 0]).await?;
+    reply
+}
+
+/// Send a command and wait for its reply
+async fn sds011_exchange<RW>(
+    serial: &mut RW,
+    delay: &mut impl DelayNs,
+    cmd: u8,
+    data: &[u8],
+) -> Result<[u8; 10]>
+where
+    RW: Read + Write,
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    serial.write_all(&sds011_command(cmd, data)).await?;
+    serial.flush().await?;
+    let read_reply = async {
+        let mut reply = [0u8; 10];
+        loop {
+            serial.read_exact(&mut reply[..1]).await?;
+            if reply[0] != 0xAA {
+                continue;
+            }
+            serial.read_exact(&mut reply[1..]).await?;
+            if reply[1] == 0xC5 && reply[2] == cmd {
+                return Ok::<_, ReadExactError<RW::Error>>(reply);
+            }
+        }
+    };
+    let reply = match select(read_reply, delay.delay_ms(SDS011_REPLY_TIMEOUT_MS)).await {
+        Either::First(reply) => reply.map_err(|e| anyhow!("Unable to read reply: {e:?}"))?,
+        Either::Second(()) => bail!("No reply from the SDS011 to command {cmd}"),
+    };
+    let checksum = reply[2..8].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    if reply[8] != checksum || reply[9] != 0xAB {
+        bail!("Invalid reply from the SDS011 to command {cmd}");
+    }
+    Ok(reply)
+}
+
+/// Wake the sensor up with the commands of all the models, each one ignores
+/// the frames of the others. The SDS011 answers with a reply frame, the
+/// PMS5003 is switched to active mode and sends data frames, the PM1006
+/// answers the read request.
+async fn probe<RW>(serial: &mut RW, delay: &mut impl DelayNs) -> Result<SensorKind>
+where
+    RW: Read + Write,
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    serial
+        .write_all(&sds011_command(SDS011_CMD_WORK, &[1, 1]))
+        .await?;
+    serial
+        .write_all(&pms5003::command(pms5003::CMD_SLEEP, 1))
+        .await?;
+    serial
+        .write_all(&pms5003::command(pms5003::CMD_MODE, 1))
+        .await?;
+    serial.write_all(&pm1006::REQUEST).await?;
+    serial.flush().await?;
+    match select(read_frame_start(serial), delay.delay_ms(DETECT_TIMEOUT_MS)).await {
+        Either::First(kind) => kind.map_err(|e| anyhow!("Unable to probe sensor: {e:?}")),
+        Either::Second(()) => bail!("No answer from the sensor"),
+    }
+}
+
+async fn read_frame_start<RW: Read>(
+    serial: &mut RW,
+) -> Result<SensorKind, ReadExactError<RW::Error>> {
+    let mut byte = [0u8];
+    let mut previous = 0;
+    loop {
+        serial.read_exact(&mut byte).await?;
+        match [previous, byte[0]] {
+            // data or reply frame
+            [0xAA, 0xC0 | 0xC5] => {
+                // The SDS011 driver expects whole frames
+                serial.read_exact(&mut [0u8; 8]).await?;
+                return Ok(SensorKind::Sds011);
+            }
+            pms5003::START => return Ok(SensorKind::Pms5003),
+            [0x16, 0x11] => return Ok(SensorKind::Pm1006),
+            _ => previous = byte[0],
+        }
+    }
+}
+
+/// Discard the received bytes until the line is quiet
+pub async fn drain<RW: Read>(serial: &mut RW, delay: &mut impl DelayNs) -> Result<()>
+where
+    RW::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut buf = [0u8; 32];
+    loop {
+        match select(serial.read(&mut buf), delay.delay_ms(DRAIN_TIMEOUT_MS)).await {
+            Either::First(Ok(0)) | Either::Second(()) => return Ok(()),
+            Either::First(Ok(_)) => continue,
+            Either::First(Err(e)) => return Err(e.into()),
+        }
+    }
+}
+
+/// SDS011 command frame, addressed to all the sensors. `data` is up to 12
+/// bytes, the rest is zeroed.
+fn sds011_command(cmd: u8, data: &[u8]) -> [u8; 19] {
+    let mut frame = [0u8; 19];
+    frame[0] = 0xAA;
+    frame[1] = 0xB4;
+    frame[2] = cmd;
+    frame[3..3 + data.len()].copy_from_slice(data);
+    frame[15] = 0xFF;
+    frame[16] = 0xFF;
+    frame[17] = frame[2..17].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    frame[18] = 0xAB;
+    frame
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Given to the participants to clean up before restarting anyway
+const TIMEOUT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static PARTICIPANTS: Mutex<Vec<Arc<State>>> = Mutex::new(Vec::new());
+
+struct State {
+    name: String,
+    requested: AtomicBool,
+    done: AtomicBool,
+    /// Of the task waiting for the request
+    waker: Mutex<Option<Waker>>,
+}
+
+/// A subsystem to clean up before an intentional restart, e.g. to put a
+/// sensor to sleep. Unregistered when dropped.
+pub struct Participant(Arc<State>);
+
+impl Participant {
+    /// Resolves once the restart is requested
+    pub async fn requested(&self) {
+        core::future::poll_fn(|cx| {
+            if self.0.requested.load(Ordering::Acquire) {
+                return Poll::Ready(());
+            }
+            *self.0.waker.lock().unwrap() = Some(cx.waker().clone());
+            // Requested while registering the waker
+            if self.0.requested.load(Ordering::Acquire) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Cleaned up, the restart no longer waits for this participant
+    pub fn done(&self) {
+        log::info!("{} ready to restart", self.0.name);
+        self.0.done.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for Participant {
+    fn drop(&mut self) {
+        self.0.done.store(true, Ordering::Release);
+        PARTICIPANTS
+            .lock()
+            .unwrap()
+            .retain(|state| !Arc::ptr_eq(state, &self.0));
+    }
+}
+
+pub fn register(name: impl Into<String>) -> Participant {
+    let state = Arc::new(State {
+        name: name.into(),
+        requested: AtomicBool::new(false),
+        done: AtomicBool::new(false),
+        waker: Mutex::new(None),
+    });
+    PARTICIPANTS.lock().unwrap().push(state.clone());
+    Participant(state)
+}
+
+/// Ask all the participants to clean up, and block until they are done or
+/// [`TIMEOUT`]. Called from another thread than the tasks of the
+/// participants.
+pub fn run() {
+    let participants = PARTICIPANTS.lock().unwrap().clone();
+    log::info!("Shutting down {} subsystems", participants.len());
+    for state in &participants {
+        state.requested.store(true, Ordering::Release);
+        if let Some(waker) = state.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let pending: Vec<&str> = participants
+            .iter()
+            .filter(|state| !state.done.load(Ordering::Acquire))
+            .map(|state| state.name.as_str())
+            .collect();
+        if pending.is_empty() {
+            return;
+        }
+        if Instant::now() >= deadline {
+            log::warn!("Restarting without waiting for {}", pending.join(", "));
+            return;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,435 @@
+// The firmware only simulates the SDS011, the host all the sensors
+#![cfg_attr(target_os = "espidf", allow(dead_code))]
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::f32::consts::TAU;
+
+use embedded_hal::digital::{self, InputPin, OutputPin};
+use embedded_hal::i2c::{self, ErrorKind, I2c, NoAcknowledgeSource, Operation};
+use embedded_io_async::{ErrorType, Read, Write};
+
+use chrono::Timelike;
+
+use crate::pms5003::START;
+use crate::voc;
+use crate::{clock, co2};
+
+/// Length of a command frame sent to the SDS011
+const COMMAND_LEN: usize = 19;
+/// Length of a command frame sent to the PMS5003
+const PMS_COMMAND_LEN: usize = 7;
+/// Length of the frames of the MH-Z19, in both directions
+const MHZ19_FRAME_LEN: usize = 9;
+/// Local hour of the most polluted time of the day, when heating and
+/// cooking in the evening
+const PEAK_HOUR: f32 = 19.0;
+/// One sample in this many starts a spike
+const SPIKE_ODDS: u32 = 40;
+
+/// Measurements following the time of the day with some noise, and
+/// occasional spikes decaying over a few samples
+struct Synthetic {
+    samples: u32,
+    seed: u32,
+    /// Tenths of µg/m³ added by the current spike
+    spike: f32,
+}
+
+impl Synthetic {
+    fn new(seed: u32) -> Self {
+        Self {
+            samples: 0,
+            seed,
+            spike: 0.0,
+        }
+    }
+
+    /// xorshift, good enough for noise
+    fn random(&mut self) -> u32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed
+    }
+
+    /// A day long wave once the clock is synchronized, a faster one
+    /// following the samples otherwise
+    fn wave(&self) -> f32 {
+        let phase = match clock::now() {
+            Some(now) => {
+                let hour = clock::local(now).num_seconds_from_midnight() as f32 / 3600.0;
+                (hour - PEAK_HOUR) / 24.0 * TAU
+            }
+            None => self.samples as f32 / 20.0,
+        };
+        phase.cos()
+    }
+
+    /// PM2.5 and PM10 in tenths of µg/m³
+    fn next(&mut self) -> (u16, u16) {
+        self.samples += 1;
+        self.spike /= 2.0;
+        if self.random() % SPIKE_ODDS == 0 {
+            self.spike = (300 + self.random() % 500) as f32;
+        }
+        let noise = (self.random() % 40) as f32 - 20.0;
+        let pm25 = (150.0 + 100.0 * self.wave() + noise + self.spike).max(0.0) as u16;
+        let pm10 = pm25 + pm25 / 2 + (self.random() % 30) as u16;
+        (pm25, pm10)
+    }
+}
+
+/// Simulated SDS011 speaking the sensor's serial protocol, so that the real
+/// driver and frame parser are exercised. It starts in active mode, like a
+/// factory new sensor.
+pub struct FakeSds011 {
+    id: u16,
+    /// Sends a data frame whenever read, instead of waiting for queries
+    active: bool,
+    command: Vec<u8>,
+    reply: VecDeque<u8>,
+    values: Synthetic,
+}
+
+impl FakeSds011 {
+    pub fn new(id: u16) -> Self {
+        Self {
+            id,
+            active: true,
+            command: Vec::with_capacity(COMMAND_LEN),
+            reply: VecDeque::new(),
+            values: Synthetic::new(0x2545_f491),
+        }
+    }
+
+    fn data_frame(&mut self) -> [u8; 7] {
+        let [id_hi, id_lo] = self.id.to_be_bytes();
+        let (pm25, pm10) = self.values.next();
+        let [pm25_lo, pm25_hi] = pm25.to_le_bytes();
+        let [pm10_lo, pm10_hi] = pm10.to_le_bytes();
+        [0xC0, pm25_lo, pm25_hi, pm10_lo, pm10_hi, id_hi, id_lo]
+    }
+
+    fn push_frame(&mut self, frame: [u8; 7]) {
+        let checksum = frame[1..].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        self.reply.push_back(0xAA);
+        self.reply.extend(frame);
+        self.reply.push_back(checksum);
+        self.reply.push_back(0xAB);
+    }
+
+    fn handle_command(&mut self) {
+        let cmd = std::mem::take(&mut self.command);
+        let [id_hi, id_lo] = self.id.to_be_bytes();
+        let frame = match cmd[2] {
+            // reporting mode
+            2 => {
+                if cmd[3] == 1 {
+                    self.active = cmd[4] == 0;
+                }
+                [0xC5, 2, cmd[3], u8::from(!self.active), 0, id_hi, id_lo]
+            }
+            // query data
+            4 => self.data_frame(),
+            // set device id
+            5 => {
+                self.id = u16::from_be_bytes([cmd[13], cmd[14]]);
+                [0xC5, 5, 0, 0, 0, cmd[13], cmd[14]]
+            }
+            // firmware version
+            7 => [0xC5, 7, 24, 10, 15, id_hi, id_lo],
+            // sleep, working period: the new state is echoed
+            sub => [0xC5, sub, cmd[3], cmd[4], 0, id_hi, id_lo],
+        };
+        self.push_frame(frame);
+    }
+}
+
+impl ErrorType for FakeSds011 {
+    type Error = Infallible;
+}
+
+impl Read for FakeSds011 {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.active {
+            let frame = self.data_frame();
+            self.push_frame(frame);
+        }
+        let len = buf.len().min(self.reply.len());
+        for (dst, src) in buf.iter_mut().zip(self.reply.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for FakeSds011 {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for byte in buf {
+            // Like the real sensor, anything else than a command is ignored
+            if self.command.is_empty() && *byte != 0xAA {
+                continue;
+            }
+            self.command.push(*byte);
+            if self.command.len() == COMMAND_LEN {
+                self.handle_command();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Simulated PMS5003 in passive mode, see [`FakeSds011`].
+pub struct FakePms5003 {
+    command: Vec<u8>,
+    reply: VecDeque<u8>,
+    values: Synthetic,
+}
+
+impl FakePms5003 {
+    pub fn new() -> Self {
+        Self {
+            command: Vec::with_capacity(PMS_COMMAND_LEN),
+            reply: VecDeque::new(),
+            values: Synthetic::new(0x1b87_3593),
+        }
+    }
+
+    fn handle_command(&mut self) {
+        let cmd = std::mem::take(&mut self.command);
+        let data: Vec<u8> = match cmd[2] {
+            // read, atmospheric values are in µg/m³
+            0xE2 => {
+                let (pm25, pm10) = self.values.next();
+                let (pm25, pm10) = (pm25 / 10, pm10 / 10);
+                let mut values = [0u16; 13];
+                values[..6].copy_from_slice(&[pm25, pm25, pm10, pm25, pm25, pm10]);
+                values.iter().flat_map(|v| v.to_be_bytes()).collect()
+            }
+            // mode change is acknowledged
+            0xE1 => vec![cmd[2], cmd[4]],
+            // sleep and wake up aren't
+            _ => return,
+        };
+        let len = (data.len() as u16 + 2).to_be_bytes();
+        let mut frame = vec![START[0], START[1], len[0], len[1]];
+        frame.extend(data);
+        let checksum = frame.iter().map(|b| u16::from(*b)).sum::<u16>();
+        frame.extend(checksum.to_be_bytes());
+        self.reply.extend(frame);
+    }
+}
+
+impl ErrorType for FakePms5003 {
+    type Error = Infallible;
+}
+
+impl Read for FakePms5003 {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = buf.len().min(self.reply.len());
+        for (dst, src) in buf.iter_mut().zip(self.reply.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for FakePms5003 {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for byte in buf {
+            if self.command.len() < 2 && *byte != START[self.command.len()] {
+                self.command.clear();
+                continue;
+            }
+            self.command.push(*byte);
+            if self.command.len() == PMS_COMMAND_LEN {
+                self.handle_command();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Simulated MH-Z19, see [`FakeSds011`]. Its CO2 follows the particles.
+pub struct FakeMhz19 {
+    command: Vec<u8>,
+    reply: VecDeque<u8>,
+    values: Synthetic,
+}
+
+impl FakeMhz19 {
+    pub fn new() -> Self {
+        Self {
+            command: Vec::with_capacity(MHZ19_FRAME_LEN),
+            reply: VecDeque::new(),
+            values: Synthetic::new(0x6c07_8965),
+        }
+    }
+
+    fn handle_command(&mut self) {
+        let cmd = std::mem::take(&mut self.command);
+        // Only reads are answered
+        if cmd[2] != 0x86 {
+            return;
+        }
+        let (pm25, _) = self.values.next();
+        let [hi, lo] = (400 + pm25 * 2).to_be_bytes();
+        let mut frame = [0xFF, 0x86, hi, lo, 0, 0, 0, 0, 0];
+        frame[8] = co2::mhz19_checksum(&frame);
+        self.reply.extend(frame);
+    }
+}
+
+impl ErrorType for FakeMhz19 {
+    type Error = Infallible;
+}
+
+impl Read for FakeMhz19 {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = buf.len().min(self.reply.len());
+        for (dst, src) in buf.iter_mut().zip(self.reply.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for FakeMhz19 {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for byte in buf {
+            if self.command.is_empty() && *byte != 0xFF {
+                continue;
+            }
+            self.command.push(*byte);
+            if self.command.len() == MHZ19_FRAME_LEN {
+                self.handle_command();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Simulated SGP40 on I2C, alone on the bus and only answering the raw
+/// measurements. Its raw signal drops when the particles rise, as with
+/// cooking fumes.
+pub struct FakeSgp40 {
+    measuring: bool,
+    values: Synthetic,
+}
+
+impl FakeSgp40 {
+    pub fn new() -> Self {
+        Self {
+            measuring: false,
+            values: Synthetic::new(0x7a3e_11d9),
+        }
+    }
+}
+
+impl i2c::ErrorType for FakeSgp40 {
+    type Error = ErrorKind;
+}
+
+impl I2c for FakeSgp40 {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if address != 0x59 {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => self.measuring = bytes.starts_with(&[0x26, 0x0F]),
+                Operation::Read(buf) if self.measuring => {
+                    let (pm25, _) = self.values.next();
+                    let bytes = 32_000u16.saturating_sub(pm25 * 4).to_be_bytes();
+                    buf[..2].copy_from_slice(&bytes);
+                    buf[2] = voc::crc8(&bytes);
+                    self.measuring = false;
+                }
+                Operation::Read(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Simulated DHT22 line, see [`FakeSds011`]. The start signal queues the
+/// levels the driver reads: the answer, then each bit as a high level and
+/// its value sampled in the middle.
+pub struct FakeDht22 {
+    levels: VecDeque<bool>,
+    values: Synthetic,
+}
+
+impl FakeDht22 {
+    pub fn new() -> Self {
+        Self {
+            levels: VecDeque::new(),
+            values: Synthetic::new(0x1f2e_3d4c),
+        }
+    }
+
+    fn answer(&mut self) {
+        let humidity = 450 + (self.values.random() % 100) as u16;
+        let temperature = 200 + (self.values.random() % 30) as u16;
+        let [h0, h1] = humidity.to_be_bytes();
+        let [t0, t1] = temperature.to_be_bytes();
+        let sum = [h0, h1, t0, t1]
+            .iter()
+            .fold(0u8, |acc, b| acc.wrapping_add(*b));
+        self.levels.extend([false, true, false]);
+        for byte in [h0, h1, t0, t1, sum] {
+            for bit in (0..8).rev() {
+                let one = byte & (1 << bit) != 0;
+                self.levels.extend([true, one]);
+                if one {
+                    self.levels.push_back(false);
+                }
+            }
+        }
+    }
+}
+
+impl digital::ErrorType for FakeDht22 {
+    type Error = Infallible;
+}
+
+impl InputPin for FakeDht22 {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        // Pulled up when idle
+        Ok(self.levels.pop_front().unwrap_or(true))
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl OutputPin for FakeDht22 {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.levels.clear();
+        Ok(())
+    }
+
+    /// Releasing the line ends the start signal
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.answer();
+        Ok(())
+    }
+}
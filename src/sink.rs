@@ -0,0 +1,110 @@
+use core::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Measurements are not exactly periodic, one taken this much before the
+/// end of the interval of a sink is still sent to it
+const SLACK: Duration = Duration::from_secs(10);
+
+/// Output of the measurements, fed by a [`Dispatcher`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    Mqtt,
+    Lorawan,
+    Influx,
+    Webhook,
+    Sdcard,
+    Display,
+    /// UDP datagrams on the LAN
+    Announce,
+    /// Dashboards following the events on `/ws`
+    Websocket,
+}
+
+impl Display for SinkKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mqtt => write!(f, "MQTT"),
+            Self::Lorawan => write!(f, "LoRaWAN"),
+            Self::Influx => write!(f, "InfluxDB"),
+            Self::Webhook => write!(f, "webhook"),
+            Self::Sdcard => write!(f, "SD card"),
+            Self::Display => write!(f, "display"),
+            Self::Announce => write!(f, "UDP announcements"),
+            Self::Websocket => write!(f, "WebSocket clients"),
+        }
+    }
+}
+
+/// At most one measurement every `interval_secs` sent to `sink`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SinkInterval {
+    pub sink: SinkKind,
+    pub interval_secs: u32,
+}
+
+/// An output of the measurements of type `M`
+pub trait MeasurementSink<M> {
+    fn kind(&self) -> SinkKind;
+
+    /// Hand `measurement` over, without blocking the measurements for long:
+    /// slow requests are left to a task of the sink
+    fn send(&mut self, measurement: &M) -> Result<()>;
+}
+
+struct Registered<M> {
+    sink: Box<dyn MeasurementSink<M>>,
+    interval: Duration,
+    /// `None` until sent successfully
+    sent: Option<Instant>,
+}
+
+/// Feeds each measurement to the registered sinks, due according to their
+/// interval. A sink failing is logged without affecting the others, and
+/// tried again with the next measurement.
+pub struct Dispatcher<M> {
+    sinks: Vec<Registered<M>>,
+}
+
+impl<M> Default for Dispatcher<M> {
+    fn default() -> Self {
+        Self { sinks: Vec::new() }
+    }
+}
+
+impl<M> Dispatcher<M> {
+    /// `sink` is sent at most one measurement per `interval`, all of them
+    /// when zero
+    pub fn register(&mut self, sink: impl MeasurementSink<M> + 'static, interval: Duration) {
+        self.sinks.push(Registered {
+            sink: Box::new(sink),
+            interval,
+            sent: None,
+        });
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = SinkKind> + '_ {
+        self.sinks.iter().map(|registered| registered.sink.kind())
+    }
+
+    pub fn dispatch(&mut self, measurement: &M, now: Instant) {
+        for registered in &mut self.sinks {
+            let due = registered.sent.map_or(true, |sent| {
+                now.duration_since(sent) + SLACK >= registered.interval
+            });
+            if !due {
+                continue;
+            }
+            match registered.sink.send(measurement) {
+                Ok(()) => registered.sent = Some(now),
+                Err(e) => log::warn!(
+                    "Unable to send the measurement to the {}: {e:?}",
+                    registered.sink.kind()
+                ),
+            }
+        }
+    }
+}
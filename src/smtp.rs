@@ -0,0 +1,266 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+/// Of the message submission, with STARTTLS
+pub const DEFAULT_PORT: u16 = 587;
+/// Of SMTPS, encrypted from the start instead of with STARTTLS
+pub const IMPLICIT_TLS_PORT: u16 = 465;
+
+/// Of the lines of the base64 body, as recommended by MIME
+const LINE_LEN: usize = 76;
+
+/// Server and account the alerts are sent with
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub server: String,
+    pub port: u16,
+    /// No authentication when empty
+    pub username: String,
+    pub password: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Plain text email
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    /// RFC 5322 message, dated `now` unless the clock is not synchronized:
+    /// the submission server then adds the date
+    pub fn message(&self, from: &str, to: &[String], now: Option<DateTime<Utc>>) -> String {
+        let mut message = format!("From: {from}\r\nTo: {}\r\n", to.join(", "));
+        if let Some(now) = now {
+            message += &format!("Date: {}\r\n", now.to_rfc2822());
+        }
+        message += &format!(
+            "Subject: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n",
+            header(&self.subject)
+        );
+        // Base64 needs no dot-stuffing nor 8BITMIME
+        let body = base64(self.body.replace('\n', "\r\n").as_bytes());
+        for line in body.as_bytes().chunks(LINE_LEN) {
+            message += std::str::from_utf8(line).unwrap_or_default();
+            message += "\r\n";
+        }
+        message
+    }
+}
+
+/// SMTP dialogue over a connected stream, plain or encrypted
+pub struct Client<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Client<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    /// Wait for the greeting of the server
+    pub fn greeting(&mut self) -> Result<()> {
+        self.reply(220)?;
+        Ok(())
+    }
+
+    /// Introduce the client as `domain`, returning the extensions of the
+    /// server, such as `STARTTLS` or `AUTH PLAIN LOGIN`
+    pub fn ehlo(&mut self, domain: &str) -> Result<Vec<String>> {
+        let mut lines = self.command(&format!("EHLO {domain}"), 250)?;
+        // The first line greets the client
+        lines.remove(0);
+        Ok(lines)
+    }
+
+    /// Ask for TLS, returning the stream to negotiate it on. The dialogue
+    /// starts again with `EHLO` over the encrypted stream.
+    pub fn starttls(mut self) -> Result<S> {
+        self.command("STARTTLS", 220)?;
+        if !self.stream.buffer().is_empty() {
+            bail!("Unexpected data before the TLS negotiation");
+        }
+        Ok(self.stream.into_inner())
+    }
+
+    /// Authenticate with the first mechanism of the `extensions` among
+    /// `PLAIN` and `LOGIN`
+    pub fn login(&mut self, extensions: &[String], username: &str, password: &str) -> Result<()> {
+        let mechanisms: Vec<&str> = extensions
+            .iter()
+            .filter_map(|extension| extension.strip_prefix("AUTH "))
+            .flat_map(str::split_whitespace)
+            .collect();
+        if mechanisms.contains(&"PLAIN") {
+            let credentials = base64(format!("\0{username}\0{password}").as_bytes());
+            self.command(&format!("AUTH PLAIN {credentials}"), 235)?;
+        } else if mechanisms.contains(&"LOGIN") {
+            self.command("AUTH LOGIN", 334)?;
+            self.command(&base64(username.as_bytes()), 334)?;
+            self.command(&base64(password.as_bytes()), 235)?;
+        } else {
+            bail!("No supported authentication mechanism among {mechanisms:?}");
+        }
+        Ok(())
+    }
+
+    /// Send `message` from `from` to the `to` addresses
+    pub fn send(&mut self, from: &str, to: &[String], message: &str) -> Result<()> {
+        self.command(&format!("MAIL FROM:<{from}>"), 250)?;
+        for recipient in to {
+            self.command(&format!("RCPT TO:<{recipient}>"), 250)
+                .with_context(|| format!("Recipient {recipient} refused"))?;
+        }
+        self.command("DATA", 354)?;
+        self.stream.get_mut().write_all(message.as_bytes())?;
+        self.command(".", 250)?;
+        Ok(())
+    }
+
+    pub fn quit(mut self) -> Result<()> {
+        self.command("QUIT", 221)?;
+        Ok(())
+    }
+
+    /// Send `line`, returning the text of the reply lines if its code is
+    /// `expected`
+    fn command(&mut self, line: &str, expected: u16) -> Result<Vec<String>> {
+        let stream = self.stream.get_mut();
+        stream.write_all(line.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        self.reply(expected)
+    }
+
+    fn reply(&mut self, expected: u16) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line)? == 0 {
+                bail!("Connection closed by the SMTP server");
+            }
+            let line = line.trim_end();
+            let code: u16 = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .with_context(|| format!("Invalid SMTP reply {line}"))?;
+            lines.push(line.get(4..).unwrap_or_default().to_string());
+            // `250-` is followed by other lines, `250 ` is the last one
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            if code != expected {
+                bail!("SMTP error {code}: {}", lines.join(" "));
+            }
+            return Ok(lines);
+        }
+    }
+}
+
+/// Deliver `email` with `account`, from a blocking task
+#[cfg(target_os = "espidf")]
+pub fn send(account: &Account, hostname: &str, email: &Email) -> Result<()> {
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(15);
+
+    let stream = TcpStream::connect((account.server.as_str(), account.port))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut client = if account.port == IMPLICIT_TLS_PORT {
+        let mut client = Client::new(Tls::negotiate(stream, &account.server)?);
+        client.greeting()?;
+        client
+    } else {
+        let mut client = Client::new(stream);
+        client.greeting()?;
+        // Never send the credentials in clear text
+        if !client.ehlo(hostname)?.iter().any(|e| e == "STARTTLS") {
+            bail!("The SMTP server does not support STARTTLS");
+        }
+        Client::new(Tls::negotiate(client.starttls()?, &account.server)?)
+    };
+    let extensions = client.ehlo(hostname)?;
+    if !account.username.is_empty() {
+        client.login(&extensions, &account.username, &account.password)?;
+    }
+    let message = email.message(&account.from, &account.to, crate::clock::now());
+    client.send(&account.from, &account.to, &message)?;
+    client.quit()
+}
+
+/// TLS over the connection to the server, verified with the certificate
+/// bundle
+#[cfg(target_os = "espidf")]
+struct Tls(esp_idf_svc::tls::EspTls<std::net::TcpStream>);
+
+#[cfg(target_os = "espidf")]
+impl Tls {
+    fn negotiate(stream: std::net::TcpStream, server: &str) -> Result<Self> {
+        let mut tls = esp_idf_svc::tls::EspTls::adopt(stream)?;
+        tls.negotiate(
+            server,
+            &esp_idf_svc::tls::Config {
+                common_name: Some(server),
+                use_crt_bundle_attach: true,
+                ..Default::default()
+            },
+        )?;
+        Ok(Self(tls))
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl Read for Tls {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf).map_err(std::io::Error::other)
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl Write for Tls {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf).map_err(std::io::Error::other)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Subject as an RFC 2047 encoded word when it is not ASCII
+fn header(text: &str) -> String {
+    if text.is_ascii() {
+        text.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", base64(text.as_bytes()))
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(
+                    ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,372 @@
+/// Default SNMP agent port
+pub const PORT: u16 = 161;
+/// Of the requests and responses, within a datagram of the LAN
+pub const MAX_MESSAGE_LEN: usize = 1472;
+/// Enterprise number reserved for documentation by RFC 5612
+pub const EXAMPLE_ENTERPRISE: u32 = 32473;
+/// `iso.org.dod.internet.private.enterprises`
+pub const ENTERPRISES: [u32; 6] = [1, 3, 6, 1, 4, 1];
+/// `iso.org.dod.internet.mgmt.mib-2.system`
+pub const SYSTEM: [u32; 7] = [1, 3, 6, 1, 2, 1, 1];
+
+const VERSION_2C: i64 = 1;
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OBJECT_ID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_COUNTER32: u8 = 0x41;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_TIME_TICKS: u8 = 0x43;
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+
+const PDU_GET: u8 = 0xA0;
+const PDU_GET_NEXT: u8 = 0xA1;
+const PDU_RESPONSE: u8 = 0xA2;
+const PDU_SET: u8 = 0xA3;
+const PDU_GET_BULK: u8 = 0xA5;
+
+const ERROR_TOO_BIG: i64 = 1;
+const ERROR_NOT_WRITABLE: i64 = 17;
+
+/// Of a GetBulk response, whatever the repetitions asked
+const MAX_BULK_VARBINDS: usize = 64;
+
+/// Value of a managed object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(i32),
+    OctetString(Vec<u8>),
+    ObjectId(Vec<u32>),
+    Counter32(u32),
+    Gauge32(u32),
+    /// Hundredths of a second
+    TimeTicks(u32),
+}
+
+impl Value {
+    /// SNMPv2 `TruthValue`
+    pub fn truth(value: bool) -> Self {
+        Self::Integer(if value { 1 } else { 2 })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Integer(value) => tlv(TAG_INTEGER, &integer(i64::from(*value))),
+            Self::OctetString(bytes) => tlv(TAG_OCTET_STRING, bytes),
+            Self::ObjectId(oid) => tlv(TAG_OBJECT_ID, &object_id(oid)),
+            Self::Counter32(value) => tlv(TAG_COUNTER32, &integer(i64::from(*value))),
+            Self::Gauge32(value) => tlv(TAG_GAUGE32, &integer(i64::from(*value))),
+            Self::TimeTicks(value) => tlv(TAG_TIME_TICKS, &integer(i64::from(*value))),
+        }
+    }
+}
+
+/// Managed objects, by object identifier
+pub type Mib = Vec<(Vec<u32>, Value)>;
+
+/// Response to a request datagram, `None` when it is to be dropped: not
+/// SNMPv2c, malformed, or of another community
+pub fn respond(request: &[u8], community: &str, mib: &mut Mib) -> Option<Vec<u8>> {
+    mib.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut message = Reader(Reader(request).expect(TAG_SEQUENCE)?);
+    if message.integer()? != VERSION_2C || message.expect(TAG_OCTET_STRING)? != community.as_bytes()
+    {
+        return None;
+    }
+    let (pdu_type, pdu) = message.tlv()?;
+    let mut pdu = Reader(pdu);
+    let request_id = pdu.integer()?;
+    // Non-repeaters and max-repetitions of a GetBulk
+    let (first, second) = (pdu.integer()?, pdu.integer()?);
+    let mut varbinds = Reader(pdu.expect(TAG_SEQUENCE)?);
+    let mut oids = Vec::new();
+    while !varbinds.0.is_empty() {
+        let mut varbind = Reader(varbinds.expect(TAG_SEQUENCE)?);
+        oids.push(parse_object_id(varbind.expect(TAG_OBJECT_ID)?)?);
+    }
+    let (mut error_status, mut error_index) = (0, 0);
+    let mut response: Vec<(&[u32], Vec<u8>)> = match pdu_type {
+        PDU_GET => oids
+            .iter()
+            .map(|oid| (oid.as_slice(), get(mib, oid)))
+            .collect(),
+        PDU_GET_NEXT => oids.iter().map(|oid| next(mib, oid)).collect(),
+        PDU_GET_BULK => {
+            let non_repeaters = usize::try_from(first.max(0)).ok()?.min(oids.len());
+            let repetitions = usize::try_from(second.max(0)).ok()?;
+            let (singles, repeated) = oids.split_at(non_repeaters);
+            let mut response: Vec<_> = singles.iter().map(|oid| next(mib, oid)).collect();
+            let mut cursors: Vec<&[u32]> = repeated.iter().map(Vec::as_slice).collect();
+            'repetitions: for _ in 0..repetitions {
+                for cursor in &mut cursors {
+                    if response.len() == MAX_BULK_VARBINDS {
+                        break 'repetitions;
+                    }
+                    let (oid, value) = next(mib, cursor);
+                    *cursor = oid;
+                    response.push((oid, value));
+                }
+            }
+            response
+        }
+        PDU_SET => {
+            (error_status, error_index) = (ERROR_NOT_WRITABLE, 1);
+            oids.iter()
+                .map(|oid| (oid.as_slice(), tlv(TAG_NULL, &[])))
+                .collect()
+        }
+        _ => return None,
+    };
+    let mut encoded = encode_response(community, request_id, error_status, error_index, &response);
+    if encoded.len() > MAX_MESSAGE_LEN {
+        if pdu_type == PDU_GET_BULK {
+            // The last varbinds are dropped for the response to fit
+            while encoded.len() > MAX_MESSAGE_LEN && !response.is_empty() {
+                response.pop();
+                encoded = encode_response(community, request_id, 0, 0, &response);
+            }
+        } else {
+            encoded = encode_response(community, request_id, ERROR_TOO_BIG, 0, &[]);
+        }
+    }
+    Some(encoded)
+}
+
+fn get(mib: &Mib, oid: &[u32]) -> Vec<u8> {
+    match mib.iter().find(|(object, _)| object == oid) {
+        Some((_, value)) => value.encode(),
+        None => tlv(TAG_NO_SUCH_OBJECT, &[]),
+    }
+}
+
+/// The object following `oid`, or `oid` at the end of the MIB
+fn next<'a>(mib: &'a Mib, oid: &'a [u32]) -> (&'a [u32], Vec<u8>) {
+    match mib.iter().find(|(object, _)| object.as_slice() > oid) {
+        Some((object, value)) => (object, value.encode()),
+        None => (oid, tlv(TAG_END_OF_MIB_VIEW, &[])),
+    }
+}
+
+fn encode_response(
+    community: &str,
+    request_id: i64,
+    error_status: i64,
+    error_index: i64,
+    varbinds: &[(&[u32], Vec<u8>)],
+) -> Vec<u8> {
+    let varbinds: Vec<u8> = varbinds
+        .iter()
+        .flat_map(|(oid, value)| {
+            let mut varbind = tlv(TAG_OBJECT_ID, &object_id(oid));
+            varbind.extend(value);
+            tlv(TAG_SEQUENCE, &varbind)
+        })
+        .collect();
+    let mut pdu = tlv(TAG_INTEGER, &integer(request_id));
+    pdu.extend(tlv(TAG_INTEGER, &integer(error_status)));
+    pdu.extend(tlv(TAG_INTEGER, &integer(error_index)));
+    pdu.extend(tlv(TAG_SEQUENCE, &varbinds));
+    let mut message = tlv(TAG_INTEGER, &integer(VERSION_2C));
+    message.extend(tlv(TAG_OCTET_STRING, community.as_bytes()));
+    message.extend(tlv(PDU_RESPONSE, &pdu));
+    tlv(TAG_SEQUENCE, &message)
+}
+
+/// BER decoder over the content of a constructed value
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn tlv(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.0.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+        let len = if first & 0x80 == 0 {
+            usize::from(first)
+        } else {
+            // Long form, up to 2 bytes of length is enough for a datagram
+            let count = usize::from(first & 0x7F);
+            if count == 0 || count > 2 || rest.len() < count {
+                return None;
+            }
+            let (bytes, tail) = rest.split_at(count);
+            rest = tail;
+            bytes
+                .iter()
+                .fold(0, |len, &byte| len << 8 | usize::from(byte))
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (content, rest) = rest.split_at(len);
+        self.0 = rest;
+        Some((tag, content))
+    }
+
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        self.tlv()
+            .and_then(|(found, content)| (found == tag).then_some(content))
+    }
+
+    fn integer(&mut self) -> Option<i64> {
+        let content = self.expect(TAG_INTEGER)?;
+        if content.is_empty() || content.len() > 8 {
+            return None;
+        }
+        // Sign extended
+        let initial = if content[0] & 0x80 != 0 { -1 } else { 0 };
+        Some(
+            content
+                .iter()
+                .fold(initial, |value, &byte| value << 8 | i64::from(byte)),
+        )
+    }
+}
+
+fn parse_object_id(content: &[u8]) -> Option<Vec<u32>> {
+    let (&first, rest) = content.split_first()?;
+    let mut oid = vec![u32::from(first / 40).min(2), 0];
+    oid[1] = u32::from(first) - oid[0] * 40;
+    let mut sub_id = 0u32;
+    for &byte in rest {
+        sub_id = sub_id.checked_mul(128)? | u32::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            oid.push(sub_id);
+            sub_id = 0;
+        }
+    }
+    Some(oid)
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    match content.len() {
+        len @ 0..=0x7F => encoded.push(len as u8),
+        len @ 0x80..=0xFF => encoded.extend([0x81, len as u8]),
+        len => encoded.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    encoded.extend(content);
+    encoded
+}
+
+/// Two's complement, without redundant leading bytes
+fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < bytes.len() - 1 {
+        let redundant = (bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn object_id(oid: &[u32]) -> Vec<u8> {
+    let mut encoded = match oid {
+        [first, second, ..] => vec![(first * 40 + second) as u8],
+        _ => vec![0],
+    };
+    for &sub_id in oid.iter().skip(2) {
+        let mut bytes = vec![(sub_id & 0x7F) as u8];
+        let mut rest = sub_id >> 7;
+        while rest > 0 {
+            bytes.push((rest & 0x7F) as u8 | 0x80);
+            rest >>= 7;
+        }
+        encoded.extend(bytes.iter().rev());
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// sysName.0
+    const SYS_NAME: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 5, 0];
+
+    fn mib() -> Mib {
+        vec![
+            (SYS_NAME.to_vec(), Value::OctetString(b"sensor".to_vec())),
+            ([1, 3, 6, 1, 2, 1, 1, 3, 0].to_vec(), Value::TimeTicks(500)),
+        ]
+    }
+
+    /// Request of `pdu_type` for `oid`, with request ID 1
+    fn request(community: &str, pdu_type: u8, oid: &[u32]) -> Vec<u8> {
+        let varbind = tlv(
+            TAG_SEQUENCE,
+            &[tlv(TAG_OBJECT_ID, &object_id(oid)), tlv(TAG_NULL, &[])].concat(),
+        );
+        let pdu = tlv(
+            pdu_type,
+            &[
+                tlv(TAG_INTEGER, &integer(1)),
+                tlv(TAG_INTEGER, &integer(0)),
+                tlv(TAG_INTEGER, &integer(0)),
+                tlv(TAG_SEQUENCE, &varbind),
+            ]
+            .concat(),
+        );
+        tlv(
+            TAG_SEQUENCE,
+            &[
+                tlv(TAG_INTEGER, &integer(VERSION_2C)),
+                tlv(TAG_OCTET_STRING, community.as_bytes()),
+                pdu,
+            ]
+            .concat(),
+        )
+    }
+
+    #[test]
+    fn encodings() {
+        assert_eq!(object_id(&SYS_NAME), [0x2B, 6, 1, 2, 1, 1, 5, 0]);
+        assert_eq!(
+            object_id(&[1, 3, 6, 1, 4, 1, 32473]),
+            [0x2B, 6, 1, 4, 1, 0x81, 0xFD, 0x59]
+        );
+        assert_eq!(
+            parse_object_id(&[0x2B, 6, 1, 4, 1, 0x81, 0xFD, 0x59]),
+            Some(vec![1, 3, 6, 1, 4, 1, 32473])
+        );
+        assert_eq!(integer(0), [0]);
+        assert_eq!(integer(128), [0x00, 0x80]);
+        assert_eq!(integer(-1), [0xFF]);
+    }
+
+    #[test]
+    fn get_and_next() {
+        let response = respond(&request("public", PDU_GET, &SYS_NAME), "public", &mut mib());
+        let response = response.unwrap();
+        let value = tlv(TAG_OCTET_STRING, b"sensor");
+        assert!(response.ends_with(&value));
+        assert!(response
+            .windows(2)
+            .any(|window| window == [PDU_RESPONSE, 0x1F]));
+        // The uptime sorts before the name
+        let next = respond(
+            &request("public", PDU_GET_NEXT, &[1, 3, 6, 1, 2, 1, 1]),
+            "public",
+            &mut mib(),
+        );
+        assert!(next.unwrap().ends_with(&tlv(TAG_TIME_TICKS, &integer(500))));
+        let missing = respond(
+            &request("public", PDU_GET, &[1, 3, 6]),
+            "public",
+            &mut mib(),
+        );
+        assert!(missing.unwrap().ends_with(&tlv(TAG_NO_SUCH_OBJECT, &[])));
+    }
+
+    #[test]
+    fn dropped() {
+        let request = request("public", PDU_GET, &SYS_NAME);
+        assert!(respond(&request, "private", &mut mib()).is_none());
+        assert!(respond(&request[..request.len() - 1], "public", &mut mib()).is_none());
+    }
+}
@@ -0,0 +1,352 @@
+use std::time::Duration;
+
+use chrono::DateTime;
+use serde::Serialize;
+
+use crate::history::Sample;
+use crate::reading::{self, Kind};
+
+const DAY_SECS: i64 = 24 * 3600;
+
+/// Period summarized, in local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Hour,
+    Day,
+}
+
+impl Period {
+    fn secs(self) -> i64 {
+        match self {
+            Self::Hour => 3600,
+            Self::Day => DAY_SECS,
+        }
+    }
+
+    /// Start of the period holding `timestamp`, for a local time `utc_offset`
+    /// seconds ahead of UTC
+    fn start(self, timestamp: i64, utc_offset: i64) -> i64 {
+        timestamp - (timestamp + utc_offset).rem_euclid(self.secs())
+    }
+
+    /// Last part of the MQTT topic
+    pub fn topic(self) -> &'static str {
+        match self {
+            Self::Hour => "hourly",
+            Self::Day => "daily",
+        }
+    }
+}
+
+/// Summary of the measurements of a closed period
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub period: Period,
+    /// RFC 3339, UTC
+    pub start: String,
+    pub samples: usize,
+    /// µg/m³
+    pub pm25_mean: f32,
+    pub pm25_median: f32,
+    pub pm25_max: f32,
+    /// Time spent above the PM2.5 limit, each sample counting for a
+    /// measurement interval
+    pub exceedance_minutes: u64,
+}
+
+/// Summarize the samples of `period` starting at `start` (Unix timestamp),
+/// `None` if there are none
+pub fn compute<'a>(
+    samples: impl IntoIterator<Item = &'a Sample>,
+    period: Period,
+    start: i64,
+    measure_interval: Duration,
+    limits: &Limits,
+) -> Option<Stats> {
+    let end = start + period.secs();
+    let mut pm25: Vec<u16> = samples
+        .into_iter()
+        .filter(|sample| (start..end).contains(&i64::from(sample.timestamp)))
+        .map(|sample| sample.pm25)
+        .collect();
+    if pm25.is_empty() {
+        return None;
+    }
+    pm25.sort_unstable();
+    let count = pm25.len();
+    let sum: u32 = pm25.iter().map(|&value| u32::from(value)).sum();
+    let median = if count % 2 == 0 {
+        (f32::from(pm25[count / 2 - 1]) + f32::from(pm25[count / 2])) / 2.0
+    } else {
+        f32::from(pm25[count / 2])
+    };
+    let exceeding = pm25
+        .iter()
+        .filter(|&&value| reading::from_tenths(value) > limits.pm25)
+        .count();
+    Some(Stats {
+        period,
+        start: DateTime::from_timestamp(start, 0)?
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string(),
+        samples: count,
+        pm25_mean: sum as f32 / count as f32 / reading::TENTHS,
+        pm25_median: median / reading::TENTHS,
+        pm25_max: reading::from_tenths(pm25[count - 1]),
+        exceedance_minutes: exceeding as u64 * measure_interval.as_secs() / 60,
+    })
+}
+
+/// Detects the hours and days which ended between two samples
+#[derive(Debug, Default)]
+pub struct Rollover {
+    /// Start of the current hour and day, unknown until the clock is set
+    current: Option<(i64, i64)>,
+}
+
+impl Rollover {
+    /// Periods closed by a sample taken at `timestamp`, with their start,
+    /// the local time being `utc_offset` seconds ahead of UTC. Nothing is
+    /// closed by the first sample, the period before it may have been
+    /// summarized before a restart.
+    pub fn advance(&mut self, timestamp: u32, utc_offset: i64) -> Vec<(Period, i64)> {
+        // Taken before SNTP synchronization
+        if timestamp == 0 {
+            return Vec::new();
+        }
+        let timestamp = i64::from(timestamp);
+        let next = (
+            Period::Hour.start(timestamp, utc_offset),
+            Period::Day.start(timestamp, utc_offset),
+        );
+        let closed = match self.current {
+            Some((hour, day)) => [(Period::Hour, hour, next.0), (Period::Day, day, next.1)]
+                .into_iter()
+                .filter(|(_, current, next)| next > current)
+                .map(|(period, current, _)| (period, current))
+                .collect(),
+            None => Vec::new(),
+        };
+        self.current = Some(next);
+        closed
+    }
+}
+
+/// Regulatory thresholds, in µg/m³, applying to 24 h means
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub pm25: f32,
+    pub pm10: f32,
+}
+
+impl Limits {
+    fn get(&self, kind: Kind) -> f32 {
+        match kind {
+            Kind::Pm25 => self.pm25,
+            Kind::Pm10 => self.pm10,
+            Kind::Co2 | Kind::Voc | Kind::Tvoc | Kind::Temperature | Kind::Humidity => {
+                unreachable!("only particles have limits")
+            }
+        }
+    }
+}
+
+/// Time spent above the limits since midnight (UTC), and the means of the
+/// last 24 h they apply to
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Exceedance {
+    pub pm25_minutes: u64,
+    pub pm10_minutes: u64,
+    /// µg/m³, `None` without samples
+    pub pm25_mean_24h: Option<f32>,
+    pub pm10_mean_24h: Option<f32>,
+}
+
+impl Exceedance {
+    /// From the samples of the history at `now` (Unix timestamp), each one
+    /// counting for a measurement interval. Today starts at the local
+    /// midnight, `utc_offset` seconds ahead of UTC.
+    pub fn new<'a>(
+        samples: impl IntoIterator<Item = &'a Sample>,
+        now: i64,
+        utc_offset: i64,
+        measure_interval: Duration,
+        limits: &Limits,
+    ) -> Self {
+        let midnight = Period::Day.start(now, utc_offset);
+        let (mut above, mut sums, mut count) = ([0u64; 2], [0.0f32; 2], 0u32);
+        for sample in samples {
+            let timestamp = i64::from(sample.timestamp);
+            if timestamp <= now - DAY_SECS {
+                continue;
+            }
+            count += 1;
+            for (i, kind) in [Kind::Pm25, Kind::Pm10].into_iter().enumerate() {
+                let value = value(sample, kind);
+                sums[i] += value;
+                if timestamp >= midnight && value > limits.get(kind) {
+                    above[i] += 1;
+                }
+            }
+        }
+        let minutes = |above: u64| above * measure_interval.as_secs() / 60;
+        let mean = |sum: f32| (count > 0).then(|| sum / count as f32);
+        Self {
+            pm25_minutes: minutes(above[0]),
+            pm10_minutes: minutes(above[1]),
+            pm25_mean_24h: mean(sums[0]),
+            pm10_mean_24h: mean(sums[1]),
+        }
+    }
+
+    fn get(&self, kind: Kind) -> (u64, Option<f32>) {
+        match kind {
+            Kind::Pm25 => (self.pm25_minutes, self.pm25_mean_24h),
+            Kind::Pm10 => (self.pm10_minutes, self.pm10_mean_24h),
+            Kind::Co2 | Kind::Voc | Kind::Tvoc | Kind::Temperature | Kind::Humidity => {
+                unreachable!("only particles have limits")
+            }
+        }
+    }
+}
+
+fn value(sample: &Sample, kind: Kind) -> f32 {
+    match kind {
+        Kind::Pm25 => reading::from_tenths(sample.pm25),
+        Kind::Pm10 => reading::from_tenths(sample.pm10),
+        Kind::Co2 | Kind::Voc | Kind::Tvoc | Kind::Temperature | Kind::Humidity => {
+            unreachable!("only particles are in the history")
+        }
+    }
+}
+
+/// Published when the 24 h mean of a metric goes above its limit
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitExceeded {
+    pub metric: Kind,
+    pub limit: f32,
+    pub mean_24h: f32,
+    /// Time above the limit since the local midnight
+    pub minutes_today: u64,
+}
+
+/// Reports each metric exceeding its limit once a day
+#[derive(Debug, Default)]
+pub struct LimitAlerts {
+    /// Start of the day and metrics already reported
+    reported: Option<(i64, Vec<Kind>)>,
+}
+
+impl LimitAlerts {
+    /// The metrics which exceed their limit at `now` and were not reported
+    /// yet today, in local time
+    pub fn check(
+        &mut self,
+        now: i64,
+        utc_offset: i64,
+        exceedance: &Exceedance,
+        limits: &Limits,
+    ) -> Vec<LimitExceeded> {
+        let midnight = Period::Day.start(now, utc_offset);
+        let reported = match &mut self.reported {
+            Some((day, reported)) if *day == midnight => reported,
+            _ => &mut self.reported.insert((midnight, Vec::new())).1,
+        };
+        let mut events = Vec::new();
+        for kind in [Kind::Pm25, Kind::Pm10] {
+            let (minutes_today, mean_24h) = exceedance.get(kind);
+            let Some(mean_24h) = mean_24h else {
+                continue;
+            };
+            if mean_24h > limits.get(kind) && !reported.contains(&kind) {
+                reported.push(kind);
+                events.push(LimitExceeded {
+                    metric: kind,
+                    limit: limits.get(kind),
+                    mean_24h,
+                    minutes_today,
+                });
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2023-11-14T22:00:00Z
+    const HOUR: i64 = 1_699_999_200;
+    const LIMITS: Limits = Limits {
+        pm25: 25.0,
+        pm10: 50.0,
+    };
+
+    fn sample(timestamp: i64, pm25: u16) -> Sample {
+        Sample {
+            timestamp: timestamp as u32,
+            pm25,
+            pm10: pm25 * 2,
+        }
+    }
+
+    #[test]
+    fn hourly_summary() {
+        let samples = [
+            sample(HOUR - 60, 900),
+            sample(HOUR, 100),
+            sample(HOUR + 600, 400),
+            sample(HOUR + 1200, 200),
+            sample(HOUR + 3540, 300),
+            sample(HOUR + 3600, 900),
+        ];
+        let interval = Duration::from_secs(60);
+        let stats = compute(&samples, Period::Hour, HOUR, interval, &LIMITS).unwrap();
+        assert_eq!(stats.start, "2023-11-14T22:00:00Z");
+        assert_eq!(stats.samples, 4);
+        assert_eq!(stats.pm25_mean, 25.0);
+        assert_eq!(stats.pm25_median, 25.0);
+        assert_eq!(stats.pm25_max, 40.0);
+        assert_eq!(stats.exceedance_minutes, 2);
+        assert!(compute(&samples, Period::Hour, HOUR + 7200, interval, &LIMITS).is_none());
+    }
+
+    #[test]
+    fn rollover_at_local_midnight() {
+        // 22:30 UTC, 23:30 an hour ahead
+        let mut rollover = Rollover::default();
+        assert!(rollover.advance(0, 3600).is_empty());
+        assert!(rollover.advance((HOUR + 1800) as u32, 3600).is_empty());
+        assert!(rollover.advance((HOUR + 2400) as u32, 3600).is_empty());
+        let closed = rollover.advance((HOUR + 4200) as u32, 3600);
+        assert_eq!(
+            closed,
+            [(Period::Hour, HOUR), (Period::Day, HOUR - 23 * 3600)]
+        );
+        // UTC midnight is still two hours away
+        let mut rollover = Rollover::default();
+        rollover.advance((HOUR + 1800) as u32, 0);
+        let closed = rollover.advance((HOUR + 4200) as u32, 0);
+        assert_eq!(closed, [(Period::Hour, HOUR)]);
+    }
+
+    #[test]
+    fn limit_alerts_once_a_day() {
+        let interval = Duration::from_secs(60);
+        let samples: Vec<Sample> = (0..10).map(|i| sample(HOUR + i * 60, 300)).collect();
+        let now = HOUR + 600;
+        let exceedance = Exceedance::new(&samples, now, 0, interval, &LIMITS);
+        assert_eq!(exceedance.pm25_minutes, 10);
+        assert_eq!(exceedance.pm10_minutes, 10);
+        assert_eq!(exceedance.pm25_mean_24h, Some(30.0));
+        let mut alerts = LimitAlerts::default();
+        let events = alerts.check(now, 0, &exceedance, &LIMITS);
+        let metrics: Vec<Kind> = events.iter().map(|event| event.metric).collect();
+        assert_eq!(metrics, [Kind::Pm25, Kind::Pm10]);
+        assert!(alerts.check(now + 60, 0, &exceedance, &LIMITS).is_empty());
+        // The next day
+        assert_eq!(alerts.check(now + 7200, 0, &exceedance, &LIMITS).len(), 2);
+    }
+}
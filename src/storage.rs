@@ -0,0 +1,19 @@
+use anyhow::Result;
+use esp_idf_svc::sys::{esp, esp_vfs_spiffs_conf_t, esp_vfs_spiffs_register};
+
+/// Where the `storage` SPIFFS partition (see `partitions.csv`) is mounted.
+pub const MOUNT_POINT: &str = "/storage";
+
+pub const HISTORY_PATH: &str = "/storage/history.bin";
+
+/// Mount the SPIFFS partition, formatting it on first use.
+pub fn mount() -> Result<()> {
+    let conf = esp_vfs_spiffs_conf_t {
+        base_path: c"/storage".as_ptr(),
+        partition_label: c"storage".as_ptr(),
+        max_files: 4,
+        format_if_mount_failed: true,
+    };
+    esp!(unsafe { esp_vfs_spiffs_register(&conf) })?;
+    Ok(())
+}
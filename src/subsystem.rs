@@ -0,0 +1,109 @@
+use core::fmt::{self, Display, Formatter};
+
+use serde::Serialize;
+
+/// Optional part of the station, wired in `cfg.toml` and enabled in the
+/// settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    /// Second particle sensor
+    Sensor1,
+    Co2,
+    Voc,
+    Dht22,
+    Buzzer,
+    Relay,
+    Fan,
+    Epaper,
+    Segment,
+}
+
+impl Subsystem {
+    pub const ALL: [Self; 9] = [
+        Self::Sensor1,
+        Self::Co2,
+        Self::Voc,
+        Self::Dht22,
+        Self::Buzzer,
+        Self::Relay,
+        Self::Fan,
+        Self::Epaper,
+        Self::Segment,
+    ];
+}
+
+impl Display for Subsystem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sensor1 => write!(f, "second particle sensor"),
+            Self::Co2 => write!(f, "CO2 sensor"),
+            Self::Voc => write!(f, "VOC sensor"),
+            Self::Dht22 => write!(f, "DHT22"),
+            Self::Buzzer => write!(f, "buzzer"),
+            Self::Relay => write!(f, "relay"),
+            Self::Fan => write!(f, "fan"),
+            Self::Epaper => write!(f, "e-paper display"),
+            Self::Segment => write!(f, "7-segment display"),
+        }
+    }
+}
+
+/// A flag for each subsystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subsystems {
+    pub sensor1: bool,
+    pub co2: bool,
+    pub voc: bool,
+    pub dht22: bool,
+    pub buzzer: bool,
+    pub relay: bool,
+    pub fan: bool,
+    pub epaper: bool,
+    pub segment: bool,
+}
+
+impl Subsystems {
+    pub fn get(&self, subsystem: Subsystem) -> bool {
+        match subsystem {
+            Subsystem::Sensor1 => self.sensor1,
+            Subsystem::Co2 => self.co2,
+            Subsystem::Voc => self.voc,
+            Subsystem::Dht22 => self.dht22,
+            Subsystem::Buzzer => self.buzzer,
+            Subsystem::Relay => self.relay,
+            Subsystem::Fan => self.fan,
+            Subsystem::Epaper => self.epaper,
+            Subsystem::Segment => self.segment,
+        }
+    }
+}
+
+/// A subsystem enabled but not detected, as it does not answer, or
+/// detected but disabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Mismatch {
+    pub subsystem: Subsystem,
+    pub enabled: bool,
+    pub detected: bool,
+}
+
+/// The subsystems where `detected` differs from `enabled`. `detected` is
+/// `None` for the subsystems not wired in `cfg.toml`, which are not
+/// expected whether enabled or not.
+pub fn mismatches(
+    enabled: &Subsystems,
+    detected: impl Fn(Subsystem) -> Option<bool>,
+) -> Vec<Mismatch> {
+    Subsystem::ALL
+        .into_iter()
+        .filter_map(|subsystem| {
+            Some(Mismatch {
+                subsystem,
+                enabled: enabled.get(subsystem),
+                detected: detected(subsystem)?,
+            })
+        })
+        .filter(|mismatch| mismatch.enabled != mismatch.detected)
+        .collect()
+}
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use esp_idf_svc::hal::{
+    gpio::{AnyIOPin, Output, PinDriver},
+    spi::{config::Config, SpiDeviceDriver, SpiDriver},
+    units::Hertz,
+};
+
+use crate::lorawan;
+
+/// SPI clock, the SX1276 supports up to 10 MHz
+const BAUDRATE: Hertz = Hertz(8_000_000);
+/// Of the register read on reset, to tell the chip is there
+const VERSION: u8 = 0x12;
+/// Crystal of the RFM95 and most modules, in Hz
+const OSCILLATOR: u64 = 32_000_000;
+/// Of the public LoRaWAN networks
+const SYNC_WORD: u8 = 0x34;
+/// Of a receive window, long enough for the polling delays
+const RX_WINDOW: Duration = Duration::from_millis(100);
+/// Between the reads of the interrupt flags, the DIO pins are not wired
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_LNA: u8 = 0x0C;
+const REG_FIFO_ADDR_PTR: u8 = 0x0D;
+const REG_FIFO_TX_BASE_ADDR: u8 = 0x0E;
+const REG_FIFO_RX_BASE_ADDR: u8 = 0x0F;
+const REG_FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_RX_NB_BYTES: u8 = 0x13;
+const REG_MODEM_CONFIG_1: u8 = 0x1D;
+const REG_MODEM_CONFIG_2: u8 = 0x1E;
+const REG_SYMB_TIMEOUT_LSB: u8 = 0x1F;
+const REG_PREAMBLE_LSB: u8 = 0x21;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_MODEM_CONFIG_3: u8 = 0x26;
+const REG_INVERT_IQ: u8 = 0x33;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_INVERT_IQ_2: u8 = 0x3B;
+const REG_VERSION: u8 = 0x42;
+
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_SINGLE: u8 = 0x06;
+
+const IRQ_TX_DONE: u8 = 0x08;
+const IRQ_CRC_ERROR: u8 = 0x20;
+const IRQ_RX_DONE: u8 = 0x40;
+const IRQ_RX_TIMEOUT: u8 = 0x80;
+
+/// SX1276 or RFM95 LoRa radio, sleeping between frames
+pub struct Sx1276 {
+    spi: SpiDeviceDriver<'static, Arc<SpiDriver<'static>>>,
+    /// Held high, the chip is reset when it goes low
+    _reset: PinDriver<'static, AnyIOPin, Output>,
+}
+
+impl Sx1276 {
+    /// Reset the radio on the `spi` bus and set it up for LoRaWAN
+    pub fn new(spi: Arc<SpiDriver<'static>>, cs: AnyIOPin, rst: AnyIOPin) -> Result<Self> {
+        let mut reset = PinDriver::output(rst)?;
+        reset.set_low()?;
+        thread::sleep(Duration::from_millis(1));
+        reset.set_high()?;
+        thread::sleep(Duration::from_millis(10));
+        let spi = SpiDeviceDriver::new(spi, Some(cs), &Config::new().baudrate(BAUDRATE))?;
+        let mut radio = Self { spi, _reset: reset };
+        let version = radio.read(REG_VERSION)?;
+        if version != VERSION {
+            bail!("SX1276 not found, version {version:#04x}");
+        }
+        // The LoRa mode can only be selected while sleeping
+        radio.write(REG_OP_MODE, MODE_SLEEP)?;
+        radio.write(REG_OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)?;
+        radio.write(REG_SYNC_WORD, SYNC_WORD)?;
+        radio.write(REG_PREAMBLE_LSB, 8)?;
+        // PA_BOOST at 14 dBm, the EU868 limit
+        radio.write(REG_PA_CONFIG, 0x80 | (14 - 2))?;
+        // Highest gain, boosted
+        radio.write(REG_LNA, 0x23)?;
+        Ok(radio)
+    }
+
+    /// Send `frame`, returning once it is sent
+    pub fn transmit(&mut self, frequency: u32, spreading_factor: u8, frame: &[u8]) -> Result<()> {
+        self.configure(frequency, spreading_factor, false)?;
+        self.write(REG_FIFO_TX_BASE_ADDR, 0)?;
+        self.write(REG_FIFO_ADDR_PTR, 0)?;
+        let mut fifo = vec![REG_FIFO | 0x80];
+        fifo.extend(frame);
+        self.spi.write(&fifo)?;
+        self.write(REG_PAYLOAD_LENGTH, frame.len() as u8)?;
+        self.write(REG_IRQ_FLAGS, 0xFF)?;
+        self.write(REG_OP_MODE, MODE_LONG_RANGE | MODE_TX)?;
+        let timeout = lorawan::time_on_air(spreading_factor, frame.len()) + Duration::from_secs(1);
+        let flags = self.wait(IRQ_TX_DONE, timeout)?;
+        self.write(REG_OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)?;
+        if flags & IRQ_TX_DONE == 0 {
+            bail!("SX1276 transmission timed out");
+        }
+        Ok(())
+    }
+
+    /// Listen for a downlink during a receive window opened now, `None`
+    /// when nothing valid was received
+    pub fn receive(&mut self, frequency: u32, spreading_factor: u8) -> Result<Option<Vec<u8>>> {
+        self.configure(frequency, spreading_factor, true)?;
+        self.write(REG_FIFO_RX_BASE_ADDR, 0)?;
+        self.write(REG_FIFO_ADDR_PTR, 0)?;
+        // 2^SF / 125 kHz
+        let symbol = Duration::from_micros(8 << spreading_factor);
+        let symbols = (RX_WINDOW.as_micros() / symbol.as_micros()).clamp(8, 1023) as u16;
+        let config_2 = self.read(REG_MODEM_CONFIG_2)?;
+        self.write(REG_MODEM_CONFIG_2, config_2 & !0x03 | (symbols >> 8) as u8)?;
+        self.write(REG_SYMB_TIMEOUT_LSB, symbols as u8)?;
+        self.write(REG_IRQ_FLAGS, 0xFF)?;
+        self.write(REG_OP_MODE, MODE_LONG_RANGE | MODE_RX_SINGLE)?;
+        // Once a preamble is detected, until the end of the longest frame
+        let flags = self.wait(
+            IRQ_RX_DONE | IRQ_RX_TIMEOUT,
+            lorawan::time_on_air(spreading_factor, 255) + RX_WINDOW,
+        )?;
+        let frame = if flags & IRQ_RX_DONE != 0 && flags & IRQ_CRC_ERROR == 0 {
+            let len = usize::from(self.read(REG_RX_NB_BYTES)?);
+            let address = self.read(REG_FIFO_RX_CURRENT_ADDR)?;
+            self.write(REG_FIFO_ADDR_PTR, address)?;
+            let mut fifo = vec![0; len + 1];
+            fifo[0] = REG_FIFO;
+            self.spi.transfer_in_place(&mut fifo)?;
+            Some(fifo.split_off(1))
+        } else {
+            None
+        };
+        self.write(REG_OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)?;
+        Ok(frame)
+    }
+
+    /// 125 kHz, coding rate 4/5 and an explicit header. The downlinks have
+    /// their I and Q inverted, and no CRC.
+    fn configure(&mut self, frequency: u32, spreading_factor: u8, downlink: bool) -> Result<()> {
+        self.write(REG_OP_MODE, MODE_LONG_RANGE | MODE_STANDBY)?;
+        let frf = (u64::from(frequency) << 19) / OSCILLATOR;
+        let mut frf_bytes = vec![REG_FRF_MSB | 0x80];
+        frf_bytes.extend(&frf.to_be_bytes()[5..]);
+        self.spi.write(&frf_bytes)?;
+        self.write(REG_MODEM_CONFIG_1, 0x72)?;
+        let crc = if downlink { 0x00 } else { 0x04 };
+        self.write(REG_MODEM_CONFIG_2, spreading_factor << 4 | crc)?;
+        // Automatic gain, low data rate optimization from SF11
+        let low_data_rate = if spreading_factor >= 11 { 0x08 } else { 0x00 };
+        self.write(REG_MODEM_CONFIG_3, 0x04 | low_data_rate)?;
+        let (invert_iq, invert_iq_2) = if downlink { (0x66, 0x19) } else { (0x27, 0x1D) };
+        self.write(REG_INVERT_IQ, invert_iq)?;
+        self.write(REG_INVERT_IQ_2, invert_iq_2)
+    }
+
+    /// Interrupt flags once one of `flags` is raised, or at `timeout`
+    fn wait(&mut self, flags: u8, timeout: Duration) -> Result<u8> {
+        let start = Instant::now();
+        loop {
+            let raised = self.read(REG_IRQ_FLAGS)?;
+            if raised & flags != 0 || start.elapsed() > timeout {
+                return Ok(raised);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn read(&mut self, register: u8) -> Result<u8> {
+        let mut buf = [register & 0x7F, 0];
+        self.spi.transfer_in_place(&mut buf)?;
+        Ok(buf[1])
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> Result<()> {
+        self.spi.write(&[register | 0x80, value])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::ffi::CStr;
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_idf_svc::hal::reset::restart;
+use esp_idf_svc::hal::task::thread::ThreadSpawnConfiguration;
+
+use crate::shutdown;
+
+/// Waits before restarting, in the background
+const RESTART: Task = Task {
+    name: c"restart",
+    // `esp_restart` runs the shutdown handlers, stopping the Wi-Fi
+    stack_size: 4 * 1024,
+};
+
+/// FreeRTOS task running a thread, with its own stack size instead of the
+/// default one, and named in the stack high-water marks of the health
+/// report
+pub struct Task {
+    /// Up to 15 characters
+    pub name: &'static CStr,
+    pub stack_size: usize,
+}
+
+impl Task {
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) -> Result<()> {
+        ThreadSpawnConfiguration {
+            name: Some(self.name.to_bytes_with_nul()),
+            stack_size: self.stack_size,
+            ..Default::default()
+        }
+        .set()?;
+        let spawned = std::thread::Builder::new()
+            .stack_size(self.stack_size)
+            .spawn(f);
+        // Only applies to the threads spawned by the current one, which may
+        // spawn others
+        ThreadSpawnConfiguration::default().set()?;
+        spawned?;
+        Ok(())
+    }
+}
+
+/// Restart after `delay`, once the response to a request has gone out, and
+/// the subsystems cleaned up
+pub fn restart_after(delay: Duration) {
+    let spawned = RESTART.spawn(move || {
+        std::thread::sleep(delay);
+        shutdown::run();
+        restart();
+    });
+    if let Err(e) = spawned {
+        log::error!("Unable to schedule the restart: {e:?}");
+        restart();
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+use crate::aqi::{self, Band};
+use crate::reading::{self, Kind, Reading, Unit};
+
+/// Of the Bot API
+pub const API_URL: &str = "https://api.telegram.org";
+/// Of a `getUpdates` long polling, the server answers earlier when a
+/// message arrives
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+/// Answer to the other commands
+pub const HELP: &str = "/air: current readings and air quality";
+
+/// Of the updates, a few messages are enough
+#[cfg(target_os = "espidf")]
+const MAX_BODY_LEN: usize = 16 * 1024;
+
+/// Envelope of the Bot API results
+#[derive(Debug, Deserialize)]
+pub struct Response<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+impl<T> Response<T> {
+    pub fn into_result(self) -> Result<T> {
+        match (self.ok, self.result) {
+            (true, Some(result)) => Ok(result),
+            _ => bail!("Telegram error: {}", self.description.unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Update {
+    pub update_id: i64,
+    /// `None` for the other kinds of updates
+    pub message: Option<Message>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Message {
+    pub chat: Chat,
+    /// `None` for photos, stickers...
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Chat {
+    pub id: i64,
+}
+
+/// Commands of the bot, whatever its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Air,
+    /// `/start`, `/help` or an unknown command
+    Help,
+}
+
+impl Command {
+    /// `None` if `text` is not a command
+    pub fn parse(text: &str) -> Option<Self> {
+        let command = text.strip_prefix('/')?.split_whitespace().next()?;
+        // `/air@SomeBot` in groups
+        let command = command.split('@').next().unwrap_or_default();
+        Some(if command == "air" {
+            Self::Air
+        } else {
+            Self::Help
+        })
+    }
+}
+
+/// Answer to `/air`: the AQI and its band, then a line per reading
+pub fn air_summary(station: &str, readings: &[Reading], stale: bool) -> String {
+    let pm25 = reading::value(readings, Kind::Pm25).unwrap_or_default();
+    let pm10 = reading::value(readings, Kind::Pm10).unwrap_or_default();
+    let aqi = aqi::us_epa(pm25, pm10);
+    let mut summary = format!("{station}: {}, AQI {aqi}\n", Band::from_aqi(aqi).name());
+    for reading in readings {
+        let decimals = usize::from(reading.kind.decimals());
+        summary += &format!("{}: {:.*}", reading.kind, decimals, reading.value);
+        // The VOC index has no unit
+        if reading.unit != Unit::Index {
+            summary += &format!(" {}", reading.unit);
+        }
+        summary.push('\n');
+    }
+    if stale {
+        summary += "Stale: the sensors were not read recently\n";
+    }
+    summary
+}
+
+/// Bot API client, blocking
+#[cfg(target_os = "espidf")]
+#[derive(Clone)]
+pub struct Bot {
+    token: String,
+}
+
+#[cfg(target_os = "espidf")]
+impl Bot {
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+
+    /// Updates from `offset`, the ID following those already handled,
+    /// waiting for up to [`POLL_TIMEOUT`]
+    pub fn updates(&self, offset: i64) -> Result<Vec<Update>> {
+        let body = serde_json::json!({
+            "offset": offset,
+            "timeout": POLL_TIMEOUT.as_secs(),
+            "allowed_updates": ["message"],
+        });
+        let response = self.call("getUpdates", &body, POLL_TIMEOUT + Duration::from_secs(10))?;
+        serde_json::from_slice::<Response<Vec<Update>>>(&response)?.into_result()
+    }
+
+    /// Offset following the updates already received, without waiting
+    pub fn next_offset(&self) -> Result<i64> {
+        // The last update only
+        let body = serde_json::json!({
+            "offset": -1,
+            "timeout": 0,
+            "allowed_updates": ["message"],
+        });
+        let response = self.call("getUpdates", &body, Duration::from_secs(10))?;
+        let updates = serde_json::from_slice::<Response<Vec<Update>>>(&response)?.into_result()?;
+        Ok(updates.last().map_or(0, |update| update.update_id + 1))
+    }
+
+    pub fn send(&self, chat_id: i64, text: &str) -> Result<()> {
+        let body = serde_json::json!({"chat_id": chat_id, "text": text});
+        let response = self.call("sendMessage", &body, Duration::from_secs(10))?;
+        serde_json::from_slice::<Response<serde_json::Value>>(&response)?.into_result()?;
+        Ok(())
+    }
+
+    /// Body of the response to `method`, whatever its status: the errors
+    /// are described in it
+    fn call(&self, method: &str, body: &serde_json::Value, timeout: Duration) -> Result<Vec<u8>> {
+        use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+        use esp_idf_svc::http::Method;
+        use esp_idf_svc::io::Write;
+
+        let body = serde_json::to_vec(body)?;
+        let mut connection = EspHttpConnection::new(&Configuration {
+            timeout: Some(timeout),
+            crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+            ..Default::default()
+        })?;
+        let len = body.len().to_string();
+        let url = format!("{API_URL}/bot{}/{method}", self.token);
+        connection.initiate_request(
+            Method::Post,
+            &url,
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Length", &len),
+            ],
+        )?;
+        connection.write_all(&body)?;
+        connection.initiate_response()?;
+        crate::http::read_body(&mut connection, MAX_BODY_LEN)
+    }
+}
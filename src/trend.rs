@@ -0,0 +1,147 @@
+use core::fmt::{self, Display, Formatter};
+
+use serde::Serialize;
+
+use crate::history::Sample;
+use crate::reading;
+
+/// Recent history the trend is computed from
+const WINDOW_SECS: i64 = 30 * 60;
+/// Fewer samples make the regression meaningless
+const MIN_SAMPLES: usize = 3;
+/// Slopes (µg/m³ per hour) below this are noise
+const STEADY_SLOPE: f32 = 2.0;
+/// Slope (µg/m³ per hour) from which the LED pulses
+const FAST_SLOPE: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Rising,
+    Steady,
+    Falling,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rising => write!(f, "rising"),
+            Self::Steady => write!(f, "steady"),
+            Self::Falling => write!(f, "falling"),
+        }
+    }
+}
+
+/// Short-term evolution of PM2.5
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Trend {
+    pub direction: Direction,
+    /// µg/m³ per hour
+    pub slope: f32,
+}
+
+impl Trend {
+    /// Least squares regression of the PM2.5 samples of the last 30 minutes
+    /// before `now` (Unix timestamp), `None` without enough samples taken
+    /// after the clock was set
+    pub fn compute<'a>(samples: impl IntoIterator<Item = &'a Sample>, now: i64) -> Option<Self> {
+        let points: Vec<(f32, f32)> = samples
+            .into_iter()
+            .filter(|sample| sample.timestamp != 0)
+            .filter(|sample| i64::from(sample.timestamp) > now - WINDOW_SECS)
+            // Hours relative to `now`, to keep the precision of f32
+            .map(|sample| {
+                let hours = (i64::from(sample.timestamp) - now) as f32 / 3600.0;
+                (hours, reading::from_tenths(sample.pm25))
+            })
+            .collect();
+        if points.len() < MIN_SAMPLES {
+            return None;
+        }
+        let count = points.len() as f32;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f32>() / count;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / count;
+        let (covariance, variance) =
+            points
+                .iter()
+                .fold((0.0, 0.0), |(covariance, variance), (x, y)| {
+                    (
+                        covariance + (x - mean_x) * (y - mean_y),
+                        variance + (x - mean_x) * (x - mean_x),
+                    )
+                });
+        // All the samples taken at the same time
+        if variance == 0.0 {
+            return None;
+        }
+        let slope = covariance / variance;
+        let direction = if slope >= STEADY_SLOPE {
+            Direction::Rising
+        } else if slope <= -STEADY_SLOPE {
+            Direction::Falling
+        } else {
+            Direction::Steady
+        };
+        Some(Self { direction, slope })
+    }
+
+    /// Rising fast enough to warn before the thresholds are reached
+    pub fn is_rising_fast(&self) -> bool {
+        self.slope >= FAST_SLOPE
+    }
+}
+
+impl Display for Trend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:+.1} µg/m³/h)", self.direction, self.slope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    /// Every 5 minutes until `NOW`, PM2.5 changing by `step` tenths
+    fn samples(pm25: u16, step: i16) -> Vec<Sample> {
+        (0..6)
+            .map(|i| Sample {
+                timestamp: (NOW - 1500 + i * 300) as u32,
+                pm25: pm25.saturating_add_signed(step * i as i16),
+                pm10: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn directions() {
+        // 1 µg/m³ every 5 minutes
+        let trend = Trend::compute(&samples(100, 10), NOW).unwrap();
+        assert_eq!(trend.direction, Direction::Rising);
+        assert!((trend.slope - 12.0).abs() < 0.01);
+        assert!(trend.is_rising_fast());
+        assert_eq!(trend.to_string(), "rising (+12.0 µg/m³/h)");
+        let trend = Trend::compute(&samples(100, -2), NOW).unwrap();
+        assert_eq!(trend.direction, Direction::Falling);
+        assert!(!trend.is_rising_fast());
+        let trend = Trend::compute(&samples(100, 0), NOW).unwrap();
+        assert_eq!(trend.direction, Direction::Steady);
+    }
+
+    #[test]
+    fn needs_recent_samples() {
+        let samples = samples(100, 10);
+        assert!(Trend::compute(&samples[..2], NOW).is_none());
+        // Only the last two are in the window
+        assert!(Trend::compute(&samples, NOW + 1200).is_none());
+        let unsynchronized: Vec<Sample> = samples
+            .iter()
+            .map(|sample| Sample {
+                timestamp: 0,
+                ..*sample
+            })
+            .collect();
+        assert!(Trend::compute(&unsynchronized, NOW).is_none());
+    }
+}
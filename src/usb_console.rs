@@ -0,0 +1,124 @@
+// Only on the chips with a USB-Serial-JTAG port
+#![cfg(not(esp32))]
+
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use esp_idf_svc::hal::delay::{TickType, BLOCK};
+use esp_idf_svc::sys;
+
+use crate::improv::{self, ErrorState, Parsed, Rpc};
+
+/// Received on the console
+pub enum Input {
+    Line(String),
+    /// Improv Wi-Fi RPC, or the error to report
+    Improv(Result<Rpc, ErrorState>),
+}
+
+/// Line based console on the USB-Serial-JTAG port of the chips which have
+/// one, through its driver as the ESP-IDF console only writes to it. Improv
+/// Wi-Fi packets are told apart from the lines by their header.
+pub struct UsbConsole {
+    line: Vec<u8>,
+    /// Read but not handled yet
+    pending: VecDeque<u8>,
+}
+
+impl UsbConsole {
+    const BUFFER_SIZE: u32 = 256;
+    const MAX_LINE_LEN: usize = 512;
+
+    pub fn new() -> Result<Self> {
+        let mut config = sys::usb_serial_jtag_driver_config_t {
+            tx_buffer_size: Self::BUFFER_SIZE,
+            rx_buffer_size: Self::BUFFER_SIZE,
+        };
+        // SAFETY: the driver copies the configuration
+        sys::esp!(unsafe { sys::usb_serial_jtag_driver_install(&mut config) })?;
+        Ok(Self {
+            line: Vec::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Next line typed, echoed as it goes, or Improv packet. Blocks until
+    /// then.
+    pub fn read(&mut self) -> Input {
+        let mut buffer = [0u8; 64];
+        loop {
+            while let Some(byte) = self.pending.pop_front() {
+                if let Some(input) = self.push(byte) {
+                    return input;
+                }
+            }
+            // SAFETY: the buffer outlives the call
+            let read = unsafe {
+                sys::usb_serial_jtag_read_bytes(
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len() as u32,
+                    BLOCK,
+                )
+            };
+            self.pending.extend(&buffer[..read.max(0) as usize]);
+        }
+    }
+
+    /// Whether the line so far could be an Improv packet, which holds any
+    /// byte and is not echoed
+    fn is_improv(&self) -> bool {
+        improv::parse(&self.line) != Parsed::NotImprov
+    }
+
+    fn push(&mut self, byte: u8) -> Option<Input> {
+        let was_improv = !self.line.is_empty() && self.is_improv();
+        self.line.push(byte);
+        if let Parsed::Packet(rpc, _) = improv::parse(&self.line) {
+            self.line.clear();
+            return Some(Input::Improv(rpc));
+        }
+        if self.is_improv() {
+            return None;
+        }
+        self.line.pop();
+        // Typed after all, echoed late
+        if was_improv {
+            self.write_bytes(&self.line);
+        }
+        match byte {
+            b'\r' | b'\n' => {
+                self.write_bytes(b"\r\n");
+                let line = String::from_utf8_lossy(&self.line).into_owned();
+                self.line.clear();
+                return Some(Input::Line(line));
+            }
+            // Backspace or delete
+            0x08 | 0x7F => {
+                if self.line.pop().is_some() {
+                    self.write_bytes(b"\x08 \x08");
+                }
+            }
+            byte if self.line.len() < Self::MAX_LINE_LEN => {
+                self.line.push(byte);
+                self.write_bytes(&[byte]);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Lines end with `\r\n` for the serial terminals
+    pub fn write(&self, text: &str) {
+        self.write_bytes(text.replace('\n', "\r\n").as_bytes());
+    }
+
+    /// Without translation, for the Improv packets
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        let timeout = TickType::from(core::time::Duration::from_millis(100)).ticks();
+        // SAFETY: the bytes outlive the call, dropped when no terminal reads
+        // them
+        unsafe {
+            sys::usb_serial_jtag_write_bytes(bytes.as_ptr().cast(), bytes.len(), timeout);
+        }
+    }
+}
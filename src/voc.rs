@@ -0,0 +1,361 @@
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use embedded_hal::i2c::I2c;
+use embedded_hal_async::delay::DelayNs;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+
+use crate::gas_index::{GasIndex, States};
+use crate::reading::Kind;
+
+const SGP30_ADDRESS: u8 = 0x58;
+const SGP40_ADDRESS: u8 = 0x59;
+/// Starts the on-chip baseline algorithm of the SGP30
+const SGP30_CMD_IAQ_INIT: [u8; 2] = [0x20, 0x03];
+/// eCO2 and TVOC, to be sent every second
+const SGP30_CMD_MEASURE_IAQ: [u8; 2] = [0x20, 0x08];
+const SGP30_CMD_GET_BASELINE: [u8; 2] = [0x20, 0x15];
+const SGP30_CMD_SET_BASELINE: [u8; 2] = [0x20, 0x1E];
+/// Absolute humidity in g/m³, 8.8 fixed point
+const SGP30_CMD_SET_HUMIDITY: [u8; 2] = [0x20, 0x61];
+/// Raw signal, with the humidity and temperature as compensation
+const SGP40_CMD_MEASURE_RAW: [u8; 2] = [0x26, 0x0F];
+/// The SGP30 returns fixed values while it starts
+const SGP30_WARMUP_SAMPLES: u32 = 15;
+/// Until then the SGP30 baseline is not worth saving
+const SGP30_BASELINE_SAMPLES: u32 = 12 * 3600;
+/// Saved baselines older than this are stale, the sensor has drifted
+pub const BASELINE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VocKind {
+    /// TVOC from the on-chip algorithm, there is no VOC index
+    Sgp30,
+    /// VOC index computed from the raw signal
+    Sgp40,
+}
+
+impl VocKind {
+    /// Kind of the published value
+    pub fn reading_kind(self) -> Kind {
+        match self {
+            Self::Sgp30 => Kind::Tvoc,
+            Self::Sgp40 => Kind::Voc,
+        }
+    }
+}
+
+impl Display for VocKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sgp30 => write!(f, "SGP30"),
+            Self::Sgp40 => write!(f, "SGP40"),
+        }
+    }
+}
+
+impl VocKind {
+    /// Model from the configuration, `None` to detect it on the bus
+    pub fn from_config(model: &str) -> Result<Option<Self>> {
+        match model {
+            "auto" => Ok(None),
+            model => Ok(Some(model.parse()?)),
+        }
+    }
+}
+
+impl FromStr for VocKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sgp30" => Ok(Self::Sgp30),
+            "sgp40" => Ok(Self::Sgp40),
+            _ => bail!("Unknown VOC sensor {s}, expected sgp30 or sgp40"),
+        }
+    }
+}
+
+/// Air the sensor measures, from the DHT22 or the settings without one
+#[derive(Debug, Clone, Copy)]
+pub struct Compensation {
+    /// Percent
+    pub humidity: f32,
+    /// °C
+    pub temperature: f32,
+}
+
+impl Compensation {
+    /// g/m³, from the Magnus formula
+    pub fn absolute_humidity(&self) -> f32 {
+        let t = self.temperature;
+        let vapor_pressure = 6.112 * (17.62 * t / (243.12 + t)).exp() * self.humidity / 100.0;
+        216.7 * vapor_pressure / (273.15 + t)
+    }
+}
+
+/// What the sensor has learned of the clean air, saved to survive restarts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Baseline {
+    Sgp30 { co2eq: u16, tvoc: u16 },
+    Sgp40(States),
+}
+
+impl Baseline {
+    const LEN: usize = 17;
+
+    /// With the Unix timestamp it was taken at
+    pub fn to_bytes(self, timestamp: i64) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[1..9].copy_from_slice(&timestamp.to_le_bytes());
+        match self {
+            Self::Sgp30 { co2eq, tvoc } => {
+                bytes[0] = 30;
+                bytes[9..11].copy_from_slice(&co2eq.to_le_bytes());
+                bytes[11..13].copy_from_slice(&tvoc.to_le_bytes());
+            }
+            Self::Sgp40(states) => {
+                bytes[0] = 40;
+                bytes[9..13].copy_from_slice(&states.mean.to_le_bytes());
+                bytes[13..17].copy_from_slice(&states.std.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// The baseline and its timestamp, `None` if invalid
+    pub fn from_bytes(bytes: &[u8]) -> Option<(Self, i64)> {
+        let bytes: &[u8; Self::LEN] = bytes.try_into().ok()?;
+        let timestamp = i64::from_le_bytes(bytes[1..9].try_into().ok()?);
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let f32_at = |i: usize| f32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let baseline = match bytes[0] {
+            30 => Self::Sgp30 {
+                co2eq: u16_at(9),
+                tvoc: u16_at(11),
+            },
+            40 => Self::Sgp40(States {
+                mean: f32_at(9),
+                std: f32_at(13),
+            }),
+            _ => return None,
+        };
+        Some((baseline, timestamp))
+    }
+}
+
+/// Sensirion SGP30 or SGP40 metal oxide gas sensor on I2C, to be measured
+/// every second
+pub struct VocSensor<I2C> {
+    kind: VocKind,
+    i2c: I2C,
+    /// SGP40 only
+    gas_index: GasIndex,
+    samples: u32,
+    /// A saved baseline was restored, it is valid right away
+    restored: bool,
+}
+
+impl<I2C> VocSensor<I2C>
+where
+    I2C: I2c,
+{
+    pub async fn init(kind: VocKind, i2c: I2C, delay: &mut impl DelayNs) -> Result<Self> {
+        let mut sensor = Self {
+            kind,
+            i2c,
+            gas_index: GasIndex::default(),
+            samples: 0,
+            restored: false,
+        };
+        if kind == VocKind::Sgp30 {
+            sensor.command(SGP30_CMD_IAQ_INIT, &[], delay, 10).await?;
+        }
+        Ok(sensor)
+    }
+
+    pub fn kind(&self) -> VocKind {
+        self.kind
+    }
+
+    /// VOC index of the SGP40 or TVOC (ppb) of the SGP30, `None` while the
+    /// sensor warms up
+    pub async fn measure(
+        &mut self,
+        compensation: Compensation,
+        delay: &mut impl DelayNs,
+    ) -> Result<Option<u16>> {
+        self.samples = self.samples.saturating_add(1);
+        match self.kind {
+            VocKind::Sgp30 => {
+                // 8.8 fixed point, 0 would disable the compensation
+                let humidity = (compensation.absolute_humidity() * 256.0).clamp(1.0, 65535.0);
+                self.command(SGP30_CMD_SET_HUMIDITY, &[humidity as u16], delay, 10)
+                    .await?;
+                let [_co2eq, tvoc] = self.read(SGP30_CMD_MEASURE_IAQ, delay, 12).await?;
+                Ok((self.samples > SGP30_WARMUP_SAMPLES).then_some(tvoc))
+            }
+            VocKind::Sgp40 => {
+                let humidity = compensation.humidity.clamp(0.0, 100.0) * 65535.0 / 100.0;
+                let temperature =
+                    (compensation.temperature.clamp(-45.0, 130.0) + 45.0) * 65535.0 / 175.0;
+                self.command(
+                    SGP40_CMD_MEASURE_RAW,
+                    &[humidity as u16, temperature as u16],
+                    delay,
+                    30,
+                )
+                .await?;
+                let [sraw] = self.read_reply()?;
+                let index = self.gas_index.process(sraw);
+                Ok((index != 0).then_some(index))
+            }
+        }
+    }
+
+    /// `None` until the sensor has learned enough to be worth saving
+    pub async fn baseline(&mut self, delay: &mut impl DelayNs) -> Result<Option<Baseline>> {
+        match self.kind {
+            VocKind::Sgp30 => {
+                if !self.restored && self.samples < SGP30_BASELINE_SAMPLES {
+                    return Ok(None);
+                }
+                let [co2eq, tvoc] = self.read(SGP30_CMD_GET_BASELINE, delay, 10).await?;
+                Ok(Some(Baseline::Sgp30 { co2eq, tvoc }))
+            }
+            VocKind::Sgp40 => Ok(self.gas_index.states().map(Baseline::Sgp40)),
+        }
+    }
+
+    pub async fn set_baseline(
+        &mut self,
+        baseline: Baseline,
+        delay: &mut impl DelayNs,
+    ) -> Result<()> {
+        match (self.kind, baseline) {
+            // In the reverse order of the reads
+            (VocKind::Sgp30, Baseline::Sgp30 { co2eq, tvoc }) => {
+                self.command(SGP30_CMD_SET_BASELINE, &[tvoc, co2eq], delay, 10)
+                    .await?
+            }
+            (VocKind::Sgp40, Baseline::Sgp40(states)) => self.gas_index.set_states(states),
+            _ => bail!("Baseline of another sensor than the {}", self.kind),
+        }
+        self.restored = true;
+        Ok(())
+    }
+
+    fn address(&self) -> u8 {
+        match self.kind {
+            VocKind::Sgp30 => SGP30_ADDRESS,
+            VocKind::Sgp40 => SGP40_ADDRESS,
+        }
+    }
+
+    /// Send a command with its arguments, each followed by its CRC, then
+    /// wait for it to run
+    async fn command(
+        &mut self,
+        cmd: [u8; 2],
+        args: &[u16],
+        delay: &mut impl DelayNs,
+        duration_ms: u32,
+    ) -> Result<()> {
+        let mut frame = cmd.to_vec();
+        for arg in args {
+            let bytes = arg.to_be_bytes();
+            frame.extend(bytes);
+            frame.push(crc8(&bytes));
+        }
+        let address = self.address();
+        self.i2c
+            .write(address, &frame)
+            .map_err(|e| anyhow!("Unable to send command to the {}: {e:?}", self.kind))?;
+        delay.delay_ms(duration_ms).await;
+        Ok(())
+    }
+
+    async fn read<const N: usize>(
+        &mut self,
+        cmd: [u8; 2],
+        delay: &mut impl DelayNs,
+        duration_ms: u32,
+    ) -> Result<[u16; N]> {
+        self.command(cmd, &[], delay, duration_ms).await?;
+        self.read_reply()
+    }
+
+    /// Words of the reply, each followed by its CRC
+    fn read_reply<const N: usize>(&mut self) -> Result<[u16; N]> {
+        let mut reply = vec![0u8; N * 3];
+        let address = self.address();
+        self.i2c
+            .read(address, &mut reply)
+            .map_err(|e| anyhow!("Unable to read reply of the {}: {e:?}", self.kind))?;
+        let mut words = [0u16; N];
+        for (word, chunk) in words.iter_mut().zip(reply.chunks_exact(3)) {
+            if crc8(&chunk[..2]) != chunk[2] {
+                bail!("Invalid reply from the {}", self.kind);
+            }
+            *word = u16::from_be_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(words)
+    }
+}
+
+impl<I2C> Display for VocSensor<I2C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+/// CRC-8 of the Sensirion sensors, polynomial 0x31 from 0xFF
+pub fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0xFF, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(target_os = "espidf")]
+const NAMESPACE: &str = "voc";
+#[cfg(target_os = "espidf")]
+const KEY_BASELINE: &str = "baseline";
+
+/// Baseline of the VOC sensor, persisted in the `voc` NVS namespace
+#[cfg(target_os = "espidf")]
+pub struct BaselineStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+#[cfg(target_os = "espidf")]
+impl BaselineStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, NAMESPACE, true)?,
+        })
+    }
+
+    /// The saved baseline and its Unix timestamp
+    pub fn load(&self) -> Result<Option<(Baseline, i64)>> {
+        let mut buf = [0u8; Baseline::LEN];
+        Ok(self
+            .nvs
+            .get_blob(KEY_BASELINE, &mut buf)?
+            .and_then(Baseline::from_bytes))
+    }
+
+    pub fn save(&mut self, baseline: Baseline, timestamp: i64) -> Result<()> {
+        Ok(self
+            .nvs
+            .set_blob(KEY_BASELINE, &baseline.to_bytes(timestamp))?)
+    }
+}
@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POST the JSON `body` to `url`, successful with a 2xx status
+pub fn post(url: &str, body: &[u8]) -> Result<()> {
+    use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+    use esp_idf_svc::http::Method;
+    use esp_idf_svc::io::Write;
+
+    let mut connection = EspHttpConnection::new(&Configuration {
+        timeout: Some(HTTP_TIMEOUT),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let len = body.len().to_string();
+    let headers = [
+        ("Content-Type", "application/json"),
+        ("Content-Length", len.as_str()),
+    ];
+    connection.initiate_request(Method::Post, url, &headers)?;
+    connection.write_all(body)?;
+    connection.initiate_response()?;
+    if !(200..300).contains(&connection.status()) {
+        bail!("HTTP status {}", connection.status());
+    }
+    Ok(())
+}
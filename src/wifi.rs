@@ -1,38 +1,143 @@
-use anyhow::{bail, Result};
+use std::ffi::CString;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     hal::peripheral,
-    wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi},
+    handle::RawHandle,
+    sys::{self, esp},
+    timer::EspTaskTimerService,
+    wifi::{
+        AccessPointConfiguration, AsyncWifi, AuthMethod, BlockingWifi, ClientConfiguration,
+        Configuration, EspWifi,
+    },
 };
 use log::info;
+use macaddr::MacAddr6;
+use serde::Serialize;
+
+/// Signal improvement (dB) needed to switch to another access point
+const ROAM_MARGIN: i16 = 8;
+
+/// WPA2-Enterprise credentials, PEAP or TTLS being negotiated with the
+/// network
+pub struct Eap<'a> {
+    /// Outer identity, sent in clear, often `anonymous@<realm>`
+    pub identity: &'a str,
+    pub username: &'a str,
+    pub password: &'a str,
+    /// Inner method of TTLS: `mschapv2`, `mschap`, `pap`, `chap` or `eap`
+    pub ttls_phase2: &'a str,
+    /// PEM with its NUL terminator, the server is not verified without it.
+    /// Kept by reference by ESP-IDF.
+    pub ca_cert: Option<&'static [u8]>,
+}
+
+impl Eap<'_> {
+    fn enable(&self) -> Result<()> {
+        let phase2 = match self.ttls_phase2 {
+            "mschapv2" => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_MSCHAPV2,
+            "mschap" => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_MSCHAP,
+            "pap" => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_PAP,
+            "chap" => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_CHAP,
+            "eap" => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_EAP,
+            other => bail!("Unknown TTLS phase 2 method {other}"),
+        };
+        // SAFETY: the strings are copied by ESP-IDF, the CA certificate is
+        // 'static
+        unsafe {
+            esp!(sys::esp_eap_client_set_identity(
+                self.identity.as_ptr(),
+                self.identity.len() as _
+            ))?;
+            esp!(sys::esp_eap_client_set_username(
+                self.username.as_ptr(),
+                self.username.len() as _
+            ))?;
+            esp!(sys::esp_eap_client_set_password(
+                self.password.as_ptr(),
+                self.password.len() as _
+            ))?;
+            if let Some(ca_cert) = self.ca_cert {
+                esp!(sys::esp_eap_client_set_ca_cert(
+                    ca_cert.as_ptr(),
+                    ca_cert.len() as _
+                ))?;
+            }
+            esp!(sys::esp_eap_client_set_ttls_phase2_method(phase2))?;
+            esp!(sys::esp_wifi_sta_enterprise_enable())?;
+        }
+        Ok(())
+    }
+}
+
+/// Signal and access point of the station interface
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WifiStats {
+    /// dBm, `None` while disconnected
+    pub rssi: Option<i8>,
+    pub bssid: Option<String>,
+    pub channel: Option<u8>,
+}
+
+impl WifiStats {
+    /// Refresh the signal and access point
+    pub fn update(&mut self, wifi: &mut AsyncWifi<EspWifi<'static>>) {
+        match wifi.wifi_mut().driver_mut().get_ap_info() {
+            Ok(ap) => {
+                self.rssi = Some(ap.signal_strength);
+                self.bssid = Some(MacAddr6::from(ap.bssid).to_string());
+                self.channel = Some(ap.channel);
+            }
+            // Not connected
+            Err(_) => {
+                self.rssi = None;
+                self.bssid = None;
+                self.channel = None;
+            }
+        }
+    }
+}
 
-pub fn wifi(
+/// Join the network, with `eap` instead of `pass` for WPA2-Enterprise
+pub async fn wifi(
     ssid: &str,
     pass: &str,
+    eap: Option<&Eap<'_>>,
+    hostname: &str,
     modem: impl peripheral::Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
     sysloop: EspSystemEventLoop,
-) -> Result<Box<EspWifi<'static>>> {
+    timer_service: EspTaskTimerService,
+) -> Result<AsyncWifi<EspWifi<'static>>> {
     let mut auth_method = AuthMethod::WPA2Personal;
     if ssid.is_empty() {
         bail!("Missing WiFi name")
     }
-    if pass.is_empty() {
+    if eap.is_some() {
+        auth_method = AuthMethod::WPA2Enterprise;
+        info!("Using WPA2-Enterprise");
+    } else if pass.is_empty() {
         auth_method = AuthMethod::None;
         info!("Wifi password is empty");
     }
-    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    let esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    // Sent with the DHCP requests, ESP-IDF keeps a copy
+    let hostname = CString::new(hostname)?;
+    esp!(unsafe { sys::esp_netif_set_hostname(esp_wifi.sta_netif().handle(), hostname.as_ptr()) })?;
 
-    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop)?;
+    let mut wifi = AsyncWifi::wrap(esp_wifi, sysloop, timer_service)?;
 
     wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
 
     info!("Starting wifi...");
 
-    wifi.start()?;
+    wifi.start().await?;
 
     info!("Scanning...");
 
-    let ap_infos = wifi.scan()?;
+    let ap_infos = wifi.scan().await?;
 
     let ours = ap_infos.into_iter().find(|a| a.ssid == ssid);
 
@@ -61,18 +166,204 @@ pub fn wifi(
         auth_method,
         ..Default::default()
     }))?;
+    if let Some(eap) = eap {
+        eap.enable()?;
+    }
 
     info!("Connecting wifi...");
 
-    wifi.connect()?;
+    wifi.connect().await?;
 
     info!("Waiting for DHCP lease...");
 
-    wifi.wait_netif_up()?;
+    wifi.wait_netif_up().await?;
 
     let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
 
     info!("Wifi DHCP info: {:?}", ip_info);
 
+    Ok(wifi)
+}
+
+/// Switch to the strongest access point of the network if it beats the
+/// current one by `ROAM_MARGIN`, returns whether it did. The connection is
+/// down during the scan and the switch.
+pub async fn roam(wifi: &mut AsyncWifi<EspWifi<'static>>) -> Result<bool> {
+    let current = wifi.wifi_mut().driver_mut().get_ap_info()?;
+    let Configuration::Client(mut client) = wifi.get_configuration()? else {
+        bail!("Not a Wi-Fi client");
+    };
+    let best = wifi
+        .scan()
+        .await?
+        .into_iter()
+        .filter(|ap| ap.ssid == client.ssid)
+        .max_by_key(|ap| ap.signal_strength);
+    let Some(best) = best.filter(|ap| {
+        ap.bssid != current.bssid
+            && i16::from(ap.signal_strength) >= i16::from(current.signal_strength) + ROAM_MARGIN
+    }) else {
+        return Ok(false);
+    };
+    info!(
+        "Roaming from {} ({} dBm) to {} ({} dBm)",
+        MacAddr6::from(current.bssid),
+        current.signal_strength,
+        MacAddr6::from(best.bssid),
+        best.signal_strength
+    );
+    client.bssid = Some(best.bssid);
+    client.channel = Some(best.channel);
+    wifi.disconnect().await?;
+    wifi.set_configuration(&Configuration::Client(client))?;
+    wifi.connect().await?;
+    wifi.wait_netif_up().await?;
+    Ok(true)
+}
+
+/// Forget the access point chosen when roaming, any of the network will do
+pub fn unpin_access_point(wifi: &mut AsyncWifi<EspWifi<'static>>) -> Result<()> {
+    if let Configuration::Client(mut client) = wifi.get_configuration()? {
+        if client.bssid.is_some() {
+            client.bssid = None;
+            client.channel = None;
+            wifi.set_configuration(&Configuration::Client(client))?;
+        }
+    }
+    Ok(())
+}
+
+/// Station joining `ssid`, open when `pass` is empty
+fn client_configuration(ssid: &str, pass: &str) -> Result<ClientConfiguration> {
+    Ok(ClientConfiguration {
+        ssid: ssid.try_into().map_err(|()| anyhow!("SSID too long"))?,
+        password: pass.try_into().map_err(|()| anyhow!("Password too long"))?,
+        auth_method: if pass.is_empty() {
+            AuthMethod::None
+        } else {
+            AuthMethod::WPA2Personal
+        },
+        ..Default::default()
+    })
+}
+
+/// Switch to another network, back to the previous one if it can't be
+/// joined. Returns the new address.
+pub async fn provision(
+    wifi: &mut AsyncWifi<EspWifi<'static>>,
+    ssid: &str,
+    pass: &str,
+) -> Result<Ipv4Addr> {
+    let previous = wifi.get_configuration()?;
+    info!("Joining {ssid}...");
+    wifi.disconnect().await?;
+    let joined = async {
+        wifi.set_configuration(&Configuration::Client(client_configuration(ssid, pass)?))?;
+        wifi.connect().await?;
+        wifi.wait_netif_up().await?;
+        anyhow::Ok(wifi.wifi().sta_netif().get_ip_info()?.ip)
+    }
+    .await;
+    if joined.is_err() {
+        let _ = wifi.disconnect().await;
+        wifi.set_configuration(&previous)?;
+        // Reconnected later by the blink task otherwise
+        if let Err(e) = wifi.connect().await {
+            log::warn!("Unable to rejoin the previous network: {e:?}");
+        }
+    }
+    joined
+}
+
+/// Connect the station of the access point started by [`access_point`] to
+/// `ssid`, returns its address
+pub fn join(
+    wifi: &mut EspWifi<'static>,
+    sysloop: EspSystemEventLoop,
+    ssid: &str,
+    pass: &str,
+) -> Result<Ipv4Addr> {
+    let Configuration::Mixed(_, access_point) = wifi.get_configuration()? else {
+        bail!("Not an access point");
+    };
+    let mut wifi = BlockingWifi::wrap(wifi, sysloop)?;
+    wifi.set_configuration(&Configuration::Mixed(
+        client_configuration(ssid, pass)?,
+        access_point,
+    ))?;
+    info!("Joining {ssid}...");
+    wifi.connect()?;
+    wifi.ip_wait_while(
+        || wifi.wifi().sta_netif().is_up().map(|up| !up),
+        Some(Duration::from_secs(15)),
+    )?;
+    Ok(wifi.wifi().sta_netif().get_ip_info()?.ip)
+}
+
+/// Network found by a scan, for the configuration page
+#[derive(Debug, Clone, Serialize)]
+pub struct Network {
+    pub ssid: String,
+    /// dBm
+    pub rssi: i8,
+    pub secured: bool,
+}
+
+/// Networks in range, strongest first, once per SSID. Blocks for a few
+/// seconds.
+pub fn scan(wifi: &mut EspWifi<'static>) -> Result<Vec<Network>> {
+    let mut access_points = wifi.scan()?;
+    access_points.sort_by_key(|ap| core::cmp::Reverse(ap.signal_strength));
+    let mut networks: Vec<Network> = Vec::new();
+    for ap in access_points {
+        // Hidden, or another access point of a network already listed
+        if ap.ssid.is_empty() || networks.iter().any(|n| n.ssid == ap.ssid.as_str()) {
+            continue;
+        }
+        networks.push(Network {
+            ssid: ap.ssid.to_string(),
+            rssi: ap.signal_strength,
+            secured: !matches!(ap.auth_method, None | Some(AuthMethod::None)),
+        });
+    }
+    Ok(networks)
+}
+
+/// Start an open access point, the device is reachable on 192.168.71.1. The
+/// station interface is enabled too, unconnected, for the scans.
+pub fn access_point(
+    ssid: &str,
+    modem: impl peripheral::Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
+    sysloop: EspSystemEventLoop,
+) -> Result<Box<EspWifi<'static>>> {
+    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+
+    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop)?;
+
+    wifi.set_configuration(&Configuration::Mixed(
+        ClientConfiguration::default(),
+        AccessPointConfiguration {
+            ssid: ssid
+                .try_into()
+                .expect("Could not parse the given SSID into WiFi config"),
+            auth_method: AuthMethod::None,
+            channel: 1,
+            ..Default::default()
+        },
+    ))?;
+
+    info!("Starting access point {}...", ssid);
+
+    wifi.start()?;
+    // Not `wait_netif_up`, which would wait for the station to connect
+    wifi.ip_wait_while(
+        || wifi.wifi().ap_netif().is_up().map(|up| !up),
+        Some(Duration::from_secs(15)),
+    )?;
+
+    let ip_info = wifi.wifi().ap_netif().get_ip_info()?;
+
+    info!("Access point IP info: {:?}", ip_info);
+
     Ok(Box::new(esp_wifi))
 }
@@ -0,0 +1,92 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use esp_idf_svc::http::server::ws::{EspHttpWsConnection, EspHttpWsDetachedSender};
+use esp_idf_svc::ws::FrameType;
+use serde::Serialize;
+
+use crate::http;
+
+/// Clients connected to the WebSocket endpoint
+#[derive(Default)]
+pub struct Clients {
+    senders: Mutex<Vec<EspHttpWsDetachedSender>>,
+}
+
+impl Clients {
+    /// Send `event` as JSON to every client, dropping the ones which left.
+    /// Blocks until the server task has sent it, so must not be called from
+    /// an HTTP handler.
+    pub fn broadcast(&self, event: &impl Serialize) {
+        let mut senders = self.senders.lock().unwrap();
+        if senders.is_empty() {
+            return;
+        }
+        let json = match serde_json::to_string(event) {
+            Ok(json) => json,
+            Err(e) => return log::error!("Unable to serialize WebSocket event: {e:?}"),
+        };
+        senders.retain_mut(|sender| {
+            !sender.is_closed()
+                && sender
+                    .send(FrameType::Text(false), json.as_bytes())
+                    .inspect_err(|e| log::warn!("WebSocket {} dropped: {e:?}", sender.session()))
+                    .is_ok()
+        });
+    }
+
+    /// Forget every client, before the server is stopped
+    pub fn clear(&self) {
+        self.senders.lock().unwrap().clear();
+    }
+}
+
+/// Accept WebSocket connections on `uri`. The JSON messages of `on_open`
+/// are sent to each new client, and every text message received is answered
+/// with the one returned by `on_message`.
+pub fn register_handler(
+    handlers: &mut http::Handlers,
+    uri: &str,
+    clients: Arc<Clients>,
+    on_open: impl Fn() -> Vec<String> + Send + Sync + 'static,
+    on_message: impl Fn(&[u8]) -> String + Send + Sync + 'static,
+) -> Result<()> {
+    let handler = move |connection: &mut EspHttpWsConnection| -> Result<()> {
+        if connection.is_new() {
+            log::info!("WebSocket {} connected", connection.session());
+            for message in on_open() {
+                connection.send(FrameType::Text(false), message.as_bytes())?;
+            }
+            let sender = connection.create_detached_sender()?;
+            clients.senders.lock().unwrap().push(sender);
+            return Ok(());
+        }
+        if connection.is_closed() {
+            let session = connection.session();
+            log::info!("WebSocket {session} closed");
+            clients
+                .senders
+                .lock()
+                .unwrap()
+                .retain(|sender| sender.session() != session);
+            return Ok(());
+        }
+        // The first call only gives the length, nothing is read yet
+        let (frame_type, len) = connection.recv(&mut [])?;
+        if len > http::MAX_BODY_LEN {
+            // Closes the connection, the frame is left unread
+            bail!("WebSocket message too large: {len}");
+        }
+        let mut buf = vec![0u8; len];
+        connection.recv(&mut buf)?;
+        if let FrameType::Text(false) = frame_type {
+            let reply = on_message(&buf);
+            connection.send(FrameType::Text(false), reply.as_bytes())?;
+        }
+        Ok(())
+    };
+    handlers.register(&format!("WebSocket {uri}"), |server| {
+        server.ws_handler(uri, handler)?;
+        Ok(())
+    })
+}
@@ -0,0 +1,227 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::hal::delay::BLOCK;
+use esp_idf_svc::sys::{esp, zigbee, EspError};
+
+use crate::voc::Compensation;
+
+/// Of the basic cluster, for the coordinator to tell the devices apart
+const MODEL: &str = "air-quality-sensor";
+/// Of the sensor clusters
+const ENDPOINT: u8 = 1;
+const PROFILE_HOME_AUTOMATION: u16 = 0x0104;
+const DEVICE_SIMPLE_SENSOR: u16 = 0x000C;
+
+const CLUSTER_TEMPERATURE: u16 = 0x0402;
+const CLUSTER_HUMIDITY: u16 = 0x0405;
+const CLUSTER_PM25: u16 = 0x042A;
+/// First attribute of the measurement clusters
+const ATTR_MEASURED_VALUE: u16 = 0x0000;
+const ATTR_MANUFACTURER_NAME: u16 = 0x0004;
+const ATTR_MODEL_IDENTIFIER: u16 = 0x0005;
+const ROLE_SERVER: u8 = 0x01;
+const ZCL_VERSION: u8 = 3;
+const POWER_SOURCE_DC: u8 = 0x04;
+
+// `esp_zb_nwk_device_type_t`
+const END_DEVICE: u32 = 0x02;
+/// Forgotten by its parent when silent for longer than 64 minutes
+const ED_AGING_TIMEOUT_64MIN: u8 = 6;
+/// Polls of the parent, in ms
+const KEEP_ALIVE: u32 = 3000;
+/// Channels 11 to 26
+const ALL_CHANNELS: u32 = 0x07FF_F800;
+/// Of the network steering after a failure, in ms
+const STEERING_RETRY: u32 = 10_000;
+
+// `esp_zb_app_signal_type_t`
+const SIGNAL_SKIP_STARTUP: u32 = 0x01;
+const SIGNAL_DEVICE_FIRST_START: u32 = 0x05;
+const SIGNAL_DEVICE_REBOOT: u32 = 0x06;
+const SIGNAL_STEERING: u32 = 0x0A;
+// `esp_zb_bdb_commissioning_mode_t`
+const MODE_INITIALIZATION: u8 = 0x00;
+const MODE_NETWORK_STEERING: u8 = 0x02;
+
+/// Values of the measurement clusters until the first measurement
+const UNKNOWN_TEMPERATURE: i16 = i16::MIN;
+const UNKNOWN_HUMIDITY: u16 = u16::MAX;
+
+/// Register the end device, with the temperature and humidity clusters when
+/// there is a `climate` sensor, then run the Zigbee stack. Only returns on
+/// error.
+pub fn run(climate: bool) -> Result<()> {
+    // SAFETY: the stack is only set up here, once, before it runs
+    unsafe {
+        // Zeroed: the native radio, without a host
+        let mut platform: zigbee::esp_zb_platform_config_t = core::mem::zeroed();
+        esp!(zigbee::esp_zb_platform_config(&mut platform))?;
+        let mut config: zigbee::esp_zb_cfg_t = core::mem::zeroed();
+        config.esp_zb_role = END_DEVICE as _;
+        config.nwk_cfg.zed_cfg.ed_timeout = ED_AGING_TIMEOUT_64MIN as _;
+        config.nwk_cfg.zed_cfg.keep_alive = KEEP_ALIVE;
+        zigbee::esp_zb_init(&mut config);
+        register(climate);
+        esp!(zigbee::esp_zb_set_primary_network_channel_set(ALL_CHANNELS))?;
+        // Commissioning starts from the signal handler
+        esp!(zigbee::esp_zb_start(false))?;
+        zigbee::esp_zb_stack_main_loop();
+    }
+    bail!("Zigbee stack stopped")
+}
+
+/// Set the measured values, reported to the coordinator as it configured
+/// the reporting
+pub fn update(pm25: f32, climate: Option<Compensation>) {
+    // SAFETY: the attributes are only changed while holding the stack lock
+    unsafe {
+        if !zigbee::esp_zb_lock_acquire(BLOCK) {
+            return;
+        }
+        let mut pm25 = pm25;
+        set(CLUSTER_PM25, &mut pm25);
+        if let Some(climate) = climate {
+            // Hundredths of °C and of %
+            let mut temperature = (climate.temperature * 100.0).round() as i16;
+            let mut humidity = (climate.humidity * 100.0).round().clamp(0.0, 10000.0) as u16;
+            set(CLUSTER_TEMPERATURE, &mut temperature);
+            set(CLUSTER_HUMIDITY, &mut humidity);
+        }
+        zigbee::esp_zb_lock_release();
+    }
+}
+
+unsafe fn set<T>(cluster: u16, value: &mut T) {
+    let status = zigbee::esp_zb_zcl_set_attribute_val(
+        ENDPOINT,
+        cluster,
+        ROLE_SERVER,
+        ATTR_MEASURED_VALUE,
+        (value as *mut T).cast(),
+        false,
+    );
+    if status != 0 {
+        log::warn!("Unable to set the Zigbee attribute of cluster {cluster:#06x}: {status}");
+    }
+}
+
+/// Endpoint of the sensor, with the basic and identify clusters expected
+/// of every device
+unsafe fn register(climate: bool) {
+    let mut basic = zigbee::esp_zb_basic_cluster_cfg_t {
+        zcl_version: ZCL_VERSION,
+        power_source: POWER_SOURCE_DC,
+    };
+    let attributes = zigbee::esp_zb_basic_cluster_create(&mut basic);
+    let mut manufacturer = zcl_string(env!("CARGO_PKG_NAME"));
+    let mut model = zcl_string(MODEL);
+    zigbee::esp_zb_basic_cluster_add_attr(
+        attributes,
+        ATTR_MANUFACTURER_NAME,
+        manufacturer.as_mut_ptr().cast(),
+    );
+    zigbee::esp_zb_basic_cluster_add_attr(
+        attributes,
+        ATTR_MODEL_IDENTIFIER,
+        model.as_mut_ptr().cast(),
+    );
+    let clusters = zigbee::esp_zb_zcl_cluster_list_create();
+    zigbee::esp_zb_cluster_list_add_basic_cluster(clusters, attributes, ROLE_SERVER);
+    let mut identify = zigbee::esp_zb_identify_cluster_cfg_t { identify_time: 0 };
+    zigbee::esp_zb_cluster_list_add_identify_cluster(
+        clusters,
+        zigbee::esp_zb_identify_cluster_create(&mut identify),
+        ROLE_SERVER,
+    );
+    let mut pm25 = zigbee::esp_zb_pm2_5_measurement_cluster_cfg_t {
+        measured_value: f32::NAN,
+        min_measured_value: 0.0,
+        max_measured_value: 1000.0,
+    };
+    zigbee::esp_zb_cluster_list_add_pm2_5_measurement_cluster(
+        clusters,
+        zigbee::esp_zb_pm2_5_measurement_cluster_create(&mut pm25),
+        ROLE_SERVER,
+    );
+    if climate {
+        // The range of the DHT22
+        let mut temperature = zigbee::esp_zb_temperature_meas_cluster_cfg_t {
+            measured_value: UNKNOWN_TEMPERATURE,
+            min_value: -4000,
+            max_value: 8000,
+        };
+        zigbee::esp_zb_cluster_list_add_temperature_meas_cluster(
+            clusters,
+            zigbee::esp_zb_temperature_meas_cluster_create(&mut temperature),
+            ROLE_SERVER,
+        );
+        let mut humidity = zigbee::esp_zb_humidity_meas_cluster_cfg_t {
+            measured_value: UNKNOWN_HUMIDITY,
+            min_value: 0,
+            max_value: 10000,
+        };
+        zigbee::esp_zb_cluster_list_add_humidity_meas_cluster(
+            clusters,
+            zigbee::esp_zb_humidity_meas_cluster_create(&mut humidity),
+            ROLE_SERVER,
+        );
+    }
+    let endpoints = zigbee::esp_zb_ep_list_create();
+    let mut endpoint: zigbee::esp_zb_endpoint_config_t = core::mem::zeroed();
+    endpoint.endpoint = ENDPOINT;
+    endpoint.app_profile_id = PROFILE_HOME_AUTOMATION;
+    endpoint.app_device_id = DEVICE_SIMPLE_SENSOR;
+    zigbee::esp_zb_ep_list_add_ep(endpoints, clusters, endpoint);
+    zigbee::esp_zb_device_register(endpoints);
+}
+
+/// ZCL character string, prefixed with its length
+fn zcl_string(text: &str) -> Vec<u8> {
+    let text = &text.as_bytes()[..text.len().min(32)];
+    let mut string = vec![text.len() as u8];
+    string.extend(text);
+    string
+}
+
+/// Start the commissioning in `mode`, from the stack task
+unsafe extern "C" fn commission(mode: u8) {
+    if let Err(e) = esp!(zigbee::esp_zb_bdb_start_top_level_commissioning(mode)) {
+        log::error!("Unable to start the Zigbee commissioning: {e}");
+    }
+}
+
+/// Called by the stack on its events: joins a network on the first start,
+/// and until it succeeds
+#[no_mangle]
+extern "C" fn esp_zb_app_signal_handler(signal: *mut zigbee::esp_zb_app_signal_t) {
+    // SAFETY: the stack passes a valid signal, only used during the call
+    let (kind, status) = unsafe { (*(*signal).p_app_signal, (*signal).esp_err_status) };
+    match (kind, EspError::convert(status)) {
+        (SIGNAL_SKIP_STARTUP, _) => unsafe { commission(MODE_INITIALIZATION) },
+        (SIGNAL_DEVICE_FIRST_START | SIGNAL_DEVICE_REBOOT, Ok(())) => {
+            if unsafe { zigbee::esp_zb_bdb_is_factory_new() } {
+                log::info!("Joining a Zigbee network");
+                unsafe { commission(MODE_NETWORK_STEERING) };
+            } else {
+                log::info!("Back on the Zigbee network");
+            }
+        }
+        (SIGNAL_STEERING, Ok(())) => unsafe {
+            log::info!(
+                "Joined the Zigbee network {:#06x} on channel {}",
+                zigbee::esp_zb_get_pan_id(),
+                zigbee::esp_zb_get_current_channel()
+            );
+        },
+        (SIGNAL_DEVICE_FIRST_START | SIGNAL_DEVICE_REBOOT | SIGNAL_STEERING, Err(e)) => {
+            log::warn!("No Zigbee network to join: {e}");
+            unsafe {
+                zigbee::esp_zb_scheduler_alarm(
+                    Some(commission),
+                    MODE_NETWORK_STEERING,
+                    STEERING_RETRY,
+                )
+            };
+        }
+        (kind, result) => log::debug!("Zigbee signal {kind:#04x}: {result:?}"),
+    }
+}
@@ -0,0 +1,56 @@
+//! Unit tests of the modules which run without ESP-IDF, with
+//! `cargo +stable host-test`. The firmware binary can't use the test
+//! harness, its modules are compiled again here along with those they
+//! depend on.
+#![allow(dead_code)]
+
+#[path = "../src/aqi.rs"]
+mod aqi;
+#[path = "../src/build_info.rs"]
+mod build_info;
+#[path = "../src/calibration.rs"]
+mod calibration;
+#[path = "../src/cayenne.rs"]
+mod cayenne;
+#[path = "../src/clock.rs"]
+mod clock;
+#[path = "../src/coap.rs"]
+mod coap;
+#[path = "../src/fan.rs"]
+mod fan;
+#[path = "../src/gas_index.rs"]
+mod gas_index;
+#[path = "../src/history.rs"]
+mod history;
+#[path = "../src/i2c_bus.rs"]
+mod i2c_bus;
+#[path = "../src/improv.rs"]
+mod improv;
+#[path = "../src/lorawan.rs"]
+mod lorawan;
+#[path = "../src/modbus.rs"]
+mod modbus;
+#[path = "../src/mqtt.rs"]
+mod mqtt;
+#[path = "../src/pipeline.rs"]
+mod pipeline;
+#[path = "../src/pm1006.rs"]
+mod pm1006;
+#[path = "../src/pms5003.rs"]
+mod pms5003;
+#[path = "../src/reading.rs"]
+mod reading;
+#[path = "../src/relay.rs"]
+mod relay;
+#[path = "../src/schedule.rs"]
+mod schedule;
+#[path = "../src/sensor.rs"]
+mod sensor;
+#[path = "../src/snmp.rs"]
+mod snmp;
+#[path = "../src/stats.rs"]
+mod stats;
+#[path = "../src/trend.rs"]
+mod trend;
+#[path = "../src/voc.rs"]
+mod voc;